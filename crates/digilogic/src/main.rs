@@ -3,19 +3,26 @@
     windows_subsystem = "windows"
 )]
 
+#[cfg(not(target_arch = "wasm32"))]
+mod render;
 mod ui;
 
 use bevy_ecs::prelude::*;
 use bevy_reflect::Reflect;
 use bevy_state::prelude::*;
 use bevy_time::{Time, Virtual};
+use digilogic_core::events::{CircuitLoadEvent, ProjectLoadEvent};
+use digilogic_core::resources::Project;
 use digilogic_core::states::SimulationConnected;
 use digilogic_core::SharedStr;
 use digilogic_routing::RoutingConfig;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 const ROUTING_CONFIG_KEY: &str = "routing";
 
+const APP_ID: &str = "digilogic";
+
 #[cfg(debug_assertions)]
 const LOG_LEVEL: bevy_log::Level = bevy_log::Level::DEBUG;
 #[cfg(not(debug_assertions))]
@@ -60,6 +67,19 @@ struct AppSettings {
     backend: Backend,
     builtin_backend_engine: native_main::SimulationEngine,
     external_backend_addr: (SharedStr, u16),
+    /// Recently opened projects and circuits, most recent first
+    #[serde(default)]
+    recent_files: Vec<PathBuf>,
+}
+
+const MAX_RECENT_FILES: usize = 10;
+
+impl AppSettings {
+    fn add_recent_file(&mut self, filename: PathBuf) {
+        self.recent_files.retain(|recent| *recent != filename);
+        self.recent_files.insert(0, filename);
+        self.recent_files.truncate(MAX_RECENT_FILES);
+    }
 }
 
 const DEFAULT_LOCAL_SERVER_ADDR: (SharedStr, u16) = (
@@ -77,6 +97,7 @@ impl Default for AppSettings {
             backend: Backend::default(),
             builtin_backend_engine: native_main::SimulationEngine::default(),
             external_backend_addr: DEFAULT_LOCAL_SERVER_ADDR,
+            recent_files: Vec::new(),
         }
     }
 }
@@ -89,6 +110,51 @@ enum FileDialogEvent {
     AddCircuit,
     ImportCircuit,
     SaveCircuit,
+    ExportCircuit,
+}
+
+/// Opens a project or a circuit, depending on the file extension, and adds it
+/// to the recent files.
+#[derive(Event)]
+struct OpenFileEvent(PathBuf);
+
+fn handle_open_file_events(
+    mut commands: Commands,
+    mut open_file_events: EventReader<OpenFileEvent>,
+    mut project_load_events: EventWriter<ProjectLoadEvent>,
+    mut circuit_load_events: EventWriter<CircuitLoadEvent>,
+    mut settings: ResMut<AppSettings>,
+    project: Option<Res<Project>>,
+) {
+    let mut has_project = project.is_some();
+
+    for OpenFileEvent(filename) in open_file_events.read() {
+        if !filename.exists() {
+            bevy_log::error!("{} doesn't exist", filename.display());
+            settings.recent_files.retain(|recent| recent != filename);
+            continue;
+        }
+
+        let filename = filename.canonicalize().unwrap_or_else(|_| filename.clone());
+        settings.add_recent_file(filename.clone());
+
+        if filename.extension().is_some_and(|ext| ext == "dlp") {
+            project_load_events.send(ProjectLoadEvent { filename });
+            has_project = true;
+        } else {
+            // Circuits are only shown as part of a project
+            if !has_project {
+                commands.insert_resource(Project {
+                    name: SharedStr::new_static("Unnamed Project"),
+                    file_path: None,
+                    root_circuit: None,
+                });
+                has_project = true;
+            }
+
+            circuit_load_events.send(CircuitLoadEvent { filename });
+        }
+    }
 }
 
 #[repr(transparent)]
@@ -103,7 +169,7 @@ fn resume_time(mut time: ResMut<Time<Virtual>>) {
 }
 
 impl App {
-    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    fn new(cc: &eframe::CreationContext<'_>, files: Vec<PathBuf>) -> Self {
         let context = &cc.egui_ctx;
         let render_state = cc.wgpu_render_state.as_ref().unwrap();
 
@@ -134,11 +200,13 @@ impl App {
             },
         ));
 
-        app.register_type::<std::path::PathBuf>()
+        app.register_type::<PathBuf>()
             .register_type::<std::time::Instant>()
             .register_type::<AppSettings>();
         app.insert_resource(app_state);
         app.add_event::<FileDialogEvent>();
+        app.add_event::<OpenFileEvent>();
+        app.add_systems(bevy_app::Update, handle_open_file_events);
 
         // Setup virtual time to only advance while simulating.
         let mut virt_time = app.world_mut().get_resource_mut::<Time<Virtual>>().unwrap();
@@ -155,6 +223,12 @@ impl App {
             app.insert_resource(routing_config);
         }
 
+        // Unsaved changes are autosaved next to the settings
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(storage_dir) = eframe::storage_dir(APP_ID) {
+            app.insert_resource(digilogic_serde::Recovery::new(storage_dir.join("recovery")));
+        }
+
         // Digilogic plugins
         app.add_plugins((
             digilogic_core::CorePlugin,
@@ -165,20 +239,50 @@ impl App {
             ui::UiPlugin::new(context, render_state),
         ));
 
+        #[cfg(not(target_arch = "wasm32"))]
+        load_symbol_library(app.world_mut());
+
+        for filename in files {
+            app.world_mut().send_event(OpenFileEvent(filename));
+        }
+
         Self(app)
     }
 }
 
+/// Adds the symbols of the user's symbol library, which lives next to the
+/// settings, to the symbol registry.
+#[cfg(not(target_arch = "wasm32"))]
+fn load_symbol_library(world: &mut World) {
+    let Some(dir) = eframe::storage_dir(APP_ID).map(|dir| dir.join("symbols")) else {
+        return;
+    };
+
+    let mut symbols = world.resource_mut::<digilogic_core::symbol::SymbolRegistry>();
+    let result = digilogic_serde::load_symbol_library(&mut symbols, &dir);
+    match result {
+        Ok(0) => {}
+        Ok(count) => bevy_log::info!("loaded {count} symbols from {}", dir.display()),
+        // TODO: instead of this, send an ErrorEvent
+        Err(e) => bevy_log::error!("error loading symbol library: {:?}", e),
+    }
+}
+
 fn handle_exit_events(world: &mut World, context: &egui::Context) {
     type AppExitEvents = Events<bevy_app::AppExit>;
 
-    let mut exit_events = world.get_resource_mut::<AppExitEvents>().unwrap();
+    let exit_events = world.get_resource::<AppExitEvents>().unwrap();
     let close_requested = context.input(|i| i.viewport().close_requested());
 
     if !exit_events.is_empty() && !close_requested {
         context.send_viewport_cmd(egui::ViewportCommand::Close);
     } else if close_requested {
-        exit_events.send(bevy_app::AppExit::Success);
+        if ui::confirm_close(world) {
+            let mut exit_events = world.get_resource_mut::<AppExitEvents>().unwrap();
+            exit_events.send(bevy_app::AppExit::Success);
+        } else {
+            context.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+        }
     }
 }
 
@@ -186,6 +290,7 @@ trait FileDialogExt {
     fn add_project_filters(self) -> Self;
     fn add_circuit_filters(self) -> Self;
     fn add_import_filters(self) -> Self;
+    fn add_export_filters(self) -> Self;
 }
 
 impl FileDialogExt for rfd::FileDialog {
@@ -194,19 +299,42 @@ impl FileDialogExt for rfd::FileDialog {
     }
 
     fn add_circuit_filters(self) -> Self {
-        self.add_filter("Digilogic Circuit", &["dlc"])
+        self.add_filter("Digilogic Circuit", &["dlc", "dlcb"])
     }
 
     fn add_import_filters(self) -> Self {
         self.add_filter("Digital Circuit", &["dig"])
+            .add_filter("Logisim Circuit", &["circ"])
             .add_filter("Yosys JSON", &["yosys", "json"])
     }
+
+    fn add_export_filters(self) -> Self {
+        self.add_filter("Verilog", &["v"])
+            .add_filter("Yosys JSON", &["yosys", "json"])
+            .add_filter("Digital Circuit", &["dig"])
+    }
+}
+
+/// The circuit shown in the focused tab, or the root circuit if there is none.
+fn focused_circuit(world: &mut World) -> Option<digilogic_core::components::CircuitID> {
+    let viewport = world
+        .get_non_send_resource_mut::<egui_dock::DockState<Entity>>()
+        .and_then(|mut dock_state| dock_state.find_active_focused().map(|(_, &mut tab)| tab));
+
+    viewport
+        .and_then(|viewport| world.get::<digilogic_core::components::CircuitID>(viewport))
+        .copied()
+        .or_else(|| {
+            world
+                .get_resource::<Project>()
+                .and_then(|project| project.root_circuit)
+        })
 }
 
 fn handle_file_dialog(world: &mut World, frame: &mut eframe::Frame) {
     type FileDialogEvents = Events<FileDialogEvent>;
-    type ProjectLoadEvents = Events<digilogic_core::events::ProjectLoadEvent>;
-    type CircuitLoadEvents = Events<digilogic_core::events::CircuitLoadEvent>;
+    type OpenFileEvents = Events<OpenFileEvent>;
+    type CircuitSaveEvents = Events<digilogic_core::events::CircuitSaveEvent>;
 
     let mut file_dialog_events = world.get_resource_mut::<FileDialogEvents>().unwrap();
     let file_dialog_events: Vec<_> = file_dialog_events.drain().collect();
@@ -219,9 +347,8 @@ fn handle_file_dialog(world: &mut World, frame: &mut eframe::Frame) {
             match file_dialog_event {
                 FileDialogEvent::OpenProject => {
                     if let Some(filename) = dialog.add_project_filters().pick_file() {
-                        let mut load_events =
-                            world.get_resource_mut::<ProjectLoadEvents>().unwrap();
-                        load_events.send(digilogic_core::events::ProjectLoadEvent { filename });
+                        let mut open_events = world.get_resource_mut::<OpenFileEvents>().unwrap();
+                        open_events.send(OpenFileEvent(filename));
                     }
                 }
                 FileDialogEvent::SaveProject => {
@@ -231,21 +358,38 @@ fn handle_file_dialog(world: &mut World, frame: &mut eframe::Frame) {
                 }
                 FileDialogEvent::AddCircuit => {
                     if let Some(filename) = dialog.add_circuit_filters().pick_file() {
-                        let mut load_events =
-                            world.get_resource_mut::<CircuitLoadEvents>().unwrap();
-                        load_events.send(digilogic_core::events::CircuitLoadEvent { filename });
+                        let mut open_events = world.get_resource_mut::<OpenFileEvents>().unwrap();
+                        open_events.send(OpenFileEvent(filename));
                     }
                 }
                 FileDialogEvent::ImportCircuit => {
                     if let Some(filename) = dialog.add_import_filters().pick_file() {
-                        let mut load_events =
-                            world.get_resource_mut::<CircuitLoadEvents>().unwrap();
-                        load_events.send(digilogic_core::events::CircuitLoadEvent { filename });
+                        let mut open_events = world.get_resource_mut::<OpenFileEvents>().unwrap();
+                        open_events.send(OpenFileEvent(filename));
                     }
                 }
                 FileDialogEvent::SaveCircuit => {
-                    if let Some(filename) = dialog.add_project_filters().save_file() {
-                        // TODO: save circuit file
+                    let Some(circuit) = focused_circuit(world) else {
+                        continue;
+                    };
+
+                    if let Some(filename) = dialog.add_circuit_filters().save_file() {
+                        let mut save_events =
+                            world.get_resource_mut::<CircuitSaveEvents>().unwrap();
+                        save_events
+                            .send(digilogic_core::events::CircuitSaveEvent { circuit, filename });
+                    }
+                }
+                FileDialogEvent::ExportCircuit => {
+                    let Some(circuit) = focused_circuit(world) else {
+                        continue;
+                    };
+
+                    if let Some(filename) = dialog.add_export_filters().save_file() {
+                        let mut save_events =
+                            world.get_resource_mut::<CircuitSaveEvents>().unwrap();
+                        save_events
+                            .send(digilogic_core::events::CircuitSaveEvent { circuit, filename });
                     }
                 }
             }
@@ -296,6 +440,7 @@ impl eframe::App for App {
 mod native_main {
     use clap::{Parser, Subcommand, ValueEnum};
     use serde::{Deserialize, Serialize};
+    use std::path::PathBuf;
 
     #[derive(
         Default,
@@ -332,16 +477,41 @@ mod native_main {
             #[arg(short, long)]
             port: Option<u16>,
         },
+        /// Renders a circuit file to an SVG image without opening a window
+        Render {
+            /// The circuit file to render
+            file: PathBuf,
+            /// The SVG file to write
+            #[arg(short, long)]
+            output: PathBuf,
+        },
+        /// Converts a circuit between the .dlc and binary .dlcb formats
+        Convert {
+            /// The circuit file to convert
+            file: PathBuf,
+            /// The circuit file to write, its extension picks the format
+            #[arg(short, long)]
+            output: PathBuf,
+        },
     }
 
     #[derive(Parser)]
-    #[command(name = "Digilogic", version, about, long_about = None, propagate_version = true)]
+    #[command(
+        name = "Digilogic",
+        version,
+        about,
+        long_about = None,
+        propagate_version = true,
+        args_conflicts_with_subcommands = true
+    )]
     struct Args {
         #[command(subcommand)]
         pub command: Option<Commands>,
+        /// Project (.dlp) or circuit files to open
+        pub files: Vec<PathBuf>,
     }
 
-    fn run_gui() {
+    fn run_gui(files: Vec<PathBuf>) {
         let native_options = eframe::NativeOptions {
             viewport: egui::ViewportBuilder::default()
                 .with_inner_size([1024.0, 768.0])
@@ -354,9 +524,9 @@ mod native_main {
         };
 
         eframe::run_native(
-            "digilogic",
+            crate::APP_ID,
             native_options,
-            Box::new(|cc| Ok(Box::new(crate::App::new(cc)))),
+            Box::new(|cc| Ok(Box::new(crate::App::new(cc, files)))),
         )
         .unwrap();
     }
@@ -364,7 +534,7 @@ mod native_main {
     pub fn run() {
         let args = Args::parse();
         match args.command {
-            None => run_gui(),
+            None => run_gui(args.files),
             Some(Commands::Server { engine, port }) => match engine.unwrap_or_default() {
                 SimulationEngine::Gsim => {
                     digilogic_netcode::run_server(port, digilogic_gsim::GsimServer::default())
@@ -372,6 +542,20 @@ mod native_main {
                 }
                 SimulationEngine::GsimCompute => todo!(),
             },
+            Some(Commands::Render { file, output }) => {
+                if let Err(err) = crate::render::render_to_svg(&file, &output) {
+                    eprintln!("error: {err:?}");
+                    std::process::exit(1);
+                }
+            }
+            Some(Commands::Convert { file, output }) => {
+                let result = digilogic_serde::dlc::CircuitFile::load(&file)
+                    .and_then(|circuit| circuit.save(&output));
+                if let Err(err) = result {
+                    eprintln!("error: {err:?}");
+                    std::process::exit(1);
+                }
+            }
         }
     }
 }
@@ -390,7 +574,7 @@ fn main() {
             .start(
                 "egui-host",
                 web_options,
-                Box::new(|cc| Ok(Box::new(App::new(cc)))),
+                Box::new(|cc| Ok(Box::new(App::new(cc, Vec::new())))),
             )
             .await;
 
@@ -0,0 +1,446 @@
+use crate::ui::{
+    build_symbol_shapes, bus_entry, chip_shape, gate_shape, label_text_position, pin_label_anchor,
+    port_color, transform_to_affine, wire_color, PathKind, SymbolShape, TextAnchor,
+    BACKGROUND_COLOR, PIN_LABEL_SIZE, SYMBOL_FILL_COLOR, SYMBOL_STROKE_COLOR, TEXT_COLOR,
+};
+use aery::prelude::*;
+use anyhow::{anyhow, bail, Result};
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::SystemState;
+use digilogic_core::components::*;
+use digilogic_core::events::{CircuitLoadEvent, CircuitLoadedEvent};
+use digilogic_core::transform::{AbsoluteDirections, BoundingBox, GlobalTransform};
+use digilogic_core::visibility::ComputedVisibility;
+use digilogic_routing::{RoutingComplete, VertexKind, Vertices};
+use std::fmt::Write as _;
+use std::path::Path;
+use vello::kurbo::{Affine, BezPath, Circle, Point, Rect, Shape as _};
+use vello::peniko::Color;
+
+/// Upper bound on the number of frames to wait for a circuit to load and route.
+const MAX_UPDATES: usize = 100;
+
+const MARGIN: f64 = 20.0;
+
+/// Roughly how wide a character of text is, relative to its size
+const CHAR_WIDTH: f64 = 0.6;
+
+/// The SVG notation of `color`
+fn hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+/// Loads the circuit in `input` without opening a window, and writes it to
+/// `output` as an SVG image, including the routed wires.
+pub fn render_to_svg(input: &Path, output: &Path) -> Result<()> {
+    let mut app = bevy_app::App::default();
+
+    app.add_plugins((
+        bevy_core::TaskPoolPlugin::default(),
+        bevy_core::TypeRegistrationPlugin,
+        bevy_state::app::StatesPlugin,
+        bevy_log::LogPlugin {
+            level: crate::LOG_LEVEL,
+            ..Default::default()
+        },
+    ));
+
+    app.add_plugins((
+        digilogic_core::CorePlugin,
+        digilogic_serde::LoadSavePlugin,
+        digilogic_routing::RoutingPlugin,
+    ));
+
+    app.finish();
+    app.cleanup();
+
+    crate::load_symbol_library(app.world_mut());
+
+    app.world_mut().send_event(CircuitLoadEvent {
+        filename: input.to_owned(),
+    });
+
+    let mut loaded_cursor = app
+        .world()
+        .resource::<Events<CircuitLoadedEvent>>()
+        .get_cursor();
+    let mut routed_cursor = app
+        .world()
+        .resource::<Events<RoutingComplete>>()
+        .get_cursor();
+
+    let mut circuit = None;
+    let mut routed = false;
+    for _ in 0..MAX_UPDATES {
+        app.update();
+
+        let world = app.world();
+        if circuit.is_none() {
+            circuit = loaded_cursor
+                .read(world.resource::<Events<CircuitLoadedEvent>>())
+                .map(|ev| ev.circuit)
+                .next();
+        }
+
+        if let Some(circuit) = circuit {
+            routed |= routed_cursor
+                .read(world.resource::<Events<RoutingComplete>>())
+                .any(|ev| ev.circuit == circuit);
+
+            if routed {
+                break;
+            }
+        }
+    }
+
+    let Some(circuit) = circuit else {
+        bail!("failed to load circuit {}", input.display());
+    };
+    if !routed {
+        bail!("timed out waiting for circuit {} to route", input.display());
+    }
+
+    let svg = build_svg(app.world_mut(), circuit);
+    std::fs::write(output, svg)
+        .map_err(|err| anyhow!("failed to write {}: {err}", output.display()))
+}
+
+type RenderQueries<'w, 's> = (
+    Query<'w, 's, (Entity, Relations<Child>)>,
+    Query<
+        'w,
+        's,
+        (
+            &'static Shape,
+            &'static BoundingBox,
+            &'static GlobalTransform,
+            &'static ComputedVisibility,
+            &'static Name,
+        ),
+        With<Symbol>,
+    >,
+    Query<
+        'w,
+        's,
+        (
+            &'static GlobalTransform,
+            &'static ComputedVisibility,
+            Has<Input>,
+            Has<Output>,
+            &'static Name,
+            &'static AbsoluteDirections,
+        ),
+        With<Port>,
+    >,
+    Query<
+        'w,
+        's,
+        (
+            (
+                Option<&'static Vertices>,
+                Option<&'static ComputedVisibility>,
+            ),
+            Relations<Child>,
+        ),
+    >,
+    Query<'w, 's, &'static GlobalTransform, (With<Endpoint>, With<Bits>)>,
+);
+
+#[derive(Default)]
+struct SvgBuilder {
+    bounds: Option<Rect>,
+    body: String,
+}
+
+impl SvgBuilder {
+    fn include(&mut self, rect: Rect) {
+        self.bounds = Some(self.bounds.map_or(rect, |bounds| bounds.union(rect)));
+    }
+
+    fn path(&mut self, path: &BezPath, fill: &str, stroke: &str, stroke_width: f64) {
+        self.include(path.bounding_box());
+        writeln!(
+            self.body,
+            r#"<path d="{}" fill="{fill}" stroke="{stroke}" stroke-width="{stroke_width}" stroke-linejoin="miter" stroke-linecap="butt" stroke-miterlimit="2.2"/>"#,
+            path.to_svg(),
+        )
+        .unwrap();
+    }
+
+    fn circle(&mut self, circle: Circle, fill: &str) {
+        self.include(circle.bounding_box());
+        writeln!(
+            self.body,
+            r#"<circle cx="{}" cy="{}" r="{}" fill="{fill}"/>"#,
+            circle.center.x, circle.center.y, circle.radius,
+        )
+        .unwrap();
+    }
+
+    fn text(&mut self, text: &str, position: Point, anchor: TextAnchor) {
+        let size = PIN_LABEL_SIZE as f64;
+        let width = text.chars().count() as f64 * size * CHAR_WIDTH;
+        let x0 = match anchor {
+            TextAnchor::Start => position.x,
+            TextAnchor::Middle => position.x - width / 2.0,
+            TextAnchor::End => position.x - width,
+        };
+        self.include(Rect::new(
+            x0,
+            position.y - size,
+            x0 + width,
+            position.y + size * 0.25,
+        ));
+
+        let anchor = match anchor {
+            TextAnchor::Start => "start",
+            TextAnchor::Middle => "middle",
+            TextAnchor::End => "end",
+        };
+        let text = text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+
+        writeln!(
+            self.body,
+            r#"<text x="{}" y="{}" font-family="sans-serif" font-size="{PIN_LABEL_SIZE}" text-anchor="{anchor}" fill="{}">{text}</text>"#,
+            position.x,
+            position.y,
+            hex(TEXT_COLOR),
+        )
+        .unwrap();
+    }
+
+    fn symbol(&mut self, shape: &SymbolShape, transform: Affine) {
+        for path in shape.paths.iter() {
+            let fill = if path.kind.contains(PathKind::FILL) {
+                hex(SYMBOL_FILL_COLOR)
+            } else {
+                "none".to_owned()
+            };
+            let stroke = if path.kind.contains(PathKind::STROKE) {
+                hex(SYMBOL_STROKE_COLOR)
+            } else {
+                "none".to_owned()
+            };
+
+            self.path(&(transform * path.path.clone()), &fill, &stroke, 3.0);
+        }
+    }
+
+    fn finish(self) -> String {
+        let bounds = self.bounds.unwrap_or_default().inflate(MARGIN, MARGIN);
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+            bounds.x0,
+            bounds.y0,
+            bounds.width(),
+            bounds.height(),
+            bounds.width(),
+            bounds.height(),
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+            bounds.x0,
+            bounds.y0,
+            bounds.width(),
+            bounds.height(),
+            hex(BACKGROUND_COLOR),
+        )
+        .unwrap();
+        svg.push_str(&self.body);
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+fn build_svg(world: &mut World, circuit: CircuitID) -> String {
+    let symbol_shapes = build_symbol_shapes();
+    let mut state = SystemState::<RenderQueries<'static, 'static>>::new(world);
+    let (children, symbols, ports, vertices, bus_entries) = state.get(world);
+
+    let mut svg = SvgBuilder::default();
+    let wire_color = hex(wire_color(false, false));
+
+    // Wires are drawn first so they end up beneath the symbols, like in the editor.
+    vertices
+        .traverse::<Child>(std::iter::once(circuit.0))
+        .for_each(|&mut (vertices, visibility), relations| {
+            let Some(vertices) = vertices else {
+                return;
+            };
+
+            if !*visibility.copied().unwrap_or_default() {
+                return;
+            }
+
+            let mut path = BezPath::new();
+            for vertex in vertices.iter() {
+                let pos = (vertex.position.x.to_f64(), vertex.position.y.to_f64());
+
+                match vertex.kind {
+                    VertexKind::Normal | VertexKind::Dummy => path.line_to(pos),
+                    VertexKind::WireStart { .. } => {
+                        path = BezPath::new();
+                        path.move_to(pos);
+                    }
+                    VertexKind::WireEnd { junction_kind } => {
+                        path.line_to(pos);
+                        svg.path(&path, "none", &wire_color, 2.5);
+
+                        if junction_kind.is_some() {
+                            svg.circle(Circle::new(pos, 4.0), &wire_color);
+                        }
+                    }
+                }
+            }
+
+            relations
+                .join::<Child>(&bus_entries)
+                .for_each(|global_transform| {
+                    let entry = bus_entry(global_transform).to_path(0.1);
+                    svg.path(&entry, "none", &wire_color, 2.5);
+                });
+        });
+
+    children
+        .traverse::<Child>(std::iter::once(circuit.0))
+        .for_each(|&mut entity, _| {
+            let Ok((shape, bounds, transform, &visibility, name)) = symbols.get(entity) else {
+                return;
+            };
+
+            if !*visibility {
+                return;
+            }
+
+            let transform = transform_to_affine(transform);
+            let symbol_shape = &symbol_shapes[*shape as usize];
+            match shape {
+                Shape::Chip => svg.symbol(&chip_shape(bounds), transform),
+                Shape::And | Shape::Or | Shape::Xor | Shape::Nand | Shape::Nor | Shape::Xnor => {
+                    match gate_shape(symbol_shape, bounds) {
+                        Some(stretched) => svg.symbol(&stretched, transform),
+                        None => svg.symbol(symbol_shape, transform),
+                    }
+                }
+                _ => svg.symbol(symbol_shape, transform),
+            }
+
+            if matches!(shape, Shape::Label | Shape::GlobalLabel) {
+                svg.text(
+                    name.0.as_str(),
+                    label_text_position(transform),
+                    TextAnchor::Middle,
+                );
+            }
+
+            if let (Shape::Chip, Ok((_, edges))) = (shape, children.get(entity)) {
+                edges.join::<Child>(&ports).for_each(
+                    |(transform, &visibility, _, _, name, &directions)| {
+                        if *visibility {
+                            let (position, anchor) = pin_label_anchor(transform, directions);
+                            svg.text(name.0.as_str(), position, anchor);
+                        }
+                    },
+                );
+            }
+        });
+
+    children
+        .traverse::<Child>(std::iter::once(circuit.0))
+        .for_each(|&mut entity, _| {
+            let Ok((transform, &visibility, is_input, is_output, _, _)) = ports.get(entity) else {
+                return;
+            };
+
+            if !*visibility {
+                return;
+            }
+
+            let center = transform_to_affine(transform) * Point::ORIGIN;
+            svg.circle(
+                Circle::new(center, 4.0),
+                &hex(port_color(is_input, is_output)),
+            );
+        });
+
+    svg.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every tag that's opened is closed again, in order
+    fn assert_well_formed(svg: &str) {
+        let mut open = Vec::new();
+        for tag in svg.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').expect("unterminated tag")];
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop(), Some(name), "mismatched </{name}>");
+            } else if !tag.ends_with('/') {
+                open.push(tag.split_whitespace().next().unwrap());
+            }
+        }
+        assert!(open.is_empty(), "unclosed tags {open:?}");
+    }
+
+    fn attribute<'a>(element: &'a str, name: &str) -> &'a str {
+        let start = element.find(&format!(r#" {name}=""#)).unwrap() + name.len() + 3;
+        let len = element[start..].find('"').unwrap();
+        &element[start..start + len]
+    }
+
+    #[test]
+    fn renders_gates_wires_and_labels() {
+        let output =
+            std::env::temp_dir().join(format!("digilogic-render-{}.svg", std::process::id()));
+        render_to_svg(Path::new("testdata/render.dlc"), &output).unwrap();
+        let svg = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+
+        assert!(svg.starts_with("<svg "));
+        assert_well_formed(&svg);
+
+        let lines: Vec<_> = svg.lines().collect();
+        let wire_color = hex(wire_color(false, false));
+        let gate_color = hex(SYMBOL_STROKE_COLOR);
+        assert!(lines.iter().any(|line| line.starts_with("<path")
+            && attribute(line, "stroke") == wire_color
+            && attribute(line, "stroke-width") == "2.5"));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("<path") && attribute(line, "stroke") == gate_color));
+        let label = lines
+            .iter()
+            .find(|line| line.starts_with("<text") && line.ends_with(">out</text>"))
+            .unwrap();
+
+        // The label's text is inside the image
+        let view_box: Vec<f64> = attribute(lines[0], "viewBox")
+            .split(' ')
+            .map(|value| value.parse().unwrap())
+            .collect();
+        let [x, y, width, height] = view_box[..] else {
+            panic!("invalid viewBox");
+        };
+        let text_x: f64 = attribute(label, "x").parse().unwrap();
+        let text_y: f64 = attribute(label, "y").parse().unwrap();
+        let size = PIN_LABEL_SIZE as f64;
+        let text_width = 3.0 * size * CHAR_WIDTH;
+        let text_x0 = match attribute(label, "text-anchor") {
+            "start" => text_x,
+            "middle" => text_x - text_width / 2.0,
+            _ => text_x - text_width,
+        };
+        assert!(x <= text_x0 && text_x0 + text_width <= x + width);
+        assert!(y <= text_y - size && text_y <= y + height);
+    }
+}
@@ -4,6 +4,11 @@ use canvas::*;
 mod draw;
 use digilogic_ux::DragType;
 use draw::*;
+pub(crate) use draw::{
+    build_symbol_shapes, bus_entry, chip_shape, gate_shape, label_text_position, pin_label_anchor,
+    port_color, transform_to_affine, wire_color, PathKind, SymbolShape, TextAnchor,
+    BACKGROUND_COLOR, PIN_LABEL_SIZE, SYMBOL_FILL_COLOR, SYMBOL_STROKE_COLOR, TEXT_COLOR,
+};
 
 mod settings;
 use settings::*;
@@ -14,16 +19,28 @@ use explorer::*;
 mod palette;
 use palette::*;
 
-use crate::{AppSettings, Backend, FileDialogEvent, DEFAULT_LOCAL_SERVER_ADDR};
+mod annotate;
+use annotate::*;
+
+mod erc;
+use erc::*;
+
+mod unsaved;
+pub(crate) use unsaved::confirm_close;
+use unsaved::*;
+
+use crate::{AppSettings, Backend, FileDialogEvent, OpenFileEvent, DEFAULT_LOCAL_SERVER_ADDR};
 use bevy_ecs::prelude::*;
 use bevy_ecs::system::lifetimeless::{Read, Write};
 use bevy_ecs::system::SystemParam;
 use bevy_reflect::Reflect;
 use bevy_state::prelude::*;
-use digilogic_core::components::{Circuit, CircuitID, Name, Viewport};
+use digilogic_core::components::{Circuit, CircuitID, Modified, Name, Viewport};
+use digilogic_core::events::CheckCircuitEvent;
+use digilogic_core::history::{History, HistoryEvent};
 use digilogic_core::resources::Project;
 use digilogic_core::states::{SimulationConnected, SimulationState};
-use digilogic_core::{fixed, Fixed, SharedStr};
+use digilogic_core::{fixed, Fixed};
 use egui::*;
 use egui_dock::*;
 use egui_wgpu::RenderState;
@@ -146,11 +163,17 @@ fn combine_scenes(
 #[reflect(Resource)]
 struct OpenWindows {
     settings: bool,
+    discard_prompt: Option<DiscardAction>,
+    recovery_prompt: bool,
+    annotate: Option<AnnotateDialog>,
 }
 
 impl OpenWindows {
     fn any(&self) -> bool {
         self.settings
+            || self.discard_prompt.is_some()
+            || self.recovery_prompt
+            || self.annotate.is_some()
     }
 }
 
@@ -162,28 +185,48 @@ fn update_menu(
     mut settings: ResMut<AppSettings>,
     mut routing_config: ResMut<digilogic_routing::RoutingConfig>,
     mut file_dialog_events: EventWriter<FileDialogEvent>,
+    mut open_file_events: EventWriter<OpenFileEvent>,
     mut open_windows: ResMut<OpenWindows>,
     project: Option<Res<Project>>,
-    circuits: Query<Entity, With<Circuit>>,
+    circuits: Query<(Entity, Has<Modified>, &History), With<Circuit>>,
+    mut dock_state: NonSendMut<DockState<Entity>>,
+    viewports: Query<&CircuitID, With<Viewport>>,
+    mut history_events: EventWriter<HistoryEvent>,
+    mut erc_panel: ResMut<ErcPanel>,
+    mut check_events: EventWriter<CheckCircuitEvent>,
 ) {
+    let focused_circuit = dock_state
+        .find_active_focused()
+        .and_then(|(_, &mut viewport)| viewports.get(viewport).ok().copied());
+    let history = focused_circuit.and_then(|circuit| circuits.get(circuit.0).ok());
+    let can_undo = history.is_some_and(|(_, _, history)| history.can_undo());
+    let can_redo = history.is_some_and(|(_, _, history)| history.can_redo());
+
+    let undo_shortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+    let redo_shortcut = KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z);
+    let mut undo = false;
+    let mut redo = false;
+    if !open_windows.any() && !egui.context.wants_keyboard_input() {
+        // Ctrl+Z also matches Ctrl+Shift+Z, so redo has to be checked first
+        egui.context.input_mut(|input| {
+            redo = input.consume_shortcut(&redo_shortcut);
+            undo = !redo && input.consume_shortcut(&undo_shortcut);
+        });
+    }
+
     TopBottomPanel::top("menu_panel").show(&egui.context, |ui| {
         ui.add_enabled_ui(!open_windows.any(), |ui| {
             menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
                     if ui.button("New Project").clicked() {
-                        if project.is_some() {
-                            // TODO: check for unsaved changes
-
-                            for circuit in circuits.iter() {
-                                commands.entity(circuit).despawn();
-                            }
+                        if circuits.iter().any(|(_, modified, _)| modified) {
+                            open_windows.discard_prompt = Some(DiscardAction::NewProject);
+                        } else {
+                            new_project(
+                                &mut commands,
+                                circuits.iter().map(|(circuit, _, _)| circuit),
+                            );
                         }
-
-                        commands.insert_resource(Project {
-                            name: SharedStr::new_static("Unnamed Project"),
-                            file_path: None,
-                            root_circuit: None,
-                        });
                         ui.close_menu();
                     }
 
@@ -192,6 +235,34 @@ fn update_menu(
                         ui.close_menu();
                     }
 
+                    ui.add_enabled_ui(!settings.recent_files.is_empty(), |ui| {
+                        ui.menu_button("Open Recent", |ui| {
+                            let mut open = None;
+                            for filename in settings.recent_files.iter() {
+                                let name = filename.file_name().unwrap_or(filename.as_os_str());
+                                if ui
+                                    .button(name.to_string_lossy())
+                                    .on_hover_text(filename.display().to_string())
+                                    .clicked()
+                                {
+                                    open = Some(filename.clone());
+                                }
+                            }
+
+                            ui.separator();
+
+                            if ui.button("Clear Recent").clicked() {
+                                settings.recent_files.clear();
+                                ui.close_menu();
+                            }
+
+                            if let Some(filename) = open {
+                                open_file_events.send(OpenFileEvent(filename));
+                                ui.close_menu();
+                            }
+                        });
+                    });
+
                     if ui.button("Save Project").clicked() {
                         file_dialog_events.send(FileDialogEvent::SaveProject);
                         ui.close_menu();
@@ -219,6 +290,11 @@ fn update_menu(
                             file_dialog_events.send(FileDialogEvent::SaveCircuit);
                             ui.close_menu();
                         }
+
+                        if ui.button("Export Circuit").clicked() {
+                            file_dialog_events.send(FileDialogEvent::ExportCircuit);
+                            ui.close_menu();
+                        }
                     });
 
                     ui.separator();
@@ -230,6 +306,23 @@ fn update_menu(
                 });
                 ui.add_space(8.0);
 
+                ui.menu_button("Edit", |ui| {
+                    let button =
+                        Button::new("Undo").shortcut_text(ui.ctx().format_shortcut(&undo_shortcut));
+                    if ui.add_enabled(can_undo, button).clicked() {
+                        undo = true;
+                        ui.close_menu();
+                    }
+
+                    let button =
+                        Button::new("Redo").shortcut_text(ui.ctx().format_shortcut(&redo_shortcut));
+                    if ui.add_enabled(can_redo, button).clicked() {
+                        redo = true;
+                        ui.close_menu();
+                    }
+                });
+                ui.add_space(8.0);
+
                 ui.menu_button("View", |ui| {
                     ui.menu_button("Debug", |ui| {
                         ui.checkbox(&mut settings.show_bounding_boxes, "Bounding boxes");
@@ -246,6 +339,23 @@ fn update_menu(
                 });
                 ui.add_space(8.0);
 
+                ui.menu_button("Tools", |ui| {
+                    let button = Button::new("Electrical Rule Check");
+                    if ui.add_enabled(focused_circuit.is_some(), button).clicked() {
+                        if let Some(circuit) = focused_circuit {
+                            check_events.send(CheckCircuitEvent { circuit });
+                            erc_panel.circuit = Some(circuit);
+                        }
+                        ui.close_menu();
+                    }
+
+                    if ui.button("Annotate...").clicked() {
+                        open_windows.annotate = Some(AnnotateDialog::new(focused_circuit));
+                        ui.close_menu();
+                    }
+                });
+                ui.add_space(8.0);
+
                 ui.menu_button("Routing", |ui| {
                     let mut prune_graph = routing_config.prune_graph;
                     ui.checkbox(&mut prune_graph, "Prune graph");
@@ -264,13 +374,42 @@ fn update_menu(
             });
         });
     });
+
+    if let Some(circuit) = focused_circuit {
+        if undo && can_undo {
+            history_events.send(HistoryEvent::Undo(circuit));
+        } else if redo && can_redo {
+            history_events.send(HistoryEvent::Redo(circuit));
+        }
+    }
 }
 
+/// Why the simulation last failed to build, shown until it's started again
+#[derive(Debug, Default, Resource)]
+struct BuildFailure(Option<String>);
+
+/// Remembers why the simulation failed to build, and lists the errors of the
+/// circuit that didn't pass the electrical rule check.
+fn record_build_failures(
+    mut events: EventReader<digilogic_netcode::BuildFailed>,
+    mut failure: ResMut<BuildFailure>,
+    mut erc_panel: ResMut<ErcPanel>,
+) {
+    for digilogic_netcode::BuildFailed(err) in events.read() {
+        failure.0 = Some(format!("Can't simulate: {err}"));
+        if let digilogic_netcode::BuildError::Erc { circuit, .. } = *err {
+            erc_panel.circuit = Some(CircuitID(circuit));
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn update_tool_bar(
     mut commands: Commands,
     egui: Res<Egui>,
     settings: Res<AppSettings>,
     open_windows: Res<OpenWindows>,
+    mut build_failure: ResMut<BuildFailure>,
     mut project: Option<ResMut<Project>>,
     simulation_state: Res<State<SimulationState>>,
     circuits: Query<(Entity, &Name), With<Circuit>>,
@@ -305,6 +444,7 @@ fn update_tool_bar(
                 match simulation_state.is_connected() {
                     false => {
                         if ui.button("Start").clicked() {
+                            build_failure.0 = None;
                             match settings.backend {
                                 #[cfg(not(target_arch = "wasm32"))]
                                 Backend::Builtin => {
@@ -355,6 +495,10 @@ fn update_tool_bar(
                     }
                 }
             });
+
+            if let Some(failure) = &build_failure.0 {
+                ui.colored_label(ui.visuals().error_fg_color, failure);
+            }
         });
     });
 }
@@ -393,7 +537,7 @@ fn update_viewport(
             renderer,
             &egui.render_state,
             &scene.combined,
-            vello::peniko::Color::rgb8(6, 6, 6),
+            BACKGROUND_COLOR,
         );
 
         let response = Image::new((canvas.texture_id(), canvas_size))
@@ -526,7 +670,7 @@ struct TabViewer<'w, 's> {
     egui: Res<'w, Egui>,
     renderer: NonSendMut<'w, CanvasRenderer>,
     viewports: ViewportQuery<'w, 's>,
-    circuits: Query<'w, 's, Read<Name>, With<Circuit>>,
+    circuits: Query<'w, 's, (Read<Name>, Has<Modified>), With<Circuit>>,
     open_windows: Res<'w, OpenWindows>,
 }
 
@@ -535,8 +679,12 @@ impl egui_dock::TabViewer for TabViewer<'_, '_> {
 
     fn title(&mut self, tab: &mut Self::Tab) -> WidgetText {
         let (&circuit, _, _, _) = self.viewports.get(*tab).expect("invalid viewport ID");
-        let name = self.circuits.get(circuit.0).expect("invalid circuit ID");
-        name.0.as_str().into()
+        let (name, modified) = self.circuits.get(circuit.0).expect("invalid circuit ID");
+        if modified {
+            format!("{} *", name.0).into()
+        } else {
+            name.0.as_str().into()
+        }
     }
 
     fn ui(&mut self, ui: &mut Ui, tab: &mut Self::Tab) {
@@ -638,6 +786,7 @@ impl bevy_app::Plugin for UiPlugin {
             0,
         )));
         app.init_resource::<OpenWindows>();
+        app.init_resource::<BuildFailure>();
         app.register_type::<Viewport>();
 
         app.add_systems(bevy_app::Startup, init_symbol_shapes);
@@ -666,13 +815,15 @@ impl bevy_app::Plugin for UiPlugin {
                 .chain()
                 .in_set(MenuSet),
         );
+        app.add_systems(bevy_app::Update, record_build_failures.before(MenuSet));
 
         app.add_systems(
             bevy_app::Update,
             update_tabs
                 .after(combine_scenes)
                 .after(MenuSet)
-                .after(ExplorerSet),
+                .after(ExplorerSet)
+                .after(ErcPanelSet),
         );
 
         app.add_systems(
@@ -682,7 +833,10 @@ impl bevy_app::Plugin for UiPlugin {
 
         app.add_plugins(SettingsPlugin)
             .add_plugins(ExplorerPlugin)
-            .add_plugins(PalettePlugin);
+            .add_plugins(PalettePlugin)
+            .add_plugins(ErcPanelPlugin)
+            .add_plugins(AnnotatePlugin)
+            .add_plugins(UnsavedPlugin);
 
         #[cfg(feature = "inspector")]
         {
@@ -0,0 +1,85 @@
+use super::{Egui, OpenWindows};
+use bevy_ecs::prelude::*;
+use bevy_reflect::Reflect;
+use digilogic_core::annotate::AnnotateOptions;
+use digilogic_core::components::CircuitID;
+use digilogic_core::events::AnnotateEvent;
+use egui::*;
+
+/// The choices of the annotate window
+#[derive(Debug, Clone, Reflect)]
+pub(super) struct AnnotateDialog {
+    /// The circuit to annotate when not annotating the whole project
+    circuit: Option<CircuitID>,
+    project_wide: bool,
+    options: AnnotateOptions,
+}
+
+impl AnnotateDialog {
+    pub(super) fn new(circuit: Option<CircuitID>) -> Self {
+        Self {
+            circuit,
+            project_wide: circuit.is_none(),
+            options: AnnotateOptions::default(),
+        }
+    }
+}
+
+fn update_annotate_window(
+    egui: Res<Egui>,
+    mut open_windows: ResMut<OpenWindows>,
+    mut annotate_events: EventWriter<AnnotateEvent>,
+) {
+    let Some(dialog) = open_windows.annotate.as_mut() else {
+        return;
+    };
+
+    let mut close = false;
+    Window::new("Annotate")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+        .show(&egui.context, |ui| {
+            ui.add_enabled_ui(dialog.circuit.is_some(), |ui| {
+                ui.radio_value(&mut dialog.project_wide, false, "Open circuit");
+            });
+            ui.radio_value(&mut dialog.project_wide, true, "Whole project");
+
+            ui.separator();
+
+            ui.checkbox(&mut dialog.options.keep_existing, "Keep existing numbers");
+            ui.checkbox(
+                &mut dialog.options.multi_part,
+                "Pack gates into ICs with several parts",
+            );
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button("Annotate").clicked() {
+                    annotate_events.send(AnnotateEvent {
+                        circuit: dialog.circuit.filter(|_| !dialog.project_wide),
+                        options: dialog.options,
+                    });
+                    close = true;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    close = true;
+                }
+            });
+        });
+
+    if close {
+        open_windows.annotate = None;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AnnotatePlugin;
+
+impl bevy_app::Plugin for AnnotatePlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.add_systems(bevy_app::Update, update_annotate_window);
+    }
+}
@@ -1,55 +1,258 @@
 use super::{Layer, PaletteBrushes, Scene, Viewport};
+use aery::operations::Join as _; // Shadowed by kurbo::Join
 use aery::prelude::*;
 use bevy_ecs::prelude::*;
 use bevy_ecs::system::lifetimeless::Read;
 use bitflags::bitflags;
 use digilogic_core::components::*;
+use digilogic_core::erc::ErcViolations;
 use digilogic_core::transform::*;
 use digilogic_core::visibility::ComputedVisibility;
 use digilogic_routing::{VertexKind, Vertices};
 use vello::kurbo::Shape as _;
-use vello::kurbo::{Affine, BezPath, Cap, Circle, Join, Line, Rect, Stroke, Vec2};
+use vello::kurbo::{Affine, BezPath, Cap, Circle, Join, Line, Point, Rect, Stroke, Vec2};
 use vello::peniko::{Color, Fill, Font};
+use vello::skrifa::instance::{LocationRef, Size};
+use vello::skrifa::{FontRef, GlyphId, MetadataProvider};
+use vello::Glyph;
 
 include!("bez_path.rs");
 
 bitflags! {
+    #[derive(Clone, Copy)]
     pub struct PathKind: u8 {
         const FILL = 0x1;
         const STROKE = 0x2;
+        /// Keeps its size when the shape is stretched, like inversion bubbles
+        const RIGID = 0x4;
     }
 }
 
-struct PathInfo {
-    kind: PathKind,
-    path: BezPath,
+pub struct PathInfo {
+    pub kind: PathKind,
+    pub path: BezPath,
 }
 
 #[derive(Default)]
 pub struct SymbolShape {
-    paths: Vec<PathInfo>,
+    pub paths: Vec<PathInfo>,
 }
 
 #[derive(Default, Resource)]
 pub struct SymbolShapes(pub Vec<SymbolShape>);
 
+/// Converts a schematic transform into the equivalent kurbo transform.
+pub fn transform_to_affine(transform: &Transform) -> Affine {
+    let flip_x = if transform.flip.contains(Flip::HORIZONTAL) {
+        -1.0
+    } else {
+        1.0
+    };
+    let flip_y = if transform.flip.contains(Flip::VERTICAL) {
+        -1.0
+    } else {
+        1.0
+    };
+
+    Affine::scale(transform.scale.to_f64())
+        .then_scale_non_uniform(flip_x, flip_y)
+        .then_rotate(transform.rotation.radians())
+        .then_translate(Vec2::new(
+            transform.translation.x.to_f64(),
+            transform.translation.y.to_f64(),
+        ))
+}
+
+/// Chips come in many sizes, so unlike other shapes they are drawn to fit the
+/// symbol's bounding box.
+pub fn chip_shape(bounds: &BoundingBox) -> SymbolShape {
+    SymbolShape {
+        paths: vec![PathInfo {
+            kind: PathKind::FILL | PathKind::STROKE,
+            path: Rect::new(
+                bounds.min().x.to_f64(),
+                bounds.min().y.to_f64(),
+                bounds.max().x.to_f64(),
+                bounds.max().y.to_f64(),
+            )
+            .to_path(0.1),
+        }],
+    }
+}
+
+/// Gates with more inputs are taller than the drawn shape, which is stretched
+/// to the height of the symbol's bounding box. Returns `None` if it fits.
+pub fn gate_shape(shape: &SymbolShape, bounds: &BoundingBox) -> Option<SymbolShape> {
+    const GATE_HEIGHT: f64 = 60.0;
+
+    let height = bounds.height().to_f64();
+    if height == GATE_HEIGHT {
+        return None;
+    }
+
+    let top = bounds.min().y.to_f64();
+    let stretch = Affine::translate((0.0, -top))
+        .then_scale_non_uniform(1.0, height / GATE_HEIGHT)
+        .then_translate(Vec2::new(0.0, top));
+
+    Some(SymbolShape {
+        paths: shape
+            .paths
+            .iter()
+            .map(|path| {
+                let path_info = if path.kind.contains(PathKind::RIGID) {
+                    let center = path.path.bounding_box().center();
+                    Affine::translate(stretch * center - center) * path.path.clone()
+                } else {
+                    stretch * path.path.clone()
+                };
+                PathInfo {
+                    kind: path.kind,
+                    path: path_info,
+                }
+            })
+            .collect(),
+    })
+}
+
+// Colors shared by the viewport and the SVG renderer, so both look the same
+pub const BACKGROUND_COLOR: Color = Color::rgb8(6, 6, 6);
+pub const SYMBOL_FILL_COLOR: Color = Color::rgb8(3, 3, 3);
+pub const SYMBOL_STROKE_COLOR: Color = Color::rgb8(150, 150, 150);
+pub const TEXT_COLOR: Color = Color::rgb8(150, 150, 150);
+
+pub fn port_color(is_input: bool, is_output: bool) -> Color {
+    match (is_input, is_output) {
+        (true, true) => Color::rgb8(232, 225, 40),
+        (true, false) => Color::rgb8(40, 110, 228),
+        (false, true) => Color::rgb8(240, 13, 13),
+        (false, false) => Color::rgb8(140, 140, 140),
+    }
+}
+
+pub const PIN_LABEL_SIZE: f32 = 10.0;
+const PIN_LABEL_INSET: f64 = 6.0;
+
+#[derive(Debug, Clone, Copy)]
+pub enum TextAnchor {
+    Start,
+    Middle,
+    End,
+}
+
+/// Where the name of a chip's pin goes: just inside the edge the pin sticks
+/// out of. Labels stay upright when the symbol is rotated.
+pub fn pin_label_anchor(
+    transform: &GlobalTransform,
+    directions: AbsoluteDirections,
+) -> (Point, TextAnchor) {
+    let x = transform.translation.x.to_f64();
+    let y = transform.translation.y.to_f64();
+    let size = PIN_LABEL_SIZE as f64;
+
+    if directions.contains(Directions::NEG_X) {
+        (
+            Point::new(x + PIN_LABEL_INSET, y + size * 0.35),
+            TextAnchor::Start,
+        )
+    } else if directions.contains(Directions::POS_X) {
+        (
+            Point::new(x - PIN_LABEL_INSET, y + size * 0.35),
+            TextAnchor::End,
+        )
+    } else if directions.contains(Directions::NEG_Y) {
+        (
+            Point::new(x, y + PIN_LABEL_INSET + size * 0.7),
+            TextAnchor::Middle,
+        )
+    } else {
+        (Point::new(x, y - PIN_LABEL_INSET), TextAnchor::Middle)
+    }
+}
+
+/// Where the name of a label goes, centered in its tag. Labels are drawn
+/// with `TextAnchor::Middle`.
+pub fn label_text_position(transform: Affine) -> Point {
+    transform * Point::new(35.0, PIN_LABEL_SIZE as f64 * 0.35)
+}
+
+/// Draws a line of text with its baseline at `position`. Glyphs are placed by
+/// their advances without any shaping, which is enough for pin names.
+fn draw_text(
+    scene: &mut vello::Scene,
+    font: &Font,
+    text: &str,
+    position: Point,
+    anchor: TextAnchor,
+    color: Color,
+) {
+    let Ok(font_ref) = FontRef::from_index(font.data.data(), font.index) else {
+        return;
+    };
+    let charmap = font_ref.charmap();
+    let metrics = font_ref.glyph_metrics(Size::new(PIN_LABEL_SIZE), LocationRef::default());
+
+    let mut width = 0.0;
+    let glyphs: Vec<_> = text
+        .chars()
+        .map(|c| {
+            let id = charmap.map(c).unwrap_or(GlyphId::NOTDEF);
+            let glyph = Glyph {
+                id: id.to_u32(),
+                x: width,
+                y: 0.0,
+            };
+            width += metrics.advance_width(id).unwrap_or_default();
+            glyph
+        })
+        .collect();
+
+    let offset = match anchor {
+        TextAnchor::Start => 0.0,
+        TextAnchor::Middle => width as f64 / 2.0,
+        TextAnchor::End => width as f64,
+    };
+
+    scene
+        .draw_glyphs(font)
+        .font_size(PIN_LABEL_SIZE)
+        .brush(color)
+        .transform(Affine::translate((position.x - offset, position.y)))
+        .draw(Fill::NonZero, glyphs.into_iter());
+}
+
 type SymbolQuery<'w, 's> = Query<
     'w,
     's,
     (
         Read<Shape>,
+        Read<BoundingBox>,
         Read<GlobalTransform>,
         Read<ComputedVisibility>,
         Option<Read<digilogic_netcode::StateOffset>>,
         Option<Read<BitWidth>>,
+        Read<Name>,
         Has<Hovered>,
     ),
     With<Symbol>,
 >;
 
+type PinLabelQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Read<Name>,
+        Read<GlobalTransform>,
+        Read<AbsoluteDirections>,
+        Read<ComputedVisibility>,
+    ),
+    With<Port>,
+>;
+
 #[derive(Resource)]
 pub struct VelloFont(pub Font);
 
+#[allow(clippy::too_many_arguments)]
 pub fn draw_symbols(
     symbol_shapes: Res<SymbolShapes>,
     palette: Res<PaletteBrushes>,
@@ -58,6 +261,7 @@ pub fn draw_symbols(
     viewports: Query<(&Scene, &CircuitID), With<Viewport>>,
     children: Query<(Entity, Relations<Child>)>,
     symbols: SymbolQuery,
+    pin_labels: PinLabelQuery,
 ) {
     for (scene, circuit) in viewports.iter() {
         let mut scene = scene.for_layer(Layer::Symbol);
@@ -66,8 +270,16 @@ pub fn draw_symbols(
         children
             .traverse::<Child>(std::iter::once(circuit.0))
             .for_each(|&mut entity, _| {
-                let Ok((shape, transform, &visibility, state_offset, bit_width, hovered)) =
-                    symbols.get(entity)
+                let Ok((
+                    shape,
+                    bounds,
+                    transform,
+                    &visibility,
+                    state_offset,
+                    bit_width,
+                    name,
+                    hovered,
+                )) = symbols.get(entity)
                 else {
                     return;
                 };
@@ -76,17 +288,32 @@ pub fn draw_symbols(
                     return;
                 }
 
-                let transform = Affine::scale(transform.scale.to_f64())
-                    .then_rotate(transform.rotation.radians())
-                    .then_translate(Vec2::new(
-                        transform.translation.x.to_f64(),
-                        transform.translation.y.to_f64(),
-                    ));
+                let transform = transform_to_affine(transform);
 
-                // TODO: figure out how to layout text, as draw requires a Glyph iterator
-                //scene.draw_glyphs(&font.0).hint(true).font_size(12.0).draw();
+                let fitted;
+                let symbol_shape = match shape {
+                    Shape::Chip => {
+                        fitted = chip_shape(bounds);
+                        &fitted
+                    }
+                    Shape::And
+                    | Shape::Or
+                    | Shape::Xor
+                    | Shape::Nand
+                    | Shape::Nor
+                    | Shape::Xnor => {
+                        let gate = &symbol_shapes.0[*shape as usize];
+                        match gate_shape(gate, bounds) {
+                            Some(stretched) => {
+                                fitted = stretched;
+                                &fitted
+                            }
+                            None => gate,
+                        }
+                    }
+                    _ => &symbol_shapes.0[*shape as usize],
+                };
 
-                let symbol_shape = &symbol_shapes.0[*shape as usize];
                 for path in symbol_shape.paths.iter() {
                     let color = palette
                         .get_color_for_state(
@@ -94,7 +321,7 @@ pub fn draw_symbols(
                             state_offset.copied(),
                             bit_width.copied(),
                         )
-                        .unwrap_or(Color::rgb8(3, 3, 3));
+                        .unwrap_or(SYMBOL_FILL_COLOR);
 
                     if path.kind.contains(PathKind::FILL) {
                         scene.fill(Fill::NonZero, transform, color, None, &path.path);
@@ -104,7 +331,7 @@ pub fn draw_symbols(
                         let (width, color) = if hovered {
                             (3.5, Color::WHITE)
                         } else {
-                            (3.0, Color::rgb8(150, 150, 150))
+                            (3.0, SYMBOL_STROKE_COLOR)
                         };
 
                         scene.stroke(
@@ -119,6 +346,37 @@ pub fn draw_symbols(
                         );
                     }
                 }
+
+                // Labels connect by name, so it has to be visible
+                if matches!(shape, Shape::Label | Shape::GlobalLabel) {
+                    draw_text(
+                        &mut scene,
+                        &font.0,
+                        name.0.as_str(),
+                        label_text_position(transform),
+                        TextAnchor::Middle,
+                        TEXT_COLOR,
+                    );
+                }
+
+                // Chips don't show what their pins are, so they're labelled
+                if let (Shape::Chip, Ok((_, edges))) = (shape, children.get(entity)) {
+                    edges.join::<Child>(&pin_labels).for_each(
+                        |(name, transform, &directions, &visibility)| {
+                            if *visibility {
+                                let (position, anchor) = pin_label_anchor(transform, directions);
+                                draw_text(
+                                    &mut scene,
+                                    &font.0,
+                                    name.0.as_str(),
+                                    position,
+                                    anchor,
+                                    TEXT_COLOR,
+                                );
+                            }
+                        },
+                    );
+                }
             });
     }
 }
@@ -132,14 +390,40 @@ type PortQuery<'w, 's> = Query<
         Has<Input>,
         Has<Output>,
         Has<Hovered>,
+        Option<Read<ErcViolations>>,
     ),
     With<Port>,
 >;
 
+type EndpointMarkerQuery<'w, 's> =
+    Query<'w, 's, (Read<GlobalTransform>, Option<Read<ErcViolations>>), With<Endpoint>>;
+
+/// Rings a port or endpoint that failed the electrical rule check, red for
+/// errors and amber for warnings.
+fn draw_erc_marker(scene: &mut vello::Scene, transform: Affine, violations: &ErcViolations) {
+    let color = if violations.0.iter().any(|violation| violation.is_error()) {
+        Color::rgb8(240, 40, 40)
+    } else if !violations.0.is_empty() {
+        Color::rgb8(240, 170, 20)
+    } else {
+        return;
+    };
+
+    scene.stroke(
+        &Stroke::new(2.0),
+        transform,
+        color,
+        None,
+        &Circle::new((0.0, 0.0), 8.0),
+    );
+}
+
 pub fn draw_ports(
     viewports: Query<(&Scene, &CircuitID), With<Viewport>>,
     children: Query<(Entity, Relations<Child>)>,
     ports: PortQuery,
+    nets: Query<Option<&ErcViolations>, With<Net>>,
+    endpoints: EndpointMarkerQuery,
 ) {
     for (scene, circuit) in viewports.iter() {
         let mut scene = scene.for_layer(Layer::Port);
@@ -147,30 +431,32 @@ pub fn draw_ports(
 
         children
             .traverse::<Child>(std::iter::once(circuit.0))
-            .for_each(|&mut entity, _| {
+            .for_each(|&mut entity, relations| {
+                if let Ok(net_violations) = nets.get(entity) {
+                    relations
+                        .join::<Child>(&endpoints)
+                        .for_each(|(transform, violations)| {
+                            let transform = transform_to_affine(transform);
+                            for violations in [net_violations, violations].into_iter().flatten() {
+                                draw_erc_marker(&mut scene, transform, violations);
+                            }
+                        });
+                    return;
+                }
+
                 let Ok(entity) = ports.get(entity) else {
                     return;
                 };
 
-                let (transform, &visibility, is_input, is_output, hovered) = entity;
+                let (transform, &visibility, is_input, is_output, hovered, violations) = entity;
 
                 if !*visibility {
                     return;
                 }
 
-                let transform = Affine::scale(transform.scale.to_f64())
-                    .then_rotate(transform.rotation.radians())
-                    .then_translate(Vec2::new(
-                        transform.translation.x.to_f64(),
-                        transform.translation.y.to_f64(),
-                    ));
-
-                let color = match (is_input, is_output) {
-                    (true, true) => Color::rgb8(232, 225, 40),
-                    (true, false) => Color::rgb8(40, 110, 228),
-                    (false, true) => Color::rgb8(240, 13, 13),
-                    (false, false) => Color::rgb8(140, 140, 140),
-                };
+                let transform = transform_to_affine(transform);
+
+                let color = port_color(is_input, is_output);
 
                 let radius = if hovered { 6.0 } else { 4.0 };
 
@@ -181,6 +467,10 @@ pub fn draw_ports(
                     None,
                     &Circle::new((0.0, 0.0), radius),
                 );
+
+                if let Some(violations) = violations {
+                    draw_erc_marker(&mut scene, transform, violations);
+                }
             });
     }
 }
@@ -200,12 +490,36 @@ type VertexQuery<'w, 's> = Query<
     ),
 >;
 
+/// Half the size of the mark drawn where a wire uses only some bits of its net
+const BUS_ENTRY_SIZE: f64 = 5.0;
+
+/// The bus entry of an endpoint that only uses some bits of its net, a short
+/// diagonal across the wire.
+pub fn bus_entry(global_transform: &GlobalTransform) -> Line {
+    let pos = Point::new(
+        global_transform.translation.x.to_f64(),
+        global_transform.translation.y.to_f64(),
+    );
+    let offset = Vec2::new(BUS_ENTRY_SIZE, -BUS_ENTRY_SIZE);
+    Line::new(pos - offset, pos + offset)
+}
+
+pub fn wire_color(is_root: bool, hovered: bool) -> Color {
+    match (is_root, hovered) {
+        (true, true) => Color::rgb8(245, 220, 116),
+        (true, false) => Color::rgb8(208, 166, 2),
+        (false, true) => Color::rgb8(125, 240, 147),
+        (false, false) => Color::rgb8(8, 190, 42),
+    }
+}
+
 pub fn draw_wires(
     app_state: Res<crate::AppSettings>,
     palette: Res<PaletteBrushes>,
     sim_state: Option<Res<digilogic_netcode::SimState>>,
     viewports: Query<(&Scene, &CircuitID), With<Viewport>>,
     vertices: VertexQuery,
+    bus_entries: Query<&GlobalTransform, (With<Endpoint>, With<Bits>)>,
 ) {
     let brush_transform = palette.get_brush_transform();
 
@@ -216,7 +530,7 @@ pub fn draw_wires(
         vertices
             .traverse::<Child>(std::iter::once(circuit.0))
             .for_each(
-                |&mut (vertices, visibility, state_offset, bit_width, hovered), _| {
+                |&mut (vertices, visibility, state_offset, bit_width, hovered), relations| {
                     let Some(vertices) = vertices else {
                         return;
                     };
@@ -255,13 +569,7 @@ pub fn draw_wires(
                             VertexKind::WireEnd { junction_kind } => {
                                 let brush = brush.unwrap_or_else(|| {
                                     let is_root = is_root_path && app_state.show_root_wires;
-
-                                    match (is_root, hovered) {
-                                        (true, true) => Color::rgb8(245, 220, 116).into(),
-                                        (true, false) => Color::rgb8(208, 166, 2).into(),
-                                        (false, true) => Color::rgb8(125, 240, 147).into(),
-                                        (false, false) => Color::rgb8(8, 190, 42).into(),
-                                    }
+                                    wire_color(is_root, hovered).into()
                                 });
 
                                 path.line_to(pos);
@@ -286,6 +594,20 @@ pub fn draw_wires(
                             }
                         }
                     }
+
+                    // Endpoints using only some bits of the net get a bus entry
+                    let brush = brush.unwrap_or_else(|| wire_color(false, hovered).into());
+                    relations
+                        .join::<Child>(&bus_entries)
+                        .for_each(|global_transform| {
+                            scene.stroke(
+                                &Stroke::new(width).with_caps(Cap::Round),
+                                Affine::IDENTITY,
+                                brush,
+                                brush_transform,
+                                &bus_entry(global_transform),
+                            );
+                        });
                 },
             );
     }
@@ -380,6 +702,84 @@ fn scale_path(mut path: BezPath, scale: f64, translate: (f64, f64)) -> BezPath {
 const GATE_SCALE: f64 = 12.5;
 const GATE_TRANSLATE: (f64, f64) = (-34.5, -29.5);
 
+/// Where the body of an inverted gate ends and its bubble starts
+const BUBBLE_START: f64 = 70.0;
+const BUBBLE_RADIUS: f64 = 4.0;
+
+/// Squeezes the body of a gate to make room for an inversion bubble in front
+/// of its output.
+fn inverted_gate(mut paths: Vec<PathInfo>) -> SymbolShape {
+    const BODY_START: f64 = 3.0;
+    const BODY_END: f64 = 78.0;
+
+    let squeeze = Affine::translate((-BODY_START, 0.0))
+        .then_scale_non_uniform((BUBBLE_START - BODY_START) / (BODY_END - BODY_START), 1.0)
+        .then_translate(Vec2::new(BODY_START, 0.0));
+    for path in paths.iter_mut() {
+        path.path.apply_affine(squeeze);
+    }
+
+    paths.push(PathInfo {
+        kind: PathKind::FILL | PathKind::STROKE | PathKind::RIGID,
+        path: Circle::new((BUBBLE_START + BUBBLE_RADIUS, 20.0), BUBBLE_RADIUS).to_path(0.1),
+    });
+    SymbolShape { paths }
+}
+
+fn and_paths() -> Vec<PathInfo> {
+    vec![PathInfo {
+        kind: PathKind::FILL | PathKind::STROKE,
+        path: scale_path(
+            bez_path!(M 5.9,7 H 3 V 1 L 5.9,1 C 7.7,1 9,2.2 9,4 9,5.8 7.4,7 5.9,7 Z),
+            GATE_SCALE,
+            GATE_TRANSLATE,
+        ),
+    }]
+}
+
+fn or_paths() -> Vec<PathInfo> {
+    vec![PathInfo {
+        kind: PathKind::FILL | PathKind::STROKE,
+        path: scale_path(
+            bez_path!(
+                M 3,7 H 4.4 C 6.7,7 7.7,6.9 9,4 7.7,1.1 6.7,1 4.4,1 H 3 C 4.4,3.1 4.4,4.9 3,7 Z
+            ),
+            GATE_SCALE,
+            GATE_TRANSLATE,
+        ),
+    }]
+}
+
+fn xor_paths() -> Vec<PathInfo> {
+    let mut paths = or_paths();
+    paths.push(PathInfo {
+        kind: PathKind::STROKE,
+        path: scale_path(
+            bez_path!(
+                M 2.2,1 C 3.6,3.1 3.6,4.9 2.2,7
+            ),
+            GATE_SCALE,
+            GATE_TRANSLATE,
+        ),
+    });
+    paths
+}
+
+/// The triangle of a buffer, the same as the one of a NOT gate but reaching
+/// all the way to the output.
+fn buffer_paths() -> Vec<PathInfo> {
+    vec![PathInfo {
+        kind: PathKind::FILL | PathKind::STROKE,
+        path: scale_path(
+            bez_path!(
+                M 7.7,3 3.3,1.5 V 4.5 L 7.7,3 Z
+            ),
+            NOT_SCALE,
+            NOT_TRANSLATE,
+        ),
+    }]
+}
+
 const NOT_SCALE: f64 = 7.75;
 const NOT_TRANSLATE: (f64, f64) = (-22.0, -22.75);
 
@@ -388,7 +788,11 @@ const INPUT_TRANSLATE: (f64, f64) = (-46.5, -17.75);
 const OUTPUT_TRANSLATE: (f64, f64) = (-12.0, -17.75);
 
 pub fn init_symbol_shapes(mut symbol_svgs: ResMut<SymbolShapes>) {
-    symbol_svgs.0 = vec![
+    symbol_svgs.0 = build_symbol_shapes();
+}
+
+pub fn build_symbol_shapes() -> Vec<SymbolShape> {
+    vec![
         // Chip
         SymbolShape {
             paths: vec![PathInfo {
@@ -397,54 +801,11 @@ pub fn init_symbol_shapes(mut symbol_svgs: ResMut<SymbolShapes>) {
             }],
         },
         // And -- from schemalib-and2-l.svg
-        SymbolShape {
-            paths: vec![PathInfo {
-                kind: PathKind::FILL | PathKind::STROKE,
-                path: scale_path(
-                    bez_path!(M 5.9,7 H 3 V 1 L 5.9,1 C 7.7,1 9,2.2 9,4 9,5.8 7.4,7 5.9,7 Z),
-                    GATE_SCALE,
-                    GATE_TRANSLATE,
-                ),
-            }],
-        },
+        SymbolShape { paths: and_paths() },
         // Or -- from schemalib-or2-l.svg
-        SymbolShape {
-            paths: vec![PathInfo {
-                kind: PathKind::FILL | PathKind::STROKE,
-                path: scale_path(
-                    bez_path!(
-                        M 3,7 H 4.4 C 6.7,7 7.7,6.9 9,4 7.7,1.1 6.7,1 4.4,1 H 3 C 4.4,3.1 4.4,4.9 3,7 Z
-                    ),
-                    GATE_SCALE,
-                    GATE_TRANSLATE,
-                ),
-            }],
-        },
+        SymbolShape { paths: or_paths() },
         // Xor -- from schemalib-xor2-l.svg
-        SymbolShape {
-            paths: vec![
-                PathInfo {
-                    kind: PathKind::FILL | PathKind::STROKE,
-                    path: scale_path(
-                        bez_path!(
-                            M 3,7 H 4.4 C 6.7,7 7.7,6.9 9,4 7.7,1.1 6.7,1 4.4,1 H 3 C 4.4,3.1 4.4,4.9 3,7 Z
-                        ),
-                        GATE_SCALE,
-                        GATE_TRANSLATE,
-                    ),
-                },
-                PathInfo {
-                    kind: PathKind::STROKE,
-                    path: scale_path(
-                        bez_path!(
-                            M 2.2,1 C 3.6,3.1 3.6,4.9 2.2,7
-                        ),
-                        GATE_SCALE,
-                        GATE_TRANSLATE,
-                    ),
-                },
-            ],
-        },
+        SymbolShape { paths: xor_paths() },
         // Not -- from schemalib-inv-l.svg
         SymbolShape {
             paths: vec![
@@ -492,5 +853,40 @@ pub fn init_symbol_shapes(mut symbol_svgs: ResMut<SymbolShapes>) {
                 ),
             }],
         },
-    ];
+        // Nand
+        inverted_gate(and_paths()),
+        // Nor
+        inverted_gate(or_paths()),
+        // Xnor
+        inverted_gate(xor_paths()),
+        // Buffer
+        SymbolShape {
+            paths: buffer_paths(),
+        },
+        // TriState -- a buffer with its enable input coming in from the top
+        SymbolShape {
+            paths: {
+                let mut paths = buffer_paths();
+                paths.push(PathInfo {
+                    kind: PathKind::STROKE,
+                    path: Line::new((20.0, -20.0), (20.0, -5.5)).to_path(0.1),
+                });
+                paths
+            },
+        },
+        // Label -- a tag pointing at the net it's attached to
+        SymbolShape {
+            paths: vec![PathInfo {
+                kind: PathKind::FILL | PathKind::STROKE,
+                path: bez_path!(M 0,0 L 10,-10 H 60 V 10 H 10 Z),
+            }],
+        },
+        // GlobalLabel -- pointed at both ends, as it leaves the circuit
+        SymbolShape {
+            paths: vec![PathInfo {
+                kind: PathKind::FILL | PathKind::STROKE,
+                path: bez_path!(M 0,0 L 10,-10 H 50 L 60,0 L 50,10 H 10 Z),
+            }],
+        },
+    ]
 }
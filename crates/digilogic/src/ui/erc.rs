@@ -0,0 +1,166 @@
+use super::{Egui, MenuSet, OpenWindows};
+use aery::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::lifetimeless::Read;
+use bevy_reflect::Reflect;
+use digilogic_core::components::*;
+use digilogic_core::erc::{ErcReport, ErcViolations};
+use digilogic_core::events::CheckCircuitEvent;
+use egui::*;
+
+/// The circuit whose electrical rule check results are listed, if any
+#[derive(Debug, Default, Resource, Reflect)]
+#[reflect(Resource)]
+pub(super) struct ErcPanel {
+    pub(super) circuit: Option<CircuitID>,
+}
+
+type SymbolQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        (
+            Read<DesignatorPrefix>,
+            Read<DesignatorNumber>,
+            Option<Read<DesignatorSuffix>>,
+        ),
+        Relations<Child>,
+    ),
+    With<Symbol>,
+>;
+
+type NetQuery<'w, 's> =
+    Query<'w, 's, ((Read<Name>, Option<Read<ErcViolations>>), Relations<Child>), With<Net>>;
+
+/// Lists every violation of the circuit, where they are and what's wrong.
+fn list_violations(
+    circuit: CircuitID,
+    children: &Query<(Entity, Relations<Child>)>,
+    symbols: &SymbolQuery,
+    nets: &NetQuery,
+    violations: &Query<(Option<Read<Name>>, Read<ErcViolations>)>,
+) -> Vec<(bool, String)> {
+    let mut rows = Vec::new();
+    let mut push = |location: &str, found: &ErcViolations| {
+        for violation in found.0.iter() {
+            rows.push((violation.is_error(), format!("{location}: {violation}")));
+        }
+    };
+
+    let Ok((_, circuit_children)) = children.get(circuit.0) else {
+        return rows;
+    };
+
+    circuit_children.join::<Child>(symbols).for_each(
+        |((prefix, number, suffix), symbol_children)| {
+            let designator = format!(
+                "{}{}{}",
+                prefix.0,
+                number.0,
+                suffix.map(|suffix| suffix.0.as_str()).unwrap_or_default()
+            );
+            symbol_children
+                .join::<Child>(violations)
+                .for_each(|(name, found)| {
+                    let pin = name.map(|name| name.0.as_str()).unwrap_or_default();
+                    push(&format!("{designator} pin {pin}"), found);
+                });
+        },
+    );
+
+    circuit_children
+        .join::<Child>(nets)
+        .for_each(|((name, found), net_children)| {
+            let net = if name.0.is_empty() {
+                "Unnamed net".to_owned()
+            } else {
+                format!("Net {}", name.0)
+            };
+            if let Some(found) = found {
+                push(&net, found);
+            }
+            net_children
+                .join::<Child>(violations)
+                .for_each(|(_, found)| push(&format!("{net} endpoint"), found));
+        });
+
+    // Errors first, as they keep the circuit from being simulated
+    rows.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    rows
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_erc_panel(
+    egui: Res<Egui>,
+    open_windows: Res<OpenWindows>,
+    mut panel: ResMut<ErcPanel>,
+    mut check_events: EventWriter<CheckCircuitEvent>,
+    circuits: Query<(&Name, Option<&ErcReport>), With<Circuit>>,
+    children: Query<(Entity, Relations<Child>)>,
+    symbols: SymbolQuery,
+    nets: NetQuery,
+    violations: Query<(Option<Read<Name>>, Read<ErcViolations>)>,
+) {
+    let Some(circuit) = panel.circuit else {
+        return;
+    };
+    let Ok((name, report)) = circuits.get(circuit.0) else {
+        panel.circuit = None;
+        return;
+    };
+
+    TopBottomPanel::bottom("erc_panel")
+        .resizable(true)
+        .show(&egui.context, |ui| {
+            ui.add_enabled_ui(!open_windows.any(), |ui| {
+                ui.horizontal(|ui| {
+                    ui.strong(format!("Electrical Rule Check: {}", name.0));
+                    if let Some(report) = report {
+                        ui.label(format!(
+                            "{} errors, {} warnings",
+                            report.errors, report.warnings
+                        ));
+                    }
+
+                    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                        if ui.button("Close").clicked() {
+                            panel.circuit = None;
+                        }
+                        if ui.button("Check Again").clicked() {
+                            check_events.send(CheckCircuitEvent { circuit });
+                        }
+                    });
+                });
+
+                ui.separator();
+
+                let rows = list_violations(circuit, &children, &symbols, &nets, &violations);
+                ScrollArea::vertical().show(ui, |ui| {
+                    if rows.is_empty() {
+                        ui.weak("No problems found");
+                    }
+                    for (is_error, row) in rows {
+                        if is_error {
+                            ui.colored_label(ui.visuals().error_fg_color, row);
+                        } else {
+                            ui.colored_label(ui.visuals().warn_fg_color, row);
+                        }
+                    }
+                });
+            });
+        });
+}
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ErcPanelSet;
+
+#[derive(Debug, Default)]
+pub struct ErcPanelPlugin;
+
+impl bevy_app::Plugin for ErcPanelPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.register_type::<ErcPanel>().init_resource::<ErcPanel>();
+        app.configure_sets(bevy_app::Update, ErcPanelSet.after(MenuSet));
+        app.add_systems(bevy_app::Update, update_erc_panel.in_set(ErcPanelSet));
+    }
+}
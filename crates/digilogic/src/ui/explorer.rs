@@ -1,12 +1,14 @@
-use super::{Canvas, Egui, MenuSet, OpenWindows, ViewportBundle};
+use super::{Canvas, Egui, MenuSet, OpenWindows, PanZoom, ViewportBundle};
 use bevy_derive::{Deref, DerefMut};
 use bevy_ecs::prelude::*;
 use bevy_ecs::system::lifetimeless::Read;
 use bevy_ecs::system::SystemParam;
 use bevy_reflect::Reflect;
-use digilogic_core::components::{Circuit, CircuitID, Name, Viewport};
+use digilogic_core::components::{Circuit, CircuitID, Modified, Name, Viewport};
+use digilogic_core::events::PlaceSubCircuitEvent;
+use digilogic_core::history::{Edit, HistoryEvent};
 use digilogic_core::resources::Project;
-use digilogic_core::SharedStr;
+use digilogic_core::{Fixed, SharedStr};
 use egui::*;
 use egui_dock::*;
 use egui_wgpu::RenderState;
@@ -105,17 +107,48 @@ impl ViewportSpawner<'_, '_> {
 
         self.spawn_viewport(circuit, render_state);
     }
+
+    fn focused_viewport(&mut self) -> Option<Entity> {
+        self.dock_state
+            .find_active_focused()
+            .map(|(_, &mut viewport)| viewport)
+    }
+}
+
+/// The circuit shown in the focused viewport, and the middle of the part of
+/// it that's visible.
+fn placement_target(
+    viewport_spawner: &mut ViewportSpawner,
+    viewports: &Query<(&CircuitID, &PanZoom, &Canvas), With<Viewport>>,
+) -> Option<(CircuitID, digilogic_core::transform::Vec2)> {
+    let viewport = viewport_spawner.focused_viewport()?;
+    let (&circuit, pan_zoom, canvas) = viewports.get(viewport).ok()?;
+
+    let size = Vec2::new(canvas.width() as f32, canvas.height() as f32);
+    let center = size / 2.0 / pan_zoom.zoom - pan_zoom.pan;
+    let position = digilogic_core::transform::Vec2 {
+        x: Fixed::try_from_f32(center.x.round())?,
+        y: Fixed::try_from_f32(center.y.round())?,
+    };
+
+    Some((circuit, position))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn update_explorer(
     egui: Res<Egui>,
     open_windows: Res<OpenWindows>,
     mut project: Option<ResMut<Project>>,
     mut project_name_edit_state: Local<EditState>,
-    mut circuits: Query<(Entity, &mut Name, &mut NameEditState), With<Circuit>>,
+    mut circuits: Query<(Entity, &Name, &mut NameEditState, Has<Modified>), With<Circuit>>,
+    mut history_events: EventWriter<HistoryEvent>,
+    mut place_events: EventWriter<PlaceSubCircuitEvent>,
     mut edit_buffer: Local<String>,
     mut viewport_spawner: ViewportSpawner,
+    viewports: Query<(&CircuitID, &PanZoom, &Canvas), With<Viewport>>,
 ) {
+    let target = placement_target(&mut viewport_spawner, &viewports);
+
     SidePanel::left("explorer_panel")
         .resizable(true)
         .show(&egui.context, |ui| {
@@ -135,7 +168,7 @@ fn update_explorer(
                         );
                     })
                     .body(|ui| {
-                        for (circuit_id, mut circuit_name, mut circuit_name_edit_state) in
+                        for (circuit_id, circuit_name, mut circuit_name_edit_state, modified) in
                             circuits.iter_mut()
                         {
                             if project
@@ -145,14 +178,49 @@ fn update_explorer(
                                 // TODO: visually mark root circuit
                             }
 
-                            let clicked = show_editable_name(
-                                ui,
-                                &mut circuit_name_edit_state,
-                                &mut edit_buffer,
-                                &mut circuit_name.0,
-                            );
-
-                            if clicked {
+                            let row = ui.horizontal(|ui| {
+                                let mut name = circuit_name.0.clone();
+                                let clicked = show_editable_name(
+                                    ui,
+                                    &mut circuit_name_edit_state,
+                                    &mut edit_buffer,
+                                    &mut name,
+                                );
+                                if name != circuit_name.0 {
+                                    history_events.send(HistoryEvent::Edit(
+                                        CircuitID(circuit_id),
+                                        Edit::Rename {
+                                            entity: circuit_id,
+                                            from: circuit_name.0.clone(),
+                                            to: name,
+                                        },
+                                    ));
+                                }
+                                if modified {
+                                    ui.weak("*").on_hover_text("Unsaved changes");
+                                }
+                                clicked
+                            });
+
+                            row.response.interact(Sense::click()).context_menu(|ui| {
+                                // Placing a circuit in itself is refused anyway,
+                                // but that's easy to tell here already.
+                                let enabled =
+                                    target.is_some_and(|(circuit, _)| circuit.0 != circuit_id);
+                                let button = Button::new("Place in focused circuit");
+                                if ui.add_enabled(enabled, button).clicked() {
+                                    if let Some((circuit, position)) = target {
+                                        place_events.send(PlaceSubCircuitEvent {
+                                            circuit,
+                                            sub_circuit: CircuitID(circuit_id),
+                                            position,
+                                        });
+                                    }
+                                    ui.close_menu();
+                                }
+                            });
+
+                            if row.inner {
                                 viewport_spawner.focus_or_spawn_viewport(
                                     CircuitID(circuit_id),
                                     &egui.render_state,
@@ -0,0 +1,153 @@
+use super::{Egui, OpenWindows};
+use bevy_ecs::prelude::*;
+use bevy_reflect::Reflect;
+use digilogic_core::components::{Circuit, Modified, Name};
+use digilogic_core::resources::Project;
+use digilogic_core::SharedStr;
+use egui::*;
+
+/// What to do once unsaved changes have been discarded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub(super) enum DiscardAction {
+    NewProject,
+    Quit,
+}
+
+pub(super) fn new_project(commands: &mut Commands, circuits: impl Iterator<Item = Entity>) {
+    for circuit in circuits {
+        commands.entity(circuit).despawn();
+    }
+
+    commands.insert_resource(Project {
+        name: SharedStr::new_static("Unnamed Project"),
+        file_path: None,
+        root_circuit: None,
+    });
+}
+
+/// Whether the window may close. If there are unsaved changes, this asks
+/// whether to discard them instead, and closes the window once they are.
+pub(crate) fn confirm_close(world: &mut World) -> bool {
+    let mut modified = world.query_filtered::<(), (With<Circuit>, With<Modified>)>();
+    if modified.iter(world).next().is_none() {
+        return true;
+    }
+
+    world.resource_mut::<OpenWindows>().discard_prompt = Some(DiscardAction::Quit);
+    false
+}
+
+fn update_discard_prompt(
+    mut commands: Commands,
+    egui: Res<Egui>,
+    mut open_windows: ResMut<OpenWindows>,
+    circuits: Query<(Entity, &Name, Has<Modified>), With<Circuit>>,
+) {
+    let Some(action) = open_windows.discard_prompt else {
+        return;
+    };
+
+    Window::new("Unsaved Changes")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+        .show(&egui.context, |ui| {
+            ui.label("These circuits have unsaved changes:");
+            for (_, name, modified) in circuits.iter() {
+                if modified {
+                    ui.label(format!("• {}", name.0));
+                }
+            }
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button("Discard").clicked() {
+                    match action {
+                        DiscardAction::NewProject => {
+                            new_project(
+                                &mut commands,
+                                circuits.iter().map(|(circuit, _, _)| circuit),
+                            );
+                        }
+                        DiscardAction::Quit => {
+                            for (circuit, _, _) in circuits.iter() {
+                                commands.entity(circuit).remove::<Modified>();
+                            }
+                            egui.context.send_viewport_cmd(ViewportCommand::Close);
+                        }
+                    }
+                    open_windows.discard_prompt = None;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    open_windows.discard_prompt = None;
+                }
+            });
+        });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn open_recovery_prompt(
+    recovery: Option<Res<digilogic_serde::Recovery>>,
+    mut open_windows: ResMut<OpenWindows>,
+) {
+    open_windows.recovery_prompt =
+        recovery.is_some_and(|recovery| recovery.found().next().is_some());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn update_recovery_prompt(
+    egui: Res<Egui>,
+    recovery: Option<Res<digilogic_serde::Recovery>>,
+    mut open_windows: ResMut<OpenWindows>,
+    mut recovery_events: EventWriter<digilogic_serde::RecoveryEvent>,
+) {
+    let Some(recovery) = recovery.filter(|_| open_windows.recovery_prompt) else {
+        return;
+    };
+
+    Window::new("Recover Unsaved Changes")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+        .show(&egui.context, |ui| {
+            ui.label("digilogic didn't exit cleanly, these circuits had unsaved changes:");
+            for (name, file) in recovery.found() {
+                match file {
+                    Some(file) => ui.label(format!("• {}", file.display())),
+                    None => ui.label(format!("• {name} (never saved)")),
+                };
+            }
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button("Restore").clicked() {
+                    recovery_events.send(digilogic_serde::RecoveryEvent::Restore);
+                    open_windows.recovery_prompt = false;
+                }
+
+                if ui.button("Discard").clicked() {
+                    recovery_events.send(digilogic_serde::RecoveryEvent::Discard);
+                    open_windows.recovery_prompt = false;
+                }
+            });
+        });
+}
+
+#[derive(Debug, Default)]
+pub struct UnsavedPlugin;
+
+impl bevy_app::Plugin for UnsavedPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.register_type::<DiscardAction>();
+        app.add_systems(bevy_app::Update, update_discard_prompt);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            app.add_systems(bevy_app::Startup, open_recovery_prompt);
+            app.add_systems(bevy_app::Update, update_recovery_prompt);
+        }
+    }
+}
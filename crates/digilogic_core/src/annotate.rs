@@ -0,0 +1,516 @@
+//! Annotation, which numbers the symbols of circuits so every one has a
+//! unique reference designator.
+//!
+//! Numbers are unique per designator prefix across all annotated circuits.
+//! Symbols are numbered top to bottom, then left to right, so the same
+//! circuit is always numbered the same way. In multi-part mode, gates that
+//! fit into one IC share a number and are told apart by their suffix, with
+//! every part but the first `PartOf` the first.
+
+use crate::components::*;
+use crate::events::AnnotateEvent;
+use crate::history::{Edit, HistoryEvent, HistorySet};
+use crate::transform::{Transform, Vec2};
+use crate::{HashMap, SharedStr};
+use aery::edges::{EdgeInfo, Edges};
+use aery::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_reflect::Reflect;
+
+/// How symbols are annotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+pub struct AnnotateOptions {
+    /// Keeps the designators of symbols that are already numbered, unless
+    /// another symbol has the same designator
+    pub keep_existing: bool,
+    /// Packs gates into ICs with several of them, which share a number
+    pub multi_part: bool,
+}
+
+impl Default for AnnotateOptions {
+    fn default() -> Self {
+        Self {
+            keep_existing: true,
+            multi_part: false,
+        }
+    }
+}
+
+/// The parts of a reference designator annotation can change.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Designator {
+    pub number: u32,
+    pub suffix: Option<SharedStr>,
+    /// The first part of the component the symbol is part of, if it isn't
+    /// the first part itself
+    pub part_of: Option<Entity>,
+}
+
+impl Designator {
+    fn of(world: &World, part_of: &mut QueryState<Edges<PartOf>>, entity: Entity) -> Self {
+        Self {
+            number: world
+                .get::<DesignatorNumber>(entity)
+                .map(|number| number.0)
+                .unwrap_or_default(),
+            suffix: world
+                .get::<DesignatorSuffix>(entity)
+                .map(|suffix| suffix.0.clone()),
+            part_of: part_of
+                .get(world, entity)
+                .ok()
+                .and_then(|edges| edges.targets().first().copied()),
+        }
+    }
+
+    pub(crate) fn apply(&self, entity: &mut EntityWorldMut) {
+        entity.insert(DesignatorNumber(self.number));
+        match &self.suffix {
+            Some(suffix) => entity.insert(DesignatorSuffix(suffix.clone())),
+            None => entity.remove::<DesignatorSuffix>(),
+        };
+
+        entity.unset_all::<PartOf>();
+        if let Some(part_of) = self.part_of {
+            let exists = entity.world_scope(|world| world.get_entity(part_of).is_ok());
+            if exists {
+                entity.set::<PartOf>(part_of);
+            }
+        }
+    }
+}
+
+/// Gates are assumed to come in 14 pin ICs, like the 7400 series, which
+/// leaves 12 pins for their inputs and outputs.
+const PACKAGE_PINS: usize = 12;
+
+/// How many symbols fit into one IC, e.g. the four gates of a 7400. Only
+/// single bit gates are packed.
+fn parts_per_package(kind: SymbolKind, port_widths: &[BitWidth]) -> usize {
+    let gate = matches!(
+        kind,
+        SymbolKind::And
+            | SymbolKind::Or
+            | SymbolKind::Xor
+            | SymbolKind::Nand
+            | SymbolKind::Nor
+            | SymbolKind::Xnor
+            | SymbolKind::Not
+            | SymbolKind::Buffer
+            | SymbolKind::TriState
+    );
+    if !gate || port_widths.is_empty() || port_widths.iter().any(|width| width.0.get() != 1) {
+        return 1;
+    }
+
+    (PACKAGE_PINS / port_widths.len()).clamp(1, 26)
+}
+
+fn suffix_letter(slot: usize) -> SharedStr {
+    char::from(b'A' + slot as u8).to_string().into()
+}
+
+fn suffix_slot(suffix: &str) -> Option<usize> {
+    match suffix.as_bytes() {
+        &[letter @ b'A'..=b'Z'] => Some((letter - b'A') as usize),
+        _ => None,
+    }
+}
+
+/// What symbols need in common to be packed into the same IC
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PackageKind {
+    circuit: Entity,
+    kind: SymbolKind,
+    ports: usize,
+}
+
+struct Part {
+    entity: Entity,
+    circuit: Entity,
+    prefix: SharedStr,
+    package_kind: PackageKind,
+    parts: usize,
+    position: Vec2,
+    from: Designator,
+}
+
+/// A designator number in use, and which of its suffixes are taken. Single
+/// part symbols take a package with no slots.
+struct Package {
+    kind: Option<PackageKind>,
+    slots: Vec<bool>,
+}
+
+impl Package {
+    fn free_slot(&self) -> Option<usize> {
+        self.slots.iter().position(|&taken| !taken)
+    }
+}
+
+#[derive(Default)]
+struct Packages {
+    packages: HashMap<(SharedStr, u32), Package>,
+}
+
+impl Packages {
+    /// Takes `part`'s current designator, if no other part has it already.
+    fn keep(&mut self, part: &Part) -> bool {
+        let number = part.from.number;
+        if number == 0 {
+            return false;
+        }
+
+        let key = (part.prefix.clone(), number);
+        match &part.from.suffix {
+            None => {
+                if self.packages.contains_key(&key) {
+                    return false;
+                }
+                self.packages.insert(
+                    key,
+                    Package {
+                        kind: None,
+                        slots: Vec::new(),
+                    },
+                );
+                true
+            }
+            Some(suffix) => {
+                let Some(slot) = suffix_slot(suffix).filter(|&slot| slot < part.parts) else {
+                    return false;
+                };
+                let package = self.packages.entry(key).or_insert_with(|| Package {
+                    kind: Some(part.package_kind.clone()),
+                    slots: vec![false; part.parts],
+                });
+                if package.kind.as_ref() != Some(&part.package_kind) || package.slots[slot] {
+                    return false;
+                }
+                package.slots[slot] = true;
+                true
+            }
+        }
+    }
+
+    fn next_number(&self, prefix: &SharedStr) -> u32 {
+        (1..)
+            .find(|&number| !self.packages.contains_key(&(prefix.clone(), number)))
+            .unwrap()
+    }
+
+    /// A new number for `part`, or the number and suffix of a free slot in an
+    /// IC of its kind when packing gates.
+    fn assign(&mut self, part: &Part, multi_part: bool) -> (u32, Option<SharedStr>) {
+        if !multi_part || part.parts <= 1 {
+            let number = self.next_number(&part.prefix);
+            self.packages.insert(
+                (part.prefix.clone(), number),
+                Package {
+                    kind: None,
+                    slots: Vec::new(),
+                },
+            );
+            return (number, None);
+        }
+
+        let open = self
+            .packages
+            .iter()
+            .filter(|((prefix, _), package)| {
+                *prefix == part.prefix
+                    && package.kind.as_ref() == Some(&part.package_kind)
+                    && package.free_slot().is_some()
+            })
+            .map(|(&(_, number), _)| number)
+            .min();
+        let number = open.unwrap_or_else(|| self.next_number(&part.prefix));
+
+        let package = self
+            .packages
+            .entry((part.prefix.clone(), number))
+            .or_insert_with(|| Package {
+                kind: Some(part.package_kind.clone()),
+                slots: vec![false; part.parts],
+            });
+        let slot = package.free_slot().unwrap();
+        package.slots[slot] = true;
+        (number, Some(suffix_letter(slot)))
+    }
+}
+
+/// The edits that annotate the symbols of `circuits`, without applying them.
+/// Labels aren't components, so they aren't numbered.
+pub fn annotation_edits(
+    world: &mut World,
+    circuits: &[Entity],
+    options: AnnotateOptions,
+) -> Vec<(CircuitID, Edit)> {
+    let mut children = world.query::<Edges<Child>>();
+    let mut part_of = world.query::<Edges<PartOf>>();
+
+    let mut parts = Vec::new();
+    for &circuit in circuits {
+        let symbols = children
+            .get(world, circuit)
+            .map(|edges| edges.hosts().to_vec())
+            .unwrap_or_default();
+        for entity in symbols {
+            if world.get::<Symbol>(entity).is_none() {
+                continue;
+            }
+            let (Some(&kind), Some(prefix)) = (
+                world.get::<SymbolKind>(entity),
+                world.get::<DesignatorPrefix>(entity),
+            ) else {
+                continue;
+            };
+            if matches!(kind, SymbolKind::Label | SymbolKind::GlobalLabel) {
+                continue;
+            }
+
+            let port_widths: Vec<_> = children
+                .get(world, entity)
+                .map(|edges| edges.hosts().to_vec())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|&port| world.get::<Port>(port).is_some())
+                .filter_map(|port| world.get::<BitWidth>(port).copied())
+                .collect();
+
+            parts.push(Part {
+                entity,
+                circuit,
+                prefix: prefix.0.clone(),
+                package_kind: PackageKind {
+                    circuit,
+                    kind,
+                    ports: port_widths.len(),
+                },
+                parts: parts_per_package(kind, &port_widths),
+                position: world
+                    .get::<Transform>(entity)
+                    .map(|transform| transform.translation)
+                    .unwrap_or_default(),
+                from: Designator::of(world, &mut part_of, entity),
+            });
+        }
+    }
+
+    let circuit_order = |circuit: Entity| circuits.iter().position(|&c| c == circuit);
+    parts.sort_by_key(|part| {
+        (
+            circuit_order(part.circuit),
+            part.position.y,
+            part.position.x,
+            part.entity,
+        )
+    });
+
+    let mut packages = Packages::default();
+    let mut kept = vec![false; parts.len()];
+    if options.keep_existing {
+        for (part, kept) in parts.iter().zip(kept.iter_mut()) {
+            *kept = packages.keep(part);
+        }
+    }
+
+    let mut designators: Vec<_> = parts
+        .iter()
+        .zip(kept.iter())
+        .map(|(part, &kept)| {
+            let (number, suffix) = if kept {
+                (part.from.number, part.from.suffix.clone())
+            } else {
+                packages.assign(part, options.multi_part)
+            };
+            Designator {
+                number,
+                suffix,
+                part_of: None,
+            }
+        })
+        .collect();
+
+    // The first part of a component is the one with the lowest suffix
+    let mut first_parts = HashMap::<(SharedStr, u32), (usize, Entity)>::default();
+    for (part, designator) in parts.iter().zip(designators.iter()) {
+        let Some(slot) = designator.suffix.as_deref().and_then(suffix_slot) else {
+            continue;
+        };
+        let first = first_parts
+            .entry((part.prefix.clone(), designator.number))
+            .or_insert((slot, part.entity));
+        if slot < first.0 {
+            *first = (slot, part.entity);
+        }
+    }
+    for (part, designator) in parts.iter().zip(designators.iter_mut()) {
+        if designator.suffix.is_none() {
+            continue;
+        }
+        designator.part_of = first_parts
+            .get(&(part.prefix.clone(), designator.number))
+            .map(|&(_, first)| first)
+            .filter(|&first| first != part.entity);
+    }
+
+    parts
+        .into_iter()
+        .zip(designators)
+        .filter(|(part, to)| part.from != *to)
+        .map(|(part, to)| {
+            (
+                CircuitID(part.circuit),
+                Edit::Designate {
+                    entity: part.entity,
+                    from: part.from,
+                    to,
+                },
+            )
+        })
+        .collect()
+}
+
+fn handle_annotate_events(world: &mut World) {
+    let events: Vec<_> = world
+        .resource_mut::<Events<AnnotateEvent>>()
+        .drain()
+        .collect();
+
+    for event in events {
+        let circuits = match event.circuit {
+            Some(circuit) => vec![circuit.0],
+            None => {
+                let mut circuits: Vec<_> = world
+                    .query_filtered::<(Entity, &Name), With<Circuit>>()
+                    .iter(world)
+                    .map(|(entity, name)| (name.0.clone(), entity))
+                    .collect();
+                circuits.sort();
+                circuits.into_iter().map(|(_, entity)| entity).collect()
+            }
+        };
+
+        let edits = annotation_edits(world, &circuits, event.options);
+
+        // Each circuit is undone in one step
+        for &circuit in circuits.iter() {
+            world.send_event(HistoryEvent::BeginGroup(CircuitID(circuit)));
+        }
+        for (circuit, edit) in edits {
+            world.send_event(HistoryEvent::Edit(circuit, edit));
+        }
+        for &circuit in circuits.iter() {
+            world.send_event(HistoryEvent::EndGroup(CircuitID(circuit)));
+        }
+    }
+}
+
+pub(crate) struct AnnotatePlugin;
+
+impl bevy_app::Plugin for AnnotatePlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.add_event::<AnnotateEvent>();
+        app.add_systems(
+            bevy_app::PostUpdate,
+            handle_annotate_events.before(HistorySet),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundles::CircuitBundle;
+    use crate::history::handle_history_events;
+    use crate::symbol::SymbolRegistry;
+
+    fn annotate(world: &mut World, circuit: Entity, options: AnnotateOptions) {
+        world.send_event(AnnotateEvent {
+            circuit: Some(CircuitID(circuit)),
+            options,
+        });
+        world.run_system_cached(handle_annotate_events).unwrap();
+        world.run_system_cached(handle_history_events).unwrap();
+    }
+
+    fn designator(world: &World, entity: Entity) -> String {
+        let number = world.get::<DesignatorNumber>(entity).unwrap().0;
+        let suffix = world
+            .get::<DesignatorSuffix>(entity)
+            .map(|suffix| suffix.0.to_string())
+            .unwrap_or_default();
+        format!(
+            "{}{number}{suffix}",
+            world.get::<DesignatorPrefix>(entity).unwrap().0
+        )
+    }
+
+    #[test]
+    fn numbers_symbols_by_prefix() {
+        let mut world = World::new();
+        world.init_resource::<Events<AnnotateEvent>>();
+        world.init_resource::<Events<HistoryEvent>>();
+        let registry = SymbolRegistry::default();
+
+        let circuit = world
+            .spawn((
+                CircuitBundle {
+                    circuit: Circuit,
+                    name: Name(SharedStr::new_static("annotate")),
+                },
+                crate::history::History::default(),
+            ))
+            .id();
+        let mut spawn = |kind, x: i16, y: i16, number| {
+            let mut builder = registry.get(kind);
+            builder
+                .position(Vec2 {
+                    x: crate::Fixed::from_i16(x),
+                    y: crate::Fixed::from_i16(y),
+                })
+                .designator_number(number);
+            builder.build(&mut world.commands(), circuit)
+        };
+        let nands = [
+            spawn(SymbolKind::Nand, 0, 0, 0),
+            spawn(SymbolKind::Nand, 0, 100, 0),
+            spawn(SymbolKind::Nand, 0, 200, 7),
+            spawn(SymbolKind::Nand, 0, 300, 0),
+            spawn(SymbolKind::Nand, 0, 400, 0),
+            spawn(SymbolKind::Nand, 0, 500, 0),
+        ];
+        let input = spawn(SymbolKind::In, -100, 0, 0);
+        let label = spawn(SymbolKind::Label, -100, 100, 0);
+        world.flush();
+
+        annotate(&mut world, circuit, AnnotateOptions::default());
+        let names = nands.map(|nand| designator(&world, nand));
+        assert_eq!(names, ["U1", "U2", "U7", "U3", "U4", "U5"]);
+        assert_eq!(designator(&world, input), "J1");
+        assert_eq!(designator(&world, label), "N0");
+
+        annotate(
+            &mut world,
+            circuit,
+            AnnotateOptions {
+                keep_existing: false,
+                multi_part: true,
+            },
+        );
+        let names = nands.map(|nand| designator(&world, nand));
+        assert_eq!(names, ["U1A", "U1B", "U1C", "U1D", "U2A", "U2B"]);
+
+        let mut part_of = world.query::<Edges<PartOf>>();
+        let mut first_part = |world: &World, entity| {
+            part_of
+                .get(world, entity)
+                .ok()
+                .and_then(|edges| edges.targets().first().copied())
+        };
+        assert_eq!(first_part(&world, nands[0]), None);
+        assert_eq!(first_part(&world, nands[3]), Some(nands[0]));
+        assert_eq!(first_part(&world, nands[5]), Some(nands[4]));
+    }
+}
@@ -3,8 +3,10 @@ use aery::prelude::*;
 use bevy_derive::{Deref, DerefMut};
 use bevy_ecs::prelude::*;
 use bevy_reflect::Reflect;
+use serde::{Deserialize, Serialize};
 use smallvec::{smallvec, SmallVec};
 use std::num::NonZeroU8;
+use std::ops::Range;
 use std::path::PathBuf;
 
 /////
@@ -15,6 +17,11 @@ use std::path::PathBuf;
 #[aery(Recursive)]
 pub struct Child;
 
+/// A Symbol that's one part of a multi-part component, like one gate of a
+/// quad NAND IC, is PartOf the first part of that component.
+#[derive(Debug, Relation)]
+pub struct PartOf;
+
 /////
 // Entity ID components
 /////
@@ -27,12 +34,35 @@ pub enum SymbolKind {
     And,
     Or,
     Xor,
+    Nand,
+    Nor,
+    Xnor,
     Not,
+    Buffer,
+    /// A buffer that only drives its output while its enable input is high
+    TriState,
     In,
     Out,
     Mux,
+    /// An instance of another Circuit, which the Symbol links to with a CircuitID
+    SubCircuit,
+    /// Drives its output with the value in its LogicState
+    Const,
+    /// A black box defined in a symbol library, which has no logic of its own.
+    /// The Symbol names its definition with a CustomKind.
+    Custom,
+    /// Connects its Net to the Nets of the other Labels in its Circuit that
+    /// have the same Name
+    Label,
+    /// Like a Label, but also connects to the GlobalLabels with the same Name
+    /// in the Circuits above and below it in the hierarchy
+    GlobalLabel,
 }
 
+/// The name of the symbol library definition a Custom Symbol was built from
+#[derive(Debug, Clone, Deref, Component, Reflect)]
+pub struct CustomKind(pub SharedStr);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Component, Reflect)]
 pub struct SymbolID(pub Entity);
 
@@ -53,7 +83,7 @@ pub struct CircuitID(pub Entity);
 /////
 
 /// The Shape of the Entity as an index into the Shapes Vello can draw
-#[derive(Default, Debug, Component, Clone, Copy, Reflect)]
+#[derive(Default, Debug, Component, Clone, Copy, Serialize, Deserialize, Reflect)]
 pub enum Shape {
     #[default]
     Chip,
@@ -63,6 +93,13 @@ pub enum Shape {
     Not,
     Input,
     Output,
+    Nand,
+    Nor,
+    Xnor,
+    Buffer,
+    TriState,
+    Label,
+    GlobalLabel,
 }
 
 /// A Name for the entity.
@@ -108,6 +145,23 @@ impl LogicState {
             bit_plane_1: smallvec![1],
         }
     }
+
+    /// A fully defined state holding the lowest `bit_width` bits of `value`.
+    pub fn from_value(value: u64, bit_width: BitWidth) -> Self {
+        let bit_width = bit_width.0.get() as usize;
+        let byte_count = bit_width.div_ceil(8);
+
+        let mut state = Self::default();
+        for i in 0..byte_count {
+            let bits_in_byte = (bit_width - i * 8).min(8);
+            let mask = (((1u16 << bits_in_byte) - 1) & 0xFF) as u8;
+            let byte = value.checked_shr((i * 8) as u32).unwrap_or(0) as u8;
+
+            state.bit_plane_0.push(byte & mask);
+            state.bit_plane_1.push(mask);
+        }
+        state
+    }
 }
 
 /// The list of bits that the entity uses in a Net. The order of the bits becomes
@@ -115,9 +169,35 @@ impl LogicState {
 /// a Net is 4 bits wide, and an entity uses bits 1, 3, and 0, then the entity
 /// will be presented with 3 bits, bit 0 being the Net's bit 1, bit 1 being the
 /// Net's bit 3, and bit 2 being the Net's bit 0.
-#[derive(Debug, Component, Reflect)]
+#[derive(Debug, Clone, Component, Reflect)]
 pub struct Bits(pub SmallVec<[u8; 8]>);
 
+impl Bits {
+    /// The `Bits` of an entity using `bits` of a net `bit_width` wide, or `None`
+    /// if those are all of the net's bits in order.
+    pub fn subset(bits: &[u8], bit_width: BitWidth) -> Option<Self> {
+        let uses_whole_net = bits.len() == bit_width.0.get() as usize
+            && bits.iter().enumerate().all(|(i, &bit)| i == bit as usize);
+        (!uses_whole_net).then(|| Self(bits.into()))
+    }
+
+    /// Splits the bits into runs of consecutive net bits, in the order the
+    /// entity uses them.
+    pub fn runs(&self) -> impl Iterator<Item = Range<u8>> + '_ {
+        let mut rest = &self.0[..];
+        std::iter::from_fn(move || {
+            let (&start, _) = rest.split_first()?;
+            let len = rest
+                .iter()
+                .zip(start..)
+                .take_while(|&(&bit, expected)| bit == expected)
+                .count();
+            rest = &rest[len..];
+            Some(start..start + len as u8)
+        })
+    }
+}
+
 /// The entity is an input
 #[derive(Default, Debug, Component, Reflect)]
 pub struct Input;
@@ -135,6 +215,11 @@ pub struct Selected;
 #[component(storage = "SparseSet")]
 pub struct Hovered;
 
+/// Whether the circuit has changes that haven't been saved to its file
+#[derive(Default, Debug, Component, Reflect)]
+#[component(storage = "SparseSet")]
+pub struct Modified;
+
 // Entity type tags
 
 /// A Port is a connection point for an Endpoint. For sub-Circuits,
@@ -169,3 +254,19 @@ pub struct Circuit;
 /// but defined here for other systems to use.
 #[derive(Default, Debug, Component, Reflect)]
 pub struct Viewport;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_split_into_runs() {
+        let bits = Bits(smallvec![4, 5, 6, 0, 2, 3]);
+        assert_eq!(bits.runs().collect::<Vec<_>>(), [4..7, 0..1, 2..4]);
+
+        let width = BitWidth(NonZeroU8::new(3).unwrap());
+        assert!(Bits::subset(&[0, 1, 2], width).is_none());
+        assert!(Bits::subset(&[0, 1], width).is_some());
+        assert!(Bits::subset(&[2, 1, 0], width).is_some());
+    }
+}
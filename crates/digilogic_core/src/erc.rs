@@ -0,0 +1,518 @@
+//! Electrical rule checks, which find mistakes in a circuit before it's
+//! simulated or exported.
+//!
+//! Checking a circuit attaches `ErcViolations` to the ports, nets and
+//! endpoints that break a rule, replacing the results of the last check.
+
+use crate::components::*;
+use crate::events::CheckCircuitEvent;
+use crate::history::HistorySet;
+use crate::labels::LabelNets;
+use crate::resources::Project;
+use crate::states::SimulationState;
+use crate::{HashMap, HashSet};
+use aery::edges::{EdgeInfo, Edges};
+use bevy_ecs::prelude::*;
+use bevy_reflect::Reflect;
+use bevy_state::prelude::*;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Reflect)]
+pub enum ErcViolation {
+    /// An input port that isn't connected to a net
+    UnconnectedInput,
+    /// A net with bits driven by more than one output, other than by
+    /// tri-state buffers sharing a bus
+    MultipleDrivers,
+    /// A net that no output drives
+    NoDriver,
+    /// A port that's a different width than the bits of the net it uses
+    WidthMismatch { port: u8, net: u8 },
+    /// A label connecting to another label with the same name that's
+    /// labelling a net of a different width
+    LabelWidthMismatch { label: u8, other: u8 },
+    /// An endpoint of a net that isn't attached to a port of that net
+    FloatingEndpoint,
+    /// A port of a gate that's both an input and an output
+    BidirectionalPort,
+    /// A port of a gate that's neither an input nor an output
+    PortWithoutDirection,
+    /// An output of a gate that has more than one
+    MultipleOutputs,
+}
+
+impl ErcViolation {
+    /// Whether the violation keeps the circuit from being simulated, the
+    /// others are only warnings.
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Self::UnconnectedInput
+                | Self::WidthMismatch { .. }
+                | Self::LabelWidthMismatch { .. }
+                | Self::BidirectionalPort
+                | Self::PortWithoutDirection
+                | Self::MultipleOutputs
+        )
+    }
+}
+
+impl fmt::Display for ErcViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnconnectedInput => f.write_str("unconnected input"),
+            Self::MultipleDrivers => f.write_str("driven by several outputs"),
+            Self::NoDriver => f.write_str("not driven by any output"),
+            Self::WidthMismatch { port, net } => {
+                write!(f, "{port} bit port connected to {net} bits")
+            }
+            Self::LabelWidthMismatch { label, other } => {
+                write!(f, "{label} bit label has the name of a {other} bit label")
+            }
+            Self::FloatingEndpoint => f.write_str("wire end not attached to a port"),
+            Self::BidirectionalPort => f.write_str("gate port is both an input and an output"),
+            Self::PortWithoutDirection => {
+                f.write_str("gate port is neither an input nor an output")
+            }
+            Self::MultipleOutputs => f.write_str("gate has more than one output"),
+        }
+    }
+}
+
+/// The rules the entity broke when its circuit was last checked
+#[derive(Debug, Default, Clone, Component, Reflect)]
+pub struct ErcViolations(pub Vec<ErcViolation>);
+
+/// How many violations the last check of a circuit found
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component, Reflect)]
+pub struct ErcReport {
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+/// Runs after checks requested by `CheckCircuitEvent`, and before the
+/// simulation is built from the checked root circuit.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ErcSet;
+
+fn children_of(
+    world: &World,
+    children: &mut QueryState<Edges<Child>>,
+    entity: Entity,
+) -> Vec<Entity> {
+    children
+        .get(world, entity)
+        .map(|edges| edges.hosts().to_vec())
+        .unwrap_or_default()
+}
+
+/// Whether the simulation builds a cell for symbols of `kind`, with one output
+/// and inputs for its other ports
+fn is_gate(kind: SymbolKind) -> bool {
+    !matches!(
+        kind,
+        SymbolKind::In
+            | SymbolKind::Out
+            | SymbolKind::Const
+            | SymbolKind::SubCircuit
+            | SymbolKind::Custom
+            | SymbolKind::Label
+            | SymbolKind::GlobalLabel
+    )
+}
+
+struct PortInfo {
+    symbol_kind: SymbolKind,
+    input: bool,
+    output: bool,
+    bit_width: BitWidth,
+    net: Option<Entity>,
+}
+
+/// Checks `circuit`, replacing the `ErcViolations` of its entities and its
+/// `ErcReport`. Returns every violation found.
+pub fn check_circuit(world: &mut World, circuit: Entity) -> Vec<(Entity, ErcViolation)> {
+    let mut children = world.query::<Edges<Child>>();
+
+    let mut ports = HashMap::<Entity, PortInfo>::default();
+    let mut labels = Vec::new();
+    let mut nets = Vec::new();
+    let mut endpoints = Vec::new();
+    let mut checked = Vec::new();
+    let mut violations = Vec::new();
+    for child in children_of(world, &mut children, circuit) {
+        if world.get::<Net>(child).is_some() {
+            let Some(&bit_width) = world.get::<BitWidth>(child) else {
+                continue;
+            };
+
+            nets.push((child, bit_width));
+            for endpoint in children_of(world, &mut children, child) {
+                if world.get::<Endpoint>(endpoint).is_some() {
+                    endpoints.push((endpoint, child));
+                    checked.push(endpoint);
+                }
+            }
+        } else if let Some(&symbol_kind) = world.get::<SymbolKind>(child) {
+            let mut gate_outputs = Vec::new();
+            for port in children_of(world, &mut children, child) {
+                let Some(&bit_width) = world.get::<BitWidth>(port) else {
+                    continue;
+                };
+
+                let net = world.get::<NetID>(port).map(|net| net.0);
+                let input = world.get::<Input>(port).is_some();
+                let output = world.get::<Output>(port).is_some();
+                ports.insert(
+                    port,
+                    PortInfo {
+                        symbol_kind,
+                        input,
+                        output,
+                        bit_width,
+                        net,
+                    },
+                );
+                checked.push(port);
+
+                // The simulation builds a cell with one output for every gate
+                if is_gate(symbol_kind) {
+                    match (input, output) {
+                        (true, true) => violations.push((port, ErcViolation::BidirectionalPort)),
+                        (false, true) => gate_outputs.push(port),
+                        (true, false) => {}
+                        (false, false) => {
+                            violations.push((port, ErcViolation::PortWithoutDirection))
+                        }
+                    }
+                }
+
+                let global = match symbol_kind {
+                    SymbolKind::Label => false,
+                    SymbolKind::GlobalLabel => true,
+                    _ => continue,
+                };
+                if let (Some(net), Some(name)) = (net, world.get::<Name>(child)) {
+                    labels.push((port, name.0.clone(), global, net));
+                }
+            }
+
+            if gate_outputs.len() > 1 {
+                for port in gate_outputs {
+                    violations.push((port, ErcViolation::MultipleOutputs));
+                }
+            }
+        }
+        checked.push(child);
+    }
+
+    let net_widths: HashMap<_, _> = nets.iter().copied().collect();
+
+    // The bits of its net each port uses, all of them if it has no Bits
+    let mut port_bits = HashMap::<Entity, Vec<u8>>::default();
+    for &(endpoint, net) in endpoints.iter() {
+        let port = world.get::<PortID>(endpoint).map(|port| port.0);
+        match port.and_then(|port| ports.get(&port).map(|info| (port, info))) {
+            Some((port, info)) if info.net == Some(net) => {
+                let bits = match world.get::<Bits>(endpoint) {
+                    Some(bits) => bits.0.to_vec(),
+                    None => (0..net_widths[&net].0.get()).collect(),
+                };
+                port_bits.insert(port, bits);
+            }
+            _ => violations.push((endpoint, ErcViolation::FloatingEndpoint)),
+        }
+    }
+
+    let mut label_nets = LabelNets::default();
+    let mut global_nets = HashSet::default();
+    for (port, name, global, net) in labels {
+        let Some(&bit_width) = net_widths.get(&net) else {
+            continue;
+        };
+        if let Err(err) = label_nets.add(&name, global, net, bit_width) {
+            violations.push((
+                port,
+                ErcViolation::LabelWidthMismatch {
+                    label: err.found.0.get(),
+                    other: err.expected.0.get(),
+                },
+            ));
+        }
+        if global {
+            global_nets.insert(net);
+        }
+    }
+
+    // Bit drivers of each logical net, and whether they're all tri-state
+    let mut drivers = HashMap::<Entity, HashMap<u8, (usize, bool)>>::default();
+    for (&port, info) in ports.iter() {
+        let Some(net) = info.net.filter(|net| net_widths.contains_key(net)) else {
+            if info.input && !info.output {
+                violations.push((port, ErcViolation::UnconnectedInput));
+            }
+            continue;
+        };
+
+        let Some(bits) = port_bits.get(&port) else {
+            continue;
+        };
+
+        // Labels take on the width of the net they're attached to
+        let is_label = matches!(
+            info.symbol_kind,
+            SymbolKind::Label | SymbolKind::GlobalLabel
+        );
+        if !is_label && (bits.len() != info.bit_width.0.get() as usize) {
+            violations.push((
+                port,
+                ErcViolation::WidthMismatch {
+                    port: info.bit_width.0.get(),
+                    net: bits.len() as u8,
+                },
+            ));
+        }
+
+        if info.output {
+            // Bidirectional ports only drive the net some of the time, like tri-states
+            let shared = info.input || (info.symbol_kind == SymbolKind::TriState);
+            let net_drivers = drivers.entry(label_nets.resolve(net)).or_default();
+            for &bit in bits.iter() {
+                let (count, all_shared) = net_drivers.entry(bit).or_insert((0, true));
+                *count += 1;
+                *all_shared &= shared;
+            }
+        }
+    }
+
+    let mut driven_globally = HashSet::default();
+    for &net in global_nets.iter() {
+        driven_globally.insert(label_nets.resolve(net));
+    }
+    for &(net, _) in nets.iter() {
+        let root = label_nets.resolve(net);
+        match drivers.get(&root) {
+            Some(bits)
+                if bits
+                    .values()
+                    .any(|&(count, all_shared)| (count > 1) && !all_shared) =>
+            {
+                violations.push((net, ErcViolation::MultipleDrivers));
+            }
+            Some(_) => (),
+            // Global labels can be driven from another circuit
+            None if !driven_globally.contains(&root) => {
+                violations.push((net, ErcViolation::NoDriver));
+            }
+            None => (),
+        }
+    }
+
+    for entity in checked {
+        world.entity_mut(entity).remove::<ErcViolations>();
+    }
+    let mut by_entity = HashMap::<Entity, Vec<ErcViolation>>::default();
+    for (entity, violation) in violations.iter() {
+        by_entity
+            .entry(*entity)
+            .or_default()
+            .push(violation.clone());
+    }
+    for (entity, entity_violations) in by_entity {
+        world
+            .entity_mut(entity)
+            .insert(ErcViolations(entity_violations));
+    }
+
+    let errors = violations
+        .iter()
+        .filter(|(_, violation)| violation.is_error())
+        .count();
+    world.entity_mut(circuit).insert(ErcReport {
+        errors,
+        warnings: violations.len() - errors,
+    });
+
+    violations
+}
+
+fn handle_check_events(world: &mut World) {
+    let events: Vec<_> = world
+        .resource_mut::<Events<CheckCircuitEvent>>()
+        .drain()
+        .collect();
+
+    for event in events {
+        check_circuit(world, event.circuit.0);
+    }
+}
+
+/// The simulation is built from the root circuit and the circuits it
+/// instantiates, which all have to pass first.
+fn check_root_circuit(world: &mut World) {
+    let root_circuit = world
+        .get_resource::<Project>()
+        .and_then(|project| project.root_circuit);
+    let Some(root_circuit) = root_circuit else {
+        return;
+    };
+
+    let mut children = world.query::<Edges<Child>>();
+    let mut checked = HashSet::default();
+    let mut stack = vec![root_circuit.0];
+    while let Some(circuit) = stack.pop() {
+        if !checked.insert(circuit) {
+            continue;
+        }
+
+        check_circuit(world, circuit);
+        for symbol in children_of(world, &mut children, circuit) {
+            if let Some(&CircuitID(sub_circuit)) = world.get::<CircuitID>(symbol) {
+                stack.push(sub_circuit);
+            }
+        }
+    }
+}
+
+pub(crate) struct ErcPlugin;
+
+impl bevy_app::Plugin for ErcPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.register_type::<ErcViolation>()
+            .register_type::<ErcViolations>()
+            .register_type::<ErcReport>();
+        app.add_event::<CheckCircuitEvent>();
+        app.add_systems(
+            bevy_app::PostUpdate,
+            handle_check_events.in_set(ErcSet).after(HistorySet),
+        );
+        app.add_systems(
+            OnEnter(SimulationState::Building),
+            check_root_circuit.in_set(ErcSet),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundles::{CircuitBundle, NetBundle};
+    use crate::symbol::SymbolRegistry;
+    use crate::SharedStr;
+    use aery::prelude::*;
+    use std::num::NonZeroU8;
+
+    fn connect(world: &mut World, net: Entity, port: Entity) {
+        world.spawn((Endpoint, PortID(port))).set::<Child>(net);
+        world.entity_mut(port).insert(NetID(net));
+        world.flush();
+    }
+
+    #[test]
+    fn finds_violations() {
+        let mut world = World::new();
+        let registry = SymbolRegistry::default();
+        let circuit = world
+            .spawn(CircuitBundle {
+                circuit: Circuit,
+                name: Name(SharedStr::new_static("erc")),
+            })
+            .id();
+
+        let mut and = registry.get(SymbolKind::And);
+        and.build(&mut world.commands(), circuit);
+        let mut first = registry.get(SymbolKind::In);
+        first.build(&mut world.commands(), circuit);
+        let mut second = registry.get(SymbolKind::In);
+        second.build(&mut world.commands(), circuit);
+        world.flush();
+
+        let net = world
+            .spawn(NetBundle {
+                net: Net,
+                name: Default::default(),
+                bit_width: BitWidth(NonZeroU8::MIN),
+                visibility: Default::default(),
+            })
+            .set::<Child>(circuit)
+            .id();
+        let wide = world
+            .spawn(NetBundle {
+                net: Net,
+                name: Default::default(),
+                bit_width: BitWidth(NonZeroU8::new(2).unwrap()),
+                visibility: Default::default(),
+            })
+            .set::<Child>(circuit)
+            .id();
+        let floating = world
+            .spawn(NetBundle {
+                net: Net,
+                name: Default::default(),
+                bit_width: BitWidth(NonZeroU8::MIN),
+                visibility: Default::default(),
+            })
+            .set::<Child>(circuit)
+            .id();
+        world.flush();
+
+        // Both inputs drive the first input of the gate, its second input is
+        // left unconnected and its output is connected to a wider net.
+        let [a, b, y] = [0, 1, 2].map(|i| and.ports()[i].id);
+        connect(&mut world, net, first.ports()[0].id);
+        connect(&mut world, net, second.ports()[0].id);
+        connect(&mut world, net, a);
+        connect(&mut world, wide, y);
+        let endpoint = world.spawn(Endpoint).set::<Child>(floating).id();
+        world.flush();
+
+        let violations = check_circuit(&mut world, circuit);
+        let has = |entity: Entity, violation: ErcViolation| {
+            violations.contains(&(entity, violation.clone()))
+                && world
+                    .get::<ErcViolations>(entity)
+                    .is_some_and(|found| found.0.contains(&violation))
+        };
+
+        assert!(has(b, ErcViolation::UnconnectedInput));
+        assert!(has(net, ErcViolation::MultipleDrivers));
+        assert!(has(y, ErcViolation::WidthMismatch { port: 1, net: 2 }));
+        assert!(!has(wide, ErcViolation::NoDriver));
+        assert!(has(floating, ErcViolation::NoDriver));
+        assert!(has(endpoint, ErcViolation::FloatingEndpoint));
+        assert_eq!(world.get::<ErcReport>(circuit).unwrap().errors, 2);
+    }
+
+    #[test]
+    fn finds_gates_that_cant_be_simulated() {
+        let mut world = World::new();
+        let registry = SymbolRegistry::default();
+        let circuit = world
+            .spawn(CircuitBundle {
+                circuit: Circuit,
+                name: Name(SharedStr::new_static("erc")),
+            })
+            .id();
+
+        let mut and = registry.get(SymbolKind::And);
+        let symbol = and.build(&mut world.commands(), circuit);
+        world.flush();
+
+        // The first input also drives, the second has no direction and the
+        // output is one of two
+        let [a, b, y] = [0, 1, 2].map(|i| and.ports()[i].id);
+        world.entity_mut(a).insert(Output);
+        world.entity_mut(b).remove::<Input>();
+        let z = world
+            .spawn((Port, Output, BitWidth(NonZeroU8::MIN)))
+            .set::<Child>(symbol)
+            .id();
+        world.flush();
+
+        let violations = check_circuit(&mut world, circuit);
+        assert!(violations.contains(&(a, ErcViolation::BidirectionalPort)));
+        assert!(violations.contains(&(b, ErcViolation::PortWithoutDirection)));
+        assert!(violations.contains(&(y, ErcViolation::MultipleOutputs)));
+        assert!(violations.contains(&(z, ErcViolation::MultipleOutputs)));
+    }
+}
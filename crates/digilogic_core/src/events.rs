@@ -1,4 +1,6 @@
+use crate::annotate::AnnotateOptions;
 use crate::components::CircuitID;
+use crate::transform::Vec2;
 use bevy_ecs::prelude::*;
 use std::path::PathBuf;
 
@@ -20,6 +22,37 @@ pub struct CircuitLoadedEvent {
     pub circuit: CircuitID,
 }
 
+/// Writes a circuit (and any sub-circuits it uses) to a file, in the format
+/// implied by the file extension.
+#[derive(Debug, Event)]
+pub struct CircuitSaveEvent {
+    pub circuit: CircuitID,
+    pub filename: PathBuf,
+}
+
+/// Runs the electrical rule check on a circuit, see `erc::check_circuit`.
+#[derive(Debug, Event)]
+pub struct CheckCircuitEvent {
+    pub circuit: CircuitID,
+}
+
+/// Numbers the symbols of a circuit, or of every circuit in the project if
+/// `circuit` is `None`, see `annotate::annotation_edits`.
+#[derive(Debug, Event)]
+pub struct AnnotateEvent {
+    pub circuit: Option<CircuitID>,
+    pub options: AnnotateOptions,
+}
+
+/// Places an instance of `sub_circuit` in `circuit`, unless `sub_circuit`
+/// contains `circuit` itself.
+#[derive(Debug, Event)]
+pub struct PlaceSubCircuitEvent {
+    pub circuit: CircuitID,
+    pub sub_circuit: CircuitID,
+    pub position: Vec2,
+}
+
 // TODO: fixme
 // #[derive(Event)]
 // pub struct ErrorEvent {
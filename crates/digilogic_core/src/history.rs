@@ -0,0 +1,611 @@
+//! Reversible edits to circuits, with an undo/redo history per circuit.
+//!
+//! Every change to a circuit goes through a `HistoryEvent::Edit`, which
+//! applies the edit and records it in the `History` of the circuit, so it can
+//! be undone and redone later. Edits made between `BeginGroup` and `EndGroup`,
+//! like the moves of a drag, are undone in one step.
+
+use crate::annotate::Designator;
+use crate::components::*;
+use crate::transform::{GlobalTransform, InheritTransform, Transform, TransformSet};
+use crate::SharedStr;
+use aery::edges::{EdgeInfo, Edges};
+use aery::prelude::*;
+use bevy_ecs::prelude::*;
+
+/// How many steps a circuit can be undone at most
+const MAX_UNDO_STEPS: usize = 100;
+
+/// A reversible change to a circuit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// Moves or rotates a symbol
+    Move {
+        entity: Entity,
+        from: Transform,
+        to: Transform,
+    },
+    /// Renames a circuit, symbol or net
+    Rename {
+        entity: Entity,
+        from: SharedStr,
+        to: SharedStr,
+    },
+    /// Changes the value a symbol drives, e.g. of a constant
+    SetValue { entity: Entity, from: u64, to: u64 },
+    /// Changes the reference designator number, suffix or first part of a
+    /// symbol
+    Designate {
+        entity: Entity,
+        from: Designator,
+        to: Designator,
+    },
+    /// Adds a symbol or net to a circuit, a port to a symbol along with the
+    /// endpoints it was connected to, or connects an endpoint to a net along
+    /// with the port it was connected to
+    Attach { entity: Entity, parent: Entity },
+    /// Removes a symbol or net from a circuit or a port from a symbol, or
+    /// disconnects an endpoint from a net and its port. The entity is kept
+    /// around until the edit can't be undone anymore.
+    Detach { entity: Entity, parent: Entity },
+}
+
+impl Edit {
+    /// The inverse of this edit.
+    fn reversed(&self) -> Self {
+        match self.clone() {
+            Edit::Move { entity, from, to } => Edit::Move {
+                entity,
+                from: to,
+                to: from,
+            },
+            Edit::Rename { entity, from, to } => Edit::Rename {
+                entity,
+                from: to,
+                to: from,
+            },
+            Edit::SetValue { entity, from, to } => Edit::SetValue {
+                entity,
+                from: to,
+                to: from,
+            },
+            Edit::Designate { entity, from, to } => Edit::Designate {
+                entity,
+                from: to,
+                to: from,
+            },
+            Edit::Attach { entity, parent } => Edit::Detach { entity, parent },
+            Edit::Detach { entity, parent } => Edit::Attach { entity, parent },
+        }
+    }
+
+    fn apply(&self, world: &mut World) {
+        match *self {
+            Edit::Move { entity, to, .. } => {
+                if let Some(mut transform) = world.get_mut::<Transform>(entity) {
+                    *transform = to;
+                }
+            }
+            Edit::Rename { entity, ref to, .. } => {
+                if let Ok(mut entity) = world.get_entity_mut(entity) {
+                    entity.insert(Name(to.clone()));
+                }
+            }
+            Edit::SetValue { entity, to, .. } => {
+                let Some(&bit_width) = world.get::<BitWidth>(entity) else {
+                    return;
+                };
+                if let Some(mut state) = world.get_mut::<LogicState>(entity) {
+                    *state = LogicState::from_value(to, bit_width);
+                }
+            }
+            Edit::Designate { entity, ref to, .. } => {
+                if let Ok(mut entity) = world.get_entity_mut(entity) {
+                    to.apply(&mut entity);
+                }
+            }
+            Edit::Attach { entity, parent } => {
+                if world.get_entity(entity).is_err() || world.get_entity(parent).is_err() {
+                    return;
+                }
+                world.entity_mut(entity).set::<Child>(parent);
+                connect_port(world, entity, parent);
+                connect_endpoints(world, entity);
+                world.trigger_targets(Attached { parent }, entity);
+            }
+            Edit::Detach { entity, parent } => {
+                if world.get_entity(entity).is_err() || world.get_entity(parent).is_err() {
+                    return;
+                }
+                // Observers still see where the entity was, so they can forget it
+                world.trigger_targets(Detached { parent }, entity);
+                disconnect_port(world, entity, parent);
+                disconnect_endpoints(world, entity);
+                world.entity_mut(entity).unset::<Child>(parent);
+            }
+        }
+    }
+
+    /// Cleans up after an edit that was dropped from the history. Detached
+    /// entities that can't be attached again are despawned.
+    fn discard(&self, world: &mut World, applied: bool) {
+        let entity = match *self {
+            Edit::Detach { entity, .. } if applied => entity,
+            Edit::Attach { entity, .. } if !applied => entity,
+            _ => return,
+        };
+        if world.get_entity(entity).is_ok() {
+            world.despawn(entity);
+        }
+    }
+
+    /// Folds `next` into this edit if both change the same thing, keeping
+    /// where this edit started from.
+    fn merge(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (
+                Edit::Move { entity, to, .. },
+                Edit::Move {
+                    entity: e, to: t, ..
+                },
+            ) if entity == e => {
+                *to = *t;
+                true
+            }
+            (
+                Edit::Rename { entity, to, .. },
+                Edit::Rename {
+                    entity: e, to: t, ..
+                },
+            ) if entity == e => {
+                *to = t.clone();
+                true
+            }
+            (
+                Edit::SetValue { entity, to, .. },
+                Edit::SetValue {
+                    entity: e, to: t, ..
+                },
+            ) if entity == e => {
+                *to = *t;
+                true
+            }
+            (
+                Edit::Designate { entity, to, .. },
+                Edit::Designate {
+                    entity: e, to: t, ..
+                },
+            ) if entity == e => {
+                *to = t.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The port a detached endpoint was connected to, so attaching the endpoint
+/// again connects it to the same port.
+#[derive(Debug, Component)]
+struct DetachedPort(Entity);
+
+/// Connects an endpoint attached to `net` to the port it was detached from.
+fn connect_port(world: &mut World, endpoint: Entity, net: Entity) {
+    let Some(DetachedPort(port)) = world.entity_mut(endpoint).take::<DetachedPort>() else {
+        return;
+    };
+    if world.get_entity(port).is_err() {
+        return;
+    }
+
+    world
+        .entity_mut(endpoint)
+        .insert(PortID(port))
+        .set::<InheritTransform>(port);
+    world.entity_mut(port).insert(NetID(net));
+}
+
+/// Disconnects an endpoint detached from `net` from its port, remembering
+/// the port in case the endpoint is attached again.
+fn disconnect_port(world: &mut World, endpoint: Entity, net: Entity) {
+    let Some(PortID(port)) = world.entity_mut(endpoint).take::<PortID>() else {
+        return;
+    };
+
+    let mut endpoint = world.entity_mut(endpoint);
+    endpoint.insert(DetachedPort(port));
+    if endpoint.world().get_entity(port).is_err() {
+        return;
+    }
+    endpoint.unset::<InheritTransform>(port);
+
+    if world.get::<NetID>(port) == Some(&NetID(net)) {
+        world.entity_mut(port).remove::<NetID>();
+    }
+}
+
+/// The endpoints a detached port was connected to, so attaching the port
+/// again connects them to it again.
+#[derive(Debug, Component)]
+struct DetachedEndpoints(Vec<Entity>);
+
+/// Connects an attached port to the endpoints it was detached from, unless
+/// they were connected to another port since.
+fn connect_endpoints(world: &mut World, port: Entity) {
+    let Some(DetachedEndpoints(endpoints)) = world.entity_mut(port).take::<DetachedEndpoints>()
+    else {
+        return;
+    };
+
+    let mut nets = world.query::<Edges<Child>>();
+    for endpoint in endpoints {
+        if world.get_entity(endpoint).is_err() || world.get::<PortID>(endpoint).is_some() {
+            continue;
+        }
+        let net = nets
+            .get(world, endpoint)
+            .ok()
+            .and_then(|edges| edges.targets().first().copied());
+
+        world
+            .entity_mut(endpoint)
+            .insert((PortID(port), Transform::default()))
+            .set::<InheritTransform>(port);
+        if let Some(net) = net {
+            world.entity_mut(port).insert(NetID(net));
+        }
+    }
+}
+
+/// Disconnects the endpoints of a detached port, leaving them where the port
+/// was, and remembers them in case the port is attached again.
+fn disconnect_endpoints(world: &mut World, port: Entity) {
+    if world.get::<Port>(port).is_none() {
+        return;
+    }
+
+    let endpoints: Vec<_> = world
+        .query_filtered::<(Entity, &PortID), With<Endpoint>>()
+        .iter(world)
+        .filter(|&(_, &PortID(connected))| connected == port)
+        .map(|(endpoint, _)| endpoint)
+        .collect();
+
+    for &endpoint in endpoints.iter() {
+        let position = world
+            .get::<GlobalTransform>(endpoint)
+            .map(|global_transform| **global_transform)
+            .unwrap_or_default();
+
+        world
+            .entity_mut(endpoint)
+            .remove::<PortID>()
+            .unset::<InheritTransform>(port)
+            .insert(position);
+    }
+
+    world
+        .entity_mut(port)
+        .remove::<NetID>()
+        .insert(DetachedEndpoints(endpoints));
+}
+
+/// Triggered on an entity after an edit attached it to `parent`.
+#[derive(Debug, Clone, Copy, Event)]
+pub struct Attached {
+    pub parent: Entity,
+}
+
+/// Triggered on an entity right before an edit detaches it from `parent`.
+#[derive(Debug, Clone, Copy, Event)]
+pub struct Detached {
+    pub parent: Entity,
+}
+
+/// The edits made to a circuit, for undo and redo. Added to every circuit.
+#[derive(Debug, Default, Component)]
+pub struct History {
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    /// How many groups are open
+    group_depth: usize,
+    /// Whether the open group already has a step on the undo stack
+    group_step: bool,
+}
+
+impl History {
+    #[inline]
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    #[inline]
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Records an edit that was applied. Returns the steps that were dropped,
+    /// with whether they are applied.
+    fn record(&mut self, edit: Edit) -> Vec<(Vec<Edit>, bool)> {
+        let mut dropped: Vec<_> = self.redo.drain(..).map(|step| (step, false)).collect();
+
+        if self.group_depth > 0 && self.group_step {
+            let step = self.undo.last_mut().expect("group step missing");
+            if !step.iter_mut().any(|last| last.merge(&edit)) {
+                step.push(edit);
+            }
+        } else {
+            self.undo.push(vec![edit]);
+            self.group_step = self.group_depth > 0;
+
+            if self.undo.len() > MAX_UNDO_STEPS {
+                dropped.push((self.undo.remove(0), true));
+            }
+        }
+
+        dropped
+    }
+
+    fn end_groups(&mut self) {
+        self.group_depth = 0;
+        self.group_step = false;
+    }
+}
+
+/// Edits circuits or moves through their history.
+#[derive(Debug, Event)]
+pub enum HistoryEvent {
+    /// Applies an edit to a circuit and records it
+    Edit(CircuitID, Edit),
+    /// Starts recording edits to a circuit as a single step. Groups can be
+    /// nested, the step ends with the outermost group.
+    BeginGroup(CircuitID),
+    EndGroup(CircuitID),
+    Undo(CircuitID),
+    Redo(CircuitID),
+}
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HistorySet;
+
+fn inject_history(trigger: Trigger<OnAdd, Circuit>, mut commands: Commands) {
+    commands.entity(trigger.entity()).insert(History::default());
+}
+
+pub(crate) fn handle_history_events(world: &mut World) {
+    let events: Vec<_> = world
+        .resource_mut::<Events<HistoryEvent>>()
+        .drain()
+        .collect();
+
+    for ev in events {
+        let circuit = match ev {
+            HistoryEvent::Edit(circuit, _)
+            | HistoryEvent::BeginGroup(circuit)
+            | HistoryEvent::EndGroup(circuit)
+            | HistoryEvent::Undo(circuit)
+            | HistoryEvent::Redo(circuit) => circuit.0,
+        };
+        let Some(mut history) = world.get_mut::<History>(circuit) else {
+            continue;
+        };
+
+        match ev {
+            HistoryEvent::Edit(_, edit) => {
+                let dropped = history.record(edit.clone());
+                edit.apply(world);
+                for (step, applied) in dropped {
+                    for edit in step.iter().rev() {
+                        edit.discard(world, applied);
+                    }
+                }
+            }
+            HistoryEvent::BeginGroup(_) => {
+                history.group_depth += 1;
+                if history.group_depth == 1 {
+                    history.group_step = false;
+                }
+                continue;
+            }
+            HistoryEvent::EndGroup(_) => {
+                history.group_depth = history.group_depth.saturating_sub(1);
+                continue;
+            }
+            HistoryEvent::Undo(_) => {
+                history.end_groups();
+                let Some(step) = history.undo.pop() else {
+                    continue;
+                };
+                for edit in step.iter().rev() {
+                    edit.reversed().apply(world);
+                }
+                world.get_mut::<History>(circuit).unwrap().redo.push(step);
+            }
+            HistoryEvent::Redo(_) => {
+                history.end_groups();
+                let Some(step) = history.redo.pop() else {
+                    continue;
+                };
+                for edit in step.iter() {
+                    edit.apply(world);
+                }
+                world.get_mut::<History>(circuit).unwrap().undo.push(step);
+            }
+        }
+
+        world.entity_mut(circuit).insert(Modified);
+    }
+}
+
+pub(crate) struct HistoryPlugin;
+
+impl bevy_app::Plugin for HistoryPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.add_event::<HistoryEvent>();
+        app.add_observer(inject_history);
+        app.add_systems(
+            bevy_app::PostUpdate,
+            handle_history_events
+                .in_set(HistorySet)
+                .before(TransformSet),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Fixed;
+
+    fn transform(x: i16) -> Transform {
+        let mut transform = Transform::default();
+        transform.translation.x = Fixed::from_i16(x);
+        transform
+    }
+
+    fn send(world: &mut World, ev: HistoryEvent) {
+        world.send_event(ev);
+        world.run_system_cached(handle_history_events).unwrap();
+    }
+
+    #[test]
+    fn drags_undo_in_one_step() {
+        let mut world = World::new();
+        world.init_resource::<Events<HistoryEvent>>();
+        world.add_observer(inject_history);
+        let circuit = CircuitID(world.spawn(Circuit).id());
+        let symbol = world.spawn(transform(0)).id();
+
+        let move_to = |from, to| Edit::Move {
+            entity: symbol,
+            from: transform(from),
+            to: transform(to),
+        };
+        let x = |world: &World| world.get::<Transform>(symbol).unwrap().translation.x;
+
+        send(&mut world, HistoryEvent::Edit(circuit, move_to(0, 10)));
+        send(&mut world, HistoryEvent::BeginGroup(circuit));
+        for to in [20, 30, 40] {
+            send(
+                &mut world,
+                HistoryEvent::Edit(circuit, move_to(to - 10, to)),
+            );
+        }
+        send(&mut world, HistoryEvent::EndGroup(circuit));
+        assert_eq!(x(&world), Fixed::from_i16(40));
+
+        send(&mut world, HistoryEvent::Undo(circuit));
+        assert_eq!(x(&world), Fixed::from_i16(10));
+        send(&mut world, HistoryEvent::Undo(circuit));
+        assert_eq!(x(&world), Fixed::from_i16(0));
+        send(&mut world, HistoryEvent::Redo(circuit));
+        send(&mut world, HistoryEvent::Redo(circuit));
+        assert_eq!(x(&world), Fixed::from_i16(40));
+
+        // A new edit can't be followed by an older redo
+        send(&mut world, HistoryEvent::Undo(circuit));
+        send(&mut world, HistoryEvent::Edit(circuit, move_to(10, 50)));
+        let history = world.get::<History>(circuit.0).unwrap();
+        assert!(history.can_undo() && !history.can_redo());
+        assert!(world.get::<Modified>(circuit.0).is_some());
+    }
+
+    /// A circuit with a net whose endpoint is connected to a port
+    fn connected_endpoint(world: &mut World) -> (CircuitID, Entity, Entity, Entity) {
+        world.init_resource::<Events<HistoryEvent>>();
+        world.add_observer(inject_history);
+        let circuit = world.spawn(Circuit).id();
+        let net = world.spawn(Net).set::<Child>(circuit).id();
+        let port = world.spawn((Port, NetID(net))).id();
+        let endpoint = world
+            .spawn((Endpoint, PortID(port)))
+            .set::<Child>(net)
+            .set::<InheritTransform>(port)
+            .id();
+        world.flush();
+        (CircuitID(circuit), net, port, endpoint)
+    }
+
+    fn inherits_from(world: &mut World, endpoint: Entity) -> Vec<Entity> {
+        let mut edges = world.query::<Edges<InheritTransform>>();
+        edges
+            .get(world, endpoint)
+            .map(|edges| edges.targets().to_vec())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn detaching_endpoints_disconnects_their_port() {
+        let mut world = World::new();
+        let (circuit, net, port, endpoint) = connected_endpoint(&mut world);
+        let detach = Edit::Detach {
+            entity: endpoint,
+            parent: net,
+        };
+
+        send(&mut world, HistoryEvent::Edit(circuit, detach));
+        assert_eq!(world.get::<NetID>(port), None);
+        assert_eq!(world.get::<PortID>(endpoint), None);
+        assert!(inherits_from(&mut world, endpoint).is_empty());
+
+        send(&mut world, HistoryEvent::Undo(circuit));
+        assert_eq!(world.get::<NetID>(port), Some(&NetID(net)));
+        assert_eq!(world.get::<PortID>(endpoint), Some(&PortID(port)));
+        assert_eq!(inherits_from(&mut world, endpoint), [port]);
+
+        send(&mut world, HistoryEvent::Redo(circuit));
+        assert_eq!(world.get::<NetID>(port), None);
+        assert!(inherits_from(&mut world, endpoint).is_empty());
+    }
+
+    #[test]
+    fn detaching_ports_disconnects_their_endpoints() {
+        let mut world = World::new();
+        let (circuit, net, port, endpoint) = connected_endpoint(&mut world);
+        let symbol = world.spawn(Symbol).set::<Child>(circuit.0).id();
+        world.entity_mut(port).set::<Child>(symbol);
+        world.flush();
+        let detach = Edit::Detach {
+            entity: port,
+            parent: symbol,
+        };
+
+        send(&mut world, HistoryEvent::Edit(circuit, detach));
+        assert_eq!(world.get::<NetID>(port), None);
+        assert_eq!(world.get::<PortID>(endpoint), None);
+        assert!(inherits_from(&mut world, endpoint).is_empty());
+
+        send(&mut world, HistoryEvent::Undo(circuit));
+        assert_eq!(world.get::<NetID>(port), Some(&NetID(net)));
+        assert_eq!(world.get::<PortID>(endpoint), Some(&PortID(port)));
+        assert_eq!(inherits_from(&mut world, endpoint), [port]);
+    }
+
+    #[test]
+    fn despawns_detached_entities_that_cant_be_undone() {
+        let mut world = World::new();
+        let (circuit, net, _, endpoint) = connected_endpoint(&mut world);
+        let symbol = world.spawn(transform(0)).id();
+
+        send(
+            &mut world,
+            HistoryEvent::Edit(
+                circuit,
+                Edit::Detach {
+                    entity: endpoint,
+                    parent: net,
+                },
+            ),
+        );
+        for to in 1..=MAX_UNDO_STEPS as i16 {
+            assert!(world.get_entity(endpoint).is_ok());
+            let move_to = Edit::Move {
+                entity: symbol,
+                from: transform(to - 1),
+                to: transform(to),
+            };
+            send(&mut world, HistoryEvent::Edit(circuit, move_to));
+        }
+        assert!(world.get_entity(endpoint).is_err());
+    }
+}
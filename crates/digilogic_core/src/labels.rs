@@ -0,0 +1,111 @@
+use crate::components::BitWidth;
+use crate::{HashMap, SharedStr};
+use bevy_ecs::prelude::*;
+use std::fmt;
+
+/// Two labels with the same name that connect nets of different widths.
+#[derive(Debug, Clone)]
+pub struct LabelWidthError {
+    pub name: SharedStr,
+    pub expected: BitWidth,
+    pub found: BitWidth,
+}
+
+impl fmt::Display for LabelWidthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "label `{}` is {} bits wide, but another label with that name is {} bits wide",
+            self.name,
+            self.found.0.get(),
+            self.expected.0.get(),
+        )
+    }
+}
+
+impl std::error::Error for LabelWidthError {}
+
+/// Merges the nets that labels with the same name connect into one logical
+/// net, represented by one of them. Labels and global labels don't connect to
+/// each other, even if they have the same name.
+#[derive(Debug, Default)]
+pub struct LabelNets {
+    parents: HashMap<Entity, Entity>,
+    labels: HashMap<(bool, SharedStr), (Entity, BitWidth)>,
+}
+
+impl LabelNets {
+    /// Connects `net` to the nets of the labels added before with the same name.
+    pub fn add(
+        &mut self,
+        name: &SharedStr,
+        global: bool,
+        net: Entity,
+        bit_width: BitWidth,
+    ) -> Result<(), LabelWidthError> {
+        match self.labels.get(&(global, name.clone())) {
+            Some(&(other, expected)) => {
+                if expected != bit_width {
+                    return Err(LabelWidthError {
+                        name: name.clone(),
+                        expected,
+                        found: bit_width,
+                    });
+                }
+
+                let root = self.resolve(other);
+                let net = self.resolve(net);
+                if root != net {
+                    self.parents.insert(net, root);
+                }
+            }
+            None => {
+                self.labels.insert((global, name.clone()), (net, bit_width));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The net that represents the logical net `net` is part of.
+    pub fn resolve(&self, mut net: Entity) -> Entity {
+        while let Some(&parent) = self.parents.get(&net) {
+            net = parent;
+        }
+        net
+    }
+
+    /// The name, representative net and width of every global label.
+    pub fn globals(&self) -> impl Iterator<Item = (&SharedStr, Entity, BitWidth)> + '_ {
+        self.labels
+            .iter()
+            .filter(|((global, _), _)| *global)
+            .map(|((_, name), &(net, bit_width))| (name, self.resolve(net), bit_width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU8;
+
+    #[test]
+    fn merges_nets_by_name() {
+        let mut world = World::new();
+        let [a, b, c, d] = [(); 4].map(|_| world.spawn_empty().id());
+        let one = BitWidth(NonZeroU8::MIN);
+
+        let mut labels = LabelNets::default();
+        labels.add(&"x".into(), false, a, one).unwrap();
+        labels.add(&"y".into(), false, b, one).unwrap();
+        labels.add(&"x".into(), false, c, one).unwrap();
+        labels.add(&"x".into(), true, d, one).unwrap();
+
+        assert_eq!(labels.resolve(a), labels.resolve(c));
+        assert_ne!(labels.resolve(a), labels.resolve(b));
+        assert_ne!(labels.resolve(a), labels.resolve(d));
+
+        let wide = BitWidth(NonZeroU8::new(8).unwrap());
+        assert!(labels.add(&"y".into(), false, c, wide).is_err());
+    }
+}
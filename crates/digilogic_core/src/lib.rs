@@ -1,8 +1,13 @@
+pub mod annotate;
 pub mod bundles;
 pub mod components;
+pub mod erc;
 pub mod events;
+pub mod history;
+pub mod labels;
 pub mod resources;
 pub mod states;
+pub mod sub_circuit;
 pub mod symbol;
 pub mod transform;
 pub mod visibility;
@@ -70,10 +75,12 @@ impl bevy_app::Plugin for CorePlugin {
                 .register_type_data::<Fixed, InspectorEguiImpl>();
         }
 
-        app.register_relation::<components::Child>();
+        app.register_relation::<components::Child>()
+            .register_relation::<components::PartOf>();
 
         app.register_type::<components::PortID>()
             .register_type::<components::SymbolKind>()
+            .register_type::<components::CustomKind>()
             .register_type::<components::SymbolID>()
             .register_type::<components::WaypointID>()
             .register_type::<components::EndpointID>()
@@ -92,6 +99,7 @@ impl bevy_app::Plugin for CorePlugin {
             .register_type::<components::Output>()
             .register_type::<components::Selected>()
             .register_type::<components::Hovered>()
+            .register_type::<components::Modified>()
             .register_type::<components::Port>()
             .register_type::<components::Symbol>()
             .register_type::<components::Endpoint>()
@@ -111,8 +119,16 @@ impl bevy_app::Plugin for CorePlugin {
         app.add_event::<events::ProjectLoadEvent>()
             .add_event::<events::ProjectLoadedEvent>()
             .add_event::<events::CircuitLoadEvent>()
-            .add_event::<events::CircuitLoadedEvent>();
-
-        app.add_plugins((transform::TransformPlugin, visibility::VisibilityPlugin));
+            .add_event::<events::CircuitLoadedEvent>()
+            .add_event::<events::CircuitSaveEvent>();
+
+        app.add_plugins((
+            transform::TransformPlugin,
+            visibility::VisibilityPlugin,
+            history::HistoryPlugin,
+            sub_circuit::SubCircuitPlugin,
+            erc::ErcPlugin,
+            annotate::AnnotatePlugin,
+        ));
     }
 }
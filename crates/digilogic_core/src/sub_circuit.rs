@@ -0,0 +1,471 @@
+//! Symbols that instantiate other circuits of the project.
+//!
+//! A sub-circuit symbol has a port for every In and Out symbol of the circuit
+//! it instantiates. When those change, all instances are updated to match.
+
+use crate::components::*;
+use crate::events::PlaceSubCircuitEvent;
+use crate::history::{handle_history_events, Edit, HistoryEvent, HistorySet};
+use crate::symbol::{PortDef, SubCircuitDef, SubCircuitPort, SymbolRegistry};
+use crate::transform::*;
+use crate::{HashMap, HashSet};
+use aery::edges::{EdgeInfo, Edges};
+use bevy_ecs::prelude::*;
+use std::num::NonZeroU8;
+
+/// The ports the instances of a circuit were last updated to
+#[derive(Debug, Component)]
+struct InstancePorts(Vec<SubCircuitPort>);
+
+fn children_of(
+    world: &World,
+    children: &mut QueryState<Edges<Child>>,
+    entity: Entity,
+) -> Vec<Entity> {
+    children
+        .get(world, entity)
+        .map(|edges| edges.hosts().to_vec())
+        .unwrap_or_default()
+}
+
+/// The ports of a symbol instantiating `circuit`, one for each of its In and
+/// Out symbols. Inputs come first, each ordered from top to bottom.
+pub fn circuit_ports(world: &mut World, circuit: Entity) -> Vec<SubCircuitPort> {
+    let mut children = world.query::<Edges<Child>>();
+    ports_of(world, &mut children, circuit)
+}
+
+fn ports_of(
+    world: &World,
+    children: &mut QueryState<Edges<Child>>,
+    circuit: Entity,
+) -> Vec<SubCircuitPort> {
+    let mut pins: Vec<_> = children_of(world, children, circuit)
+        .into_iter()
+        .filter_map(|symbol| {
+            let output = match world.get::<SymbolKind>(symbol)? {
+                SymbolKind::In => false,
+                SymbolKind::Out => true,
+                _ => return None,
+            };
+            let name = world.get::<Name>(symbol)?.0.clone();
+            let position = world.get::<Transform>(symbol)?.translation;
+
+            // In and Out symbols have a single port, as wide as the pin
+            let bit_width = children_of(world, children, symbol)
+                .into_iter()
+                .find_map(|port| world.get::<BitWidth>(port).copied())
+                .unwrap_or(BitWidth(NonZeroU8::MIN));
+
+            Some((
+                (output, position.y, position.x),
+                SubCircuitPort {
+                    name,
+                    output,
+                    bit_width,
+                },
+            ))
+        })
+        .collect();
+    pins.sort_by_key(|&(key, _)| key);
+
+    pins.into_iter().map(|(_, port)| port).collect()
+}
+
+/// Whether placing `sub_circuit` in `circuit` would make `circuit` contain
+/// itself, directly or through other sub-circuits.
+pub fn is_recursive(world: &mut World, circuit: Entity, sub_circuit: Entity) -> bool {
+    let mut children = world.query::<Edges<Child>>();
+    let mut visited = HashSet::default();
+    let mut stack = vec![sub_circuit];
+
+    while let Some(next) = stack.pop() {
+        if next == circuit {
+            return true;
+        }
+        if !visited.insert(next) {
+            continue;
+        }
+
+        for symbol in children_of(world, &mut children, next) {
+            if world.get::<SymbolKind>(symbol) == Some(&SymbolKind::SubCircuit) {
+                if let Some(&CircuitID(instantiated)) = world.get::<CircuitID>(symbol) {
+                    stack.push(instantiated);
+                }
+            }
+        }
+    }
+
+    false
+}
+
+fn handle_place_events(world: &mut World) {
+    let events: Vec<_> = world
+        .resource_mut::<Events<PlaceSubCircuitEvent>>()
+        .drain()
+        .collect();
+
+    for ev in events {
+        let Some(name) = world.get::<Name>(ev.sub_circuit.0).cloned() else {
+            continue;
+        };
+        if is_recursive(world, ev.circuit.0, ev.sub_circuit.0) {
+            bevy_log::warn!("can't place {}, it would contain itself", name.0);
+            continue;
+        }
+
+        let ports = circuit_ports(world, ev.sub_circuit.0);
+        let symbol = world.resource_scope(|world, registry: Mut<SymbolRegistry>| {
+            let mut commands = world.commands();
+            registry
+                .get(SymbolKind::SubCircuit)
+                .name(name.0)
+                .position(ev.position)
+                .sub_circuit(ev.sub_circuit, &ports)
+                .build(&mut commands, ev.circuit.0)
+        });
+        world.flush();
+
+        // Attaching it again is harmless, and makes the placement undoable
+        world.send_event(HistoryEvent::Edit(
+            ev.circuit,
+            Edit::Attach {
+                entity: symbol,
+                parent: ev.circuit.0,
+            },
+        ));
+    }
+}
+
+type ChangedSymbolQuery<'w, 's> =
+    Query<'w, 's, &'static SymbolKind, (With<Symbol>, Or<(Changed<Transform>, Changed<Name>)>)>;
+
+/// Whether In and Out symbols could have changed since the instances were last
+/// updated, because symbols were added or removed, In and Out symbols were
+/// moved, renamed or resized, or a circuit was edited.
+fn pins_changed(
+    mut removed: RemovedComponents<Symbol>,
+    added: Query<(), Added<Symbol>>,
+    edited: Query<(), (With<Circuit>, Changed<Modified>)>,
+    symbols: ChangedSymbolQuery,
+    ports: Query<Edges<Child>, (With<Port>, Changed<BitWidth>)>,
+    kinds: Query<&SymbolKind>,
+) -> bool {
+    let is_pin = |kind: &SymbolKind| matches!(kind, SymbolKind::In | SymbolKind::Out);
+
+    removed.read().count() > 0
+        || !added.is_empty()
+        || !edited.is_empty()
+        || symbols.iter().any(is_pin)
+        || ports.iter().any(|edges| {
+            edges
+                .targets()
+                .iter()
+                .any(|&symbol| kinds.get(symbol).is_ok_and(is_pin))
+        })
+}
+
+/// Updates instances whose circuit gained, lost or changed In and Out symbols.
+fn update_instances(world: &mut World) {
+    let mut instances = HashMap::<Entity, Vec<Entity>>::default();
+    for (symbol, &kind, &CircuitID(circuit)) in world
+        .query_filtered::<(Entity, &SymbolKind, &CircuitID), With<Symbol>>()
+        .iter(world)
+    {
+        if kind == SymbolKind::SubCircuit {
+            instances.entry(circuit).or_default().push(symbol);
+        }
+    }
+
+    let mut children = world.query::<Edges<Child>>();
+    let mut updated = false;
+    for (circuit, symbols) in instances {
+        if world.get::<Circuit>(circuit).is_none() {
+            continue;
+        }
+
+        let ports = ports_of(world, &mut children, circuit);
+        match world.get::<InstancePorts>(circuit) {
+            Some(InstancePorts(known)) if *known == ports => continue,
+            Some(_) => {}
+            // Instances start out with the ports the circuit had when they
+            // were built, or that the file they were loaded from gave them.
+            None => {
+                world.entity_mut(circuit).insert(InstancePorts(ports));
+                continue;
+            }
+        }
+
+        let def = SubCircuitDef::new(CircuitID(circuit), &ports);
+        for symbol in symbols {
+            update_instance(world, &mut children, symbol, &def);
+        }
+        world.entity_mut(circuit).insert(InstancePorts(ports));
+        updated = true;
+    }
+
+    // Applies the edits right away, so removed ports don't linger for a frame
+    if updated {
+        handle_history_events(world);
+    }
+}
+
+/// Matches the ports of an instance to `def` by name, so wires stay connected
+/// to ports that were only moved. Ports are added and removed through the
+/// history of the circuit the instance is in, so undoing brings them back.
+fn update_instance(
+    world: &mut World,
+    children: &mut QueryState<Edges<Child>>,
+    symbol: Entity,
+    def: &SubCircuitDef,
+) {
+    let Some(circuit) = children
+        .get(world, symbol)
+        .ok()
+        .and_then(|edges| edges.targets().first().copied())
+    else {
+        return;
+    };
+
+    let mut edits = Vec::new();
+    let mut old_ports: Vec<_> = children_of(world, children, symbol)
+        .into_iter()
+        .filter(|&port| world.get::<Port>(port).is_some())
+        .collect();
+
+    for (port_def, bit_width) in def.ports.iter() {
+        let existing = old_ports.iter().position(|&port| {
+            world
+                .get::<Name>(port)
+                .is_some_and(|name| name.0 == port_def.name)
+        });
+
+        match existing {
+            Some(index) => update_port(world, old_ports.swap_remove(index), port_def, *bit_width),
+            None => {
+                let port = port_def.build(&mut world.commands(), symbol, *bit_width);
+                edits.push(Edit::Attach {
+                    entity: port,
+                    parent: symbol,
+                });
+            }
+        }
+    }
+    world.flush();
+
+    // Wires connected to removed ports are left ending where they were
+    edits.extend(old_ports.into_iter().map(|port| Edit::Detach {
+        entity: port,
+        parent: symbol,
+    }));
+    let circuit = CircuitID(circuit);
+    world.send_event(HistoryEvent::BeginGroup(circuit));
+    for edit in edits {
+        world.send_event(HistoryEvent::Edit(circuit, edit));
+    }
+    world.send_event(HistoryEvent::EndGroup(circuit));
+
+    let mut symbol = world.entity_mut(symbol);
+    symbol.insert(def.bounding_box);
+    // Recomputes the absolute bounds and reroutes the circuit
+    if let Some(mut global_transform) = symbol.get_mut::<GlobalTransform>() {
+        global_transform.set_changed();
+    }
+}
+
+fn update_port(world: &mut World, port: Entity, def: &PortDef, bit_width: BitWidth) {
+    let mut port = world.entity_mut(port);
+
+    if let Some(mut transform) = port.get_mut::<Transform>() {
+        if transform.translation != def.position {
+            transform.translation = def.position;
+        }
+    }
+    if port.get::<BitWidth>() != Some(&bit_width) {
+        port.insert(bit_width);
+    }
+    if port.get::<Directions>() != Some(&def.directions) {
+        port.insert(def.directions);
+        if let Some(mut global_transform) = port.get_mut::<GlobalTransform>() {
+            global_transform.set_changed();
+        }
+    }
+
+    if def.input {
+        port.insert(Input);
+    } else {
+        port.remove::<Input>();
+    }
+    if def.output {
+        port.insert(Output);
+    } else {
+        port.remove::<Output>();
+    }
+}
+
+pub(crate) struct SubCircuitPlugin;
+
+impl bevy_app::Plugin for SubCircuitPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.add_event::<PlaceSubCircuitEvent>();
+        app.add_systems(
+            bevy_app::PostUpdate,
+            (
+                handle_place_events.before(HistorySet),
+                update_instances
+                    .run_if(pins_changed)
+                    .after(HistorySet)
+                    .before(TransformSet),
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundles::CircuitBundle;
+    use crate::history::History;
+    use crate::{fixed, Fixed, SharedStr};
+
+    fn spawn_circuit(world: &mut World, name: &'static str) -> Entity {
+        world
+            .spawn((
+                CircuitBundle {
+                    circuit: Circuit,
+                    name: Name(SharedStr::new_static(name)),
+                },
+                History::default(),
+            ))
+            .id()
+    }
+
+    fn place(
+        world: &mut World,
+        circuit: Entity,
+        kind: SymbolKind,
+        name: &'static str,
+        y: Fixed,
+    ) -> Entity {
+        let symbol = world.resource_scope(|world, registry: Mut<SymbolRegistry>| {
+            registry
+                .get(kind)
+                .name(SharedStr::new_static(name))
+                .position(Vec2 { x: fixed!(0), y })
+                .build(&mut world.commands(), circuit)
+        });
+        world.flush();
+        symbol
+    }
+
+    /// An adder circuit with an `a` input and a `sum` output, and a top
+    /// circuit with an instance of it
+    fn adder_instance(world: &mut World) -> (Entity, Entity, Entity) {
+        world.init_resource::<SymbolRegistry>();
+        world.init_resource::<Events<HistoryEvent>>();
+        let adder = spawn_circuit(world, "adder");
+        place(world, adder, SymbolKind::In, "a", fixed!(0));
+        place(world, adder, SymbolKind::Out, "sum", fixed!(0));
+        let top = spawn_circuit(world, "top");
+
+        let ports = circuit_ports(world, adder);
+        let instance = world.resource_scope(|world, registry: Mut<SymbolRegistry>| {
+            registry
+                .get(SymbolKind::SubCircuit)
+                .sub_circuit(CircuitID(adder), &ports)
+                .build(&mut world.commands(), top)
+        });
+        world.flush();
+        world.run_system_cached(update_instances).unwrap();
+
+        (adder, top, instance)
+    }
+
+    fn port_names(world: &mut World, instance: Entity) -> Vec<SharedStr> {
+        let mut children = world.query::<Edges<Child>>();
+        let mut names: Vec<_> = children_of(world, &mut children, instance)
+            .into_iter()
+            .map(|port| world.get::<Name>(port).unwrap().0.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn send(world: &mut World, ev: HistoryEvent) {
+        world.send_event(ev);
+        world.run_system_cached(handle_history_events).unwrap();
+    }
+
+    #[test]
+    fn instances_follow_circuit_ports() {
+        let mut world = World::new();
+        let (adder, top, instance) = adder_instance(&mut world);
+
+        assert!(is_recursive(&mut world, adder, top));
+        assert!(!is_recursive(&mut world, top, adder));
+
+        place(&mut world, adder, SymbolKind::In, "b", fixed!(20));
+        world.run_system_cached(update_instances).unwrap();
+
+        assert_eq!(
+            port_names(&mut world, instance),
+            ["a", "b", "sum"].map(SharedStr::new_static)
+        );
+        let ports = circuit_ports(&mut world, adder);
+        assert_eq!(
+            *world.get::<BoundingBox>(instance).unwrap(),
+            SubCircuitDef::new(CircuitID(adder), &ports).bounding_box
+        );
+    }
+
+    #[test]
+    fn undo_restores_removed_ports() {
+        let mut world = World::new();
+        let (adder, top, instance) = adder_instance(&mut world);
+        let mut children = world.query::<Edges<Child>>();
+        let sum = children_of(&world, &mut children, adder)
+            .into_iter()
+            .find(|&symbol| world.get::<SymbolKind>(symbol) == Some(&SymbolKind::Out))
+            .unwrap();
+
+        let detach = Edit::Detach {
+            entity: sum,
+            parent: adder,
+        };
+        send(&mut world, HistoryEvent::Edit(CircuitID(adder), detach));
+        world.run_system_cached(update_instances).unwrap();
+        assert_eq!(
+            port_names(&mut world, instance),
+            ["a"].map(SharedStr::new_static)
+        );
+
+        send(&mut world, HistoryEvent::Undo(CircuitID(top)));
+        assert_eq!(
+            port_names(&mut world, instance),
+            ["a", "sum"].map(SharedStr::new_static)
+        );
+    }
+
+    #[test]
+    fn updates_instances_only_when_pins_change() {
+        let mut world = World::new();
+        let (adder, _, _) = adder_instance(&mut world);
+        assert!(world.run_system_cached(pins_changed).unwrap());
+        assert!(!world.run_system_cached(pins_changed).unwrap());
+
+        // Gates don't change the ports of instances
+        let gate = place(&mut world, adder, SymbolKind::And, "and", fixed!(40));
+        assert!(world.run_system_cached(pins_changed).unwrap());
+        world.get_mut::<Transform>(gate).unwrap().translation.x = fixed!(10);
+        assert!(!world.run_system_cached(pins_changed).unwrap());
+
+        let pin = place(&mut world, adder, SymbolKind::In, "b", fixed!(20));
+        assert!(world.run_system_cached(pins_changed).unwrap());
+        world.get_mut::<Transform>(pin).unwrap().translation.x = fixed!(10);
+        assert!(world.run_system_cached(pins_changed).unwrap());
+
+        world.despawn(pin);
+        assert!(world.run_system_cached(pins_changed).unwrap());
+        assert!(!world.run_system_cached(pins_changed).unwrap());
+    }
+}
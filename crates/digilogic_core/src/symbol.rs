@@ -5,28 +5,54 @@ use crate::visibility::*;
 use crate::{fixed, Fixed, SharedStr};
 use aery::prelude::*;
 use bevy_ecs::prelude::*;
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
+use std::borrow::Cow;
+use std::fmt;
 use std::num::NonZeroU8;
 
-#[derive(Debug, Clone)]
-struct PortDef {
-    name: SharedStr,
-    position: Vec2,
-    input: bool,
-    output: bool,
-    directions: Directions,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct PortDef {
+    pub(crate) name: SharedStr,
+    pub(crate) position: Vec2,
+    #[serde(default)]
+    pub(crate) input: bool,
+    #[serde(default)]
+    pub(crate) output: bool,
+    pub(crate) directions: Directions,
 }
 
-#[derive(Debug, Clone)]
+/// Describes a kind of symbol: its ports, size and how it's drawn. Besides
+/// the built-in kinds, definitions can be deserialized, e.g. from a symbol
+/// library, and added with `SymbolRegistry::register`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SymbolDef {
+    #[serde(skip, default = "custom_kind")]
     kind: SymbolKind,
     name: SharedStr,
     designator_prefix: SharedStr,
-    ports: &'static [PortDef],
+    ports: Cow<'static, [PortDef]>,
     bounding_box: BoundingBox,
+    #[serde(default)]
     shape: Shape,
 }
 
+fn custom_kind() -> SymbolKind {
+    SymbolKind::Custom
+}
+
+impl SymbolDef {
+    #[inline]
+    pub fn kind(&self) -> SymbolKind {
+        self.kind
+    }
+
+    #[inline]
+    pub fn name(&self) -> &SharedStr {
+        &self.name
+    }
+}
+
 const PORT_HALF_WIDTH: Fixed = fixed!(4);
 
 const GATE_PORTS_2_INPUT: &[PortDef] = &[
@@ -85,6 +111,18 @@ const GATE_PORTS_1_INPUT: &[PortDef] = &[
     },
 ];
 
+/// Labels neither drive nor read their net, they only connect it to others.
+const LABEL_PORTS: &[PortDef] = &[PortDef {
+    name: SharedStr::new_static("A"),
+    position: Vec2 {
+        x: fixed!(0),
+        y: fixed!(0),
+    },
+    input: false,
+    output: false,
+    directions: Directions::NEG_X,
+}];
+
 const KINDS: &[SymbolDef] = &[
     SymbolDef {
         kind: SymbolKind::And,
@@ -99,7 +137,7 @@ const KINDS: &[SymbolDef] = &[
             fixed!(60),
         ),
         shape: Shape::And,
-        ports: GATE_PORTS_2_INPUT,
+        ports: Cow::Borrowed(GATE_PORTS_2_INPUT),
     },
     SymbolDef {
         kind: SymbolKind::Or,
@@ -114,7 +152,7 @@ const KINDS: &[SymbolDef] = &[
             fixed!(60),
         ),
         shape: Shape::Or,
-        ports: GATE_PORTS_2_INPUT,
+        ports: Cow::Borrowed(GATE_PORTS_2_INPUT),
     },
     SymbolDef {
         kind: SymbolKind::Xor,
@@ -129,7 +167,52 @@ const KINDS: &[SymbolDef] = &[
             fixed!(60),
         ),
         shape: Shape::Xor,
-        ports: GATE_PORTS_2_INPUT,
+        ports: Cow::Borrowed(GATE_PORTS_2_INPUT),
+    },
+    SymbolDef {
+        kind: SymbolKind::Nand,
+        name: SharedStr::new_static("NAND"),
+        designator_prefix: SharedStr::new_static("U"),
+        bounding_box: BoundingBox::from_top_left_size(
+            Vec2 {
+                x: fixed!(0),
+                y: fixed!(-10),
+            },
+            fixed!(80),
+            fixed!(60),
+        ),
+        shape: Shape::Nand,
+        ports: Cow::Borrowed(GATE_PORTS_2_INPUT),
+    },
+    SymbolDef {
+        kind: SymbolKind::Nor,
+        name: SharedStr::new_static("NOR"),
+        designator_prefix: SharedStr::new_static("U"),
+        bounding_box: BoundingBox::from_top_left_size(
+            Vec2 {
+                x: fixed!(0),
+                y: fixed!(-10),
+            },
+            fixed!(80),
+            fixed!(60),
+        ),
+        shape: Shape::Nor,
+        ports: Cow::Borrowed(GATE_PORTS_2_INPUT),
+    },
+    SymbolDef {
+        kind: SymbolKind::Xnor,
+        name: SharedStr::new_static("XNOR"),
+        designator_prefix: SharedStr::new_static("U"),
+        bounding_box: BoundingBox::from_top_left_size(
+            Vec2 {
+                x: fixed!(0),
+                y: fixed!(-10),
+            },
+            fixed!(80),
+            fixed!(60),
+        ),
+        shape: Shape::Xnor,
+        ports: Cow::Borrowed(GATE_PORTS_2_INPUT),
     },
     SymbolDef {
         kind: SymbolKind::Not,
@@ -144,7 +227,68 @@ const KINDS: &[SymbolDef] = &[
             fixed!(20),
         ),
         shape: Shape::Not,
-        ports: GATE_PORTS_1_INPUT,
+        ports: Cow::Borrowed(GATE_PORTS_1_INPUT),
+    },
+    SymbolDef {
+        kind: SymbolKind::Buffer,
+        name: SharedStr::new_static("BUF"),
+        designator_prefix: SharedStr::new_static("U"),
+        bounding_box: BoundingBox::from_top_left_size(
+            Vec2 {
+                x: fixed!(0),
+                y: fixed!(-10),
+            },
+            fixed!(40),
+            fixed!(20),
+        ),
+        shape: Shape::Buffer,
+        ports: Cow::Borrowed(GATE_PORTS_1_INPUT),
+    },
+    SymbolDef {
+        kind: SymbolKind::TriState,
+        name: SharedStr::new_static("TRIBUF"),
+        designator_prefix: SharedStr::new_static("U"),
+        bounding_box: BoundingBox::from_top_left_size(
+            Vec2 {
+                x: fixed!(0),
+                y: fixed!(-20),
+            },
+            fixed!(40),
+            fixed!(30),
+        ),
+        shape: Shape::TriState,
+        ports: Cow::Borrowed(&[
+            PortDef {
+                name: SharedStr::new_static("A"),
+                position: Vec2 {
+                    x: fixed!(0),
+                    y: fixed!(0),
+                },
+                input: true,
+                output: false,
+                directions: Directions::NEG_X,
+            },
+            PortDef {
+                name: SharedStr::new_static("EN"),
+                position: Vec2 {
+                    x: fixed!(20),
+                    y: fixed!(-20),
+                },
+                input: true,
+                output: false,
+                directions: Directions::NEG_Y,
+            },
+            PortDef {
+                name: SharedStr::new_static("Y"),
+                position: Vec2 {
+                    x: fixed!(40),
+                    y: fixed!(0),
+                },
+                input: false,
+                output: true,
+                directions: Directions::POS_X,
+            },
+        ]),
     },
     SymbolDef {
         kind: SymbolKind::In,
@@ -159,7 +303,7 @@ const KINDS: &[SymbolDef] = &[
             fixed!(40),
         ),
         shape: Shape::Input,
-        ports: &[PortDef {
+        ports: Cow::Borrowed(&[PortDef {
             name: SharedStr::new_static("Y"),
             position: Vec2 {
                 x: fixed!(0),
@@ -168,7 +312,7 @@ const KINDS: &[SymbolDef] = &[
             input: false,
             output: true,
             directions: Directions::POS_X,
-        }],
+        }]),
     },
     SymbolDef {
         kind: SymbolKind::Out,
@@ -183,7 +327,7 @@ const KINDS: &[SymbolDef] = &[
             fixed!(40),
         ),
         shape: Shape::Output,
-        ports: &[PortDef {
+        ports: Cow::Borrowed(&[PortDef {
             name: SharedStr::new_static("A"),
             position: Vec2 {
                 x: fixed!(0),
@@ -192,7 +336,7 @@ const KINDS: &[SymbolDef] = &[
             input: true,
             output: false,
             directions: Directions::NEG_X,
-        }],
+        }]),
     },
     SymbolDef {
         kind: SymbolKind::Mux,
@@ -207,7 +351,7 @@ const KINDS: &[SymbolDef] = &[
             fixed!(40),
         ),
         shape: Shape::Chip,
-        ports: &[
+        ports: Cow::Borrowed(&[
             PortDef {
                 name: SharedStr::new_static("S"),
                 position: Vec2 {
@@ -248,10 +392,155 @@ const KINDS: &[SymbolDef] = &[
                 output: true,
                 directions: Directions::POS_X,
             },
-        ],
+        ]),
+    },
+    SymbolDef {
+        kind: SymbolKind::SubCircuit,
+        name: SharedStr::new_static("SUBCIRCUIT"),
+        designator_prefix: SharedStr::new_static("U"),
+        bounding_box: BoundingBox::from_top_left_size(
+            Vec2 {
+                x: fixed!(0),
+                y: fixed!(0),
+            },
+            fixed!(40),
+            fixed!(40),
+        ),
+        shape: Shape::Chip,
+        ports: Cow::Borrowed(&[]),
+    },
+    SymbolDef {
+        kind: SymbolKind::Const,
+        name: SharedStr::new_static("CONST"),
+        designator_prefix: SharedStr::new_static("K"),
+        bounding_box: BoundingBox::from_top_left_size(
+            Vec2 {
+                x: fixed!(-40),
+                y: fixed!(-20),
+            },
+            fixed!(40),
+            fixed!(40),
+        ),
+        shape: Shape::Input,
+        ports: Cow::Borrowed(&[PortDef {
+            name: SharedStr::new_static("Y"),
+            position: Vec2 {
+                x: fixed!(0),
+                y: fixed!(0),
+            },
+            input: false,
+            output: true,
+            directions: Directions::POS_X,
+        }]),
+    },
+    SymbolDef {
+        kind: SymbolKind::Label,
+        name: SharedStr::new_static("LABEL"),
+        designator_prefix: SharedStr::new_static("N"),
+        bounding_box: BoundingBox::from_top_left_size(
+            Vec2 {
+                x: fixed!(0),
+                y: fixed!(-10),
+            },
+            fixed!(60),
+            fixed!(20),
+        ),
+        shape: Shape::Label,
+        ports: Cow::Borrowed(LABEL_PORTS),
+    },
+    SymbolDef {
+        kind: SymbolKind::GlobalLabel,
+        name: SharedStr::new_static("GLOBAL_LABEL"),
+        designator_prefix: SharedStr::new_static("N"),
+        bounding_box: BoundingBox::from_top_left_size(
+            Vec2 {
+                x: fixed!(0),
+                y: fixed!(-10),
+            },
+            fixed!(60),
+            fixed!(20),
+        ),
+        shape: Shape::GlobalLabel,
+        ports: Cow::Borrowed(LABEL_PORTS),
     },
 ];
 
+const GATE_WIDTH: Fixed = fixed!(80);
+const GATE_INPUT_SPACING: Fixed = fixed!(20);
+const GATE_INPUT_NAMES: &str = "ABCDEFGHIJKLMNOPQRSTUVWX";
+
+/// Whether the symbol kind is a gate, which can have any number of inputs.
+fn is_gate(kind: SymbolKind) -> bool {
+    matches!(
+        kind,
+        SymbolKind::And
+            | SymbolKind::Or
+            | SymbolKind::Xor
+            | SymbolKind::Nand
+            | SymbolKind::Nor
+            | SymbolKind::Xnor
+    )
+}
+
+fn gate_input_name(index: usize) -> SharedStr {
+    match GATE_INPUT_NAMES.get(index..index + 1) {
+        Some(name) => SharedStr::new_static(name),
+        None => format!("A{index}").into(),
+    }
+}
+
+/// Lays the ports of a gate out like Digital does: inputs one grid step apart
+/// down the left side, leaving the middle row free for the output if there's
+/// an even number of inputs. Two inputs end up where `GATE_PORTS_2_INPUT` has
+/// them.
+fn gate_layout(input_count: u8) -> (Vec<PortDef>, BoundingBox) {
+    let input_count = input_count.max(2);
+    let skip_middle = input_count % 2 == 0;
+
+    let mut ports: Vec<_> = (0..input_count)
+        .map(|i| {
+            let row = i + (skip_middle && i >= input_count / 2) as u8;
+            PortDef {
+                name: gate_input_name(i as usize),
+                position: Vec2 {
+                    x: fixed!(0),
+                    y: GATE_INPUT_SPACING * Fixed::from_u8(row),
+                },
+                input: true,
+                output: false,
+                directions: Directions::NEG_X,
+            }
+        })
+        .collect();
+
+    let max_y = ports.last().map(|port| port.position.y).unwrap_or_default();
+    ports.push(PortDef {
+        name: SharedStr::new_static("Y"),
+        position: Vec2 {
+            x: GATE_WIDTH,
+            y: GATE_INPUT_SPACING * Fixed::from_u8(input_count / 2),
+        },
+        input: false,
+        output: true,
+        directions: Directions::POS_X,
+    });
+
+    let half_spacing = GATE_INPUT_SPACING / fixed!(2);
+    let bounding_box = BoundingBox::from_top_left_size(
+        Vec2 {
+            x: fixed!(0),
+            y: -half_spacing,
+        },
+        GATE_WIDTH,
+        max_y + GATE_INPUT_SPACING,
+    );
+
+    (ports, bounding_box)
+}
+
+const CHIP_WIDTH: Fixed = fixed!(60);
+const CHIP_PORT_SPACING: Fixed = fixed!(20);
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PortInfo {
     pub symbol: Entity,
@@ -259,46 +548,196 @@ pub struct PortInfo {
     pub id: Entity,
     pub position: Vec2,
     pub direction: Directions,
+    pub bit_width: BitWidth,
+}
+
+/// A port of a sub-circuit symbol, usually derived from an In or Out symbol
+/// of the instantiated circuit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubCircuitPort {
+    pub name: SharedStr,
+    pub output: bool,
+    pub bit_width: BitWidth,
+}
+
+#[derive(Debug)]
+pub(crate) struct SubCircuitDef {
+    pub(crate) circuit: CircuitID,
+    pub(crate) ports: Vec<(PortDef, BitWidth)>,
+    pub(crate) bounding_box: BoundingBox,
+}
+
+impl SubCircuitDef {
+    /// Lays the ports out like Digital does for embedded circuits: inputs
+    /// down the left side and outputs down the right side. A single output
+    /// is centered next to the inputs.
+    pub(crate) fn new(circuit: CircuitID, ports: &[SubCircuitPort]) -> Self {
+        let input_count = ports.iter().filter(|port| !port.output).count() as u16;
+        let output_count = ports.len() as u16 - input_count;
+        let symmetric = output_count == 1;
+
+        let mut input_index = 0;
+        let mut output_index = 0;
+        let mut max_y = fixed!(0);
+
+        let ports: Vec<_> = ports
+            .iter()
+            .map(|port| {
+                let (x, row, directions) = if port.output {
+                    let offset = if symmetric { input_count / 2 } else { 0 };
+                    output_index += 1;
+                    (CHIP_WIDTH, output_index - 1 + offset, Directions::POS_X)
+                } else {
+                    // Leave the middle row free for the output when centering
+                    // it between an even number of inputs.
+                    let skip = symmetric && input_count % 2 == 0 && input_index >= input_count / 2;
+                    input_index += 1;
+                    (fixed!(0), input_index - 1 + skip as u16, Directions::NEG_X)
+                };
+
+                let y = CHIP_PORT_SPACING * Fixed::from_u16(row);
+                max_y = max_y.max(y);
+
+                let def = PortDef {
+                    name: port.name.clone(),
+                    position: Vec2 { x, y },
+                    input: !port.output,
+                    output: port.output,
+                    directions,
+                };
+                (def, port.bit_width)
+            })
+            .collect();
+
+        let half_spacing = CHIP_PORT_SPACING / fixed!(2);
+        let bounding_box = BoundingBox::from_top_left_size(
+            Vec2 {
+                x: fixed!(0),
+                y: -half_spacing,
+            },
+            CHIP_WIDTH,
+            max_y + CHIP_PORT_SPACING,
+        );
+
+        Self {
+            circuit,
+            ports,
+            bounding_box,
+        }
+    }
+}
+
+/// A definition replacing a built-in kind of symbol with ports that have
+/// other names or directions than the ones its logic uses.
+#[derive(Debug, Clone)]
+pub struct IncompatiblePortsError {
+    pub name: SharedStr,
+}
+
+impl fmt::Display for IncompatiblePortsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "symbol `{}` replaces a built-in symbol, but its ports have other names or directions",
+            self.name,
+        )
+    }
+}
+
+impl std::error::Error for IncompatiblePortsError {}
+
+/// The names and directions of the ports of a definition, in a fixed order
+fn port_signature(def: &SymbolDef) -> Vec<(&SharedStr, bool, bool)> {
+    let mut ports: Vec<_> = def
+        .ports
+        .iter()
+        .map(|port| (&port.name, port.input, port.output))
+        .collect();
+    ports.sort();
+    ports
 }
 
 #[derive(Debug)]
 pub struct SymbolBuilder<'a> {
     registry: &'a SymbolRegistry,
-    kind: SymbolKind,
+    /// Index of the definition in the registry
+    def: usize,
     name: Option<SharedStr>,
     designator_number: Option<u32>,
     position: Option<Vec2>,
+    rotation: Rotation,
+    flip: Flip,
     bit_width: Option<BitWidth>,
+    value: u64,
+    sub_circuit: Option<SubCircuitDef>,
+    /// The ports and bounding box of a gate with other than two inputs
+    gate: Option<(Vec<PortDef>, BoundingBox)>,
     ports: SmallVec<[PortInfo; 7]>,
 }
 
-#[derive(Debug, Resource)]
+#[derive(Debug, Clone, Resource)]
 pub struct SymbolRegistry {
     kinds: Vec<SymbolDef>,
 }
 
 impl SymbolRegistry {
+    /// A builder for a built-in kind of symbol. Custom symbols can only be
+    /// looked up by name.
     pub fn get(&self, kind: SymbolKind) -> SymbolBuilder {
+        let def = self
+            .kinds
+            .iter()
+            .position(|def| def.kind == kind)
+            .expect("no definition for symbol kind");
+        self.builder(def)
+    }
+
+    fn builder(&self, def: usize) -> SymbolBuilder<'_> {
         SymbolBuilder {
             registry: self,
-            kind,
+            def,
             name: None,
             designator_number: None,
             position: None,
+            rotation: Rotation::Rot0,
+            flip: Flip::empty(),
             bit_width: None,
+            value: 0,
+            sub_circuit: None,
+            gate: None,
             ports: SmallVec::new(),
         }
     }
 
     pub fn get_by_name(&self, name: &SharedStr) -> Option<SymbolBuilder> {
-        let def = self.kinds.iter().find(|kind| kind.name == *name);
+        let def = self.kinds.iter().position(|kind| kind.name == *name);
 
-        def.map(|kind| self.get(kind.kind))
+        def.map(|def| self.builder(def))
     }
 
     pub fn get_by_index(&self, index: usize) -> Option<&SymbolDef> {
         self.kinds.get(index)
     }
+
+    /// Adds a definition, replacing the one with the same name. A replaced
+    /// built-in kind keeps its logic, only its pinout and looks change, so
+    /// its ports have to keep their names and directions. Any other
+    /// definition is a `SymbolKind::Custom` black box.
+    pub fn register(&mut self, mut def: SymbolDef) -> Result<(), IncompatiblePortsError> {
+        if let Some(existing) = self.kinds.iter_mut().find(|kind| kind.name == def.name) {
+            if existing.kind != SymbolKind::Custom
+                && port_signature(existing) != port_signature(&def)
+            {
+                return Err(IncompatiblePortsError { name: def.name });
+            }
+            def.kind = existing.kind;
+            *existing = def;
+        } else {
+            def.kind = SymbolKind::Custom;
+            self.kinds.push(def);
+        }
+        Ok(())
+    }
 }
 
 impl Default for SymbolRegistry {
@@ -325,25 +764,63 @@ impl SymbolBuilder<'_> {
         self
     }
 
+    /// Rotates the symbol around its origin. Port positions returned by
+    /// `ports` are not rotated.
+    pub fn rotation(&mut self, rotation: Rotation) -> &mut Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Mirrors the symbol around its origin, before rotating it. Port
+    /// positions returned by `ports` are not flipped.
+    pub fn flip(&mut self, flip: Flip) -> &mut Self {
+        self.flip = flip;
+        self
+    }
+
     pub fn bit_width(&mut self, bit_width: BitWidth) -> &mut Self {
         self.bit_width = Some(bit_width);
         self
     }
 
+    /// Sets the value driven by a `SymbolKind::Const` symbol.
+    pub fn value(&mut self, value: u64) -> &mut Self {
+        self.value = value;
+        self
+    }
+
+    /// Sets how many inputs a gate has, two if not set. Ignored for symbols
+    /// that aren't gates.
+    pub fn input_count(&mut self, input_count: u8) -> &mut Self {
+        let kind = self.registry.kinds[self.def].kind;
+        self.gate = (is_gate(kind) && input_count != 2).then(|| gate_layout(input_count));
+        self
+    }
+
+    /// Makes the symbol an instance of `circuit`, with the given ports. Only
+    /// used with `SymbolKind::SubCircuit`.
+    pub fn sub_circuit(&mut self, circuit: CircuitID, ports: &[SubCircuitPort]) -> &mut Self {
+        self.sub_circuit = Some(SubCircuitDef::new(circuit, ports));
+        self
+    }
+
     pub fn ports(&self) -> &[PortInfo] {
         &self.ports
     }
 
     pub fn bounding_box(&self) -> BoundingBox {
-        self.registry
-            .kinds
-            .get(self.kind as usize)
-            .map(|kind| kind.bounding_box)
-            .unwrap_or_default()
+        if let Some(sub_circuit) = &self.sub_circuit {
+            return sub_circuit.bounding_box;
+        }
+        if let Some((_, bounding_box)) = &self.gate {
+            return *bounding_box;
+        }
+
+        self.registry.kinds[self.def].bounding_box
     }
 
     pub fn build(&mut self, commands: &mut Commands, circuit_id: Entity) -> Entity {
-        let kind = self.registry.kinds.get(self.kind as usize).unwrap();
+        let kind = &self.registry.kinds[self.def];
 
         let symbol_id = commands
             .spawn(SymbolBundle {
@@ -355,6 +832,8 @@ impl SymbolBuilder<'_> {
                 transform: TransformBundle {
                     transform: Transform {
                         translation: self.position.unwrap_or_default(),
+                        rotation: self.rotation,
+                        flip: self.flip,
                         ..Default::default()
                     },
                     ..Default::default()
@@ -362,34 +841,55 @@ impl SymbolBuilder<'_> {
                 symbol: Symbol,
                 visibility: VisibilityBundle::default(),
                 bounds: BoundingBoxBundle {
-                    bounding_box: kind.bounding_box,
+                    bounding_box: self.bounding_box(),
                     ..Default::default()
                 },
             })
             .set::<Child>(circuit_id)
             .id();
 
-        if self.kind == SymbolKind::In {
+        if let Some(sub_circuit) = &self.sub_circuit {
+            commands.entity(symbol_id).insert(sub_circuit.circuit);
+        }
+        if kind.kind == SymbolKind::Custom {
+            commands
+                .entity(symbol_id)
+                .insert(CustomKind(kind.name.clone()));
+        }
+
+        let bit_width = self.bit_width.unwrap_or(BitWidth(NonZeroU8::MIN));
+
+        if kind.kind == SymbolKind::In {
             commands
                 .entity(symbol_id)
                 .insert(LogicState::from_bool(false));
+        } else if kind.kind == SymbolKind::Const {
+            commands
+                .entity(symbol_id)
+                .insert(LogicState::from_value(self.value, bit_width));
         }
 
-        self.ports = kind
-            .ports
-            .iter()
-            .map(|port| {
-                let id = port.build(
-                    commands,
-                    symbol_id,
-                    self.bit_width.unwrap_or(BitWidth(NonZeroU8::MIN)),
-                );
+        let ports: SmallVec<[(&PortDef, BitWidth); 7]> = match (&self.sub_circuit, &self.gate) {
+            (Some(sub_circuit), _) => sub_circuit
+                .ports
+                .iter()
+                .map(|(port, bit_width)| (port, *bit_width))
+                .collect(),
+            (None, Some((ports, _))) => ports.iter().map(|port| (port, bit_width)).collect(),
+            (None, None) => kind.ports.iter().map(|port| (port, bit_width)).collect(),
+        };
+
+        self.ports = ports
+            .into_iter()
+            .map(|(port, bit_width)| {
+                let id = port.build(commands, symbol_id, bit_width);
                 PortInfo {
                     symbol: symbol_id,
                     name: port.name.clone(),
                     id,
                     position: port.position,
                     direction: port.directions,
+                    bit_width,
                 }
             })
             .collect();
@@ -399,7 +899,12 @@ impl SymbolBuilder<'_> {
 }
 
 impl PortDef {
-    fn build(&self, commands: &mut Commands, symbol_id: Entity, bit_width: BitWidth) -> Entity {
+    pub(crate) fn build(
+        &self,
+        commands: &mut Commands,
+        symbol_id: Entity,
+        bit_width: BitWidth,
+    ) -> Entity {
         let mut port_commands = commands.spawn(PortBundle {
             port: Port,
             name: Name(self.name.clone()),
@@ -168,6 +168,39 @@ impl Rotation {
     pub fn radians(self) -> f64 {
         ((self as u8) as f64) * std::f64::consts::FRAC_PI_2
     }
+
+    /// The rotation that undoes this one.
+    #[inline]
+    pub fn inverse(self) -> Self {
+        match self {
+            Self::Rot0 => Self::Rot0,
+            Self::Rot90 => Self::Rot270,
+            Self::Rot180 => Self::Rot180,
+            Self::Rot270 => Self::Rot90,
+        }
+    }
+}
+
+bitflags! {
+    /// Mirrors an entity around its origin, before it's rotated.
+    #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect)]
+    #[repr(transparent)]
+    #[reflect(opaque)]
+    pub struct Flip: u8 {
+        /// Negates X, so the left and right sides swap
+        const HORIZONTAL = 0x1;
+        /// Negates Y, so the top and bottom sides swap
+        const VERTICAL = 0x2;
+    }
+}
+
+impl Flip {
+    /// Whether this mirrors along a single axis, which reverses the sense of
+    /// rotations. Flipping both ways is the same as rotating by 180°.
+    #[inline]
+    pub fn is_mirrored(self) -> bool {
+        self == Self::HORIZONTAL || self == Self::VERTICAL
+    }
 }
 
 impl Mul for Rotation {
@@ -193,6 +226,21 @@ impl MulAssign for Rotation {
 }
 
 impl Vec2 {
+    pub fn flip(self, flip: Flip) -> Self {
+        Self {
+            x: if flip.contains(Flip::HORIZONTAL) {
+                -self.x
+            } else {
+                self.x
+            },
+            y: if flip.contains(Flip::VERTICAL) {
+                -self.y
+            } else {
+                self.y
+            },
+        }
+    }
+
     pub fn rotate(self, rotation: Rotation) -> Self {
         match rotation {
             Rotation::Rot0 => self,
@@ -217,6 +265,8 @@ pub struct Transform {
     pub translation: Vec2,
     pub rotation: Rotation,
     pub scale: Fixed,
+    /// Applied before the rotation
+    pub flip: Flip,
 }
 
 impl Transform {
@@ -224,6 +274,7 @@ impl Transform {
         translation: Vec2::ZERO,
         rotation: Rotation::Rot0,
         scale: fixed!(1),
+        flip: Flip::empty(),
     };
 }
 
@@ -238,10 +289,22 @@ impl Mul for Transform {
     type Output = Self;
 
     fn mul(self, rhs: Self) -> Self::Output {
+        // Moving our flip past the rotation of `rhs` reverses that rotation
+        // if we only mirror along one axis.
+        let rhs_rotation = if self.flip.is_mirrored() {
+            rhs.rotation.inverse()
+        } else {
+            rhs.rotation
+        };
+
         Self {
-            translation: self.translation + (rhs.translation * self.scale).rotate(self.rotation),
-            rotation: self.rotation * rhs.rotation,
+            translation: self.translation
+                + (rhs.translation * self.scale)
+                    .flip(self.flip)
+                    .rotate(self.rotation),
+            rotation: self.rotation * rhs_rotation,
             scale: self.scale * rhs.scale,
+            flip: self.flip ^ rhs.flip,
         }
     }
 }
@@ -256,7 +319,7 @@ impl MulAssign for Transform {
 impl Vec2 {
     #[inline]
     pub fn transform(self, transform: Transform) -> Self {
-        self.rotate(transform.rotation) + transform.translation
+        self.flip(transform.flip).rotate(transform.rotation) + transform.translation
     }
 }
 
@@ -478,9 +541,12 @@ impl Direction {
         }
     }
 
+    /// Rotates the direction the same way `Vec2::rotate` rotates a vector.
     #[inline]
     pub fn rotate(self, rotation: Rotation) -> Self {
-        match ((self as u8) + (rotation as u8)) % 4 {
+        // Directions are ordered counter-clockwise on screen, while rotations
+        // are clockwise on screen (since +Y points down).
+        match ((self as u8) + 4 - (rotation as u8)) % 4 {
             0 => Self::PosX,
             1 => Self::NegY,
             2 => Self::NegX,
@@ -488,6 +554,22 @@ impl Direction {
             _ => unreachable!(),
         }
     }
+
+    /// Mirrors the direction the same way `Vec2::flip` mirrors a vector.
+    #[inline]
+    pub fn flip(self, flip: Flip) -> Self {
+        match self {
+            Self::PosX | Self::NegX if flip.contains(Flip::HORIZONTAL) => self.opposite(),
+            Self::PosY | Self::NegY if flip.contains(Flip::VERTICAL) => self.opposite(),
+            _ => self,
+        }
+    }
+
+    /// Flips and then rotates the direction, like `Vec2::transform`.
+    #[inline]
+    pub fn transform(self, transform: Transform) -> Self {
+        self.flip(transform.flip).rotate(transform.rotation)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deref, Component, Reflect)]
@@ -540,12 +622,34 @@ impl Default for Directions {
 }
 
 impl Directions {
+    /// Rotates the directions the same way `Vec2::rotate` rotates a vector.
     #[inline]
     pub fn rotate(self, rotation: Rotation) -> Self {
-        let shifted = self.bits() << (rotation as u8);
-        let rotated = (shifted & 0xF) | (shifted >> 4);
+        let shifted = (self.bits() as u16) << (4 - (rotation as u8));
+        let rotated = ((shifted & 0xF) | (shifted >> 4)) as u8;
         Self::from_bits(rotated).expect("invalid rotation")
     }
+
+    /// Mirrors the directions the same way `Vec2::flip` mirrors a vector.
+    #[inline]
+    pub fn flip(self, flip: Flip) -> Self {
+        let mut flipped = self;
+        if flip.contains(Flip::HORIZONTAL) {
+            flipped.set(Self::POS_X, self.contains(Self::NEG_X));
+            flipped.set(Self::NEG_X, self.contains(Self::POS_X));
+        }
+        if flip.contains(Flip::VERTICAL) {
+            flipped.set(Self::POS_Y, self.contains(Self::NEG_Y));
+            flipped.set(Self::NEG_Y, self.contains(Self::POS_Y));
+        }
+        flipped
+    }
+
+    /// Flips and then rotates the directions, like `Vec2::transform`.
+    #[inline]
+    pub fn transform(self, transform: Transform) -> Self {
+        self.flip(transform.flip).rotate(transform.rotation)
+    }
 }
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash, Deref, Component, Reflect)]
@@ -613,7 +717,7 @@ fn update_direction(
     >,
 ) {
     for (dir, mut abs_dir, transform) in query.iter_mut() {
-        abs_dir.0 = dir.rotate(transform.rotation);
+        abs_dir.0 = dir.transform(**transform);
     }
 }
 
@@ -624,7 +728,7 @@ fn update_directions(
     >,
 ) {
     for (dirs, mut abs_dirs, transform) in query.iter_mut() {
-        abs_dirs.0 = dirs.rotate(transform.rotation);
+        abs_dirs.0 = dirs.transform(**transform);
     }
 }
 
@@ -637,6 +741,7 @@ impl bevy_app::Plugin for TransformPlugin {
     fn build(&self, app: &mut bevy_app::App) {
         app.register_type::<Vec2>()
             .register_type::<Rotation>()
+            .register_type::<Flip>()
             .register_type::<Transform>()
             .register_type::<BoundingBox>()
             .register_type::<Direction>()
@@ -659,3 +764,63 @@ impl bevy_app::Plugin for TransformPlugin {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flipped_transforms_compose() {
+        const ROTATIONS: [Rotation; 4] = [
+            Rotation::Rot0,
+            Rotation::Rot90,
+            Rotation::Rot180,
+            Rotation::Rot270,
+        ];
+        const FLIPS: [Flip; 4] = [Flip::empty(), Flip::HORIZONTAL, Flip::VERTICAL, Flip::all()];
+
+        let transforms: Vec<_> = ROTATIONS
+            .into_iter()
+            .flat_map(|rotation| {
+                FLIPS.into_iter().map(move |flip| Transform {
+                    translation: Vec2 {
+                        x: fixed!(10),
+                        y: fixed!(-20),
+                    },
+                    rotation,
+                    flip,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let point = Vec2 {
+            x: fixed!(3),
+            y: fixed!(7),
+        };
+
+        for &a in transforms.iter() {
+            for &b in transforms.iter() {
+                assert_eq!(
+                    point.transform(a * b),
+                    point.transform(b).transform(a),
+                    "{a:?} * {b:?}"
+                );
+                for direction in Direction::ALL {
+                    assert_eq!(
+                        direction.transform(a * b),
+                        direction.transform(b).transform(a)
+                    );
+                }
+            }
+        }
+
+        let flipped = Transform {
+            flip: Flip::HORIZONTAL,
+            ..Default::default()
+        };
+        assert_eq!(
+            (Directions::POS_X | Directions::NEG_Y).transform(flipped),
+            Directions::NEG_X | Directions::NEG_Y
+        );
+    }
+}
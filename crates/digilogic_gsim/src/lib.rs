@@ -166,6 +166,50 @@ impl SimServer for GsimServer {
             .map_err(component_error_to_server_error)
     }
 
+    fn add_buffer(
+        &mut self,
+        client_id: ClientId,
+        width: NonZeroU8,
+        input: Self::NetId,
+        output: Self::NetId,
+    ) -> ServerResult<Self::CellId> {
+        let builder = self.get_builder_mut(client_id)?;
+
+        let output_width = builder
+            .get_wire_width(output)
+            .map_err(|_| ServerError::InvalidNetId)?;
+        if width != output_width {
+            return Err(ServerError::WidthMismatch);
+        }
+
+        // gsim has no plain buffer, ANDing the input with itself passes it through
+        builder
+            .add_and_gate(&[input, input], output)
+            .map_err(component_error_to_server_error)
+    }
+
+    fn add_tri_state_buffer(
+        &mut self,
+        client_id: ClientId,
+        width: NonZeroU8,
+        input: Self::NetId,
+        enable: Self::NetId,
+        output: Self::NetId,
+    ) -> ServerResult<Self::CellId> {
+        let builder = self.get_builder_mut(client_id)?;
+
+        let output_width = builder
+            .get_wire_width(output)
+            .map_err(|_| ServerError::InvalidNetId)?;
+        if width != output_width {
+            return Err(ServerError::WidthMismatch);
+        }
+
+        builder
+            .add_buffer(input, enable, output)
+            .map_err(component_error_to_server_error)
+    }
+
     fn add_mux(
         &mut self,
         client_id: ClientId,
@@ -191,6 +235,29 @@ impl SimServer for GsimServer {
             .map_err(component_error_to_server_error)
     }
 
+    fn add_slice(
+        &mut self,
+        client_id: ClientId,
+        input: Self::NetId,
+        offset: u8,
+        output: Self::NetId,
+    ) -> ServerResult<Self::CellId> {
+        self.get_builder_mut(client_id)?
+            .add_slice(input, offset, output)
+            .map_err(component_error_to_server_error)
+    }
+
+    fn add_merge(
+        &mut self,
+        client_id: ClientId,
+        inputs: &[Self::NetId],
+        output: Self::NetId,
+    ) -> ServerResult<Self::CellId> {
+        self.get_builder_mut(client_id)?
+            .add_merge(inputs, output)
+            .map_err(component_error_to_server_error)
+    }
+
     fn set_net_drive(
         &mut self,
         client_id: ClientId,
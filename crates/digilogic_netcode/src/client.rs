@@ -4,12 +4,16 @@ use bevy_app::prelude::*;
 use bevy_ecs::prelude::*;
 use bevy_ecs::system::lifetimeless::Read;
 use bevy_ecs::system::SystemParam;
+use bevy_log::warn;
 use bevy_reflect::prelude::*;
 use bevy_state::prelude::*;
 use bevy_time::prelude::*;
 use digilogic_core::components::*;
+use digilogic_core::erc::{ErcReport, ErcSet};
+use digilogic_core::labels::LabelNets;
 use digilogic_core::resources::Project;
 use digilogic_core::states::*;
+use digilogic_core::transform::Transform;
 use digilogic_core::{HashMap, SharedStr, StateMut};
 use std::net::ToSocketAddrs;
 
@@ -63,6 +67,31 @@ pub struct Connect {
 #[derive(Debug, Clone, Reflect, Event)]
 pub struct Disconnect;
 
+/// Why a simulation couldn't be built from the root circuit
+#[derive(Debug, Clone, PartialEq, Eq, Reflect)]
+pub enum BuildError {
+    /// The circuit didn't pass the electrical rule check
+    Erc { circuit: Entity, name: SharedStr },
+    /// The circuit contains itself, directly or through other sub-circuits
+    Recursive { circuit: Entity, name: SharedStr },
+    /// A sub-circuit symbol instantiates something that isn't a circuit
+    MissingCircuit,
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Erc { name, .. } => write!(f, "{name} has electrical rule check errors"),
+            Self::Recursive { name, .. } => write!(f, "{name} contains itself"),
+            Self::MissingCircuit => f.write_str("a sub-circuit instantiates a missing circuit"),
+        }
+    }
+}
+
+/// Sent when the simulation couldn't be built, right before disconnecting
+#[derive(Debug, Clone, Reflect, Event)]
+pub struct BuildFailed(pub BuildError);
+
 fn connect(
     trigger: Trigger<Connect>,
     mut commands: Commands,
@@ -180,15 +209,17 @@ fn send_input_states(
     next_message_id: &mut NextMessageId,
     inputs: &Query<(&SimNet, &LogicState), With<Symbol>>,
 ) {
-    for (input_net, input_state) in inputs.iter() {
-        client.send_command_message(ClientMessage {
-            id: next_message_id.get(),
-            kind: ClientMessageKind::SetNetDrive {
-                net: input_net.0,
-                bit_plane_0: input_state.bit_plane_0.as_slice().to_vec(),
-                bit_plane_1: input_state.bit_plane_1.as_slice().to_vec(),
-            },
-        });
+    for (input_nets, input_state) in inputs.iter() {
+        for &net in input_nets.0.iter() {
+            client.send_command_message(ClientMessage {
+                id: next_message_id.get(),
+                kind: ClientMessageKind::SetNetDrive {
+                    net,
+                    bit_plane_0: input_state.bit_plane_0.as_slice().to_vec(),
+                    bit_plane_1: input_state.bit_plane_1.as_slice().to_vec(),
+                },
+            });
+        }
     }
 }
 
@@ -269,161 +300,553 @@ fn process_eval_events(
 }
 
 #[derive(Debug, Clone, Component)]
-pub struct SimNet(NetId);
+pub struct SimNet(Vec<NetId>);
 
-type CircuitQuery<'w, 's> = Query<'w, 's, ((), Relations<Child>), With<Circuit>>;
-type SymbolQuery<'w, 's> =
-    Query<'w, 's, ((Entity, Read<SymbolKind>), Relations<Child>), With<Symbol>>;
-type PortQuery<'w, 's> = Query<'w, 's, (Option<Read<NetID>>, Has<Input>, Has<Output>), With<Port>>;
-type NetQuery<'w, 's> = Query<'w, 's, Entity, With<Net>>;
+type CircuitQuery<'w, 's> = Query<'w, 's, (Entity, Relations<Child>), With<Circuit>>;
+type ReportQuery<'w, 's> = Query<'w, 's, (Read<Name>, Option<Read<ErcReport>>), With<Circuit>>;
+type SymbolQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        (
+            Entity,
+            Read<SymbolKind>,
+            Read<Name>,
+            Read<Transform>,
+            Option<Read<CircuitID>>,
+        ),
+        Relations<Child>,
+    ),
+    With<Symbol>,
+>;
+type PortQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        Read<Name>,
+        Option<Read<NetID>>,
+        Read<BitWidth>,
+        Has<Input>,
+        Has<Output>,
+    ),
+    With<Port>,
+>;
+type NetQuery<'w, 's> = Query<'w, 's, (Entity, Read<BitWidth>), With<Net>>;
+type EndpointQuery<'w, 's> = Query<'w, 's, (Read<PortID>, Read<Bits>), With<Endpoint>>;
 
 #[derive(SystemParam)]
 struct BuildQueries<'w, 's> {
     circuits: CircuitQuery<'w, 's>,
+    reports: ReportQuery<'w, 's>,
     symbols: SymbolQuery<'w, 's>,
     ports: PortQuery<'w, 's>,
     nets: NetQuery<'w, 's>,
+    endpoints: EndpointQuery<'w, 's>,
 }
 
-fn build(
-    mut commands: Commands,
-    mut client: ResMut<RenetClient>,
-    project: Res<Project>,
-    mut next_message_id: ResMut<NextMessageId>,
-    queries: BuildQueries,
-) {
-    let root_circuit = project
-        .root_circuit
-        .expect("simulation started with no root");
-    let (_, root_children) = queries
-        .circuits
-        .get(root_circuit.0)
-        .expect("invalid root circuit");
+#[derive(Debug, Clone, Copy)]
+struct BuiltNet {
+    id: NetId,
+    offset: u64,
+    width: NonZeroU8,
+}
 
-    client.send_command_message(ClientMessage {
-        id: next_message_id.get(),
-        kind: ClientMessageKind::BeginBuild,
-    });
+/// Sends the nets and cells of a simulation, keeping track of where the state
+/// of each net ends up in the reports.
+/// Where a [`SimBuilder`] sends the messages building the simulation.
+trait BuildSink {
+    fn send(&mut self, message: ClientMessage);
+}
 
-    let mut net_map = HashMap::default();
+impl BuildSink for RenetClient {
+    fn send(&mut self, message: ClientMessage) {
+        self.send_command_message(message);
+    }
+}
 
-    let mut net_id = NetId(0);
-    let mut offset = 0u64;
-    root_children.join::<Child>(&queries.nets).for_each(|net| {
-        client.send_command_message(ClientMessage {
-            id: next_message_id.get(),
-            kind: ClientMessageKind::AddNet {
-                width: NonZeroU8::MIN, // TODO: use actual net width
-            },
+struct SimBuilder<'a> {
+    client: &'a mut dyn BuildSink,
+    next_message_id: &'a mut NextMessageId,
+    next_net_id: NetId,
+    next_offset: u64,
+}
+
+impl SimBuilder<'_> {
+    fn send(&mut self, kind: ClientMessageKind) {
+        self.client.send(ClientMessage {
+            id: self.next_message_id.get(),
+            kind,
         });
+    }
 
-        commands.entity(net).insert(StateOffset(offset));
-        net_map.insert(net, (net_id, offset));
-
-        net_id.0 += 1;
-        offset += 1; // TODO: use actual net width
-    });
-
-    root_children.join::<Child>(&queries.symbols).for_each(
-        |((symbol, symbol_kind), symbol_children)| {
-            if matches!(symbol_kind, SymbolKind::In | SymbolKind::Out) {
-                let mut first = true;
-                symbol_children
-                    .join::<Child>(&queries.ports)
-                    .for_each(|(connected_net, _, _)| {
-                        assert!(first, "input/output symbol has more than one port");
-                        first = false;
-
-                        if let Some(connected_net) = connected_net {
-                            let &(net_id, net_offset) = net_map
-                                .get(&connected_net.0)
-                                .expect("port connected to invalid net");
-                            commands.entity(symbol).insert(StateOffset(net_offset));
-
-                            if *symbol_kind == SymbolKind::In {
-                                // Note: only do this for the root
-                                commands.entity(symbol).insert(SimNet(net_id));
-                            }
-                        }
+    fn add_net(&mut self, width: NonZeroU8) -> BuiltNet {
+        self.send(ClientMessageKind::AddNet { width });
+
+        let net = BuiltNet {
+            id: self.next_net_id,
+            offset: self.next_offset,
+            width,
+        };
+        self.next_net_id.0 += 1;
+        self.next_offset += width.get() as u64;
+        net
+    }
+
+    /// The net a port connects to when it only uses `bits` of `net`. Inputs
+    /// get their bits sliced out of the net, outputs get theirs merged into it.
+    fn connect_bits(&mut self, net: BuiltNet, bits: &Bits, is_output: bool) -> BuiltNet {
+        let width = NonZeroU8::new(bits.0.len() as u8).expect("port uses no bits of its net");
+        let port_net = self.add_net(width);
+
+        let runs: Vec<_> = bits.runs().collect();
+        let mut pieces = Vec::with_capacity(runs.len());
+        let mut port_offset = 0;
+        for run in runs.iter() {
+            let run_width = NonZeroU8::new(run.end - run.start).unwrap();
+            let piece = if runs.len() == 1 {
+                port_net
+            } else {
+                self.add_net(run_width)
+            };
+
+            if is_output {
+                if runs.len() > 1 {
+                    self.send(ClientMessageKind::AddSlice {
+                        input: port_net.id,
+                        offset: port_offset,
+                        output: piece.id,
                     });
-                assert!(!first, "input/output symbol has no ports");
+                }
+
+                // The other bits of the net are left undriven, so other ports can drive them
+                let mut inputs = Vec::with_capacity(3);
+                if let Some(low_width) = NonZeroU8::new(run.start) {
+                    inputs.push(self.add_net(low_width).id);
+                }
+                inputs.push(piece.id);
+                if let Some(high_width) = NonZeroU8::new(net.width.get().saturating_sub(run.end)) {
+                    inputs.push(self.add_net(high_width).id);
+                }
+                self.send(ClientMessageKind::AddMerge {
+                    inputs,
+                    output: net.id,
+                });
             } else {
-                let mut inputs = Vec::new();
-                let mut output = None;
+                self.send(ClientMessageKind::AddSlice {
+                    input: net.id,
+                    offset: run.start,
+                    output: piece.id,
+                });
+            }
+
+            pieces.push(piece.id);
+            port_offset += run_width.get();
+        }
+
+        if !is_output && (pieces.len() > 1) {
+            self.send(ClientMessageKind::AddMerge {
+                inputs: pieces,
+                output: port_net.id,
+            });
+        }
+
+        port_net
+    }
+}
+
+/// Checks that `circuit` and the circuits it instantiates can be simulated,
+/// they have to pass the electrical rule check and can't contain themselves.
+fn check_hierarchy(
+    queries: &BuildQueries,
+    circuit: Entity,
+    stack: &mut Vec<Entity>,
+) -> Result<(), BuildError> {
+    let Ok((name, erc_report)) = queries.reports.get(circuit) else {
+        return Err(BuildError::MissingCircuit);
+    };
+    let name = name.0.clone();
+    if stack.contains(&circuit) {
+        return Err(BuildError::Recursive { circuit, name });
+    }
+    if erc_report.is_some_and(|report| report.errors > 0) {
+        return Err(BuildError::Erc { circuit, name });
+    }
+
+    // Circuits without children have nothing else to check
+    let Ok((_, children)) = queries.circuits.get(circuit) else {
+        return Ok(());
+    };
+
+    stack.push(circuit);
+    let mut result = Ok(());
+    children
+        .join::<Child>(&queries.symbols)
+        .for_each(|((_, _, _, _, sub_circuit), _)| {
+            if let (Ok(()), Some(&CircuitID(sub_circuit))) = (&result, sub_circuit) {
+                result = check_hierarchy(queries, sub_circuit, stack);
+            }
+        });
+    stack.pop();
+
+    result
+}
+
+/// Flattens the circuit hierarchy into one simulation. Every instance of a
+/// sub-circuit gets its own nets and cells, connected to the nets of the
+/// instance's ports through buffers.
+struct Flattener<'a, 'w, 's> {
+    builder: SimBuilder<'a>,
+    queries: &'a BuildQueries<'w, 's>,
+    port_bits: HashMap<Entity, &'a Bits>,
+    /// The nets of the global labels, which connect across the hierarchy
+    globals: HashMap<SharedStr, BuiltNet>,
+    /// Where the state of the nets and pins of the root circuit ends up
+    offsets: Vec<(Entity, StateOffset)>,
+    /// The nets the inputs of the root circuit and every instance of a
+    /// constant drive
+    drives: HashMap<Entity, Vec<NetId>>,
+}
+
+impl Flattener<'_, '_, '_> {
+    fn port_net(
+        &mut self,
+        net_map: &HashMap<Entity, BuiltNet>,
+        port: Entity,
+        net: Entity,
+        is_output: bool,
+    ) -> BuiltNet {
+        let &net = net_map.get(&net).expect("port connected to invalid net");
+        match self.port_bits.get(&port) {
+            Some(bits) => self.builder.connect_bits(net, bits, is_output),
+            None => net,
+        }
+    }
+
+    /// The In and Out symbols of `circuit`, in the order of the ports of the
+    /// sub-circuit symbols instantiating it.
+    fn pins(&self, circuit: Entity) -> Vec<(Entity, SharedStr, bool)> {
+        let Ok((_, children)) = self.queries.circuits.get(circuit) else {
+            return Vec::new();
+        };
+
+        let mut pins = Vec::new();
+        children.join::<Child>(&self.queries.symbols).for_each(
+            |((symbol, symbol_kind, name, transform, _), _)| {
+                let output = match symbol_kind {
+                    SymbolKind::In => false,
+                    SymbolKind::Out => true,
+                    _ => return,
+                };
+                let position = transform.translation;
+                pins.push((
+                    (output, position.y, position.x),
+                    (symbol, name.0.clone(), output),
+                ));
+            },
+        );
+        pins.sort_by_key(|&(key, _)| key);
+
+        pins.into_iter().map(|(_, pin)| pin).collect()
+    }
+
+    /// Builds an instance of `circuit`, with `pins` mapping its In and Out
+    /// symbols to the nets they connect to outside of it. Only the root
+    /// circuit has no pins, its entities get the offsets of their state.
+    fn build_circuit(&mut self, circuit: Entity, pins: &HashMap<Entity, BuiltNet>, root: bool) {
+        let queries = self.queries;
+        let Ok((_, children)) = queries.circuits.get(circuit) else {
+            return;
+        };
+
+        let mut labels = LabelNets::default();
+        children.join::<Child>(&queries.symbols).for_each(
+            |((_, symbol_kind, name, _, _), symbol_children)| {
+                let global = match symbol_kind {
+                    SymbolKind::Label => false,
+                    SymbolKind::GlobalLabel => true,
+                    _ => return,
+                };
 
-                // TODO: this only works for basic gates
                 symbol_children.join::<Child>(&queries.ports).for_each(
-                    |(connected_net, is_input, is_output)| {
-                        let &(net_id, _) = net_map
-                            .get(&connected_net.expect("unconnected port").0)
-                            .expect("port connected to invalid net");
-
-                        match (is_input, is_output) {
-                            (true, true) => panic!("unsupported bidirectional port"),
-                            (true, false) => inputs.push(net_id),
-                            (false, true) => {
-                                assert!(output.is_none(), "multiple output ports");
-                                output = Some(net_id);
-                            }
-                            (false, false) => panic!("port with missing direction"),
+                    |(_, _, connected_net, _, _, _)| {
+                        let Some(&NetID(net)) = connected_net else {
+                            return;
+                        };
+                        let (_, &bit_width) = queries.nets.get(net).expect("invalid net");
+                        // The ERC reports mismatched widths, this label is left
+                        // unconnected if one slipped through anyway
+                        if let Err(err) = labels.add(&name.0, global, net, bit_width) {
+                            warn!("not connecting label: {err}");
                         }
                     },
                 );
+            },
+        );
+        let global_names: HashMap<_, _> = labels
+            .globals()
+            .map(|(name, net, _)| (net, name.clone()))
+            .collect();
+
+        // Nets connected by labels share the net built for one of them
+        let mut net_map = HashMap::default();
+        children
+            .join::<Child>(&queries.nets)
+            .for_each(|(net, bit_width)| {
+                if labels.resolve(net) != net {
+                    return;
+                }
 
-                let output = output.expect("missing output port");
+                let built = match global_names.get(&net) {
+                    Some(name) => match self.globals.get(name) {
+                        Some(&global) if global.width == bit_width.0 => global,
+                        // The ERC doesn't compare global labels across circuits
+                        Some(_) => self.builder.add_net(bit_width.0),
+                        None => {
+                            let global = self.builder.add_net(bit_width.0);
+                            self.globals.insert(name.clone(), global);
+                            global
+                        }
+                    },
+                    None => self.builder.add_net(bit_width.0),
+                };
+                net_map.insert(net, built);
+            });
+        children.join::<Child>(&queries.nets).for_each(|(net, _)| {
+            let built = net_map[&labels.resolve(net)];
+            if root {
+                self.offsets.push((net, StateOffset(built.offset)));
+            }
+            net_map.insert(net, built);
+        });
+
+        children.join::<Child>(&queries.symbols).for_each(
+            |((symbol, symbol_kind, _, _, sub_circuit), symbol_children)| {
+                if matches!(
+                    symbol_kind,
+                    SymbolKind::In | SymbolKind::Out | SymbolKind::Const
+                ) {
+                    let mut first = true;
+                    symbol_children.join::<Child>(&queries.ports).for_each(
+                        |(port, _, connected_net, _, _, is_output)| {
+                            assert!(first, "input/output symbol has more than one port");
+                            first = false;
+
+                            let Some(connected_net) = connected_net else {
+                                return;
+                            };
+                            let net = self.port_net(&net_map, port, connected_net.0, is_output);
+                            if root {
+                                self.offsets.push((symbol, StateOffset(net.offset)));
+                            }
+
+                            match (symbol_kind, pins.get(&symbol)) {
+                                // Constants drive their net just like inputs do.
+                                (SymbolKind::In, _) if root => {
+                                    self.drives.entry(symbol).or_default().push(net.id)
+                                }
+                                (SymbolKind::Const, _) => {
+                                    self.drives.entry(symbol).or_default().push(net.id)
+                                }
+                                (SymbolKind::In, Some(outer)) if outer.width == net.width => {
+                                    self.builder.send(ClientMessageKind::AddBuffer {
+                                        width: net.width,
+                                        input: outer.id,
+                                        output: net.id,
+                                    })
+                                }
+                                (SymbolKind::Out, Some(outer)) if outer.width == net.width => {
+                                    self.builder.send(ClientMessageKind::AddBuffer {
+                                        width: net.width,
+                                        input: net.id,
+                                        output: outer.id,
+                                    })
+                                }
+                                _ => {}
+                            }
+                        },
+                    );
+                    assert!(!first, "input/output symbol has no ports");
+                } else if matches!(symbol_kind, SymbolKind::SubCircuit) {
+                    let Some(&CircuitID(sub_circuit)) = sub_circuit else {
+                        return;
+                    };
+
+                    // Ports are matched to the pins with the same name and
+                    // direction, in order.
+                    let mut sub_pins = self.pins(sub_circuit);
+                    let mut sub_pin_nets = HashMap::default();
+                    symbol_children.join::<Child>(&queries.ports).for_each(
+                        |(port, name, connected_net, _, _, is_output)| {
+                            let Some(index) = sub_pins.iter().position(|(_, pin_name, output)| {
+                                *pin_name == name.0 && *output == is_output
+                            }) else {
+                                return;
+                            };
+                            let (pin, _, _) = sub_pins.remove(index);
 
-                match symbol_kind {
-                    SymbolKind::In | SymbolKind::Out => unreachable!(),
+                            if let Some(connected_net) = connected_net {
+                                let net = self.port_net(&net_map, port, connected_net.0, is_output);
+                                sub_pin_nets.insert(pin, net);
+                            }
+                        },
+                    );
+
+                    self.build_circuit(sub_circuit, &sub_pin_nets, false);
+                } else if matches!(symbol_kind, SymbolKind::Custom) {
+                    // Black boxes have no logic to simulate, their outputs stay undriven
+                } else if matches!(symbol_kind, SymbolKind::Label | SymbolKind::GlobalLabel) {
+                    // Labels were already handled by merging their nets
+                } else {
+                    let mut inputs = Vec::new();
+                    let mut output = None;
+
+                    // TODO: this only works for basic gates
+                    symbol_children.join::<Child>(&queries.ports).for_each(
+                        |(port, _, connected_net, bit_width, is_input, is_output)| {
+                            match (is_input, is_output) {
+                                // The electrical rule check refuses gates with
+                                // these ports, so the build never gets here
+                                (true, true) | (false, false) => {}
+                                (false, true) if output.is_some() => {}
+                                (true, false) => {
+                                    let connected_net = connected_net.expect("unconnected input").0;
+                                    inputs.push(
+                                        self.port_net(&net_map, port, connected_net, false).id,
+                                    )
+                                }
+                                (false, true) => {
+                                    // An unconnected output still needs a net to drive
+                                    output = Some(match connected_net {
+                                        Some(net) => self.port_net(&net_map, port, net.0, true),
+                                        None => self.builder.add_net(bit_width.0),
+                                    });
+                                }
+                            }
+                        },
+                    );
 
-                    SymbolKind::And => client.send_command_message(ClientMessage {
-                        id: next_message_id.get(),
-                        kind: ClientMessageKind::AddAndGate {
-                            width: NonZeroU8::MIN, // TODO: use actual net width
+                    let BuiltNet {
+                        id: output, width, ..
+                    } = output.expect("missing output port");
+                    let builder = &mut self.builder;
+                    match symbol_kind {
+                        SymbolKind::In
+                        | SymbolKind::Out
+                        | SymbolKind::Const
+                        | SymbolKind::SubCircuit
+                        | SymbolKind::Custom
+                        | SymbolKind::Label
+                        | SymbolKind::GlobalLabel => unreachable!(),
+
+                        SymbolKind::And => builder.send(ClientMessageKind::AddAndGate {
+                            width,
                             inputs,
                             output,
-                        },
-                    }),
-                    SymbolKind::Or => client.send_command_message(ClientMessage {
-                        id: next_message_id.get(),
-                        kind: ClientMessageKind::AddOrGate {
-                            width: NonZeroU8::MIN, // TODO: use actual net width
+                        }),
+                        SymbolKind::Or => builder.send(ClientMessageKind::AddOrGate {
+                            width,
                             inputs,
                             output,
-                        },
-                    }),
-                    SymbolKind::Xor => client.send_command_message(ClientMessage {
-                        id: next_message_id.get(),
-                        kind: ClientMessageKind::AddXorGate {
-                            width: NonZeroU8::MIN, // TODO: use actual net width
+                        }),
+                        SymbolKind::Xor => builder.send(ClientMessageKind::AddXorGate {
+                            width,
                             inputs,
                             output,
-                        },
-                    }),
-                    SymbolKind::Not => client.send_command_message(ClientMessage {
-                        id: next_message_id.get(),
-                        kind: ClientMessageKind::AddNotGate {
-                            width: NonZeroU8::MIN, // TODO: use actual net width
+                        }),
+                        SymbolKind::Nand => builder.send(ClientMessageKind::AddNandGate {
+                            width,
+                            inputs,
+                            output,
+                        }),
+                        SymbolKind::Nor => builder.send(ClientMessageKind::AddNorGate {
+                            width,
+                            inputs,
+                            output,
+                        }),
+                        SymbolKind::Xnor => builder.send(ClientMessageKind::AddXnorGate {
+                            width,
+                            inputs,
+                            output,
+                        }),
+                        SymbolKind::Buffer => builder.send(ClientMessageKind::AddBuffer {
+                            width,
                             input: inputs[0],
                             output,
-                        },
-                    }),
-                    SymbolKind::Mux => client.send_command_message(ClientMessage {
-                        id: next_message_id.get(),
-                        kind: ClientMessageKind::AddMux {
-                            width: NonZeroU8::MIN, // TODO: use actual net width
+                        }),
+                        SymbolKind::TriState => {
+                            builder.send(ClientMessageKind::AddTriStateBuffer {
+                                width,
+                                input: inputs[0],
+                                enable: inputs[1],
+                                output,
+                            })
+                        }
+                        SymbolKind::Not => builder.send(ClientMessageKind::AddNotGate {
+                            width,
+                            input: inputs[0],
+                            output,
+                        }),
+                        SymbolKind::Mux => builder.send(ClientMessageKind::AddMux {
+                            width,
                             inputs,
                             output,
-                        },
-                    }),
+                        }),
+                    }
                 }
-            }
+            },
+        );
+    }
+}
+
+fn build(
+    mut commands: Commands,
+    mut build_failed: EventWriter<BuildFailed>,
+    mut client: ResMut<RenetClient>,
+    project: Res<Project>,
+    mut next_message_id: ResMut<NextMessageId>,
+    queries: BuildQueries,
+) {
+    let root_circuit = project
+        .root_circuit
+        .expect("simulation started with no root");
+
+    // The electrical rule check ran right before, the simulation can't be
+    // built if it found errors. They're shown on the circuits instead.
+    if let Err(err) = check_hierarchy(&queries, root_circuit.0, &mut Vec::new()) {
+        warn!("can't build the simulation: {err}");
+        build_failed.send(BuildFailed(err));
+        commands.trigger(Disconnect);
+        return;
+    }
+
+    let mut flattener = Flattener {
+        builder: SimBuilder {
+            client: &mut *client,
+            next_message_id: &mut next_message_id,
+            next_net_id: NetId(0),
+            next_offset: 0,
         },
-    );
+        queries: &queries,
+        port_bits: queries
+            .endpoints
+            .iter()
+            .map(|(port, bits)| (port.0, bits))
+            .collect(),
+        globals: HashMap::default(),
+        offsets: Vec::new(),
+        drives: HashMap::default(),
+    };
+    flattener.builder.send(ClientMessageKind::BeginBuild);
+    flattener.build_circuit(root_circuit.0, &HashMap::default(), true);
+    flattener.builder.send(ClientMessageKind::EndBuild);
 
-    client.send_command_message(ClientMessage {
-        id: next_message_id.get(),
-        kind: ClientMessageKind::EndBuild,
-    });
+    for (entity, offset) in flattener.offsets {
+        commands.entity(entity).insert(offset);
+    }
+    for (symbol, nets) in flattener.drives {
+        commands.entity(symbol).insert(SimNet(nets));
+    }
 }
 
 #[derive(Default, Debug)]
@@ -435,9 +858,10 @@ impl Plugin for ClientPlugin {
             .register_type::<StateOffset>()
             .register_type::<NextMessageId>()
             .register_type::<Connect>()
-            .register_type::<Disconnect>();
+            .register_type::<Disconnect>()
+            .register_type::<BuildFailed>();
 
-        app.add_event::<Eval>();
+        app.add_event::<Eval>().add_event::<BuildFailed>();
 
         app.init_resource::<NextMessageId>()
             .add_event::<NetcodeTransportError>()
@@ -465,7 +889,7 @@ impl Plugin for ClientPlugin {
                 .run_if(resource_exists::<NetcodeClientTransport>),
         );
 
-        app.add_systems(OnEnter(SimulationState::Building), build);
+        app.add_systems(OnEnter(SimulationState::Building), build.after(ErcSet));
 
         app.add_systems(
             Update,
@@ -473,3 +897,151 @@ impl Plugin for ClientPlugin {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl BuildSink for Vec<ClientMessage> {
+        fn send(&mut self, message: ClientMessage) {
+            self.push(message);
+        }
+    }
+
+    fn connect_bits(bits: &[u8], is_output: bool) -> Vec<ClientMessageKind> {
+        let mut messages = Vec::new();
+        let mut next_message_id = NextMessageId::default();
+        let mut builder = SimBuilder {
+            client: &mut messages,
+            next_message_id: &mut next_message_id,
+            next_net_id: NetId(0),
+            next_offset: 0,
+        };
+
+        let net = builder.add_net(NonZeroU8::new(4).unwrap());
+        let port_net = builder.connect_bits(net, &Bits(bits.iter().copied().collect()), is_output);
+        assert_eq!(port_net.id, NetId(1));
+        assert_eq!(port_net.width.get(), bits.len() as u8);
+
+        messages.into_iter().map(|message| message.kind).collect()
+    }
+
+    fn add_net(width: u8) -> ClientMessageKind {
+        ClientMessageKind::AddNet {
+            width: NonZeroU8::new(width).unwrap(),
+        }
+    }
+
+    #[test]
+    fn slices_bits_for_inputs() {
+        assert_eq!(
+            connect_bits(&[2, 3], false),
+            [
+                add_net(4),
+                add_net(2),
+                ClientMessageKind::AddSlice {
+                    input: NetId(0),
+                    offset: 2,
+                    output: NetId(1),
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn merges_bits_for_outputs() {
+        assert_eq!(
+            connect_bits(&[1, 0], true),
+            [
+                add_net(4),
+                add_net(2),
+                // Port bit 0 drives net bit 1
+                add_net(1),
+                ClientMessageKind::AddSlice {
+                    input: NetId(1),
+                    offset: 0,
+                    output: NetId(2),
+                },
+                add_net(1),
+                add_net(2),
+                ClientMessageKind::AddMerge {
+                    inputs: vec![NetId(3), NetId(2), NetId(4)],
+                    output: NetId(0),
+                },
+                // Port bit 1 drives net bit 0
+                add_net(1),
+                ClientMessageKind::AddSlice {
+                    input: NetId(1),
+                    offset: 1,
+                    output: NetId(5),
+                },
+                add_net(3),
+                ClientMessageKind::AddMerge {
+                    inputs: vec![NetId(5), NetId(6)],
+                    output: NetId(0),
+                },
+            ],
+        );
+    }
+
+    fn check(world: &mut World, circuit: Entity) -> Result<(), BuildError> {
+        world
+            .run_system_cached_with(
+                |In(circuit): In<Entity>, queries: BuildQueries| {
+                    check_hierarchy(&queries, circuit, &mut Vec::new())
+                },
+                circuit,
+            )
+            .unwrap()
+    }
+
+    fn spawn_circuit(world: &mut World, name: &'static str) -> Entity {
+        world
+            .spawn((Circuit, Name(SharedStr::new_static(name))))
+            .id()
+    }
+
+    fn instantiate(world: &mut World, circuit: Entity, sub_circuit: Entity) {
+        world
+            .spawn((
+                Symbol,
+                SymbolKind::SubCircuit,
+                Name(SharedStr::new_static("instance")),
+                Transform::default(),
+                CircuitID(sub_circuit),
+            ))
+            .set::<Child>(circuit);
+        world.flush();
+    }
+
+    #[test]
+    fn refuses_to_build_circuits_with_errors() {
+        let mut world = World::new();
+        let top = spawn_circuit(&mut world, "top");
+        let adder = spawn_circuit(&mut world, "adder");
+        instantiate(&mut world, top, adder);
+        assert_eq!(check(&mut world, top), Ok(()));
+
+        world.entity_mut(adder).insert(ErcReport {
+            errors: 1,
+            warnings: 0,
+        });
+        assert_eq!(
+            check(&mut world, top),
+            Err(BuildError::Erc {
+                circuit: adder,
+                name: SharedStr::new_static("adder"),
+            })
+        );
+
+        world.entity_mut(adder).remove::<ErcReport>();
+        instantiate(&mut world, adder, top);
+        assert_eq!(
+            check(&mut world, top),
+            Err(BuildError::Recursive {
+                circuit: top,
+                name: SharedStr::new_static("top"),
+            })
+        );
+    }
+}
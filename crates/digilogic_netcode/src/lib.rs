@@ -93,7 +93,7 @@ enum ServerMessage {
 }
 
 // TODO: uses borrowed slices instead of vecs
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 enum ClientMessageKind {
     BeginBuild,
     EndBuild,
@@ -136,6 +136,17 @@ enum ClientMessageKind {
         input: NetId,
         output: NetId,
     },
+    AddBuffer {
+        width: NonZeroU8,
+        input: NetId,
+        output: NetId,
+    },
+    AddTriStateBuffer {
+        width: NonZeroU8,
+        input: NetId,
+        enable: NetId,
+        output: NetId,
+    },
 
     AddMux {
         width: NonZeroU8,
@@ -143,6 +154,18 @@ enum ClientMessageKind {
         output: NetId,
     },
 
+    /// Drives `output` with the bits of `input` starting at `offset`
+    AddSlice {
+        input: NetId,
+        offset: u8,
+        output: NetId,
+    },
+    /// Drives `output` with all `inputs` side by side, the first one in the lowest bits
+    AddMerge {
+        inputs: Vec<NetId>,
+        output: NetId,
+    },
+
     SetNetDrive {
         net: NetId,
         bit_plane_0: Vec<u8>,
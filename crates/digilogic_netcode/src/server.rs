@@ -72,6 +72,29 @@ pub trait SimServer {
         Err(ServerError::Unsupported)
     }
 
+    fn add_buffer(
+        &mut self,
+        client_id: ClientId,
+        width: NonZeroU8,
+        input: Self::NetId,
+        output: Self::NetId,
+    ) -> ServerResult<Self::CellId> {
+        let _ = (client_id, width, input, output);
+        Err(ServerError::Unsupported)
+    }
+
+    fn add_tri_state_buffer(
+        &mut self,
+        client_id: ClientId,
+        width: NonZeroU8,
+        input: Self::NetId,
+        enable: Self::NetId,
+        output: Self::NetId,
+    ) -> ServerResult<Self::CellId> {
+        let _ = (client_id, width, input, enable, output);
+        Err(ServerError::Unsupported)
+    }
+
     fn add_mux(
         &mut self,
         client_id: ClientId,
@@ -83,6 +106,27 @@ pub trait SimServer {
         Err(ServerError::Unsupported)
     }
 
+    fn add_slice(
+        &mut self,
+        client_id: ClientId,
+        input: Self::NetId,
+        offset: u8,
+        output: Self::NetId,
+    ) -> ServerResult<Self::CellId> {
+        let _ = (client_id, input, offset, output);
+        Err(ServerError::Unsupported)
+    }
+
+    fn add_merge(
+        &mut self,
+        client_id: ClientId,
+        inputs: &[Self::NetId],
+        output: Self::NetId,
+    ) -> ServerResult<Self::CellId> {
+        let _ = (client_id, inputs, output);
+        Err(ServerError::Unsupported)
+    }
+
     fn set_net_drive(
         &mut self,
         client_id: ClientId,
@@ -335,6 +379,40 @@ impl<S: SimServer> Adapter<S> {
         Ok(())
     }
 
+    fn add_buffer(
+        &mut self,
+        client_id: ClientId,
+        width: NonZeroU8,
+        input: NetId,
+        output: NetId,
+    ) -> ServerResult<()> {
+        let client_state = client_state!(mut self, client_id);
+        let input = client_state.net_map[input];
+        let output = client_state.net_map[output];
+        let cell_id = self.inner.add_buffer(client_id, width, input, output)?;
+        client_state.cell_map.insert(cell_id)?;
+        Ok(())
+    }
+
+    fn add_tri_state_buffer(
+        &mut self,
+        client_id: ClientId,
+        width: NonZeroU8,
+        input: NetId,
+        enable: NetId,
+        output: NetId,
+    ) -> ServerResult<()> {
+        let client_state = client_state!(mut self, client_id);
+        let input = client_state.net_map[input];
+        let enable = client_state.net_map[enable];
+        let output = client_state.net_map[output];
+        let cell_id = self
+            .inner
+            .add_tri_state_buffer(client_id, width, input, enable, output)?;
+        client_state.cell_map.insert(cell_id)?;
+        Ok(())
+    }
+
     fn add_mux(
         &mut self,
         client_id: ClientId,
@@ -354,6 +432,39 @@ impl<S: SimServer> Adapter<S> {
         Ok(())
     }
 
+    fn add_slice(
+        &mut self,
+        client_id: ClientId,
+        input: NetId,
+        offset: u8,
+        output: NetId,
+    ) -> ServerResult<()> {
+        let client_state = client_state!(mut self, client_id);
+        let input = client_state.net_map[input];
+        let output = client_state.net_map[output];
+        let cell_id = self.inner.add_slice(client_id, input, offset, output)?;
+        client_state.cell_map.insert(cell_id)?;
+        Ok(())
+    }
+
+    fn add_merge(
+        &mut self,
+        client_id: ClientId,
+        inputs: &[NetId],
+        output: NetId,
+    ) -> ServerResult<()> {
+        let client_state = client_state!(mut self, client_id);
+        self.net_id_buffer.clear();
+        self.net_id_buffer
+            .extend(inputs.iter().map(|&id| client_state.net_map[id]));
+        let output = client_state.net_map[output];
+        let cell_id = self
+            .inner
+            .add_merge(client_id, &self.net_id_buffer, output)?;
+        client_state.cell_map.insert(cell_id)?;
+        Ok(())
+    }
+
     fn set_net_drive(
         &mut self,
         client_id: ClientId,
@@ -436,6 +547,17 @@ fn process_message<S: SimServer>(
             input,
             output,
         } => adapter.add_not_gate(client_id, width, input, output)?,
+        ClientMessageKind::AddBuffer {
+            width,
+            input,
+            output,
+        } => adapter.add_buffer(client_id, width, input, output)?,
+        ClientMessageKind::AddTriStateBuffer {
+            width,
+            input,
+            enable,
+            output,
+        } => adapter.add_tri_state_buffer(client_id, width, input, enable, output)?,
 
         ClientMessageKind::AddMux {
             width,
@@ -443,6 +565,15 @@ fn process_message<S: SimServer>(
             output,
         } => adapter.add_mux(client_id, width, &inputs, output)?,
 
+        ClientMessageKind::AddSlice {
+            input,
+            offset,
+            output,
+        } => adapter.add_slice(client_id, input, offset, output)?,
+        ClientMessageKind::AddMerge { inputs, output } => {
+            adapter.add_merge(client_id, &inputs, output)?
+        }
+
         ClientMessageKind::SetNetDrive {
             net,
             bit_plane_0,
@@ -216,6 +216,40 @@ fn route_on_endpoint_change(
     }
 }
 
+/// Reroutes the circuit an edit attached something to or detached something from.
+fn route_on_reparent(
+    parent: Entity,
+    commands: &mut Commands,
+    circuits: &Query<Entity, With<Circuit>>,
+    children: &Query<(Entity, Relations<Child>)>,
+) {
+    children
+        .traverse::<Up<Child>>([parent])
+        .for_each(|&mut entity, _| {
+            if circuits.contains(entity) {
+                commands.entity(entity).insert(GraphDirty);
+            }
+        });
+}
+
+fn route_on_attach(
+    trigger: Trigger<digilogic_core::history::Attached>,
+    mut commands: Commands,
+    circuits: Query<Entity, With<Circuit>>,
+    children: Query<(Entity, Relations<Child>)>,
+) {
+    route_on_reparent(trigger.event().parent, &mut commands, &circuits, &children);
+}
+
+fn route_on_detach(
+    trigger: Trigger<digilogic_core::history::Detached>,
+    mut commands: Commands,
+    circuits: Query<Entity, With<Circuit>>,
+    children: Query<(Entity, Relations<Child>)>,
+) {
+    route_on_reparent(trigger.event().parent, &mut commands, &circuits, &children);
+}
+
 #[derive(Debug, Default)]
 pub struct RoutingPlugin;
 
@@ -229,6 +263,8 @@ impl bevy_app::Plugin for RoutingPlugin {
         app.add_event::<RoutingComplete>();
         app.add_observer(inject_graph);
         app.add_observer(inject_vertices);
+        app.add_observer(route_on_attach);
+        app.add_observer(route_on_detach);
         app.add_systems(bevy_app::PreUpdate, route.in_set(RoutingSet));
         app.add_systems(bevy_app::PostUpdate, route_on_config_change);
         app.add_systems(
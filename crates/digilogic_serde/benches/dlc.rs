@@ -0,0 +1,55 @@
+//! Compares loading `testdata/large.dlc` as JSON with loading the same circuit
+//! in the binary `.dlcb` encoding. Run with `cargo bench -p digilogic_serde`.
+
+// The bench only uses the library, not the rest of its dependencies.
+#![allow(unused_crate_dependencies)]
+
+use digilogic_serde::dlc::CircuitFile;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 20;
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    // Warm up caches and the allocator before measuring.
+    f();
+
+    let mut fastest = Duration::MAX;
+    let mut total = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        f();
+        let elapsed = start.elapsed();
+        fastest = fastest.min(elapsed);
+        total += elapsed;
+    }
+
+    println!(
+        "{name:<16} mean {:>10.3?}  min {:>10.3?}",
+        total / ITERATIONS,
+        fastest
+    );
+}
+
+fn main() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/large.dlc");
+    let json = std::fs::read_to_string(path).unwrap();
+    let circuit = CircuitFile::try_from(json.as_str()).unwrap();
+    let binary = circuit.to_binary().unwrap();
+
+    println!(
+        "large.dlc: {} bytes as JSON, {} bytes as binary",
+        json.len(),
+        binary.len()
+    );
+
+    bench("parse json", || {
+        black_box(CircuitFile::try_from(black_box(json.as_str())).unwrap());
+    });
+    bench("parse binary", || {
+        black_box(CircuitFile::from_binary(black_box(&binary)).unwrap());
+    });
+    bench("encode binary", || {
+        black_box(circuit.to_binary().unwrap());
+    });
+}
@@ -1,30 +1,27 @@
 mod circuitfile;
 
-use aery::prelude::*;
+use crate::extract::*;
+use crate::wires::*;
+use crate::{back_up_source, load_circuit_file, record_save, FileId, FileRegistry};
 use anyhow::{bail, Result};
 use bevy_ecs::prelude::*;
-use bevy_log::info;
+use bevy_log::{info, warn};
 use digilogic_core::bundles::*;
 use digilogic_core::components::*;
-use digilogic_core::symbol::SymbolRegistry;
+use digilogic_core::symbol::{SubCircuitPort, SymbolRegistry};
 use digilogic_core::transform::*;
-use digilogic_core::visibility::VisibilityBundle;
-use digilogic_core::{fixed, HashMap, HashSet};
-use std::cell::Cell;
+use digilogic_core::{fixed, Fixed, HashMap};
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::BufReader;
 use std::num::NonZeroU8;
-use std::path::Path;
-
-struct PosEntry {
-    port: Option<Entity>,
-    endpoint: Cell<Option<Entity>>,
-    wires: Vec<[Vec2; 2]>,
-}
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 pub fn load_digital(
     commands: &mut Commands,
     filename: &Path,
+    registry: &mut FileRegistry,
     symbols: &SymbolRegistry,
 ) -> Result<Entity> {
     info!("loading Digital circuit {}", filename.display());
@@ -37,30 +34,219 @@ pub fn load_digital(
         bail!("error getting file name of {}", filename.display(),);
     };
 
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-    let circuit = serde_xml_rs::from_reader(reader)?;
+    let circuit = read_circuit(filename)?;
+
+    // Circuits embedding this one need its ports, which may have changed if
+    // the file is being reloaded.
+    registry
+        .digital_ports
+        .insert(FileId::for_path(filename)?, sub_circuit_ports(&circuit)?);
 
     translate_circuit(
         commands,
         &circuit,
+        registry,
         symbols,
         basedir,
         &name.to_string_lossy(),
     )
 }
 
+/// How many directories deep embedded circuits are searched for
+const MAX_SEARCH_DEPTH: usize = 4;
+
+/// Finds the file of an embedded circuit. Like Digital, this looks next to the
+/// circuit using it first, and then in the subdirectories of that directory.
+/// Symlinked directories aren't searched, so links to a parent directory can't
+/// make the search go in circles.
+fn find_sub_circuit(basedir: &Path, file_name: &str) -> Result<PathBuf> {
+    let filename = basedir.join(file_name);
+    if filename.is_file() {
+        return Ok(filename);
+    }
+
+    let Some(name) = Path::new(file_name).file_name() else {
+        bail!("invalid embedded circuit '{file_name}'");
+    };
+
+    let root = if basedir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        basedir
+    };
+
+    // Breadth first, so the least nested match wins
+    let mut dirs = VecDeque::from([(root.to_owned(), 0)]);
+    while let Some((dir, depth)) = dirs.pop_front() {
+        if depth == MAX_SEARCH_DEPTH {
+            continue;
+        }
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut subdirs: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+            .map(|entry| entry.path())
+            .collect();
+        subdirs.sort();
+
+        for subdir in subdirs {
+            let filename = subdir.join(name);
+            if filename.is_file() {
+                return Ok(filename);
+            }
+            dirs.push_back((subdir, depth + 1));
+        }
+    }
+
+    bail!(
+        "embedded circuit '{file_name}' not found in {} or its subdirectories",
+        root.display()
+    );
+}
+
+/// Like `find_sub_circuit`, but remembers where each embedded circuit was
+/// found, so circuits embedded many times are only searched for once.
+fn find_sub_circuit_cached(
+    basedir: &Path,
+    file_name: &str,
+    registry: &mut FileRegistry,
+) -> Result<PathBuf> {
+    let key = (basedir.to_owned(), file_name.to_owned());
+    if let Some(filename) = registry.digital_files.get(&key) {
+        if filename.is_file() {
+            return Ok(filename.clone());
+        }
+    }
+
+    let filename = find_sub_circuit(basedir, file_name)?;
+    registry.digital_files.insert(key, filename.clone());
+    Ok(filename)
+}
+
+fn read_circuit(filename: &Path) -> Result<circuitfile::Circuit> {
+    let file = File::open(filename)?;
+    let reader = BufReader::new(file);
+    Ok(serde_xml_rs::from_reader(reader)?)
+}
+
+fn bit_width(attributes: &circuitfile::Attributes) -> Result<BitWidth> {
+    match attributes.get("Bits") {
+        None => Ok(BitWidth(NonZeroU8::MIN)),
+        Some(circuitfile::AttributeValue::Int(bits)) => {
+            match u8::try_from(*bits).ok().and_then(NonZeroU8::new) {
+                Some(bits) => Ok(BitWidth(bits)),
+                None => bail!("invalid bit width {bits}"),
+            }
+        }
+        Some(_) => bail!("invalid Bits attribute"),
+    }
+}
+
+/// Parses a splitter's "Input Splitting" or "Output Splitting" attribute into
+/// the bits of each port. Ports are separated by commas and are either a bit
+/// count `4`, a repeated bit count `4*2`, or a range of bits `0-3`.
+fn parse_splitting(splitting: &str) -> Result<Vec<Range<u8>>> {
+    let mut ports = Vec::new();
+    let mut next_bit = 0u8;
+    for part in splitting.split(',').map(str::trim) {
+        if let Some((from, to)) = part.split_once('-') {
+            let from: u8 = from.trim().parse()?;
+            let to: u8 = to.trim().parse()?;
+            let (from, to) = (from.min(to), from.max(to));
+            let Some(end) = to.checked_add(1) else {
+                bail!("invalid splitting '{splitting}'");
+            };
+            ports.push(from..end);
+            next_bit = next_bit.max(end);
+        } else {
+            let (bits, count) = match part.split_once('*') {
+                Some((bits, count)) => (bits.trim().parse::<u8>()?, count.trim().parse::<u8>()?),
+                None => (part.parse::<u8>()?, 1),
+            };
+            if bits == 0 {
+                bail!("invalid splitting '{splitting}'");
+            }
+            for _ in 0..count {
+                let Some(end) = next_bit.checked_add(bits) else {
+                    bail!("invalid splitting '{splitting}'");
+                };
+                ports.push(next_bit..end);
+                next_bit = end;
+            }
+        }
+    }
+    Ok(ports)
+}
+
+/// Gets the ports of an embedded circuit from its In and Out elements. Digital
+/// orders them from top to bottom, inputs first.
+fn sub_circuit_ports(circuit: &circuitfile::Circuit) -> Result<Vec<SubCircuitPort>> {
+    let mut pins: Vec<_> = circuit
+        .visual_elements
+        .visual_element
+        .iter()
+        .filter_map(|element| {
+            let output = match element.element_name {
+                circuitfile::ElementName::In => false,
+                circuitfile::ElementName::Out => true,
+                _ => return None,
+            };
+
+            let label = match element.element_attributes.get("Label") {
+                Some(circuitfile::AttributeValue::String(label)) => label.as_str(),
+                _ => "",
+            };
+
+            Some(
+                bit_width(&element.element_attributes)
+                    .map(|bit_width| (output, element.pos.y, element.pos.x, label, bit_width)),
+            )
+        })
+        .collect::<Result<_>>()?;
+    pins.sort_by_key(|&(output, y, x, _, _)| (output, y, x));
+
+    Ok(pins
+        .into_iter()
+        .map(|(output, _, _, label, bit_width)| SubCircuitPort {
+            name: label.into(),
+            output,
+            bit_width,
+        })
+        .collect())
+}
+
+/// The ports of the embedded circuit in `filename`, which is only read if it
+/// wasn't loaded already.
+fn cached_sub_circuit_ports(
+    filename: &Path,
+    registry: &mut FileRegistry,
+) -> Result<Vec<SubCircuitPort>> {
+    let file_id = FileId::for_path(filename)?;
+    if let Some(ports) = registry.digital_ports.get(&file_id) {
+        return Ok(ports.clone());
+    }
+
+    let ports = sub_circuit_ports(&read_circuit(filename)?)?;
+    registry.digital_ports.insert(file_id, ports.clone());
+    Ok(ports)
+}
+
 fn translate_circuit(
     commands: &mut Commands,
     circuit: &circuitfile::Circuit,
+    registry: &mut FileRegistry,
     symbols: &SymbolRegistry,
     basedir: &Path,
     name: &str,
 ) -> Result<Entity> {
-    File::create("test.json")?
-        .write_all(serde_json::to_string_pretty(circuit).unwrap().as_bytes())?;
+    crate::dump::dump(&format!("{name}.dig.json"), || {
+        serde_json::to_string_pretty(circuit).unwrap_or_else(|err| err.to_string())
+    });
 
     let mut pos_map = HashMap::<Vec2, PosEntry>::default();
+    let mut links = Vec::<BitLink>::new();
 
     let circuit_id = commands
         .spawn(CircuitBundle {
@@ -70,146 +256,716 @@ fn translate_circuit(
         .id();
 
     for symbol in circuit.visual_elements.visual_element.iter() {
-        translate_symbol(symbol, commands, circuit_id, &mut pos_map, symbols)?;
+        translate_symbol(
+            symbol,
+            commands,
+            circuit_id,
+            &mut pos_map,
+            &mut links,
+            registry,
+            symbols,
+            basedir,
+        )?;
     }
 
-    translate_wires(commands, circuit, circuit_id, &mut pos_map)?;
+    let wires = circuit
+        .wires
+        .wire
+        .iter()
+        .map(|wire| {
+            Ok([
+                Vec2 {
+                    x: wire.p1.x.try_into()?,
+                    y: wire.p1.y.try_into()?,
+                },
+                Vec2 {
+                    x: wire.p2.x.try_into()?,
+                    y: wire.p2.y.try_into()?,
+                },
+            ])
+        })
+        .collect::<Result<Vec<_>>>()?;
+    translate_wires(commands, &wires, &links, circuit_id, &mut pos_map)?;
 
     Ok(circuit_id)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn translate_symbol(
     symbol: &circuitfile::VisualElement,
     commands: &mut Commands,
     circuit_id: Entity,
     pos_map: &mut HashMap<Vec2, PosEntry>,
+    links: &mut Vec<BitLink>,
+    registry: &mut FileRegistry,
     symbols: &SymbolRegistry,
+    basedir: &Path,
 ) -> Result<(), anyhow::Error> {
-    let mut symbol_builder = symbols.get(symbol.element_name.into());
-
     let pos = Vec2 {
         x: symbol.pos.x.try_into()?,
         y: symbol.pos.y.try_into()?,
     };
 
-    symbol_builder.position(pos).build(commands, circuit_id);
+    // Digital rotates counter-clockwise on screen, we rotate clockwise.
+    let rotation = match symbol.element_attributes.get("rotation") {
+        None => Rotation::Rot0,
+        Some(circuitfile::AttributeValue::Rotation(rotation)) => match rotation.rotation.as_str() {
+            "0" => Rotation::Rot0,
+            "1" => Rotation::Rot270,
+            "2" => Rotation::Rot180,
+            "3" => Rotation::Rot90,
+            rotation => bail!("invalid rotation '{rotation}'"),
+        },
+        Some(_) => bail!("invalid rotation attribute"),
+    };
+
+    // Digital mirrors top to bottom, before rotating
+    let flip = match symbol.element_attributes.get("mirror") {
+        None => Flip::empty(),
+        Some(circuitfile::AttributeValue::Boolean(mirror)) if mirror == "true" => Flip::VERTICAL,
+        Some(circuitfile::AttributeValue::Boolean(_)) => Flip::empty(),
+        Some(_) => bail!("invalid mirror attribute"),
+    };
+    let transform = Transform {
+        translation: pos,
+        rotation,
+        flip,
+        ..Default::default()
+    };
+
+    match &symbol.element_name {
+        circuitfile::ElementName::Splitter => {
+            links.push(translate_splitter(symbol, transform)?);
+            return Ok(());
+        }
+        circuitfile::ElementName::Unsupported(name) => {
+            warn!(
+                "unsupported Digital element '{name}' at ({}, {})",
+                symbol.pos.x, symbol.pos.y
+            );
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let mut symbol_builder = symbols.get((&symbol.element_name).try_into()?);
+    symbol_builder.bit_width(bit_width(&symbol.element_attributes)?);
+
+    let label = match symbol.element_attributes.get("Label") {
+        Some(circuitfile::AttributeValue::String(label)) if !label.is_empty() => Some(label),
+        _ => None,
+    };
+    if let Some(label) = label {
+        symbol_builder.name(label.as_str().into());
+    }
+
+    let tunnel = matches!(symbol.element_name, circuitfile::ElementName::Tunnel);
+    if tunnel {
+        match symbol.element_attributes.get("NetName") {
+            Some(circuitfile::AttributeValue::String(net_name)) => {
+                symbol_builder.name(net_name.as_str().into());
+            }
+            Some(_) => bail!("invalid NetName attribute"),
+            None => bail!("tunnel has no NetName"),
+        }
+    }
+
+    if let circuitfile::ElementName::Circuit(file_name) = &symbol.element_name {
+        let filename = find_sub_circuit_cached(basedir, file_name, registry)?;
+        let sub_circuit = load_circuit_file(commands, &filename, registry, symbols)?;
+        let ports = cached_sub_circuit_ports(&filename, registry)?;
+        symbol_builder.sub_circuit(sub_circuit, &ports);
+
+        // Unlabelled instances are named after the circuit
+        if label.is_none() {
+            let name = filename.file_stem().unwrap_or(file_name.as_ref());
+            symbol_builder.name(name.to_string_lossy().as_ref().into());
+        }
+    }
 
+    if let Some(inputs) = symbol.element_attributes.get("Inputs") {
+        let circuitfile::AttributeValue::Int(inputs) = inputs else {
+            bail!("invalid Inputs attribute");
+        };
+        match u8::try_from(*inputs) {
+            Ok(inputs) if inputs >= 2 => symbol_builder.input_count(inputs),
+            _ => bail!("invalid number of inputs {inputs}"),
+        };
+    }
+
+    if let circuitfile::ElementName::Const = symbol.element_name {
+        // Digital's constants are 1 unless set otherwise
+        let value = match symbol.element_attributes.get("Value") {
+            None => 1,
+            Some(circuitfile::AttributeValue::Long(value)) => *value as u64,
+            Some(_) => bail!("invalid Value attribute"),
+        };
+        symbol_builder.value(value);
+    }
+
+    symbol_builder
+        .position(pos)
+        .rotation(rotation)
+        .flip(flip)
+        .build(commands, circuit_id);
+
+    // Tunnels have no width of their own, they take it from their net
     for port in symbol_builder.ports().iter() {
-        pos_map.insert(
-            pos + port.position,
-            PosEntry {
-                port: Some(port.id),
-                endpoint: Cell::new(None),
-                wires: vec![],
-            },
-        );
+        let pos_entry = if tunnel {
+            PosEntry::fitting_port(port.id)
+        } else {
+            PosEntry::port(port.id, port.bit_width)
+        };
+        pos_map.insert(port.position.transform(transform), pos_entry);
     }
 
     Ok(())
 }
 
-fn translate_wires(
-    commands: &mut Commands,
-    circuit: &circuitfile::Circuit,
-    circuit_id: Entity,
-    pos_map: &mut HashMap<Vec2, PosEntry>,
-) -> Result<(), anyhow::Error> {
-    // at this point, pos_map contains only the ports.
-    // add the wire ends to pos_map also.
-    for wire in circuit.wires.wire.iter() {
-        let ends = [
-            Vec2 {
-                x: wire.p1.x.try_into()?,
-                y: wire.p1.y.try_into()?,
-            },
-            Vec2 {
-                x: wire.p2.x.try_into()?,
-                y: wire.p2.y.try_into()?,
-            },
-        ];
+/// A splitter has its inputs on the left and its outputs on the right, one
+/// port per grid step, all sharing the same bits.
+fn translate_splitter(
+    symbol: &circuitfile::VisualElement,
+    transform: Transform,
+) -> Result<BitLink> {
+    const SIZE: i32 = 20;
+
+    // Digital splits a byte into two nibbles by default
+    let splitting = |key: &str, default: &str| match symbol.element_attributes.get(key) {
+        None => parse_splitting(default),
+        Some(circuitfile::AttributeValue::String(splitting)) => parse_splitting(splitting),
+        Some(_) => bail!("invalid {key} attribute"),
+    };
+    let spreading = match symbol.element_attributes.get("splitterSpreading") {
+        None => 1,
+        Some(circuitfile::AttributeValue::Int(spreading)) => *spreading,
+        Some(_) => bail!("invalid splitterSpreading attribute"),
+    };
+
+    let inputs = splitting("Input Splitting", "4,4")?;
+    let outputs = splitting("Output Splitting", "8")?;
 
-        for end in ends.iter() {
-            if let Some(pos_entry) = pos_map.get_mut(end) {
-                pos_entry.wires.push(ends);
+    let mut link = BitLink::default();
+    for (x, ports) in [(0, inputs), (SIZE, outputs)] {
+        for (i, bits) in ports.into_iter().enumerate() {
+            let offset = Vec2 {
+                x: x.try_into()?,
+                y: (i as i32 * SIZE * spreading).try_into()?,
+            };
+            link.ends
+                .push((offset.transform(transform), bits.collect()));
+        }
+    }
+    Ok(link)
+}
+
+pub fn save_digital(world: &mut World, circuit: Entity, filename: &Path) -> Result<()> {
+    info!("saving Digital circuit {}", filename.display());
+
+    let Some(basedir) = filename.parent() else {
+        bail!("error getting parent directory of {}", filename.display(),);
+    };
+
+    let Some(root_name) = filename.file_stem() else {
+        bail!("error getting file name of {}", filename.display(),);
+    };
+
+    let circuits = extract_hierarchy(world, circuit)?;
+
+    // Sub-circuits are written next to the root circuit, since that's where
+    // Digital looks for them. Other files that are in the way get to stay,
+    // only the file a circuit was loaded from is overwritten.
+    let mut file_names = IdentifierSet::default();
+    file_names.reserve(&root_name.to_string_lossy());
+    let mut sub_circuit_file_name = |circuit: &ExtractedCircuit| loop {
+        let file_name = format!("{}.dig", file_names.make_unique(&circuit.name, "circuit"));
+        let path = basedir.join(&file_name);
+        let is_source = circuit.file_path.as_ref().is_some_and(|file_path| {
+            FileId::for_path(file_path).ok() == FileId::for_path(&path).ok()
+        });
+        if is_source || !path.exists() {
+            break file_name;
+        }
+    };
+    let file_names: Vec<String> = circuits
+        .iter()
+        .enumerate()
+        .map(|(i, circuit)| {
+            if i + 1 == circuits.len() {
+                filename
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
             } else {
-                pos_map.insert(
-                    *end,
-                    PosEntry {
-                        port: None,
-                        endpoint: Cell::new(None),
-                        wires: vec![ends],
-                    },
+                sub_circuit_file_name(circuit)
+            }
+        })
+        .collect();
+
+    let built = circuits
+        .iter()
+        .map(|circuit| build_circuit(circuit, &file_names))
+        .collect::<Result<Vec<_>>>()?;
+    for (circuit, file_name) in built.iter().zip(file_names.iter()) {
+        let path = basedir.join(file_name);
+        back_up_source(world, &path)?;
+        std::fs::write(&path, circuit.to_xml())?;
+        record_save(world, &path);
+    }
+    Ok(())
+}
+
+fn to_point(pos: Vec2) -> circuitfile::Point {
+    let round = |value: Fixed| (value + fixed!(0.5)).to_i32();
+    circuitfile::Point {
+        x: round(pos.x),
+        y: round(pos.y),
+    }
+}
+
+fn attribute(key: &str, value: circuitfile::AttributeValue) -> circuitfile::AttributesEntry {
+    circuitfile::AttributesEntry {
+        value: [circuitfile::AttributeValue::String(key.to_owned()), value],
+    }
+}
+
+/// Builds the Digital version of one circuit of a hierarchy, `file_names` are
+/// the files the circuits of the hierarchy are written to.
+fn build_circuit(
+    circuit: &ExtractedCircuit,
+    file_names: &[String],
+) -> Result<circuitfile::Circuit> {
+    let mut elements = Vec::new();
+    for symbol in circuit.symbols.iter() {
+        let element_name = match symbol.kind {
+            SymbolKind::And => circuitfile::ElementName::And,
+            SymbolKind::Or => circuitfile::ElementName::Or,
+            SymbolKind::Xor => circuitfile::ElementName::Xor,
+            SymbolKind::Nand => circuitfile::ElementName::Nand,
+            SymbolKind::Nor => circuitfile::ElementName::Nor,
+            SymbolKind::Xnor => circuitfile::ElementName::Xnor,
+            SymbolKind::Not => circuitfile::ElementName::Not,
+            SymbolKind::TriState => circuitfile::ElementName::Driver,
+            SymbolKind::Buffer => bail!(
+                "{} is a buffer, which Digital doesn't have",
+                symbol.designator
+            ),
+            SymbolKind::In => circuitfile::ElementName::In,
+            SymbolKind::Out => circuitfile::ElementName::Out,
+            SymbolKind::Mux => circuitfile::ElementName::Multiplexer,
+            SymbolKind::Const => circuitfile::ElementName::Const,
+            SymbolKind::SubCircuit => {
+                let Some(sub_circuit) = symbol.sub_circuit else {
+                    bail!("sub-circuit {} has no circuit", symbol.designator);
+                };
+                if symbol.ports.iter().any(|port| port.input && port.output) {
+                    bail!(
+                        "{} connects to global labels, which Digital doesn't have",
+                        symbol.designator
+                    );
+                }
+                circuitfile::ElementName::Circuit(file_names[sub_circuit].clone())
+            }
+            SymbolKind::Custom => bail!(
+                "{} is a {}, custom symbols can't be saved as Digital circuits",
+                symbol.designator,
+                symbol.custom_kind.as_deref().unwrap_or_default()
+            ),
+            // Without sub-circuits to cross into, a global label is a tunnel
+            SymbolKind::Label | SymbolKind::GlobalLabel => circuitfile::ElementName::Tunnel,
+        };
+
+        let mut attributes = Vec::new();
+        if matches!(symbol.kind, SymbolKind::In | SymbolKind::Out) {
+            attributes.push(attribute(
+                "Label",
+                circuitfile::AttributeValue::String(symbol.name.to_string()),
+            ));
+        }
+        if matches!(symbol.kind, SymbolKind::Label | SymbolKind::GlobalLabel) {
+            attributes.push(attribute(
+                "NetName",
+                circuitfile::AttributeValue::String(symbol.name.to_string()),
+            ));
+        }
+
+        // The data ports decide the width, a multiplexer's select input doesn't.
+        let bit_width = symbol
+            .outputs()
+            .chain(symbol.inputs())
+            .map(|port| port.bit_width)
+            .next()
+            .unwrap_or(1);
+        if bit_width > 1 && symbol.kind != SymbolKind::SubCircuit {
+            attributes.push(attribute(
+                "Bits",
+                circuitfile::AttributeValue::Int(bit_width.into()),
+            ));
+        }
+
+        let input_count = symbol.inputs().count();
+        let gate = matches!(
+            symbol.kind,
+            SymbolKind::And
+                | SymbolKind::Or
+                | SymbolKind::Xor
+                | SymbolKind::Nand
+                | SymbolKind::Nor
+                | SymbolKind::Xnor
+        );
+        if gate && input_count != 2 {
+            attributes.push(attribute(
+                "Inputs",
+                circuitfile::AttributeValue::Int(input_count as i32),
+            ));
+        }
+
+        if let Some(value) = symbol.value {
+            attributes.push(attribute(
+                "Value",
+                circuitfile::AttributeValue::Long(value as i64),
+            ));
+        }
+
+        // Digital can only mirror top to bottom, which flipping left to right
+        // is when also rotated by 180°.
+        let mut rotation = symbol.transform.rotation;
+        let flip = symbol.transform.flip;
+        if flip.contains(Flip::HORIZONTAL) {
+            rotation *= Rotation::Rot180;
+        }
+        if flip.is_mirrored() {
+            attributes.push(attribute(
+                "mirror",
+                circuitfile::AttributeValue::Boolean("true".to_owned()),
+            ));
+        }
+
+        // Digital rotates counter-clockwise on screen, we rotate clockwise.
+        let rotation = match rotation {
+            Rotation::Rot0 => None,
+            Rotation::Rot90 => Some("3"),
+            Rotation::Rot180 => Some("2"),
+            Rotation::Rot270 => Some("1"),
+        };
+        if let Some(rotation) = rotation {
+            attributes.push(attribute(
+                "rotation",
+                circuitfile::AttributeValue::Rotation(circuitfile::Rotation {
+                    rotation: rotation.to_owned(),
+                }),
+            ));
+        }
+
+        // Digital would need splitters in between, which can't be placed
+        // without rerouting the net.
+        if symbol.ports.iter().any(|port| port.bits.is_some()) {
+            bail!(
+                "{} uses part of a net, which Digital can only do through splitters",
+                symbol.designator
+            );
+        }
+
+        elements.push(circuitfile::VisualElement {
+            element_name,
+            element_attributes: circuitfile::Attributes {
+                entry: Some(attributes),
+            },
+            pos: to_point(symbol.transform.translation),
+        });
+    }
+
+    let mut wires = Vec::new();
+    for (net_index, net) in circuit.nets.iter().enumerate() {
+        let mut segments = net.wires.clone();
+
+        // Without routing, connect the ports of the net in a straight line.
+        if segments.is_empty() {
+            let mut positions: Vec<_> = circuit
+                .symbols
+                .iter()
+                .flat_map(|symbol| symbol.ports.iter())
+                .filter(|port| port.net == Some(net_index))
+                .map(|port| port.position)
+                .collect();
+            positions.sort_by_key(|pos| (pos.x, pos.y));
+            positions.dedup();
+            segments.extend(positions.windows(2).map(|pair| [pair[0], pair[1]]));
+        }
+
+        wires.extend(segments.into_iter().map(|[p1, p2]| circuitfile::Wire {
+            p1: to_point(p1),
+            p2: to_point(p2),
+        }));
+    }
+
+    Ok(circuitfile::Circuit {
+        version: "2".to_owned(),
+        attributes: circuitfile::Attributes { entry: None },
+        visual_elements: circuitfile::VisualElements {
+            visual_element: elements,
+        },
+        wires: circuitfile::Wires { wire: wires },
+        measurement_ordering: circuitfile::MeasurementOrdering { string: None },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(path: &str) -> Vec<ExtractedCircuit> {
+        let mut world = World::new();
+        let symbols = SymbolRegistry::default();
+        let mut registry = FileRegistry::default();
+        let circuit = load_circuit_file(
+            &mut world.commands(),
+            Path::new(path),
+            &mut registry,
+            &symbols,
+        )
+        .unwrap();
+        world.flush();
+        extract_hierarchy(&mut world, circuit.0).unwrap()
+    }
+
+    fn symbol<'a>(circuit: &'a ExtractedCircuit, name: &str) -> &'a ExtractedSymbol {
+        circuit
+            .symbols
+            .iter()
+            .find(|symbol| &*symbol.name == name)
+            .unwrap()
+    }
+
+    fn port<'a>(symbol: &'a ExtractedSymbol, name: &str) -> &'a ExtractedPort {
+        symbol
+            .ports
+            .iter()
+            .find(|port| &*port.name == name)
+            .unwrap()
+    }
+
+    fn assert_all_connected(circuit: &ExtractedCircuit) {
+        for symbol in circuit.symbols.iter() {
+            for port in symbol.ports.iter() {
+                assert!(
+                    port.net.is_some(),
+                    "{}.{} is unconnected",
+                    symbol.name,
+                    port.name
                 );
             }
         }
     }
 
-    let mut visited = HashSet::<Vec2>::default();
-    let mut todo = Vec::<Vec2>::default();
-    let mut junctions = HashSet::<(Entity, Vec2)>::default();
-    let mut net_endpoints = HashMap::<Entity, Vec<Vec2>>::default();
+    #[test]
+    fn loads_rotated_components() {
+        let circuits = load("testdata/rotated.dig");
+        assert_eq!(circuits.len(), 1);
+        assert_all_connected(&circuits[0]);
+    }
 
-    // do a "flood fill" to find all connected ports and assign them to nets
-    for pos in pos_map.keys() {
-        if visited.contains(pos) {
-            continue;
+    #[test]
+    fn loads_embedded_circuits_once() {
+        let circuits = load("testdata/full_adder.dig");
+        assert_eq!(circuits.len(), 2);
+        assert_eq!(&*circuits[0].name, "half_adder");
+        assert_all_connected(&circuits[0]);
+
+        let full_adder = &circuits[1];
+        assert_all_connected(full_adder);
+        let half_adders: Vec<_> = full_adder
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.kind == SymbolKind::SubCircuit)
+            .collect();
+        assert_eq!(half_adders.len(), 2);
+        for half_adder in half_adders.iter() {
+            assert_eq!(half_adder.sub_circuit, Some(0));
         }
 
-        let net_id = commands
-            .spawn(NetBundle {
-                net: Net,
-                name: Default::default(),
-                bit_width: BitWidth(NonZeroU8::MIN), // TODO: Get bit width from somewhere
-                visibility: VisibilityBundle::default(),
-            })
-            .set::<Child>(circuit_id)
-            .id();
-
-        todo.clear();
-        todo.push(*pos);
-        while let Some(pos) = todo.pop() {
-            visited.insert(pos);
-
-            if let Some(pos_entry) = pos_map.get(&pos) {
-                if let Some(port) = pos_entry.port {
-                    // Connect port to net
-                    let endpoint_id = commands
-                        .spawn(EndpointBundle {
-                            bounds: BoundingBoxBundle {
-                                bounding_box: BoundingBox::from_half_size(fixed!(2.5), fixed!(2.5)),
-                                ..Default::default()
-                            },
-                            ..Default::default()
-                        })
-                        .insert(PortID(port))
-                        .set::<Child>(net_id)
-                        // Remember to disconnect this when disconnecting from the port.
-                        .set::<InheritTransform>(port)
-                        .id();
-
-                    commands.entity(port).insert(NetID(net_id));
-
-                    pos_entry.endpoint.set(Some(endpoint_id));
-                    if let Some(endpoints) = net_endpoints.get_mut(&net_id) {
-                        endpoints.push(pos);
-                    } else {
-                        net_endpoints.insert(net_id, vec![pos]);
-                    }
-                } else if pos_entry.wires.len() > 2 {
-                    // junction here, save it for later
-                    junctions.insert((net_id, pos));
-                }
+        // Only unlabelled instances are named after their circuit
+        assert_eq!(&*half_adders[0].name, "half_adder");
+        assert_eq!(&*half_adders[1].name, "second");
+        assert_eq!(
+            port(symbol(full_adder, "A"), "Y").net,
+            port(half_adders[0], "A").net,
+        );
+    }
 
-                for wire in pos_entry.wires.iter() {
-                    for end in wire.iter() {
-                        if !visited.contains(end) {
-                            todo.push(*end);
-                        }
-                    }
-                }
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn searches_subdirectories_without_following_symlinks() {
+        let dir = std::env::temp_dir().join(format!("digilogic-search-{}", std::process::id()));
+        let deep = dir.join("a/b/c/d/e");
+        std::fs::create_dir_all(&deep).unwrap();
+        std::fs::write(dir.join("a/b/nested.dig"), "").unwrap();
+        std::fs::write(deep.join("deep.dig"), "").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("a/loop")).unwrap();
+
+        assert_eq!(
+            find_sub_circuit(&dir, "nested.dig").unwrap(),
+            dir.join("a/b/nested.dig")
+        );
+        assert!(find_sub_circuit(&dir, "deep.dig").is_err());
+        assert!(find_sub_circuit(&dir, "missing.dig").is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parses_splitting() {
+        assert_eq!(parse_splitting("4,4").unwrap(), [0..4, 4..8]);
+        assert_eq!(parse_splitting("2*3").unwrap(), [0..2, 2..4, 4..6]);
+        assert_eq!(parse_splitting("4-7,0-3").unwrap(), [4..8, 0..4]);
+        assert!(parse_splitting("0-255").is_err());
+        assert!(parse_splitting("200,100").is_err());
+    }
+
+    #[test]
+    fn loads_splitters_and_constants() {
+        let circuits = load("testdata/bus.dig");
+        let circuit = &circuits[0];
+
+        let a = port(symbol(circuit, "A"), "Y");
+        assert_eq!(a.bit_width, 4);
+        assert_eq!(a.bits, None);
+
+        let y0 = port(symbol(circuit, "Y0"), "A");
+        let y1 = port(symbol(circuit, "Y1"), "A");
+        assert_eq!(y0.net, a.net);
+        assert_eq!(y1.net, a.net);
+        assert_eq!(y0.bits.as_deref(), Some(&[0][..]));
+        assert_eq!(y1.bits.as_deref(), Some(&[1][..]));
+
+        let constant = circuit
+            .symbols
+            .iter()
+            .find(|symbol| symbol.kind == SymbolKind::Const)
+            .unwrap();
+        assert_eq!(constant.value, Some(5));
+        assert_eq!(port(constant, "Y").net, port(symbol(circuit, "K"), "A").net);
+    }
+
+    /// The ports of each net, identified by their positions
+    fn net_ports(circuit: &ExtractedCircuit) -> Vec<Vec<(i32, i32)>> {
+        let mut nets = vec![Vec::new(); circuit.nets.len()];
+        for port in circuit
+            .symbols
+            .iter()
+            .flat_map(|symbol| symbol.ports.iter())
+        {
+            if let Some(net) = port.net {
+                let pos = to_point(port.position);
+                nets[net].push((pos.x, pos.y));
             }
         }
+        for net in nets.iter_mut() {
+            net.sort();
+        }
+        nets.sort();
+        nets
     }
 
-    Ok(())
+    #[test]
+    fn round_trips_embedded_circuits() {
+        let dir = std::env::temp_dir().join(format!("digilogic-dig-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("exported.dig");
+
+        let mut world = World::new();
+        let symbols = SymbolRegistry::default();
+        let mut registry = FileRegistry::default();
+        let circuit = load_circuit_file(
+            &mut world.commands(),
+            Path::new("testdata/full_adder.dig"),
+            &mut registry,
+            &symbols,
+        )
+        .unwrap();
+        world.flush();
+        let original = extract_hierarchy(&mut world, circuit.0).unwrap();
+        save_digital(&mut world, circuit.0, &filename).unwrap();
+
+        let exported = load(filename.to_str().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(exported.len(), original.len());
+        for (exported, original) in exported.iter().zip(original.iter()) {
+            assert_eq!(exported.symbols.len(), original.symbols.len());
+            assert_eq!(net_ports(exported), net_ports(original));
+        }
+    }
+
+    fn save(source: &str, filename: &Path) -> Result<()> {
+        let mut world = World::new();
+        let symbols = SymbolRegistry::default();
+        let mut registry = FileRegistry::default();
+        let circuit = load_circuit_file(
+            &mut world.commands(),
+            Path::new(source),
+            &mut registry,
+            &symbols,
+        )?;
+        world.flush();
+        save_digital(&mut world, circuit.0, filename)
+    }
+
+    #[test]
+    fn backs_up_sources_before_overwriting_them() {
+        let dir = std::env::temp_dir().join(format!("digilogic-dig-backup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("full_adder.dig");
+        std::fs::copy("testdata/full_adder.dig", &filename).unwrap();
+        std::fs::copy("testdata/half_adder.dig", dir.join("half_adder.dig")).unwrap();
+
+        let mut world = World::new();
+        world.init_resource::<SymbolRegistry>();
+        world.init_resource::<FileRegistry>();
+        let circuit = world.resource_scope(|world, mut registry: Mut<FileRegistry>| {
+            world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+                load_circuit_file(&mut world.commands(), &filename, &mut registry, &symbols)
+            })
+        });
+        world.flush();
+        save_digital(&mut world, circuit.unwrap().0, &filename).unwrap();
+
+        let backup = std::fs::read_to_string(dir.join("full_adder.dig.bak")).unwrap();
+        let sub_circuit_backed_up = dir.join("half_adder.dig.bak").is_file();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            backup,
+            std::fs::read_to_string("testdata/full_adder.dig").unwrap()
+        );
+        assert!(sub_circuit_backed_up);
+    }
+
+    #[test]
+    fn keeps_other_files_when_exporting() {
+        let dir = std::env::temp_dir().join(format!("digilogic-dig-keep-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let other = dir.join("half_adder.dig");
+        std::fs::write(&other, "not ours").unwrap();
+
+        let filename = dir.join("exported.dig");
+        save("testdata/full_adder.dig", &filename).unwrap();
+        let kept = std::fs::read_to_string(&other).unwrap();
+        let exported = load(filename.to_str().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(kept, "not ours");
+        assert_eq!(&*exported[0].name, "half_adder_1");
+    }
+
+    #[test]
+    fn refuses_to_export_parts_of_nets() {
+        let dir = std::env::temp_dir().join(format!("digilogic-dig-bus-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let result = save("testdata/bus.dig", &dir.join("bus.dig"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+    }
 }
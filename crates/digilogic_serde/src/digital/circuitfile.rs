@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
@@ -16,6 +16,18 @@ pub struct Attributes {
     pub entry: Option<Vec<AttributesEntry>>,
 }
 
+impl Attributes {
+    pub fn get(&self, key: &str) -> Option<&AttributeValue> {
+        self.entry
+            .iter()
+            .flatten()
+            .find_map(|entry| match &entry.value {
+                [AttributeValue::String(k), value] if k == key => Some(value),
+                _ => None,
+            })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct AttributesEntry {
@@ -45,35 +57,110 @@ pub enum AttributeValue {
 #[derive(Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct VisualElements {
-    #[serde(rename = "visualElement")]
+    #[serde(default, rename = "visualElement")]
     pub visual_element: Vec<VisualElement>,
 }
 
 /// NOTE: Must be kept in sync with SymbolKind!
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
-#[serde(deny_unknown_fields)]
+#[derive(Clone, Debug)]
 pub enum ElementName {
     And,
     Or,
-    #[serde(rename = "XOr")]
     Xor,
+    Nand,
+    Nor,
+    Xnor,
     Not,
+    /// A tri-state buffer
+    Driver,
     In,
     Out,
     Multiplexer,
+    Splitter,
+    Const,
+    /// Connects to the other tunnels in the circuit with the same "NetName"
+    Tunnel,
+    /// An embedded circuit, named by its file name relative to the
+    /// embedding circuit
+    Circuit(String),
+    /// An element we can't translate
+    Unsupported(String),
+}
+
+impl ElementName {
+    fn as_str(&self) -> &str {
+        match self {
+            Self::And => "And",
+            Self::Or => "Or",
+            Self::Xor => "XOr",
+            Self::Nand => "NAnd",
+            Self::Nor => "NOr",
+            Self::Xnor => "XNOr",
+            Self::Not => "Not",
+            Self::Driver => "Driver",
+            Self::In => "In",
+            Self::Out => "Out",
+            Self::Multiplexer => "Multiplexer",
+            Self::Splitter => "Splitter",
+            Self::Const => "Const",
+            Self::Tunnel => "Tunnel",
+            Self::Circuit(name) | Self::Unsupported(name) => name,
+        }
+    }
+}
+
+impl Serialize for ElementName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ElementName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(match name.as_str() {
+            "And" => Self::And,
+            "Or" => Self::Or,
+            "XOr" => Self::Xor,
+            "NAnd" => Self::Nand,
+            "NOr" => Self::Nor,
+            "XNOr" => Self::Xnor,
+            "Not" => Self::Not,
+            "Driver" => Self::Driver,
+            "In" => Self::In,
+            "Out" => Self::Out,
+            "Multiplexer" => Self::Multiplexer,
+            "Splitter" => Self::Splitter,
+            "Const" => Self::Const,
+            "Tunnel" => Self::Tunnel,
+            _ if name.ends_with(".dig") => Self::Circuit(name),
+            _ => Self::Unsupported(name),
+        })
+    }
 }
 
-impl From<ElementName> for digilogic_core::components::SymbolKind {
-    fn from(name: ElementName) -> Self {
-        match name {
+impl TryFrom<&ElementName> for digilogic_core::components::SymbolKind {
+    type Error = anyhow::Error;
+
+    fn try_from(name: &ElementName) -> anyhow::Result<Self> {
+        Ok(match name {
             ElementName::And => digilogic_core::components::SymbolKind::And,
             ElementName::Or => digilogic_core::components::SymbolKind::Or,
             ElementName::Xor => digilogic_core::components::SymbolKind::Xor,
+            ElementName::Nand => digilogic_core::components::SymbolKind::Nand,
+            ElementName::Nor => digilogic_core::components::SymbolKind::Nor,
+            ElementName::Xnor => digilogic_core::components::SymbolKind::Xnor,
             ElementName::Not => digilogic_core::components::SymbolKind::Not,
+            ElementName::Driver => digilogic_core::components::SymbolKind::TriState,
             ElementName::In => digilogic_core::components::SymbolKind::In,
             ElementName::Out => digilogic_core::components::SymbolKind::Out,
             ElementName::Multiplexer => digilogic_core::components::SymbolKind::Mux,
-        }
+            ElementName::Const => digilogic_core::components::SymbolKind::Const,
+            ElementName::Tunnel => digilogic_core::components::SymbolKind::Label,
+            ElementName::Circuit(_) => digilogic_core::components::SymbolKind::SubCircuit,
+            ElementName::Splitter => anyhow::bail!("a splitter is not a symbol"),
+            ElementName::Unsupported(name) => anyhow::bail!("unsupported element '{name}'"),
+        })
     }
 }
 
@@ -130,6 +217,7 @@ pub struct Point {
 #[derive(Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Wires {
+    #[serde(default)]
     pub wire: Vec<Wire>,
 }
 
@@ -145,3 +233,165 @@ pub struct Wire {
 pub struct MeasurementOrdering {
     pub string: Option<Vec<String>>,
 }
+
+// serde-xml-rs can't serialize these structs the way Digital expects them
+// (points are stored in attributes, attribute values are untyped sequences),
+// so they are written by hand, in the same layout Digital itself writes.
+
+struct XmlWriter {
+    out: String,
+    depth: usize,
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl XmlWriter {
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn open(&mut self, tag: &str) {
+        self.line(&format!("<{tag}>"));
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.line(&format!("</{tag}>"));
+    }
+
+    fn text(&mut self, tag: &str, text: &str) {
+        self.line(&format!("<{tag}>{}</{tag}>", escape(text)));
+    }
+
+    fn point(&mut self, tag: &str, point: &Point) {
+        self.line(&format!("<{tag} x=\"{}\" y=\"{}\"/>", point.x, point.y));
+    }
+
+    fn strings(&mut self, tag: &str, strings: &Option<Vec<String>>) {
+        match strings {
+            Some(strings) if !strings.is_empty() => {
+                self.open(tag);
+                for string in strings.iter() {
+                    self.text("string", string);
+                }
+                self.close(tag);
+            }
+            _ => self.line(&format!("<{tag}/>")),
+        }
+    }
+
+    fn attributes(&mut self, tag: &str, attributes: &Attributes) {
+        let entries = attributes.entry.as_deref().unwrap_or_default();
+        if entries.is_empty() {
+            self.line(&format!("<{tag}/>"));
+            return;
+        }
+
+        self.open(tag);
+        for entry in entries.iter() {
+            self.open("entry");
+            for value in entry.value.iter() {
+                self.attribute_value(value);
+            }
+            self.close("entry");
+        }
+        self.close(tag);
+    }
+
+    fn attribute_value(&mut self, value: &AttributeValue) {
+        match value {
+            AttributeValue::AwtColor(color) => {
+                self.open("awt-color");
+                self.text("red", &color.red.to_string());
+                self.text("green", &color.green.to_string());
+                self.text("blue", &color.blue.to_string());
+                self.text("alpha", &color.alpha.to_string());
+                self.close("awt-color");
+            }
+            AttributeValue::Data(data) => self.text("data", data),
+            AttributeValue::File(file) => self.text("file", file),
+            AttributeValue::TestData(test_data) => {
+                self.open("testData");
+                self.text("dataString", &test_data.data_string);
+                self.close("testData");
+            }
+            AttributeValue::Value(value) => self.line(&format!(
+                "<value v=\"{}\" z=\"{}\"/>",
+                escape(&value.v),
+                escape(&value.z)
+            )),
+            AttributeValue::InverterConfig(config) => {
+                self.strings("inverterConfig", &config.string)
+            }
+            AttributeValue::IntFormat(format) => self.text("intFormat", format),
+            AttributeValue::Long(value) => self.text("long", &value.to_string()),
+            AttributeValue::Int(value) => self.text("int", &value.to_string()),
+            AttributeValue::Boolean(value) => self.text("boolean", value),
+            AttributeValue::Rotation(rotation) => self.line(&format!(
+                "<rotation rotation=\"{}\"/>",
+                escape(&rotation.rotation)
+            )),
+            AttributeValue::String(string) => self.text("string", string),
+            AttributeValue::ShapeType(shape) => self.text("shapeType", shape),
+        }
+    }
+}
+
+impl Circuit {
+    /// Writes the circuit as a Digital `.dig` file.
+    pub fn to_xml(&self) -> String {
+        let mut writer = XmlWriter {
+            out: String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n"),
+            depth: 0,
+        };
+
+        writer.open("circuit");
+        writer.text("version", &self.version);
+        writer.attributes("attributes", &self.attributes);
+
+        writer.open("visualElements");
+        for element in self.visual_elements.visual_element.iter() {
+            writer.open("visualElement");
+            writer.text("elementName", element.element_name.as_str());
+            writer.attributes("elementAttributes", &element.element_attributes);
+            writer.point("pos", &element.pos);
+            writer.close("visualElement");
+        }
+        writer.close("visualElements");
+
+        if self.wires.wire.is_empty() {
+            writer.line("<wires/>");
+        } else {
+            writer.open("wires");
+            for wire in self.wires.wire.iter() {
+                writer.open("wire");
+                writer.point("p1", &wire.p1);
+                writer.point("p2", &wire.p2);
+                writer.close("wire");
+            }
+            writer.close("wires");
+        }
+
+        writer.strings("measurementOrdering", &self.measurement_ordering.string);
+        writer.close("circuit");
+        writer.out
+    }
+}
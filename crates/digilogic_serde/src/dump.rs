@@ -0,0 +1,75 @@
+//! Debug dumps of what the importers parsed, for figuring out why a file
+//! doesn't import the way it should.
+//!
+//! Dumps are off unless the `digilogic_serde::dump` log target is enabled at
+//! trace level, e.g. with `RUST_LOG=digilogic_serde::dump=trace`. They are
+//! written to the directory in `DIGILOGIC_DUMP_DIR`, or to `digilogic-dump` in
+//! the system's temporary directory.
+
+use bevy_log::{debug, warn};
+use std::path::PathBuf;
+
+const DUMP_DIR_VAR: &str = "DIGILOGIC_DUMP_DIR";
+
+fn enabled() -> bool {
+    tracing::enabled!(tracing::Level::TRACE)
+}
+
+fn dump_dir() -> PathBuf {
+    std::env::var_os(DUMP_DIR_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("digilogic-dump"))
+}
+
+/// Writes `contents` to `file_name` in the dump directory if dumps are enabled.
+/// `contents` is only called when the dump is written. Failing to write a dump
+/// doesn't fail the import.
+pub(crate) fn dump(file_name: &str, contents: impl FnOnce() -> String) {
+    dump_to(dump_dir, file_name, contents);
+}
+
+fn dump_to(dir: impl FnOnce() -> PathBuf, file_name: &str, contents: impl FnOnce() -> String) {
+    if !enabled() {
+        return;
+    }
+
+    let dir = dir();
+    let path = dir.join(file_name);
+    match std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, contents())) {
+        Ok(()) => debug!("dumped {}", path.display()),
+        Err(err) => warn!("error writing dump {}: {err}", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_log::tracing_subscriber::filter::LevelFilter;
+    use bevy_log::tracing_subscriber::prelude::*;
+    use bevy_log::tracing_subscriber::Registry;
+
+    #[test]
+    fn dumps_only_when_tracing() {
+        let dir = std::env::temp_dir().join(format!("digilogic-dump-{}", std::process::id()));
+        let path = dir.join("test.json");
+
+        // A dump directory alone doesn't enable dumps
+        let debug = Registry::default().with(LevelFilter::DEBUG);
+        tracing::subscriber::with_default(debug, || {
+            dump_to(
+                || dir.clone(),
+                "test.json",
+                || panic!("dump contents built while disabled"),
+            );
+        });
+        assert!(!path.exists());
+
+        let trace = Registry::default().with(LevelFilter::TRACE);
+        tracing::subscriber::with_default(trace, || {
+            dump_to(|| dir.clone(), "test.json", || "{}".to_owned());
+        });
+        let contents = std::fs::read_to_string(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(contents.unwrap(), "{}");
+    }
+}
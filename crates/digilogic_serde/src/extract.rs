@@ -0,0 +1,508 @@
+//! A flattened, read-only view of a circuit hierarchy, used by the file writers
+//! so they don't each have to walk the ECS relations themselves.
+
+use aery::edges::{EdgeInfo, Edges};
+use anyhow::{anyhow, bail, Result};
+use bevy_ecs::prelude::*;
+use digilogic_core::components::*;
+use digilogic_core::labels::LabelNets;
+use digilogic_core::transform::{Transform, Vec2};
+use digilogic_core::{HashMap, SharedStr};
+use digilogic_routing::{VertexKind, Vertices};
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub(crate) struct ExtractedPort {
+    pub name: SharedStr,
+    pub input: bool,
+    pub output: bool,
+    pub bit_width: u8,
+    /// Index into `ExtractedCircuit::nets`
+    pub net: Option<usize>,
+    /// The bits of the net this port uses, if it doesn't use the whole net
+    pub bits: Option<Vec<u8>>,
+    /// Position in the circuit
+    pub position: Vec2,
+}
+
+#[derive(Debug)]
+pub(crate) struct ExtractedSymbol {
+    pub kind: SymbolKind,
+    pub name: SharedStr,
+    pub designator: String,
+    pub transform: Transform,
+    pub ports: Vec<ExtractedPort>,
+    /// Index into the hierarchy of the circuit this symbol instantiates
+    pub sub_circuit: Option<usize>,
+    /// The value driven by a constant
+    pub value: Option<u64>,
+    /// The symbol library definition of a custom symbol
+    pub custom_kind: Option<SharedStr>,
+}
+
+impl ExtractedSymbol {
+    pub fn inputs(&self) -> impl Iterator<Item = &ExtractedPort> {
+        self.ports.iter().filter(|port| port.input && !port.output)
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = &ExtractedPort> {
+        self.ports.iter().filter(|port| port.output)
+    }
+
+    /// The bit width of the first port, which for In and Out symbols is the
+    /// width of the circuit port they represent.
+    pub fn bit_width(&self) -> u8 {
+        self.ports.first().map(|port| port.bit_width).unwrap_or(1)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ExtractedNet {
+    pub name: SharedStr,
+    pub bit_width: u8,
+    /// The routed wire segments, empty if the net hasn't been routed
+    pub wires: Vec<[Vec2; 2]>,
+}
+
+/// A net connected by global labels, which every instance of the circuit
+/// exposes as an inout port with the same name.
+#[derive(Debug, Clone)]
+pub(crate) struct ExtractedGlobal {
+    pub name: SharedStr,
+    pub bit_width: u8,
+    /// Index into `ExtractedCircuit::nets`
+    pub net: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct ExtractedCircuit {
+    pub name: SharedStr,
+    /// The file the circuit was loaded from
+    pub file_path: Option<PathBuf>,
+    pub symbols: Vec<ExtractedSymbol>,
+    /// Nets connected by labels are merged into one
+    pub nets: Vec<ExtractedNet>,
+    /// The global labels of the circuit and of the circuits it instantiates
+    pub globals: Vec<ExtractedGlobal>,
+}
+
+type SymbolQuery<'w> = (
+    &'w SymbolKind,
+    &'w Transform,
+    &'w Name,
+    &'w DesignatorPrefix,
+    &'w DesignatorNumber,
+    Option<&'w DesignatorSuffix>,
+    Option<&'w CircuitID>,
+    Option<&'w LogicState>,
+    Option<&'w CustomKind>,
+);
+
+type PortQuery<'w> = (
+    &'w Name,
+    &'w Transform,
+    &'w BitWidth,
+    Has<Input>,
+    Has<Output>,
+    Option<&'w NetID>,
+);
+
+type NetQuery<'w> = (&'w Name, &'w BitWidth, Option<&'w Vertices>);
+
+struct Extractor<'w> {
+    world: &'w World,
+    children: QueryState<Edges<Child>>,
+    circuits: QueryState<(&'static Name, Option<&'static FilePath>), With<Circuit>>,
+    symbols: QueryState<SymbolQuery<'static>, With<Symbol>>,
+    ports: QueryState<PortQuery<'static>, With<Port>>,
+    nets: QueryState<NetQuery<'static>, With<Net>>,
+    endpoints: QueryState<(&'static PortID, &'static Bits), With<Endpoint>>,
+    /// Circuits that have been extracted, in dependency order
+    extracted: Vec<ExtractedCircuit>,
+    index_map: HashMap<Entity, usize>,
+    /// Circuits currently being extracted, to detect recursive instantiation
+    stack: Vec<Entity>,
+}
+
+/// Extracts `root` and every circuit instantiated by it, directly or indirectly.
+/// Sub-circuits come before the circuits that use them, so the root circuit is
+/// always the last entry.
+pub(crate) fn extract_hierarchy(world: &mut World, root: Entity) -> Result<Vec<ExtractedCircuit>> {
+    let children = world.query::<Edges<Child>>();
+    let circuits = world.query_filtered::<(&Name, Option<&FilePath>), With<Circuit>>();
+    let symbols = world.query_filtered::<SymbolQuery<'static>, With<Symbol>>();
+    let ports = world.query_filtered::<PortQuery<'static>, With<Port>>();
+    let nets = world.query_filtered::<NetQuery<'static>, With<Net>>();
+    let endpoints = world.query_filtered::<(&PortID, &Bits), With<Endpoint>>();
+
+    let mut extractor = Extractor {
+        world,
+        children,
+        circuits,
+        symbols,
+        ports,
+        nets,
+        endpoints,
+        extracted: Vec::new(),
+        index_map: HashMap::default(),
+        stack: Vec::new(),
+    };
+
+    extractor.extract_circuit(root)?;
+    Ok(extractor.extracted)
+}
+
+impl Extractor<'_> {
+    fn children_of(&mut self, entity: Entity) -> Vec<Entity> {
+        self.children
+            .get(self.world, entity)
+            .map(|edges| edges.hosts().to_vec())
+            .unwrap_or_default()
+    }
+
+    fn extract_circuit(&mut self, circuit: Entity) -> Result<usize> {
+        if let Some(&index) = self.index_map.get(&circuit) {
+            return Ok(index);
+        }
+
+        if self.stack.contains(&circuit) {
+            bail!("circuit {circuit} instantiates itself");
+        }
+
+        let (name, file_path) = self
+            .circuits
+            .get(self.world, circuit)
+            .map_err(|_| anyhow!("entity {circuit} is not a circuit"))?;
+        let name = name.0.clone();
+        let file_path = file_path.map(|file_path| file_path.0.clone());
+
+        self.stack.push(circuit);
+
+        let children = self.children_of(circuit);
+
+        let mut circuit_nets = Vec::new();
+        let mut port_bits = HashMap::default();
+        for &child in children.iter() {
+            if let Ok((name, bit_width, vertices)) = self.nets.get(self.world, child) {
+                circuit_nets.push((
+                    child,
+                    *bit_width,
+                    ExtractedNet {
+                        name: name.0.clone(),
+                        bit_width: bit_width.0.get(),
+                        wires: vertices.map(wire_segments).unwrap_or_default(),
+                    },
+                ));
+
+                for endpoint in self.children_of(child) {
+                    if let Ok((port, bits)) = self.endpoints.get(self.world, endpoint) {
+                        port_bits.insert(port.0, bits.0.to_vec());
+                    }
+                }
+            }
+        }
+
+        let mut labels = LabelNets::default();
+        let mut label_names = Vec::new();
+        for &child in children.iter() {
+            let Ok((&kind, _, name, ..)) = self.symbols.get(self.world, child) else {
+                continue;
+            };
+            let global = match kind {
+                SymbolKind::Label => false,
+                SymbolKind::GlobalLabel => true,
+                _ => continue,
+            };
+
+            for port in self.children_of(child) {
+                let Ok((.., Some(net))) = self.ports.get(self.world, port) else {
+                    continue;
+                };
+                let Some(&(_, bit_width, _)) = circuit_nets.iter().find(|(id, ..)| *id == net.0)
+                else {
+                    continue;
+                };
+
+                labels.add(&name.0, global, net.0, bit_width)?;
+                label_names.push((net.0, name.0.clone()));
+            }
+        }
+
+        // Nets connected by labels become one, named after the label unless
+        // one of them has a name already.
+        let mut nets = Vec::new();
+        let mut net_map = HashMap::default();
+        let mut merged = Vec::new();
+        for (net, _, extracted) in circuit_nets {
+            if labels.resolve(net) == net {
+                net_map.insert(net, nets.len());
+                nets.push(extracted);
+            } else {
+                merged.push((net, extracted));
+            }
+        }
+        for (net, extracted) in merged {
+            let index = net_map[&labels.resolve(net)];
+            if nets[index].name.is_empty() {
+                nets[index].name = extracted.name;
+            }
+            nets[index].wires.extend(extracted.wires);
+            net_map.insert(net, index);
+        }
+        for (net, name) in label_names {
+            let net = &mut nets[net_map[&net]];
+            if net.name.is_empty() {
+                net.name = name;
+            }
+        }
+
+        let mut globals: Vec<_> = labels
+            .globals()
+            .map(|(name, net, bit_width)| ExtractedGlobal {
+                name: name.clone(),
+                bit_width: bit_width.0.get(),
+                net: net_map[&net],
+            })
+            .collect();
+        globals.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut symbols = Vec::new();
+        for &child in children.iter() {
+            let Ok((
+                &kind,
+                &transform,
+                name,
+                prefix,
+                number,
+                suffix,
+                sub_circuit,
+                state,
+                custom_kind,
+            )) = self.symbols.get(self.world, child)
+            else {
+                continue;
+            };
+
+            let value = match (kind, state) {
+                (SymbolKind::Const, Some(state)) => Some(
+                    state
+                        .bit_plane_0
+                        .iter()
+                        .take(8)
+                        .enumerate()
+                        .fold(0, |value, (i, &byte)| value | ((byte as u64) << (i * 8))),
+                ),
+                _ => None,
+            };
+
+            let name = name.0.clone();
+            let mut designator = format!("{}{}", prefix.0, number.0);
+            if let Some(suffix) = suffix {
+                designator.push_str(&suffix.0);
+            }
+            let sub_circuit = sub_circuit.copied();
+
+            let mut ports = Vec::new();
+            for port in self.children_of(child) {
+                let Ok((name, port_transform, bit_width, input, output, net)) =
+                    self.ports.get(self.world, port)
+                else {
+                    continue;
+                };
+
+                ports.push(ExtractedPort {
+                    name: name.0.clone(),
+                    input,
+                    output,
+                    bit_width: bit_width.0.get(),
+                    net: net.and_then(|net| net_map.get(&net.0).copied()),
+                    bits: port_bits.get(&port).cloned(),
+                    position: (transform * *port_transform).translation,
+                });
+            }
+
+            let sub_circuit = match sub_circuit {
+                Some(sub_circuit) => Some(self.extract_circuit(sub_circuit.0)?),
+                None => None,
+            };
+
+            // The global labels of the instantiated circuit connect to the
+            // ones with the same name in this circuit, or pass through it.
+            let (sub_name, sub_globals) = match sub_circuit {
+                Some(index) => {
+                    let sub_circuit = &self.extracted[index];
+                    (sub_circuit.name.clone(), sub_circuit.globals.clone())
+                }
+                None => Default::default(),
+            };
+            for sub_global in sub_globals {
+                let global = match globals.iter().find(|global| global.name == sub_global.name) {
+                    Some(global) => global.clone(),
+                    None => {
+                        let global = ExtractedGlobal {
+                            net: nets.len(),
+                            ..sub_global.clone()
+                        };
+                        nets.push(ExtractedNet {
+                            name: global.name.clone(),
+                            bit_width: global.bit_width,
+                            wires: Vec::new(),
+                        });
+                        globals.push(global.clone());
+                        global
+                    }
+                };
+
+                if global.bit_width != sub_global.bit_width {
+                    bail!(
+                        "global label `{}` of {sub_name} is {} bits wide, but {} bits wide where {designator} instantiates it",
+                        global.name,
+                        sub_global.bit_width,
+                        global.bit_width,
+                    );
+                }
+
+                ports.push(ExtractedPort {
+                    name: global.name,
+                    input: true,
+                    output: true,
+                    bit_width: global.bit_width,
+                    net: Some(global.net),
+                    bits: None,
+                    position: transform.translation,
+                });
+            }
+
+            symbols.push(ExtractedSymbol {
+                kind,
+                name,
+                designator,
+                transform,
+                ports,
+                sub_circuit,
+                value,
+                custom_kind: custom_kind.map(|custom_kind| custom_kind.0.clone()),
+            });
+        }
+
+        self.stack.pop();
+
+        let index = self.extracted.len();
+        self.extracted.push(ExtractedCircuit {
+            name,
+            file_path,
+            symbols,
+            nets,
+            globals,
+        });
+        self.index_map.insert(circuit, index);
+        Ok(index)
+    }
+}
+
+/// Splits routed vertices into the straight wire segments between them.
+fn wire_segments(vertices: &Vertices) -> Vec<[Vec2; 2]> {
+    let mut segments = Vec::new();
+    let mut prev = None;
+    for vertex in vertices.iter() {
+        if let (Some(prev), false) = (prev, matches!(vertex.kind, VertexKind::WireStart { .. })) {
+            if prev != vertex.position {
+                segments.push([prev, vertex.position]);
+            }
+        }
+        prev = Some(vertex.position);
+    }
+    segments
+}
+
+/// Replaces every character that isn't valid in an identifier with `_`.
+pub(crate) fn sanitize(name: &str, fallback: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if ident.is_empty() {
+        ident.push_str(fallback);
+    }
+    if ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    ident
+}
+
+/// The identifiers a writer gave the ports of a circuit's module, so instances
+/// of the circuit connect to the same names.
+#[derive(Debug, Default)]
+pub(crate) struct PortIdents {
+    /// The name, direction and identifier of every port
+    ports: Vec<(SharedStr, bool, bool, String)>,
+}
+
+impl PortIdents {
+    pub fn push(&mut self, name: &SharedStr, input: bool, output: bool, ident: &str) {
+        self.ports
+            .push((name.clone(), input, output, ident.to_owned()));
+    }
+
+    /// The identifier of each port of `symbol`, an instance of the circuit.
+    /// Ports with the same name and direction are matched in order.
+    pub fn for_instance(&self, symbol: &ExtractedSymbol) -> Vec<String> {
+        let mut used = vec![false; self.ports.len()];
+        symbol
+            .ports
+            .iter()
+            .map(|port| {
+                let found =
+                    self.ports
+                        .iter()
+                        .enumerate()
+                        .position(|(i, (name, input, output, _))| {
+                            !used[i]
+                                && *name == port.name
+                                && *input == port.input
+                                && *output == port.output
+                        });
+                match found {
+                    Some(i) => {
+                        used[i] = true;
+                        self.ports[i].3.clone()
+                    }
+                    None => sanitize(&port.name, "port"),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Hands out unique identifiers made only of characters that are valid in most
+/// netlist formats.
+#[derive(Debug, Default)]
+pub(crate) struct IdentifierSet {
+    used: HashMap<String, u32>,
+}
+
+impl IdentifierSet {
+    pub fn reserve(&mut self, name: &str) {
+        self.used.insert(name.to_owned(), 0);
+    }
+
+    pub fn make_unique(&mut self, name: &str, fallback: &str) -> String {
+        let ident = sanitize(name, fallback);
+
+        if !self.used.contains_key(&ident) {
+            self.used.insert(ident.clone(), 0);
+            return ident;
+        }
+
+        loop {
+            let count = self.used.get_mut(&ident).unwrap();
+            *count += 1;
+
+            let candidate = format!("{ident}_{count}");
+            if !self.used.contains_key(&candidate) {
+                self.used.insert(candidate.clone(), 0);
+                return candidate;
+            }
+        }
+    }
+}
@@ -1,16 +1,19 @@
-mod circuitfile;
+mod binary;
+pub(crate) mod circuitfile;
+mod migrations;
 use circuitfile::*;
 
+use aery::edges::{EdgeInfo, Edges};
 use aery::prelude::*;
 use anyhow::{bail, Result};
 use bevy_ecs::prelude::*;
 use bevy_log::info;
 use digilogic_core::bundles::*;
 use digilogic_core::components::*;
-use digilogic_core::symbol::SymbolRegistry;
+use digilogic_core::symbol::{SubCircuitPort, SymbolRegistry};
 use digilogic_core::transform::*;
 use digilogic_core::visibility::VisibilityBundle;
-use digilogic_core::HashMap;
+use digilogic_core::{HashMap, SharedStr};
 use std::num::NonZeroU8;
 use std::path::Path;
 
@@ -29,6 +32,233 @@ pub fn load_json(
     translate_circuit(commands, &circuit, symbols, &name.to_string_lossy())
 }
 
+/// Saves a circuit as a `.dlc` file, or a `.dlcb` file in the binary encoding.
+pub fn save_json(world: &mut World, circuit: Entity, filename: &Path) -> Result<()> {
+    info!("saving Digilogic circuit {}", filename.display());
+
+    extract_circuit(world, circuit)?.save(filename)
+}
+
+type SymbolQuery<'w> = (
+    &'w SymbolKind,
+    &'w Name,
+    &'w Transform,
+    &'w DesignatorNumber,
+    Option<&'w DesignatorSuffix>,
+    Option<&'w CircuitID>,
+    Option<&'w CustomKind>,
+    Option<&'w LogicState>,
+);
+
+type EndpointQuery<'w> = (&'w Transform, Option<&'w PortID>, Option<&'w Bits>);
+
+/// Builds the `CircuitFile` of a circuit. The circuits it instantiates are
+/// stored as further modules, which the symbols instantiating them refer to.
+pub(crate) fn extract_circuit(world: &mut World, circuit: Entity) -> Result<CircuitFile> {
+    use digilogic_core::components::{Endpoint, Net, Symbol};
+
+    let mut children = world.query::<Edges<Child>>();
+    let mut part_of = world.query::<Edges<PartOf>>();
+    let mut symbols = world.query_filtered::<SymbolQuery, With<Symbol>>();
+    let mut ports = world.query_filtered::<(&Name, &BitWidth, Has<Input>), With<Port>>();
+    let mut nets = world.query_filtered::<(&Name, &BitWidth), With<Net>>();
+    let mut endpoints = world.query_filtered::<EndpointQuery, With<Endpoint>>();
+    let world = &*world;
+    let registry = world.resource::<SymbolRegistry>();
+
+    let mut children_of = |entity: Entity| -> Vec<Entity> {
+        children
+            .get(world, entity)
+            .map(|edges| edges.hosts().to_vec())
+            .unwrap_or_default()
+    };
+
+    let mut file = CircuitFile {
+        version: migrations::CURRENT_VERSION,
+        modules: Vec::new(),
+    };
+
+    // Module `i` is built from `circuits[i]`, instantiated circuits are added
+    // as they're found. IDs are unique across modules.
+    let mut circuits = vec![circuit];
+    let mut symbol_count = 0;
+    let mut net_count = 0;
+    let mut endpoint_count = 0;
+    while let Some(&circuit) = circuits.get(file.modules.len()) {
+        let Some(name) = world.get::<Name>(circuit) else {
+            bail!("entity {circuit} is not a circuit");
+        };
+        let mut module = Module {
+            id: Id(file.modules.len().to_string().into()),
+            name: name.0.clone(),
+            prefix: SharedStr::default(),
+            symbol_kind: Id(SharedStr::default()),
+            symbols: Vec::new(),
+            nets: Vec::new(),
+        };
+
+        let circuit_children = children_of(circuit);
+
+        // The symbol and name of every port, which endpoints refer to
+        let mut port_refs = HashMap::default();
+        // The ID of every symbol, and the symbols that are part of another
+        let mut symbol_ids = HashMap::default();
+        let mut parts = Vec::new();
+        for &child in circuit_children.iter() {
+            let Ok((&kind, name, transform, number, suffix, sub_circuit, custom_kind, state)) =
+                symbols.get(world, child)
+            else {
+                continue;
+            };
+
+            let (symbol_kind_name, symbol_kind_id) = match (sub_circuit, custom_kind) {
+                (Some(&CircuitID(sub_circuit)), _) => {
+                    let index = match circuits.iter().position(|&other| other == sub_circuit) {
+                        Some(index) => index,
+                        None => {
+                            circuits.push(sub_circuit);
+                            circuits.len() - 1
+                        }
+                    };
+                    (None, Some(Id(index.to_string().into())))
+                }
+                (None, Some(custom_kind)) => (Some(custom_kind.0.clone()), None),
+                (None, None) => match (0..)
+                    .map_while(|index| registry.get_by_index(index))
+                    .find(|def| def.kind() == kind)
+                {
+                    Some(def) => (Some(def.name().clone()), None),
+                    None => bail!("{} has no definition for its kind {kind:?}", name.0),
+                },
+            };
+
+            let id = Id(format!("s{symbol_count}").into());
+            symbol_count += 1;
+            symbol_ids.insert(child, id.clone());
+            if let Some(first) = part_of
+                .get(world, child)
+                .ok()
+                .and_then(|edges| edges.targets().first().copied())
+            {
+                parts.push((module.symbols.len(), first));
+            }
+            let mut input_count = 0;
+            let mut bit_width = None;
+            for port in children_of(child) {
+                let Ok((port_name, port_bit_width, input)) = ports.get(world, port) else {
+                    continue;
+                };
+                input_count += input as u8;
+                bit_width.get_or_insert(port_bit_width.0.get());
+                port_refs.insert(port, (id.clone(), port_name.0.clone()));
+            }
+
+            let is_gate = matches!(
+                kind,
+                SymbolKind::And
+                    | SymbolKind::Or
+                    | SymbolKind::Xor
+                    | SymbolKind::Nand
+                    | SymbolKind::Nor
+                    | SymbolKind::Xnor
+            );
+            let value = match (kind, state) {
+                (SymbolKind::Const, Some(state)) => Some(
+                    state
+                        .bit_plane_0
+                        .iter()
+                        .take(8)
+                        .enumerate()
+                        .fold(0, |value, (i, &byte)| value | ((byte as u64) << (i * 8))),
+                ),
+                _ => None,
+            };
+
+            // The ports of instances are as wide as the pins of their circuit
+            let bit_width = bit_width.filter(|&bit_width| bit_width != 1 && sub_circuit.is_none());
+            let name = match &symbol_kind_name {
+                Some(kind_name) => (name.0 != *kind_name).then(|| name.0.clone()),
+                None => Some(name.0.clone()),
+            };
+            module.symbols.push(circuitfile::Symbol {
+                id,
+                symbol_kind_name,
+                symbol_kind_id,
+                position: [transform.translation.x, transform.translation.y],
+                rotation: transform.rotation,
+                flip: transform.flip,
+                number: number.0,
+                input_count: (is_gate && input_count != 2).then_some(input_count),
+                bit_width,
+                name,
+                value,
+                suffix: suffix.map(|suffix| suffix.0.clone()),
+                part_of: None,
+            });
+        }
+
+        for (index, first) in parts {
+            module.symbols[index].part_of = symbol_ids.get(&first).cloned();
+        }
+
+        for &child in circuit_children.iter() {
+            let Ok((name, bit_width)) = nets.get(world, child) else {
+                continue;
+            };
+            let net_id = format!("n{net_count}");
+            net_count += 1;
+
+            // Endpoints using the same bits of the net share a subnet
+            let mut subnets: Vec<Subnet> = Vec::new();
+            for endpoint in children_of(child) {
+                let Ok((transform, port, bits)) = endpoints.get(world, endpoint) else {
+                    continue;
+                };
+
+                let (symbol, port_name) = match port.and_then(|port| port_refs.get(&port.0)) {
+                    Some((symbol, port_name)) => (symbol.clone(), Some(port_name.clone())),
+                    None => (Id(SharedStr::default()), None),
+                };
+                let endpoint = circuitfile::Endpoint {
+                    id: Id(format!("e{endpoint_count}").into()),
+                    position: [transform.translation.x, transform.translation.y],
+                    portref: PortRef {
+                        symbol,
+                        port_name,
+                        port: None,
+                    },
+                };
+                endpoint_count += 1;
+
+                let subnet_bits = bits.map(|bits| bits.0.to_vec()).unwrap_or_default();
+                match subnets
+                    .iter_mut()
+                    .find(|subnet| subnet.subnet_bits == subnet_bits)
+                {
+                    Some(subnet) => subnet.endpoints.push(endpoint),
+                    None => subnets.push(Subnet {
+                        id: Id(format!("{net_id}.{}", subnets.len()).into()),
+                        name: SharedStr::default(),
+                        subnet_bits,
+                        endpoints: vec![endpoint],
+                    }),
+                }
+            }
+
+            module.nets.push(circuitfile::Net {
+                id: Id(net_id.into()),
+                name: name.0.clone(),
+                bit_width: bit_width.0.get(),
+                subnets,
+            });
+        }
+
+        file.modules.push(module);
+    }
+
+    Ok(file)
+}
+
 fn translate_circuit(
     commands: &mut Commands,
     circuit: &CircuitFile,
@@ -37,29 +267,111 @@ fn translate_circuit(
 ) -> Result<Entity> {
     let mut id_map = HashMap::new();
     let modules = &circuit.modules;
-    let mut top_id: Option<Entity> = None;
+    if modules.is_empty() {
+        bail!("circuit file has no modules");
+    }
 
-    for module in modules.iter() {
+    // Every module is spawned first, symbols can instantiate modules that
+    // come after them. The first module is the circuit named after the file.
+    let mut module_map = HashMap::new();
+    let mut circuit_ids = Vec::with_capacity(modules.len());
+    for (index, module) in modules.iter().enumerate() {
+        let name = if index == 0 || module.name.is_empty() {
+            name.into()
+        } else {
+            module.name.clone()
+        };
         let circuit_id = commands
             .spawn(CircuitBundle {
                 circuit: Circuit,
-                name: Name(name.into()),
+                name: Name(name),
             })
             .id();
+        module_map.insert(
+            module.id.clone(),
+            (CircuitID(circuit_id), module_ports(module, symbols)),
+        );
+        circuit_ids.push(circuit_id);
+    }
 
+    for (module, &circuit_id) in modules.iter().zip(circuit_ids.iter()) {
+        let mut symbol_map = HashMap::new();
         for symbol in module.symbols.iter() {
-            translate_symbol(symbol, &mut id_map, commands, circuit_id, symbols)?;
+            let symbol_id = translate_symbol(
+                symbol,
+                &mut id_map,
+                commands,
+                circuit_id,
+                symbols,
+                &module_map,
+            )?;
+            symbol_map.insert(symbol.id.clone(), symbol_id);
+        }
+
+        // Parts can refer to symbols that come after them
+        for symbol in module.symbols.iter() {
+            let Some(first) = &symbol.part_of else {
+                continue;
+            };
+            let Some(&first) = symbol_map.get(first) else {
+                bail!(
+                    "Symbol {} is part of unknown symbol {}",
+                    symbol.id.0,
+                    first.0
+                );
+            };
+            commands.entity(symbol_map[&symbol.id]).set::<PartOf>(first);
         }
 
         for net in module.nets.iter() {
             translate_net(net, &mut id_map, commands, circuit_id)?;
         }
-        if top_id.is_none() {
-            top_id = Some(circuit_id);
-        }
     }
 
-    Ok(top_id.unwrap())
+    Ok(circuit_ids[0])
+}
+
+/// The ports of the symbols instantiating `module`, one for each of its In
+/// and Out symbols, ordered like `sub_circuit::circuit_ports` orders them.
+fn module_ports(module: &Module, symbols: &SymbolRegistry) -> Vec<SubCircuitPort> {
+    let kind_name = |kind| {
+        (0..)
+            .map_while(|index| symbols.get_by_index(index))
+            .find(|def| def.kind() == kind)
+            .map(|def| def.name().clone())
+    };
+    let (in_name, out_name) = (kind_name(SymbolKind::In), kind_name(SymbolKind::Out));
+
+    let mut pins: Vec<_> = module
+        .symbols
+        .iter()
+        .filter_map(|symbol| {
+            let kind_name = symbol.symbol_kind_name.as_ref()?;
+            let output = if Some(kind_name) == in_name.as_ref() {
+                false
+            } else if Some(kind_name) == out_name.as_ref() {
+                true
+            } else {
+                return None;
+            };
+            let bit_width = symbol
+                .bit_width
+                .and_then(NonZeroU8::new)
+                .unwrap_or(NonZeroU8::MIN);
+
+            Some((
+                (output, symbol.position[1], symbol.position[0]),
+                SubCircuitPort {
+                    name: symbol.name.clone().unwrap_or_else(|| kind_name.clone()),
+                    output,
+                    bit_width: BitWidth(bit_width),
+                },
+            ))
+        })
+        .collect();
+    pins.sort_by_key(|&(key, _)| key);
+
+    pins.into_iter().map(|(_, port)| port).collect()
 }
 
 // TODO: a context struct would reduce the number of arguments
@@ -69,41 +381,59 @@ fn translate_symbol(
     commands: &mut Commands,
     circuit_id: Entity,
     symbols: &SymbolRegistry,
-) -> Result<()> {
-    let symbol_builder = if let Some(kind_name) = symbol.symbol_kind_name.as_ref() {
-        symbols.get_by_name(kind_name)
-    } else if symbol.symbol_kind_id.is_some() {
-        return Err(anyhow::anyhow!(
-            "Symbol {} has SymbolKindID but it's not supported",
-            symbol.id.0
-        ));
-    } else {
-        return Err(anyhow::anyhow!("Symbol {} has no SymbolKind", symbol.id.0));
+    module_map: &HashMap<Id, (CircuitID, Vec<SubCircuitPort>)>,
+) -> Result<Entity> {
+    let mut symbol_builder = match (&symbol.symbol_kind_name, &symbol.symbol_kind_id) {
+        (Some(kind_name), _) => match symbols.get_by_name(kind_name) {
+            Some(symbol_builder) => symbol_builder,
+            None => bail!("Symbol {} has unknown SymbolKind {kind_name}", symbol.id.0),
+        },
+        (None, Some(kind_id)) => {
+            let Some((sub_circuit, ports)) = module_map.get(kind_id) else {
+                bail!(
+                    "Symbol {} instantiates unknown module {}",
+                    symbol.id.0,
+                    kind_id.0
+                );
+            };
+            let mut symbol_builder = symbols.get(SymbolKind::SubCircuit);
+            symbol_builder.sub_circuit(*sub_circuit, ports);
+            symbol_builder
+        }
+        (None, None) => bail!("Symbol {} has no SymbolKind", symbol.id.0),
     };
-    if symbol_builder.is_none() {
-        return Err(anyhow::anyhow!(
-            "Symbol {} has unknown SymbolKind {}",
-            symbol.id.0,
-            symbol
-                .symbol_kind_name
-                .as_ref()
-                .unwrap_or(&symbol.symbol_kind_id.as_ref().unwrap().0)
-        ));
-    }
-    let mut symbol_builder = symbol_builder.unwrap();
     symbol_builder
         .designator_number(symbol.number)
         .position(Vec2 {
             x: symbol.position[0],
             y: symbol.position[1],
         })
-        .build(commands, circuit_id);
+        .rotation(symbol.rotation)
+        .flip(symbol.flip);
+    if let Some(input_count) = symbol.input_count {
+        symbol_builder.input_count(input_count);
+    }
+    if let Some(bit_width) = symbol.bit_width.and_then(NonZeroU8::new) {
+        symbol_builder.bit_width(BitWidth(bit_width));
+    }
+    if let Some(name) = &symbol.name {
+        symbol_builder.name(name.clone());
+    }
+    if let Some(value) = symbol.value {
+        symbol_builder.value(value);
+    }
+    let symbol_id = symbol_builder.build(commands, circuit_id);
+    if let Some(suffix) = &symbol.suffix {
+        commands
+            .entity(symbol_id)
+            .insert(DesignatorSuffix(suffix.clone()));
+    }
     for port in symbol_builder.ports().iter() {
         let symbol_name_pair = format!("{}:{}", symbol.id.0, port.name);
         id_map.insert(Id(symbol_name_pair.into()), port.id);
     }
 
-    Ok(())
+    Ok(symbol_id)
 }
 
 fn translate_net(
@@ -112,18 +442,19 @@ fn translate_net(
     commands: &mut Commands,
     circuit_id: Entity,
 ) -> Result<()> {
+    let bit_width = BitWidth(NonZeroU8::new(net.bit_width).unwrap_or(NonZeroU8::MIN));
     let net_id = commands
         .spawn(NetBundle {
             net: Net,
             name: Name(net.name.clone()),
-            bit_width: BitWidth(NonZeroU8::MIN),
+            bit_width,
             visibility: VisibilityBundle::default(),
         })
         .set::<Child>(circuit_id)
         .id();
 
     for subnet in net.subnets.iter() {
-        translate_subnet(subnet, id_map, commands, net_id)?;
+        translate_subnet(subnet, id_map, commands, net_id, bit_width)?;
     }
 
     Ok(())
@@ -134,9 +465,33 @@ fn translate_subnet(
     id_map: &mut HashMap<Id, Entity>,
     commands: &mut Commands,
     net_id: Entity,
+    bit_width: BitWidth,
 ) -> Result<()> {
+    if let Some(&bit) = subnet
+        .subnet_bits
+        .iter()
+        .find(|&&bit| bit >= bit_width.0.get())
+    {
+        bail!(
+            "Subnet {} uses bit {} of a {} bit net",
+            subnet.id.0,
+            bit,
+            bit_width.0
+        );
+    }
+
+    // Subnets without any bits use the whole net
+    let bits = if subnet.subnet_bits.is_empty() {
+        None
+    } else {
+        Bits::subset(&subnet.subnet_bits, bit_width)
+    };
+
     for endpoint in subnet.endpoints.iter() {
-        translate_endpoint(endpoint, id_map, commands, net_id)?;
+        let endpoint_id = translate_endpoint(endpoint, id_map, commands, net_id)?;
+        if let Some(bits) = &bits {
+            commands.entity(endpoint_id).insert(bits.clone());
+        }
     }
     Ok(())
 }
@@ -146,7 +501,7 @@ fn translate_endpoint(
     id_map: &mut HashMap<Id, Entity>,
     commands: &mut Commands,
     net_id: Entity,
-) -> Result<()> {
+) -> Result<Entity> {
     let portref = &endpoint.portref;
 
     let port_id = if let Some(port_name) = portref.port_name.as_ref() {
@@ -195,5 +550,176 @@ fn translate_endpoint(
         commands.entity(port_id).insert(NetID(net_id));
     }
 
-    Ok(())
+    Ok(endpoint_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_circuits() {
+        let dir = std::env::temp_dir().join(format!("digilogic-dlc-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("saved.dlc");
+
+        let mut world = World::new();
+        world.init_resource::<SymbolRegistry>();
+        let circuit = world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+            load_json(
+                &mut world.commands(),
+                Path::new("testdata/versions/v8.dlc"),
+                &symbols,
+            )
+        });
+        world.flush();
+        save_json(&mut world, circuit.unwrap(), &filename).unwrap();
+
+        let original = CircuitFile::load("testdata/versions/v8.dlc").unwrap();
+        let saved = CircuitFile::load(&filename).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (original, saved) = (&original.modules[0], &saved.modules[0]);
+        let symbols = |module: &Module| -> Vec<_> {
+            module
+                .symbols
+                .iter()
+                .map(|symbol| {
+                    (
+                        symbol.symbol_kind_name.clone(),
+                        symbol.position,
+                        symbol.rotation,
+                        symbol.flip,
+                        symbol.number,
+                        symbol.bit_width,
+                        symbol.name.clone(),
+                        symbol.value,
+                        symbol.suffix.clone(),
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(symbols(saved), symbols(original));
+
+        let endpoints = |module: &Module| -> Vec<usize> {
+            module
+                .nets
+                .iter()
+                .map(|net| {
+                    net.subnets
+                        .iter()
+                        .map(|subnet| subnet.endpoints.len())
+                        .sum()
+                })
+                .collect()
+        };
+        assert_eq!(endpoints(saved), endpoints(original));
+    }
+
+    #[test]
+    fn round_trips_sub_circuits() {
+        let mut world = World::new();
+        world.init_resource::<SymbolRegistry>();
+        world.init_resource::<crate::FileRegistry>();
+        let circuit = world.resource_scope(|world, mut registry: Mut<crate::FileRegistry>| {
+            world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+                crate::load_circuit_file(
+                    &mut world.commands(),
+                    Path::new("testdata/full_adder.dig"),
+                    &mut registry,
+                    &symbols,
+                )
+            })
+        });
+        world.flush();
+        let saved = extract_circuit(&mut world, circuit.unwrap().0).unwrap();
+
+        // Both half adders instantiate the same module
+        assert_eq!(saved.modules.len(), 2);
+        let instances: Vec<_> = saved.modules[0]
+            .symbols
+            .iter()
+            .filter_map(|symbol| symbol.symbol_kind_id.as_ref())
+            .collect();
+        assert_eq!(instances.len(), 2);
+        assert!(instances.iter().all(|&id| *id == saved.modules[1].id));
+
+        let mut world = World::new();
+        world.init_resource::<SymbolRegistry>();
+        let circuit = world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+            translate_circuit(&mut world.commands(), &saved, &symbols, "full_adder")
+        });
+        world.flush();
+        let loaded = extract_circuit(&mut world, circuit.unwrap()).unwrap();
+
+        assert_eq!(format!("{loaded:?}"), format!("{saved:?}"));
+    }
+
+    #[test]
+    fn round_trips_multi_part_symbols() {
+        let mut world = World::new();
+        world.init_resource::<SymbolRegistry>();
+        let circuit = world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+            load_json(
+                &mut world.commands(),
+                Path::new("testdata/versions/v8.dlc"),
+                &symbols,
+            )
+        });
+        world.flush();
+        let circuit = circuit.unwrap();
+
+        let mut parts = world.query::<(&DesignatorSuffix, Edges<PartOf>)>();
+        let (suffix, edges) = parts
+            .iter(&world)
+            .find(|(_, edges)| !edges.targets().is_empty())
+            .unwrap();
+        assert_eq!(&*suffix.0, "B");
+        let first = edges.targets()[0];
+        assert_eq!(&*world.get::<DesignatorSuffix>(first).unwrap().0, "A");
+
+        let saved = extract_circuit(&mut world, circuit).unwrap();
+        let symbols = &saved.modules[0].symbols;
+        let part = symbols
+            .iter()
+            .find(|symbol| symbol.part_of.is_some())
+            .unwrap();
+        let first = symbols
+            .iter()
+            .find(|symbol| Some(&symbol.id) == part.part_of.as_ref())
+            .unwrap();
+        assert_eq!(part.suffix.as_deref(), Some("B"));
+        assert_eq!(first.suffix.as_deref(), Some("A"));
+
+        let mut world = World::new();
+        world.init_resource::<SymbolRegistry>();
+        let circuit = world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+            translate_circuit(&mut world.commands(), &saved, &symbols, "v8")
+        });
+        world.flush();
+        let loaded = extract_circuit(&mut world, circuit.unwrap()).unwrap();
+
+        assert_eq!(format!("{loaded:?}"), format!("{saved:?}"));
+    }
+
+    #[test]
+    fn fails_to_save_symbols_without_definition() {
+        let mut world = World::new();
+        world.init_resource::<SymbolRegistry>();
+        let circuit = world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+            load_json(
+                &mut world.commands(),
+                Path::new("testdata/versions/v7.dlc"),
+                &symbols,
+            )
+        });
+        world.flush();
+
+        // A custom symbol whose definition is gone, e.g. after changing libraries
+        use digilogic_core::components::Symbol;
+        let mut symbols = world.query_filtered::<&mut SymbolKind, With<Symbol>>();
+        *symbols.iter_mut(&mut world).next().unwrap() = SymbolKind::Custom;
+
+        assert!(extract_circuit(&mut world, circuit.unwrap()).is_err());
+    }
 }
@@ -0,0 +1,539 @@
+//! The binary `.dlcb` encoding of `CircuitFile`. It holds the same data as a
+//! `.dlc` file, encoded as MessagePack, with every string stored once in a
+//! string table and referenced by index. IDs and port names repeat a lot in
+//! large circuits, so this is both smaller and much faster to parse.
+//!
+//! The encoding is positional, so adding a field to `Symbol` changes its
+//! layout. The layouts of older versions are kept to decode older files, which
+//! are then upgraded by the same migrations as `.dlc` files.
+
+use super::circuitfile::{self as dlc, CircuitFile, Id};
+use super::migrations::CURRENT_VERSION;
+use anyhow::{anyhow, bail, Result};
+use digilogic_core::transform::{Flip, Rotation};
+use digilogic_core::{Fixed, HashMap, SharedStr};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+
+/// Index into the string table
+type Str = u32;
+
+#[derive(Serialize, Deserialize)]
+struct BinaryFile<'a, S = Symbol> {
+    version: u32,
+    #[serde(borrow)]
+    strings: Vec<&'a str>,
+    modules: Vec<Module<S>>,
+}
+
+/// The start of every version of `BinaryFile`, to pick the layout to decode
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+    _strings: IgnoredAny,
+    _modules: IgnoredAny,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Module<S = Symbol> {
+    id: Str,
+    name: Str,
+    prefix: Str,
+    symbol_kind: Str,
+    symbols: Vec<S>,
+    nets: Vec<Net>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Symbol {
+    id: Str,
+    symbol_kind_name: Option<Str>,
+    symbol_kind_id: Option<Str>,
+    position: [i32; 2],
+    rotation: u8,
+    flip: u8,
+    number: u32,
+    input_count: Option<u8>,
+    bit_width: Option<u8>,
+    name: Option<Str>,
+    value: Option<u64>,
+    suffix: Option<Str>,
+    part_of: Option<Str>,
+}
+
+/// The layout of symbols in version 3
+#[derive(Deserialize)]
+struct SymbolV3 {
+    id: Str,
+    symbol_kind_name: Option<Str>,
+    symbol_kind_id: Option<Str>,
+    position: [i32; 2],
+    rotation: u8,
+    number: u32,
+}
+
+impl From<SymbolV3> for Symbol {
+    fn from(symbol: SymbolV3) -> Self {
+        Self {
+            id: symbol.id,
+            symbol_kind_name: symbol.symbol_kind_name,
+            symbol_kind_id: symbol.symbol_kind_id,
+            position: symbol.position,
+            rotation: symbol.rotation,
+            flip: 0,
+            number: symbol.number,
+            input_count: None,
+            bit_width: None,
+            name: None,
+            value: None,
+            suffix: None,
+            part_of: None,
+        }
+    }
+}
+
+/// The layout of symbols in version 4, which added `input_count` and
+/// `bit_width`
+#[derive(Deserialize)]
+struct SymbolV4 {
+    id: Str,
+    symbol_kind_name: Option<Str>,
+    symbol_kind_id: Option<Str>,
+    position: [i32; 2],
+    rotation: u8,
+    number: u32,
+    input_count: Option<u8>,
+    bit_width: Option<u8>,
+}
+
+impl From<SymbolV4> for Symbol {
+    fn from(symbol: SymbolV4) -> Self {
+        Self {
+            id: symbol.id,
+            symbol_kind_name: symbol.symbol_kind_name,
+            symbol_kind_id: symbol.symbol_kind_id,
+            position: symbol.position,
+            rotation: symbol.rotation,
+            flip: 0,
+            number: symbol.number,
+            input_count: symbol.input_count,
+            bit_width: symbol.bit_width,
+            name: None,
+            value: None,
+            suffix: None,
+            part_of: None,
+        }
+    }
+}
+
+/// The layout of symbols in version 5, which added `flip`
+#[derive(Deserialize)]
+struct SymbolV5 {
+    id: Str,
+    symbol_kind_name: Option<Str>,
+    symbol_kind_id: Option<Str>,
+    position: [i32; 2],
+    rotation: u8,
+    flip: u8,
+    number: u32,
+    input_count: Option<u8>,
+    bit_width: Option<u8>,
+}
+
+impl From<SymbolV5> for Symbol {
+    fn from(symbol: SymbolV5) -> Self {
+        Self {
+            id: symbol.id,
+            symbol_kind_name: symbol.symbol_kind_name,
+            symbol_kind_id: symbol.symbol_kind_id,
+            position: symbol.position,
+            rotation: symbol.rotation,
+            flip: symbol.flip,
+            number: symbol.number,
+            input_count: symbol.input_count,
+            bit_width: symbol.bit_width,
+            name: None,
+            value: None,
+            suffix: None,
+            part_of: None,
+        }
+    }
+}
+
+/// The layout of symbols in version 6, which added `name`
+#[derive(Deserialize)]
+struct SymbolV6 {
+    id: Str,
+    symbol_kind_name: Option<Str>,
+    symbol_kind_id: Option<Str>,
+    position: [i32; 2],
+    rotation: u8,
+    flip: u8,
+    number: u32,
+    input_count: Option<u8>,
+    bit_width: Option<u8>,
+    name: Option<Str>,
+}
+
+impl From<SymbolV6> for Symbol {
+    fn from(symbol: SymbolV6) -> Self {
+        Self {
+            id: symbol.id,
+            symbol_kind_name: symbol.symbol_kind_name,
+            symbol_kind_id: symbol.symbol_kind_id,
+            position: symbol.position,
+            rotation: symbol.rotation,
+            flip: symbol.flip,
+            number: symbol.number,
+            input_count: symbol.input_count,
+            bit_width: symbol.bit_width,
+            name: symbol.name,
+            value: None,
+            suffix: None,
+            part_of: None,
+        }
+    }
+}
+
+/// The layout of symbols in version 7, which added `value`
+#[derive(Deserialize)]
+struct SymbolV7 {
+    id: Str,
+    symbol_kind_name: Option<Str>,
+    symbol_kind_id: Option<Str>,
+    position: [i32; 2],
+    rotation: u8,
+    flip: u8,
+    number: u32,
+    input_count: Option<u8>,
+    bit_width: Option<u8>,
+    name: Option<Str>,
+    value: Option<u64>,
+}
+
+impl From<SymbolV7> for Symbol {
+    fn from(symbol: SymbolV7) -> Self {
+        Self {
+            id: symbol.id,
+            symbol_kind_name: symbol.symbol_kind_name,
+            symbol_kind_id: symbol.symbol_kind_id,
+            position: symbol.position,
+            rotation: symbol.rotation,
+            flip: symbol.flip,
+            number: symbol.number,
+            input_count: symbol.input_count,
+            bit_width: symbol.bit_width,
+            name: symbol.name,
+            value: symbol.value,
+            suffix: None,
+            part_of: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Net {
+    id: Str,
+    name: Str,
+    bit_width: u8,
+    subnets: Vec<Subnet>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Subnet {
+    id: Str,
+    name: Str,
+    #[serde(with = "serde_bytes")]
+    subnet_bits: Vec<u8>,
+    endpoints: Vec<Endpoint>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Endpoint {
+    id: Str,
+    position: [i32; 2],
+    symbol: Str,
+    port_name: Option<Str>,
+    port: Option<Str>,
+}
+
+fn to_bits(position: [Fixed; 2]) -> [i32; 2] {
+    position.map(Fixed::to_bits)
+}
+
+fn from_bits(position: [i32; 2]) -> [Fixed; 2] {
+    position.map(Fixed::from_bits)
+}
+
+#[derive(Default)]
+struct Interner<'a> {
+    strings: Vec<&'a str>,
+    indices: HashMap<&'a str, Str>,
+}
+
+impl<'a> Interner<'a> {
+    fn intern(&mut self, string: &'a str) -> Str {
+        *self.indices.entry(string).or_insert_with(|| {
+            self.strings.push(string);
+            (self.strings.len() - 1) as Str
+        })
+    }
+}
+
+struct Strings(Vec<SharedStr>);
+
+impl Strings {
+    fn get(&self, index: Str) -> Result<SharedStr> {
+        self.0
+            .get(index as usize)
+            .cloned()
+            .ok_or_else(|| anyhow!("invalid string index {index}"))
+    }
+
+    fn id(&self, index: Str) -> Result<Id> {
+        self.get(index).map(Id)
+    }
+
+    fn get_opt(&self, index: Option<Str>) -> Result<Option<SharedStr>> {
+        index.map(|index| self.get(index)).transpose()
+    }
+
+    fn id_opt(&self, index: Option<Str>) -> Result<Option<Id>> {
+        index.map(|index| self.id(index)).transpose()
+    }
+}
+
+/// Decodes a file whose symbols have an older layout.
+fn decode_layout<'a, S>(bytes: &'a [u8]) -> Result<BinaryFile<'a>>
+where
+    S: Deserialize<'a> + Into<Symbol>,
+{
+    let file: BinaryFile<S> = rmp_serde::from_slice(bytes)?;
+    Ok(BinaryFile {
+        version: file.version,
+        strings: file.strings,
+        modules: file
+            .modules
+            .into_iter()
+            .map(|module| Module {
+                id: module.id,
+                name: module.name,
+                prefix: module.prefix,
+                symbol_kind: module.symbol_kind,
+                symbols: module.symbols.into_iter().map(Into::into).collect(),
+                nets: module.nets,
+            })
+            .collect(),
+    })
+}
+
+fn decode_all<T, U>(items: Vec<T>, decode: impl Fn(T) -> Result<U>) -> Result<Vec<U>> {
+    items.into_iter().map(decode).collect()
+}
+
+impl Module {
+    fn encode<'a>(module: &'a dlc::Module, interner: &mut Interner<'a>) -> Self {
+        Self {
+            id: interner.intern(&module.id.0),
+            name: interner.intern(&module.name),
+            prefix: interner.intern(&module.prefix),
+            symbol_kind: interner.intern(&module.symbol_kind.0),
+            symbols: module
+                .symbols
+                .iter()
+                .map(|symbol| Symbol::encode(symbol, interner))
+                .collect(),
+            nets: module
+                .nets
+                .iter()
+                .map(|net| Net::encode(net, interner))
+                .collect(),
+        }
+    }
+
+    fn decode(self, strings: &Strings) -> Result<dlc::Module> {
+        Ok(dlc::Module {
+            id: strings.id(self.id)?,
+            name: strings.get(self.name)?,
+            prefix: strings.get(self.prefix)?,
+            symbol_kind: strings.id(self.symbol_kind)?,
+            symbols: decode_all(self.symbols, |symbol| symbol.decode(strings))?,
+            nets: decode_all(self.nets, |net| net.decode(strings))?,
+        })
+    }
+}
+
+impl Symbol {
+    fn encode<'a>(symbol: &'a dlc::Symbol, interner: &mut Interner<'a>) -> Self {
+        Self {
+            id: interner.intern(&symbol.id.0),
+            symbol_kind_name: symbol
+                .symbol_kind_name
+                .as_deref()
+                .map(|name| interner.intern(name)),
+            symbol_kind_id: symbol
+                .symbol_kind_id
+                .as_ref()
+                .map(|id| interner.intern(&id.0)),
+            position: to_bits(symbol.position),
+            rotation: symbol.rotation as u8,
+            flip: symbol.flip.bits(),
+            number: symbol.number,
+            input_count: symbol.input_count,
+            bit_width: symbol.bit_width,
+            name: symbol.name.as_deref().map(|name| interner.intern(name)),
+            value: symbol.value,
+            suffix: symbol
+                .suffix
+                .as_deref()
+                .map(|suffix| interner.intern(suffix)),
+            part_of: symbol.part_of.as_ref().map(|id| interner.intern(&id.0)),
+        }
+    }
+
+    fn decode(self, strings: &Strings) -> Result<dlc::Symbol> {
+        Ok(dlc::Symbol {
+            id: strings.id(self.id)?,
+            symbol_kind_name: strings.get_opt(self.symbol_kind_name)?,
+            symbol_kind_id: strings.id_opt(self.symbol_kind_id)?,
+            position: from_bits(self.position),
+            rotation: match self.rotation {
+                0 => Rotation::Rot0,
+                1 => Rotation::Rot90,
+                2 => Rotation::Rot180,
+                3 => Rotation::Rot270,
+                rotation => bail!("invalid rotation {rotation}"),
+            },
+            flip: Flip::from_bits(self.flip)
+                .ok_or_else(|| anyhow!("invalid flip {}", self.flip))?,
+            number: self.number,
+            input_count: self.input_count,
+            bit_width: self.bit_width,
+            name: strings.get_opt(self.name)?,
+            value: self.value,
+            suffix: strings.get_opt(self.suffix)?,
+            part_of: strings.id_opt(self.part_of)?,
+        })
+    }
+}
+
+impl Net {
+    fn encode<'a>(net: &'a dlc::Net, interner: &mut Interner<'a>) -> Self {
+        Self {
+            id: interner.intern(&net.id.0),
+            name: interner.intern(&net.name),
+            bit_width: net.bit_width,
+            subnets: net
+                .subnets
+                .iter()
+                .map(|subnet| Subnet::encode(subnet, interner))
+                .collect(),
+        }
+    }
+
+    fn decode(self, strings: &Strings) -> Result<dlc::Net> {
+        Ok(dlc::Net {
+            id: strings.id(self.id)?,
+            name: strings.get(self.name)?,
+            bit_width: self.bit_width,
+            subnets: decode_all(self.subnets, |subnet| subnet.decode(strings))?,
+        })
+    }
+}
+
+impl Subnet {
+    fn encode<'a>(subnet: &'a dlc::Subnet, interner: &mut Interner<'a>) -> Self {
+        Self {
+            id: interner.intern(&subnet.id.0),
+            name: interner.intern(&subnet.name),
+            subnet_bits: subnet.subnet_bits.clone(),
+            endpoints: subnet
+                .endpoints
+                .iter()
+                .map(|endpoint| Endpoint::encode(endpoint, interner))
+                .collect(),
+        }
+    }
+
+    fn decode(self, strings: &Strings) -> Result<dlc::Subnet> {
+        Ok(dlc::Subnet {
+            id: strings.id(self.id)?,
+            name: strings.get(self.name)?,
+            subnet_bits: self.subnet_bits,
+            endpoints: decode_all(self.endpoints, |endpoint| endpoint.decode(strings))?,
+        })
+    }
+}
+
+impl Endpoint {
+    fn encode<'a>(endpoint: &'a dlc::Endpoint, interner: &mut Interner<'a>) -> Self {
+        let portref = &endpoint.portref;
+        Self {
+            id: interner.intern(&endpoint.id.0),
+            position: to_bits(endpoint.position),
+            symbol: interner.intern(&portref.symbol.0),
+            port_name: portref
+                .port_name
+                .as_deref()
+                .map(|name| interner.intern(name)),
+            port: portref.port.as_ref().map(|port| interner.intern(&port.0)),
+        }
+    }
+
+    fn decode(self, strings: &Strings) -> Result<dlc::Endpoint> {
+        Ok(dlc::Endpoint {
+            id: strings.id(self.id)?,
+            position: from_bits(self.position),
+            portref: dlc::PortRef {
+                symbol: strings.id(self.symbol)?,
+                port_name: strings.get_opt(self.port_name)?,
+                port: strings.id_opt(self.port)?,
+            },
+        })
+    }
+}
+
+impl CircuitFile {
+    pub fn to_binary(&self) -> Result<Vec<u8>> {
+        let mut interner = Interner::default();
+        let modules = self
+            .modules
+            .iter()
+            .map(|module| Module::encode(module, &mut interner))
+            .collect();
+
+        let file = BinaryFile {
+            version: self.version,
+            strings: interner.strings,
+            modules,
+        };
+        Ok(rmp_serde::to_vec(&file)?)
+    }
+
+    pub fn from_binary(bytes: &[u8]) -> Result<Self> {
+        let header: Header = rmp_serde::from_slice(bytes)?;
+        let file = match header.version {
+            CURRENT_VERSION => rmp_serde::from_slice(bytes)?,
+            // Binary files were added in version 3
+            0..=2 => bail!("binary circuit file version {} is invalid", header.version),
+            3 => decode_layout::<SymbolV3>(bytes)?,
+            4 => decode_layout::<SymbolV4>(bytes)?,
+            5 => decode_layout::<SymbolV5>(bytes)?,
+            6 => decode_layout::<SymbolV6>(bytes)?,
+            7 => decode_layout::<SymbolV7>(bytes)?,
+            version => bail!(
+                "circuit file version {version} is newer than the supported version {CURRENT_VERSION}"
+            ),
+        };
+        let strings = Strings(file.strings.into_iter().map(SharedStr::from).collect());
+        let circuit = Self {
+            version: file.version,
+            modules: decode_all(file.modules, |module| module.decode(&strings))?,
+        };
+        if circuit.version == CURRENT_VERSION {
+            return Ok(circuit);
+        }
+
+        Self::from_document(serde_json::to_value(circuit)?)
+    }
+}
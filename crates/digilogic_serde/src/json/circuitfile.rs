@@ -1,3 +1,5 @@
+use super::migrations;
+use digilogic_core::transform::{Flip, Rotation};
 use digilogic_core::{Fixed, SharedStr};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
@@ -33,7 +35,32 @@ pub struct Symbol {
     #[serde(rename = "symbolKindID")]
     pub symbol_kind_id: Option<Id>,
     pub position: [Fixed; 2],
+    pub rotation: Rotation,
+    #[serde(default, skip_serializing_if = "Flip::is_empty")]
+    pub flip: Flip,
     pub number: u32,
+    /// How many inputs a gate has, if not two
+    #[serde(
+        rename = "inputCount",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub input_count: Option<u8>,
+    #[serde(rename = "bitWidth", default, skip_serializing_if = "Option::is_none")]
+    pub bit_width: Option<u8>,
+    /// The name of the symbol if it isn't the name of its kind, like the
+    /// name a label connects by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<SharedStr>,
+    /// The value driven by a constant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u64>,
+    /// The suffix of the reference designator, like the `A` in `U1A`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suffix: Option<SharedStr>,
+    /// The symbol in the same module that this one is a part of
+    #[serde(rename = "partOf", default, skip_serializing_if = "Option::is_none")]
+    pub part_of: Option<Id>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -41,6 +68,8 @@ pub struct Symbol {
 pub struct Net {
     pub id: Id,
     pub name: SharedStr,
+    #[serde(rename = "bitWidth")]
+    pub bit_width: u8,
     pub subnets: Vec<Subnet>,
 }
 
@@ -70,23 +99,44 @@ pub struct Endpoint {
     pub portref: PortRef,
 }
 
+fn is_binary(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "dlcb")
+}
+
 impl TryFrom<&str> for CircuitFile {
-    type Error = serde_json::Error;
+    type Error = anyhow::Error;
 
     #[inline]
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        serde_json::from_str(value)
+        Self::from_document(serde_json::from_str(value)?)
     }
 }
 
 impl CircuitFile {
+    /// Upgrades a document of any supported version and deserializes it.
+    pub(super) fn from_document(mut document: serde_json::Value) -> anyhow::Result<Self> {
+        migrations::migrate(&mut document)?;
+        Ok(serde_json::from_value(document)?)
+    }
+
+    /// Loads a `.dlc` file, or a `.dlcb` file in the binary encoding.
     pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        if is_binary(path.as_ref()) {
+            return Self::from_binary(&std::fs::read(path)?);
+        }
+
         let file = std::fs::File::open(path)?;
         let reader = std::io::BufReader::new(file);
-        Ok(serde_json::from_reader(reader)?)
+        Self::from_document(serde_json::from_reader(reader)?)
     }
 
+    /// Saves a `.dlc` file, or a `.dlcb` file in the binary encoding.
     pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        if is_binary(path.as_ref()) {
+            std::fs::write(path, self.to_binary()?)?;
+            return Ok(());
+        }
+
         let file = std::fs::File::create(path)?;
         let writer = std::io::BufWriter::new(file);
         serde_json::to_writer_pretty(writer, self)?;
@@ -96,7 +146,7 @@ impl CircuitFile {
 
 #[cfg(test)]
 mod tests {
-    use super::CircuitFile;
+    use super::*;
 
     #[test]
     fn reads_small_sample() {
@@ -112,4 +162,166 @@ mod tests {
     fn reads_large_sample() {
         CircuitFile::load("testdata/large.dlc").unwrap();
     }
+
+    #[test]
+    fn reads_version_2() {
+        let circuit = CircuitFile::load("testdata/versions/v2.dlc").unwrap();
+        assert_eq!(circuit.version, migrations::CURRENT_VERSION);
+
+        let module = &circuit.modules[0];
+        assert!(module
+            .symbols
+            .iter()
+            .all(|symbol| symbol.rotation == Rotation::Rot0));
+        assert!(module.nets.iter().all(|net| net.bit_width == 1));
+    }
+
+    #[test]
+    fn reads_version_3() {
+        let circuit = CircuitFile::load("testdata/versions/v3.dlc").unwrap();
+
+        let module = &circuit.modules[0];
+        assert!(module
+            .symbols
+            .iter()
+            .any(|symbol| symbol.rotation == Rotation::Rot90));
+    }
+
+    #[test]
+    fn reads_version_4() {
+        let circuit = CircuitFile::load("testdata/versions/v4.dlc").unwrap();
+
+        let module = &circuit.modules[0];
+        let gate = module
+            .symbols
+            .iter()
+            .find(|symbol| symbol.input_count.is_some())
+            .unwrap();
+        assert_eq!(gate.input_count, Some(3));
+        assert_eq!(gate.bit_width, Some(1));
+    }
+
+    #[test]
+    fn reads_version_5() {
+        let circuit = CircuitFile::load("testdata/versions/v5.dlc").unwrap();
+
+        let module = &circuit.modules[0];
+        assert!(module
+            .symbols
+            .iter()
+            .any(|symbol| symbol.flip == Flip::HORIZONTAL));
+    }
+
+    #[test]
+    fn reads_version_6() {
+        let circuit = CircuitFile::load("testdata/versions/v6.dlc").unwrap();
+
+        let module = &circuit.modules[0];
+        let labels: Vec<_> = module
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.symbol_kind_name.as_deref() == Some("LABEL"))
+            .collect();
+        assert_eq!(labels.len(), 2);
+        assert!(labels
+            .iter()
+            .all(|label| label.name.as_deref() == Some("carry")));
+    }
+
+    #[test]
+    fn reads_version_7() {
+        let circuit = CircuitFile::load("testdata/versions/v7.dlc").unwrap();
+
+        let module = &circuit.modules[0];
+        let constant = module
+            .symbols
+            .iter()
+            .find(|symbol| symbol.symbol_kind_name.as_deref() == Some("CONST"))
+            .unwrap();
+        assert_eq!(constant.value, Some(10));
+    }
+
+    #[test]
+    fn reads_version_8() {
+        let circuit = CircuitFile::load("testdata/versions/v8.dlc").unwrap();
+
+        let module = &circuit.modules[0];
+        let part = module
+            .symbols
+            .iter()
+            .find(|symbol| symbol.part_of.is_some())
+            .unwrap();
+        assert_eq!(part.suffix.as_deref(), Some("B"));
+
+        let first = module
+            .symbols
+            .iter()
+            .find(|symbol| Some(&symbol.id) == part.part_of.as_ref())
+            .unwrap();
+        assert_eq!(first.suffix.as_deref(), Some("A"));
+        assert_eq!(first.number, part.number);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let newer = format!(
+            r#"{{"version": {}, "modules": []}}"#,
+            migrations::CURRENT_VERSION + 1
+        );
+        assert!(CircuitFile::try_from(newer.as_str()).is_err());
+        assert!(CircuitFile::try_from(r#"{"version": 1, "modules": []}"#).is_err());
+        assert!(CircuitFile::try_from(r#"{"modules": []}"#).is_err());
+    }
+
+    #[test]
+    fn round_trips_binary() {
+        let circuit = CircuitFile::load("testdata/versions/v8.dlc").unwrap();
+        let binary = circuit.to_binary().unwrap();
+        let decoded = CircuitFile::from_binary(&binary).unwrap();
+        assert_eq!(format!("{decoded:?}"), format!("{circuit:?}"));
+    }
+
+    /// Binary files of older versions decode to the same circuit as the
+    /// `.dlc` file of that version.
+    fn assert_reads_binary(version: u32) {
+        let binary = std::fs::read(format!("testdata/versions/v{version}.dlcb")).unwrap();
+        let decoded = CircuitFile::from_binary(&binary).unwrap();
+        let expected = CircuitFile::load(format!("testdata/versions/v{version}.dlc")).unwrap();
+        assert_eq!(format!("{decoded:?}"), format!("{expected:?}"));
+    }
+
+    #[test]
+    fn reads_binary_version_3() {
+        assert_reads_binary(3);
+    }
+
+    #[test]
+    fn reads_binary_version_4() {
+        assert_reads_binary(4);
+    }
+
+    #[test]
+    fn reads_binary_version_5() {
+        assert_reads_binary(5);
+    }
+
+    #[test]
+    fn reads_binary_version_6() {
+        assert_reads_binary(6);
+    }
+
+    #[test]
+    fn reads_binary_version_7() {
+        assert_reads_binary(7);
+    }
+
+    #[test]
+    fn rejects_unknown_binary_versions() {
+        let mut circuit = CircuitFile::load("testdata/versions/v8.dlc").unwrap();
+        circuit.version = migrations::CURRENT_VERSION + 1;
+        assert!(CircuitFile::from_binary(&circuit.to_binary().unwrap()).is_err());
+
+        circuit.version = 2;
+        assert!(CircuitFile::from_binary(&circuit.to_binary().unwrap()).is_err());
+    }
 }
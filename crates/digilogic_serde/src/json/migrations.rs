@@ -0,0 +1,107 @@
+//! Upgrades `.dlc` documents written by older versions of digilogic to the
+//! current format. Documents are migrated as plain JSON, one version at a
+//! time, before they are deserialized into a `CircuitFile`.
+//!
+//! Changing the layout of `CircuitFile` means bumping `CURRENT_VERSION`,
+//! appending a migration from the previous version to `MIGRATIONS`, and adding
+//! a fixture of the new version to `testdata/versions`.
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value};
+
+/// The version `CircuitFile` corresponds to
+pub const CURRENT_VERSION: u32 = 8;
+
+/// The oldest version that can still be loaded
+const OLDEST_VERSION: u32 = 2;
+
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// `MIGRATIONS[i]` upgrades a document from version `OLDEST_VERSION + i` to
+/// the version after it.
+const MIGRATIONS: &[Migration] = &[v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8];
+
+/// Upgrades `document` to `CURRENT_VERSION` in place.
+pub fn migrate(document: &mut Value) -> Result<()> {
+    let version = document
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| anyhow!("circuit file has no version"))?;
+
+    if version > CURRENT_VERSION as u64 {
+        bail!(
+            "circuit file version {version} is newer than the supported version {CURRENT_VERSION}"
+        );
+    }
+    if version < OLDEST_VERSION as u64 {
+        bail!("circuit file version {version} is no longer supported");
+    }
+
+    for migration in MIGRATIONS[(version as usize - OLDEST_VERSION as usize)..].iter() {
+        migration(document)?;
+    }
+
+    document["version"] = json!(CURRENT_VERSION);
+    Ok(())
+}
+
+fn array_mut<'a>(value: &'a mut Value, key: &str) -> Result<&'a mut Vec<Value>> {
+    value
+        .get_mut(key)
+        .and_then(Value::as_array_mut)
+        .ok_or_else(|| anyhow!("circuit file is missing '{key}'"))
+}
+
+/// Version 3 stores the rotation of symbols and the bit width of nets. Version
+/// 2 had neither, so symbols are unrotated and nets are as wide as the bits
+/// their subnets use.
+fn v2_to_v3(document: &mut Value) -> Result<()> {
+    for module in array_mut(document, "modules")? {
+        for symbol in array_mut(module, "symbols")? {
+            symbol["rotation"] = json!("Rot0");
+        }
+
+        for net in array_mut(module, "nets")? {
+            let mut bit_width = 1;
+            for subnet in array_mut(net, "subnets")? {
+                for bit in array_mut(subnet, "subnetBits")?.iter() {
+                    if let Some(bit) = bit.as_u64() {
+                        bit_width = bit_width.max(bit + 1);
+                    }
+                }
+            }
+            net["bitWidth"] = json!(bit_width);
+        }
+    }
+    Ok(())
+}
+
+/// Version 4 can give gates more inputs and symbols a bit width. Version 3
+/// symbols have neither, which are the defaults.
+fn v3_to_v4(_document: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+/// Version 5 can mirror symbols. Version 4 symbols aren't, which is the
+/// default.
+fn v4_to_v5(_document: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+/// Version 6 symbols can have a name, which labels connect by. Version 5
+/// symbols are named after their kind, which is the default.
+fn v5_to_v6(_document: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+/// Version 7 constants can have a value. Version 6 constants drive 0, which is
+/// the default.
+fn v6_to_v7(_document: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+/// Version 8 symbols can have a designator suffix and be part of another
+/// symbol. Version 7 symbols have neither, which is the default.
+fn v7_to_v8(_document: &mut Value) -> Result<()> {
+    Ok(())
+}
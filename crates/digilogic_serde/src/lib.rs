@@ -1,15 +1,35 @@
 mod digital;
+mod dump;
+mod extract;
 mod json;
+mod library;
+mod logisim;
+mod placement;
+#[cfg(not(target_arch = "wasm32"))]
+mod recovery;
+mod verilog;
+#[cfg(not(target_arch = "wasm32"))]
+mod watch;
+mod wires;
 mod yosys;
 
-use anyhow::{bail, Result};
-use bevy_derive::{Deref, DerefMut};
+/// The native circuit file format, stored as JSON in `.dlc` files or in a
+/// compact binary encoding in `.dlcb` files.
+pub mod dlc {
+    pub use crate::json::circuitfile::*;
+}
+
+pub use library::load_symbol_library;
+#[cfg(not(target_arch = "wasm32"))]
+pub use recovery::{Recovery, RecoveryEvent};
+
+use anyhow::{bail, Context as _, Result};
 use bevy_ecs::prelude::*;
-use bevy_log::error;
-use digilogic_core::components::{CircuitID, FilePath};
+use bevy_log::{error, warn};
+use digilogic_core::components::{CircuitID, FilePath, Modified};
 use digilogic_core::events::*;
 use digilogic_core::symbol::SymbolRegistry;
-use digilogic_core::HashMap;
+use digilogic_core::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
@@ -38,9 +58,21 @@ impl FileId {
     }
 }
 
-#[derive(Debug, Default, Deref, DerefMut, Resource)]
-#[repr(transparent)]
-struct FileRegistry(HashMap<FileId, CircuitID>);
+#[derive(Debug, Default, Resource)]
+struct FileRegistry {
+    circuits: HashMap<FileId, CircuitID>,
+    /// Files that are currently being loaded, to detect circuits that
+    /// (indirectly) embed themselves
+    loading: HashSet<FileId>,
+    /// The ports of the Digital circuits embedded by others, read once per file
+    digital_ports: HashMap<FileId, Vec<digilogic_core::symbol::SubCircuitPort>>,
+    /// Where the Digital circuits embedded by others were found, by the
+    /// directory they were looked for in and their file name
+    digital_files: HashMap<(PathBuf, String), PathBuf>,
+    /// Files to poll for changes, by the circuit loaded from them
+    #[cfg(not(target_arch = "wasm32"))]
+    watched: HashMap<Entity, watch::WatchedFile>,
+}
 
 fn load_circuit_file(
     commands: &mut Commands,
@@ -50,18 +82,44 @@ fn load_circuit_file(
 ) -> Result<CircuitID> {
     let file_id = FileId::for_path(filename)?;
 
-    if let Some(circuit) = registry.0.get(&file_id) {
+    if let Some(circuit) = registry.circuits.get(&file_id) {
         // Make sure the circuit is still loaded
         if commands.get_entity(circuit.0).is_some() {
             return Ok(*circuit);
         }
     }
 
+    if !registry.loading.insert(file_id.clone()) {
+        bail!("{} embeds itself", filename.display());
+    }
+    let result = load_circuit_file_uncached(commands, filename, registry, symbols);
+    registry.loading.remove(&file_id);
+
+    let circuit = CircuitID(result?);
+    commands
+        .entity(circuit.0)
+        .insert(FilePath(filename.to_owned()));
+    registry.circuits.insert(file_id, circuit);
+    #[cfg(not(target_arch = "wasm32"))]
+    registry
+        .watched
+        .insert(circuit.0, watch::WatchedFile::new(filename));
+    Ok(circuit)
+}
+
+fn load_circuit_file_uncached(
+    commands: &mut Commands,
+    filename: &Path,
+    registry: &mut FileRegistry,
+    symbols: &SymbolRegistry,
+) -> Result<Entity> {
     if let Some(ext) = filename.extension() {
-        let circuit = if ext == "dlc" {
+        let circuit = if ext == "dlc" || ext == "dlcb" {
             json::load_json(commands, filename, symbols)?
         } else if ext == "dig" {
-            digital::load_digital(commands, filename, symbols)?
+            digital::load_digital(commands, filename, registry, symbols)?
+        } else if ext == "circ" {
+            logisim::load_logisim(commands, filename, symbols)?
         } else if ext == "yosys" {
             yosys::load_yosys(commands, filename, symbols)?
         } else if ext == "json" {
@@ -71,12 +129,6 @@ fn load_circuit_file(
             bail!("unsupported file extension '{}'", ext.to_string_lossy());
         };
 
-        commands
-            .entity(circuit)
-            .insert(FilePath(filename.to_owned()));
-
-        let circuit = CircuitID(circuit);
-        registry.0.insert(file_id, circuit);
         Ok(circuit)
     } else {
         bail!("file without extension is not supported");
@@ -103,6 +155,87 @@ fn handle_circuit_load_events(
     }
 }
 
+/// Keeps a copy of a file a circuit was loaded from before it's overwritten
+/// by an export, since other formats lose what couldn't be imported from them,
+/// e.g. Digital elements we don't support.
+fn back_up_source(world: &World, path: &Path) -> Result<()> {
+    let (Some(registry), Ok(file_id)) =
+        (world.get_resource::<FileRegistry>(), FileId::for_path(path))
+    else {
+        return Ok(());
+    };
+    if !registry.circuits.contains_key(&file_id) {
+        return Ok(());
+    }
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    std::fs::copy(path, &backup).with_context(|| format!("error backing up {}", path.display()))?;
+    warn!(
+        "overwriting {} with an export, which may lose parts of it, the previous version is kept in {}",
+        path.display(),
+        backup.display()
+    );
+    Ok(())
+}
+
+/// Lets the file watcher know we wrote `path`, so it doesn't reload the
+/// circuits loaded from it.
+fn record_save(world: &mut World, path: &Path) {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(mut registry) = world.get_resource_mut::<FileRegistry>() {
+        registry.record_save(path);
+    }
+    #[cfg(target_arch = "wasm32")]
+    let _ = (world, path);
+}
+
+fn save_circuit_file(world: &mut World, circuit: CircuitID, filename: &Path) -> Result<()> {
+    if let Some(ext) = filename.extension() {
+        if ext == "dlc" || ext == "dlcb" {
+            json::save_json(world, circuit.0, filename)
+        } else if ext == "v" {
+            back_up_source(world, filename)?;
+            verilog::save_verilog(world, circuit.0, filename)
+        } else if ext == "dig" {
+            digital::save_digital(world, circuit.0, filename)
+        } else if ext == "yosys" || ext == "json" {
+            back_up_source(world, filename)?;
+            yosys::save_yosys(world, circuit.0, filename)
+        } else {
+            bail!("unsupported file extension '{}'", ext.to_string_lossy());
+        }
+    } else {
+        bail!("file without extension is not supported");
+    }
+}
+
+fn handle_circuit_save_events(world: &mut World) {
+    let save_events: Vec<_> = world
+        .resource_mut::<Events<CircuitSaveEvent>>()
+        .drain()
+        .collect();
+
+    for ev in save_events {
+        if let Err(e) = save_circuit_file(world, ev.circuit, &ev.filename) {
+            // TODO: instead of this, send an ErrorEvent
+            error!("error saving circuit {}: {:?}", ev.filename.display(), e);
+            continue;
+        }
+        record_save(world, &ev.filename);
+
+        // Saving over the file the circuit was loaded from saves its changes
+        let saved_file = FileId::for_path(&ev.filename).ok();
+        let circuit_file = world
+            .get::<FilePath>(ev.circuit.0)
+            .and_then(|file_path| FileId::for_path(&file_path.0).ok());
+        if saved_file.is_some() && saved_file == circuit_file {
+            world.entity_mut(ev.circuit.0).remove::<Modified>();
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Project {
     name: String,
@@ -123,19 +256,30 @@ fn load_project_file(
         .with_default_extension(ron::extensions::Extensions::all())
         .from_str(&ron)?;
 
-    let prev_dir = std::env::current_dir().ok();
-    std::env::set_current_dir(filename.parent().unwrap())?;
+    let Some(project_dir) = filename.parent() else {
+        bail!("error getting parent directory of {}", filename.display());
+    };
 
     let circuits = project
         .circuits
         .iter()
-        .map(|circuit_filename| load_circuit_file(commands, circuit_filename, registry, symbols))
+        .map(|circuit_filename| {
+            // Relative paths are relative to the project file, and may point
+            // into subdirectories or outside of the project directory.
+            let path = project_dir.join(circuit_filename);
+            if !path.exists() {
+                bail!(
+                    "circuit {} of project {} not found at {}",
+                    circuit_filename.display(),
+                    filename.display(),
+                    path.display()
+                );
+            }
+            load_circuit_file(commands, &path, registry, symbols)
+                .with_context(|| format!("error loading circuit {}", path.display()))
+        })
         .collect::<Result<Vec<_>>>()?;
 
-    if let Some(prev_dir) = prev_dir {
-        std::env::set_current_dir(prev_dir)?;
-    }
-
     commands.insert_resource(digilogic_core::resources::Project {
         name: project.name.into(),
         file_path: Some(filename.to_owned()),
@@ -179,5 +323,62 @@ impl bevy_app::Plugin for LoadSavePlugin {
             bevy_app::Update,
             (handle_circuit_load_events, handle_project_load_events),
         );
+        app.add_systems(bevy_app::PostUpdate, handle_circuit_save_events);
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            app.add_systems(bevy_app::Update, watch::poll_watched_files);
+
+            app.add_event::<RecoveryEvent>();
+            app.add_observer(recovery::remove_recovery_record);
+            app.add_systems(
+                bevy_app::Update,
+                (
+                    recovery::handle_recovery_events,
+                    recovery::autosave_modified_circuits,
+                )
+                    .chain()
+                    .run_if(resource_exists::<Recovery>),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digilogic_core::components::Circuit;
+
+    fn load_project(world: &mut World, path: &str) -> Result<Vec<CircuitID>> {
+        let symbols = SymbolRegistry::default();
+        let mut registry = FileRegistry::default();
+        let circuits = load_project_file(
+            &mut world.commands(),
+            Path::new(path),
+            &mut registry,
+            &symbols,
+        );
+        world.flush();
+        circuits
+    }
+
+    #[test]
+    fn loads_projects_relative_to_project_file() {
+        let current_dir = std::env::current_dir().unwrap();
+        let mut world = World::new();
+        let circuits = load_project(&mut world, "testdata/project/project.dlp").unwrap();
+        assert_eq!(std::env::current_dir().unwrap(), current_dir);
+        assert_eq!(circuits.len(), 3);
+
+        // The half adder embedded by the full adder is found in the nested
+        // library directory, so it's only loaded once.
+        let mut all_circuits = world.query_filtered::<(), With<Circuit>>();
+        assert_eq!(all_circuits.iter(&world).count(), 3);
+    }
+
+    #[test]
+    fn reports_missing_project_circuits() {
+        let mut world = World::new();
+        let error = load_project(&mut world, "testdata/project/missing.dlp").unwrap_err();
+        assert!(error.to_string().contains("lib/missing.dig"), "{error}");
     }
 }
@@ -0,0 +1,104 @@
+//! Symbol libraries: directories of `.ron` files that each hold one
+//! `SymbolDef`, for parts the built-in symbols don't cover. A definition
+//! named like a built-in symbol replaces its pinout.
+
+use anyhow::{Context as _, Result};
+use digilogic_core::symbol::{SymbolDef, SymbolRegistry};
+use std::path::{Path, PathBuf};
+
+/// Adds the symbols defined in `dir` to the registry, in file name order, and
+/// returns how many there were. A missing directory is an empty library. If any
+/// definition can't be loaded or registered, the registry is left unchanged.
+pub fn load_symbol_library(symbols: &mut SymbolRegistry, dir: &Path) -> Result<usize> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => {
+            return Err(e).with_context(|| format!("reading symbol library {}", dir.display()))
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some("ron".as_ref()))
+        .collect();
+    paths.sort();
+
+    let defs = paths
+        .iter()
+        .map(|path| load_symbol_def(path).with_context(|| format!("loading {}", path.display())))
+        .collect::<Result<Vec<_>>>()?;
+
+    let count = defs.len();
+    let mut registered = symbols.clone();
+    for (path, def) in paths.iter().zip(defs) {
+        registered
+            .register(def)
+            .with_context(|| format!("loading {}", path.display()))?;
+    }
+    *symbols = registered;
+    Ok(count)
+}
+
+fn load_symbol_def(path: &Path) -> Result<SymbolDef> {
+    let ron = std::fs::read_to_string(path)?;
+    Ok(ron::from_str(&ron)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_ecs::prelude::*;
+    use digilogic_core::components::{CustomKind, SymbolKind};
+    use digilogic_core::fixed;
+
+    #[test]
+    fn loads_custom_and_replaced_symbols() {
+        let mut symbols = SymbolRegistry::default();
+        let count = load_symbol_library(&mut symbols, Path::new("testdata/symbols")).unwrap();
+        assert_eq!(count, 2);
+
+        let mut world = World::new();
+        let circuit = world.spawn_empty().id();
+
+        let mut alu = symbols.get_by_name(&"ALU".into()).unwrap();
+        let alu_id = alu.build(&mut world.commands(), circuit);
+        let port_names: Vec<_> = alu.ports().iter().map(|port| port.name.as_str()).collect();
+        assert_eq!(port_names, ["A", "B", "OP", "Y", "C"]);
+        world.flush();
+        assert_eq!(world.get::<SymbolKind>(alu_id), Some(&SymbolKind::Custom));
+        assert_eq!(world.get::<CustomKind>(alu_id).unwrap().as_str(), "ALU");
+
+        // The NOT gate keeps being a NOT gate, with the new pinout
+        let mut not = symbols.get(SymbolKind::Not);
+        let not_id = not.build(&mut world.commands(), circuit);
+        assert_eq!(not.ports()[1].position.x, fixed!(60));
+        world.flush();
+        assert_eq!(world.get::<SymbolKind>(not_id), Some(&SymbolKind::Not));
+        assert!(world.get::<CustomKind>(not_id).is_none());
+    }
+
+    #[test]
+    fn rejects_built_in_symbols_with_other_ports() {
+        let dir = std::env::temp_dir().join(format!("digilogic-symbols-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy("testdata/symbols/alu.ron", dir.join("alu.ron")).unwrap();
+        let not = std::fs::read_to_string("testdata/symbols/not.ron").unwrap();
+        std::fs::write(
+            dir.join("not.ron"),
+            not.replace(r#"name: "Y""#, r#"name: "OUT""#),
+        )
+        .unwrap();
+
+        let mut symbols = SymbolRegistry::default();
+        let result = load_symbol_library(&mut symbols, &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+        // The valid definition before it isn't registered either
+        assert!(symbols.get_by_name(&"ALU".into()).is_none());
+        let mut not = symbols.get(SymbolKind::Not);
+        not.build(&mut World::new().commands(), Entity::PLACEHOLDER);
+        assert_eq!(not.ports()[1].name.as_str(), "Y");
+    }
+}
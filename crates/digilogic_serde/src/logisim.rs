@@ -0,0 +1,767 @@
+mod circuitfile;
+
+use crate::wires::*;
+use anyhow::{anyhow, bail, Result};
+use bevy_ecs::prelude::*;
+use bevy_log::{info, warn};
+use circuitfile::{parse_location, CircuitItem, ProjectItem};
+use digilogic_core::bundles::*;
+use digilogic_core::components::*;
+use digilogic_core::symbol::{SubCircuitPort, SymbolRegistry};
+use digilogic_core::transform::*;
+use digilogic_core::{fixed, Fixed, HashMap, SharedStr};
+use std::fs::File;
+use std::io::BufReader;
+use std::num::NonZeroU8;
+use std::path::Path;
+
+/// Logisim coordinates are on a 10 unit grid, which is a bit tight for our symbols.
+const SCALE: Fixed = fixed!(2);
+
+/// Sub-circuit pins are spaced 20 units apart in the Logisim Evolution appearance.
+const SUB_CIRCUIT_PIN_SPACING: i32 = 20;
+/// The width of a character of the font sub-circuit labels are measured in.
+const FIXED_FONT_CHAR_WIDTH: i32 = 8;
+
+pub fn load_logisim(
+    commands: &mut Commands,
+    filename: &Path,
+    symbols: &SymbolRegistry,
+) -> Result<Entity> {
+    info!("loading Logisim circuit {}", filename.display());
+
+    let file = File::open(filename)?;
+    let reader = BufReader::new(file);
+    let project: circuitfile::Project = serde_xml_rs::from_reader(reader)?;
+    if let Some(name) = filename.file_name() {
+        let name = name.to_string_lossy();
+        crate::dump::dump(&format!("{name}.txt"), || format!("{project:#?}"));
+    }
+
+    let mut libs = HashMap::default();
+    let mut circuits = HashMap::default();
+    let mut main = None;
+    let mut first = None;
+    for item in project.items.iter() {
+        match item {
+            ProjectItem::Lib(lib) => {
+                libs.insert(lib.name.as_str(), lib);
+            }
+            ProjectItem::Main(m) => main = Some(m.name.as_str()),
+            ProjectItem::Circuit(circuit) => {
+                first.get_or_insert(circuit.name.as_str());
+                circuits.insert(circuit.name.as_str(), circuit);
+            }
+            _ => {}
+        }
+    }
+
+    let Some(main) = main.or(first) else {
+        bail!("{} contains no circuits", filename.display());
+    };
+
+    let mut translator = Translator {
+        commands,
+        symbols,
+        libs,
+        circuits,
+        translated: HashMap::default(),
+        stack: Vec::new(),
+    };
+    let circuit = translator.translate_circuit(main)?;
+    Ok(circuit.id.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Facing {
+    East,
+    West,
+    North,
+    South,
+}
+
+impl Facing {
+    fn parse(facing: Option<&str>) -> Self {
+        match facing {
+            Some("west") => Self::West,
+            Some("north") => Self::North,
+            Some("south") => Self::South,
+            _ => Self::East,
+        }
+    }
+
+    /// Turns an offset for an east facing component into one for this facing,
+    /// the same way Logisim does.
+    fn apply(self, (x, y): (i32, i32)) -> (i32, i32) {
+        match self {
+            Self::East => (x, y),
+            Self::West => (-x, y),
+            Self::North => (y, -x),
+            Self::South => (y, x),
+        }
+    }
+
+    /// The rotation and flip that turn an east facing symbol into one with
+    /// this facing, moving its ports like `apply` does.
+    fn transform(self) -> Transform {
+        let (rotation, flip) = match self {
+            Self::East => (Rotation::Rot0, Flip::empty()),
+            Self::West => (Rotation::Rot0, Flip::HORIZONTAL),
+            Self::North => (Rotation::Rot270, Flip::empty()),
+            Self::South => (Rotation::Rot270, Flip::HORIZONTAL),
+        };
+        Transform {
+            rotation,
+            flip,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pin {
+    label: Option<String>,
+    output: bool,
+    bit_width: BitWidth,
+    loc: (i32, i32),
+}
+
+struct TranslatedCircuit {
+    id: CircuitID,
+    /// Input pins followed by output pins, each sorted by location.
+    pins: Vec<Pin>,
+}
+
+struct Translator<'a, 'w, 's> {
+    commands: &'a mut Commands<'w, 's>,
+    symbols: &'a SymbolRegistry,
+    libs: HashMap<&'a str, &'a circuitfile::Lib>,
+    circuits: HashMap<&'a str, &'a circuitfile::Circuit>,
+    translated: HashMap<String, TranslatedCircuit>,
+    /// Circuits currently being translated, to detect recursive instantiation
+    stack: Vec<String>,
+}
+
+/// The state of a single circuit while it is being translated.
+struct CircuitState {
+    id: Entity,
+    pos_map: HashMap<Vec2, PosEntry>,
+    wires: Vec<[Vec2; 2]>,
+    links: Vec<BitLink>,
+    tunnels: HashMap<String, Vec2>,
+}
+
+fn to_vec2((x, y): (i32, i32)) -> Result<Vec2> {
+    Ok(Vec2 {
+        x: x.try_into()?,
+        y: y.try_into()?,
+    })
+}
+
+fn offset(loc: (i32, i32), facing: Facing, offset: (i32, i32)) -> (i32, i32) {
+    let (dx, dy) = facing.apply(offset);
+    (loc.0 + dx, loc.1 + dy)
+}
+
+fn parse_bit_width(width: Option<&str>) -> Result<BitWidth> {
+    let width = width.unwrap_or("1");
+    let width: u8 = width
+        .parse()
+        .map_err(|_| anyhow!("invalid bit width '{width}'"))?;
+    Ok(BitWidth(NonZeroU8::new(width).unwrap_or(NonZeroU8::MIN)))
+}
+
+/// The input offsets of a gate facing east, relative to its output.
+/// This follows `AbstractGate.getInputOffset` in Logisim.
+fn gate_input_offset(inputs: i32, size: i32, axis_length: i32, index: i32) -> (i32, i32) {
+    let (skip_start, skip_dist, skip_lower_even) = if inputs <= 3 {
+        if size < 40 {
+            (-5, 10, 10)
+        } else if size < 60 || inputs <= 2 {
+            (-10, 20, 20)
+        } else {
+            (-15, 30, 30)
+        }
+    } else if inputs == 4 && size >= 60 {
+        (-5, 20, 0)
+    } else {
+        (-5, 10, 10)
+    };
+
+    let dy = if inputs % 2 == 1 {
+        skip_start * (inputs - 1) + skip_dist * index
+    } else if index >= inputs / 2 {
+        skip_start * inputs + skip_dist * index + skip_lower_even
+    } else {
+        skip_start * inputs + skip_dist * index
+    };
+
+    (-axis_length, dy)
+}
+
+/// The width of a sub-circuit instance in the Logisim Evolution appearance,
+/// which makes room for the pin labels and the name of the circuit. This
+/// follows `DefaultEvolutionAppearance.build` in Logisim Evolution.
+fn sub_circuit_width(name: &str, pins: &[Pin]) -> i32 {
+    let text_width = |text: &str| text.chars().count() as i32 * FIXED_FONT_CHAR_WIDTH;
+    let label_width = |output: bool| {
+        pins.iter()
+            .filter(|pin| pin.output == output)
+            .filter_map(|pin| pin.label.as_deref())
+            .map(text_width)
+            .max()
+            .unwrap_or(0)
+    };
+
+    let width = (label_width(false) + label_width(true) + 35).max(text_width(name) + 15);
+    (width / 10) * 10 + 20
+}
+
+impl CircuitState {
+    /// Connects `port` to whatever is at `loc`. Ports that share a location
+    /// are connected through a zero length wire from an off-grid position,
+    /// since each position can only hold one port.
+    fn add_port(&mut self, loc: (i32, i32), (port, bit_width): (Entity, BitWidth)) -> Result<()> {
+        let pos = to_vec2(loc)?;
+
+        let mut free_pos = pos;
+        let mut step = fixed!(0.25);
+        while self.pos_map.contains_key(&free_pos) {
+            free_pos = pos + Vec2 { x: step, y: step };
+            step += fixed!(0.25);
+        }
+
+        if free_pos != pos {
+            self.wires.push([pos, free_pos]);
+        }
+        self.pos_map
+            .insert(free_pos, PosEntry::port(port, bit_width));
+        Ok(())
+    }
+
+    /// Connects the given locations together without a visible wire.
+    fn connect(&mut self, a: (i32, i32), b: (i32, i32)) -> Result<()> {
+        self.wires.push([to_vec2(a)?, to_vec2(b)?]);
+        Ok(())
+    }
+}
+
+impl Translator<'_, '_, '_> {
+    /// Looks up an attribute of a component, falling back to the defaults of
+    /// the library tool it was created with.
+    fn attribute<'c>(&'c self, comp: &'c circuitfile::Component, name: &str) -> Option<&'c str> {
+        comp.attribute(name).or_else(|| {
+            let lib = self.libs.get(comp.lib.as_deref()?)?;
+            lib.tools
+                .iter()
+                .find(|tool| tool.name == comp.name)?
+                .attribute(name)
+        })
+    }
+
+    fn translate_circuit(&mut self, name: &str) -> Result<&TranslatedCircuit> {
+        if self.translated.contains_key(name) {
+            return Ok(&self.translated[name]);
+        }
+
+        if self.stack.iter().any(|n| n == name) {
+            bail!("circuit '{name}' instantiates itself");
+        }
+
+        let Some(&circuit) = self.circuits.get(name) else {
+            bail!("unknown circuit '{name}'");
+        };
+
+        self.stack.push(name.to_owned());
+
+        let circuit_id = self
+            .commands
+            .spawn(CircuitBundle {
+                circuit: Circuit,
+                name: Name(name.into()),
+            })
+            .id();
+
+        let mut state = CircuitState {
+            id: circuit_id,
+            pos_map: HashMap::default(),
+            wires: Vec::new(),
+            links: Vec::new(),
+            tunnels: HashMap::default(),
+        };
+
+        for item in circuit.items.iter() {
+            if let CircuitItem::Wire(wire) = item {
+                let from = parse_location(&wire.from)?;
+                let to = parse_location(&wire.to)?;
+                state.connect(from, to)?;
+            }
+        }
+
+        let mut pins = Vec::new();
+        for item in circuit.items.iter() {
+            if let CircuitItem::Comp(comp) = item {
+                if let Some(pin) = self.translate_component(comp, &mut state)? {
+                    pins.push(pin);
+                }
+            }
+        }
+
+        translate_wires(
+            self.commands,
+            &state.wires,
+            &state.links,
+            state.id,
+            &mut state.pos_map,
+        )?;
+
+        pins.sort_by_key(|pin| (pin.output, pin.loc.1, pin.loc.0));
+
+        self.stack.pop();
+        self.translated.insert(
+            name.to_owned(),
+            TranslatedCircuit {
+                id: CircuitID(circuit_id),
+                pins,
+            },
+        );
+        Ok(&self.translated[name])
+    }
+
+    /// Builds a symbol turned towards `facing`, so that its port named
+    /// `anchor` ends up at the scaled Logisim location `loc`, and returns its ports.
+    fn build_symbol(
+        &mut self,
+        builder: &mut digilogic_core::symbol::SymbolBuilder,
+        state: &CircuitState,
+        loc: (i32, i32),
+        facing: Facing,
+        anchor: usize,
+    ) -> Result<Vec<(Entity, BitWidth)>> {
+        let symbol_id = builder.build(self.commands, state.id);
+
+        let transform = facing.transform();
+        let anchor_pos = builder
+            .ports()
+            .get(anchor)
+            .map(|port| port.position.transform(transform))
+            .unwrap_or_default();
+        self.commands.entity(symbol_id).insert(Transform {
+            translation: to_vec2(loc)? * SCALE - anchor_pos,
+            ..transform
+        });
+
+        Ok(builder
+            .ports()
+            .iter()
+            .map(|port| (port.id, port.bit_width))
+            .collect())
+    }
+
+    /// Translates a single component, returning the pin it represents if it's
+    /// an input or output of the circuit.
+    fn translate_component(
+        &mut self,
+        comp: &circuitfile::Component,
+        state: &mut CircuitState,
+    ) -> Result<Option<Pin>> {
+        let loc = parse_location(&comp.loc)?;
+        let facing = Facing::parse(self.attribute(comp, "facing"));
+        let label = self
+            .attribute(comp, "label")
+            .filter(|label| !label.is_empty())
+            .map(str::to_owned);
+
+        let lib = match comp.lib.as_deref() {
+            Some(lib) => match self.libs.get(lib) {
+                Some(lib) => Some(lib.desc.as_str()),
+                None => bail!("unknown library '{lib}'"),
+            },
+            None => None,
+        };
+
+        match (lib, comp.name.as_str()) {
+            (Some("#Wiring"), "Pin") => {
+                let output = self.attribute(comp, "output") == Some("true")
+                    || self.attribute(comp, "type") == Some("output");
+                let bit_width = parse_bit_width(self.attribute(comp, "width"))?;
+                let kind = if output {
+                    SymbolKind::Out
+                } else {
+                    SymbolKind::In
+                };
+
+                let mut builder = self.symbols.get(kind);
+                builder.bit_width(bit_width);
+                if let Some(label) = &label {
+                    builder.name(label.as_str().into());
+                }
+
+                let ports = self.build_symbol(&mut builder, state, loc, Facing::East, 0)?;
+                state.add_port(loc, ports[0])?;
+
+                return Ok(Some(Pin {
+                    label,
+                    output,
+                    bit_width,
+                    loc,
+                }));
+            }
+            (Some("#Wiring"), "Tunnel") => {
+                let Some(label) = label else {
+                    warn!("ignoring Logisim tunnel without a label at {}", comp.loc);
+                    return Ok(None);
+                };
+
+                // Tunnels with the same label are connected to each other.
+                if let Some(&other) = state.tunnels.get(&label) {
+                    state.wires.push([to_vec2(loc)?, other]);
+                } else {
+                    state.tunnels.insert(label, to_vec2(loc)?);
+                }
+            }
+            (Some("#Wiring"), "Splitter") => {
+                let link = self.translate_splitter(comp, loc, facing)?;
+                state.links.push(link);
+            }
+            (Some("#Gates"), name) => {
+                let kind = match name {
+                    "AND Gate" => SymbolKind::And,
+                    "OR Gate" => SymbolKind::Or,
+                    "XOR Gate" => SymbolKind::Xor,
+                    "NAND Gate" => SymbolKind::Nand,
+                    "NOR Gate" => SymbolKind::Nor,
+                    "XNOR Gate" => SymbolKind::Xnor,
+                    "NOT Gate" => SymbolKind::Not,
+                    "Buffer" => SymbolKind::Buffer,
+                    _ => {
+                        warn!("unsupported Logisim gate '{name}' at {}", comp.loc);
+                        return Ok(None);
+                    }
+                };
+
+                let mut builder = self.symbols.get(kind);
+                let input_offsets = if kind == SymbolKind::Buffer {
+                    vec![(-20, 0)]
+                } else if kind == SymbolKind::Not {
+                    let size: i32 = self
+                        .attribute(comp, "size")
+                        .unwrap_or("30")
+                        .parse()
+                        .unwrap_or(30);
+                    vec![(-size, 0)]
+                } else {
+                    let inputs: i32 = self
+                        .attribute(comp, "inputs")
+                        .unwrap_or("2")
+                        .parse()
+                        .unwrap_or(2);
+                    let Some(input_count) = u8::try_from(inputs).ok().filter(|&n| n >= 2) else {
+                        warn!(
+                            "unsupported Logisim gate '{name}' with {inputs} inputs at {}",
+                            comp.loc
+                        );
+                        return Ok(None);
+                    };
+                    builder.input_count(input_count);
+
+                    let size: i32 = self
+                        .attribute(comp, "size")
+                        .unwrap_or("50")
+                        .parse()
+                        .unwrap_or(50);
+                    let axis_length = if matches!(kind, SymbolKind::Xor | SymbolKind::Xnor) {
+                        size + 10
+                    } else {
+                        size
+                    };
+                    (0..inputs)
+                        .map(|i| gate_input_offset(inputs, size, axis_length, i))
+                        .collect()
+                };
+
+                builder.bit_width(parse_bit_width(self.attribute(comp, "width"))?);
+                if let Some(label) = &label {
+                    builder.name(label.as_str().into());
+                }
+
+                let output_index = input_offsets.len();
+                let ports = self.build_symbol(&mut builder, state, loc, facing, output_index)?;
+                for (&port, &input_offset) in ports.iter().zip(input_offsets.iter()) {
+                    state.add_port(offset(loc, facing, input_offset), port)?;
+                }
+                state.add_port(loc, ports[output_index])?;
+            }
+            (Some("#Plexers"), "Multiplexer") => {
+                if self.attribute(comp, "select").unwrap_or("1") != "1" {
+                    warn!(
+                        "unsupported Logisim multiplexer with more than 2 inputs at {}",
+                        comp.loc
+                    );
+                    return Ok(None);
+                }
+
+                let mut builder = self.symbols.get(SymbolKind::Mux);
+                builder.bit_width(parse_bit_width(self.attribute(comp, "width"))?);
+                if let Some(label) = &label {
+                    builder.name(label.as_str().into());
+                }
+
+                // Ports are ordered S, A, B, Y
+                let ports = self.build_symbol(&mut builder, state, loc, facing, 3)?;
+                state.add_port(offset(loc, facing, (-20, 20)), ports[0])?;
+                state.add_port(offset(loc, facing, (-30, -10)), ports[1])?;
+                state.add_port(offset(loc, facing, (-30, 10)), ports[2])?;
+                state.add_port(loc, ports[3])?;
+            }
+            (Some("#Base"), _) => {
+                // Text labels and such have no electrical meaning.
+            }
+            (None, name) => self.translate_sub_circuit(comp, name, loc, label, state)?,
+            (Some(lib), name) => {
+                warn!(
+                    "unsupported Logisim component '{name}' from library '{lib}' at {}",
+                    comp.loc
+                );
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Links the combined end of a splitter at `loc` to its split ends. Bits
+    /// go to the ends given by their `bitN` attribute, or are spread over
+    /// the ends in order like `SplitterAttributes` does in Logisim.
+    fn translate_splitter(
+        &self,
+        comp: &circuitfile::Component,
+        loc: (i32, i32),
+        facing: Facing,
+    ) -> Result<BitLink> {
+        let count = |name: &str| -> Result<u8> {
+            self.attribute(comp, name)
+                .unwrap_or("2")
+                .parse()
+                .ok()
+                .filter(|&count| count > 0)
+                .ok_or_else(|| anyhow!("invalid splitter {name} at {}", comp.loc))
+        };
+        let fanout = count("fanout")?;
+        let incoming = count("incoming")?;
+
+        let mut default_ends = Vec::with_capacity(incoming as usize);
+        for end in 0..fanout {
+            let end_width = incoming / fanout + u8::from(end < incoming % fanout);
+            default_ends.extend(std::iter::repeat(end).take(end_width as usize));
+        }
+
+        let mut end_bits = vec![Vec::new(); fanout as usize];
+        for (bit, default_end) in (0..incoming).zip(default_ends) {
+            let end = match self.attribute(comp, &format!("bit{bit}")) {
+                None => default_end,
+                Some("none") => continue,
+                Some(end) => end
+                    .parse()
+                    .ok()
+                    .filter(|&end| end < fanout)
+                    .ok_or_else(|| anyhow!("invalid splitter bit{bit} at {}", comp.loc))?,
+            };
+            end_bits[end as usize].push(bit);
+        }
+
+        let mut link = BitLink::default();
+        link.ends.push((to_vec2(loc)?, (0..incoming).collect()));
+        for (end, bits) in (0..fanout as i32).zip(end_bits) {
+            if bits.is_empty() {
+                continue;
+            }
+            let end_loc = offset(loc, facing, (20, -10 * (fanout as i32 - end)));
+            link.ends.push((to_vec2(end_loc)?, bits));
+        }
+        Ok(link)
+    }
+
+    fn translate_sub_circuit(
+        &mut self,
+        comp: &circuitfile::Component,
+        name: &str,
+        loc: (i32, i32),
+        label: Option<String>,
+        state: &mut CircuitState,
+    ) -> Result<()> {
+        let sub_circuit = self.translate_circuit(name)?;
+        let circuit_id = sub_circuit.id;
+        let pins = sub_circuit.pins.clone();
+
+        let ports: Vec<_> = pins
+            .iter()
+            .enumerate()
+            .map(|(i, pin)| SubCircuitPort {
+                name: pin
+                    .label
+                    .as_deref()
+                    .map(SharedStr::from)
+                    .unwrap_or_else(|| format!("P{i}").into()),
+                output: pin.output,
+                bit_width: pin.bit_width,
+            })
+            .collect();
+
+        // In the Logisim Evolution appearance, the instance is anchored at its
+        // first output, with the outputs down the right side and the inputs
+        // down the left side.
+        let input_count = pins.iter().filter(|pin| !pin.output).count();
+        let (width, anchor) = if input_count < pins.len() {
+            (sub_circuit_width(name, &pins), input_count)
+        } else {
+            (0, 0)
+        };
+
+        let mut builder = self.symbols.get(SymbolKind::SubCircuit);
+        builder.sub_circuit(circuit_id, &ports);
+        builder.name(label.map(SharedStr::from).unwrap_or_else(|| name.into()));
+
+        let ports = self.build_symbol(&mut builder, state, loc, Facing::East, anchor)?;
+        let mut input_index = 0;
+        let mut output_index = 0;
+        for (&port, pin) in ports.iter().zip(pins.iter()) {
+            let pin_offset = if pin.output {
+                output_index += 1;
+                (0, SUB_CIRCUIT_PIN_SPACING * (output_index - 1))
+            } else {
+                input_index += 1;
+                (-width, SUB_CIRCUIT_PIN_SPACING * (input_index - 1))
+            };
+            state.add_port(offset(loc, Facing::East, pin_offset), port)?;
+        }
+
+        let appearance = self.circuits[name].attribute("appearance");
+        if appearance != Some("logisim_evolution") {
+            warn!(
+                "Logisim sub-circuit '{name}' doesn't use the Logisim Evolution appearance, \
+                its instance at {} may be connected incorrectly",
+                comp.loc
+            );
+        }
+
+        if comp
+            .attribute("facing")
+            .is_some_and(|facing| facing != "east")
+        {
+            warn!(
+                "rotated Logisim sub-circuit '{name}' at {} may be connected incorrectly",
+                comp.loc
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::*;
+
+    fn port_net(symbol: &ExtractedSymbol, name: &str) -> Option<usize> {
+        symbol
+            .ports
+            .iter()
+            .find(|port| &*port.name == name)
+            .and_then(|port| port.net)
+    }
+
+    #[test]
+    fn loads_half_adder() {
+        let mut world = World::new();
+        let symbols = SymbolRegistry::default();
+        let circuit = load_logisim(
+            &mut world.commands(),
+            Path::new("testdata/half_adder.circ"),
+            &symbols,
+        )
+        .unwrap();
+        world.flush();
+
+        let circuits = extract_hierarchy(&mut world, circuit).unwrap();
+        assert_eq!(circuits.len(), 2);
+        assert_eq!(&*circuits[0].name, "half");
+
+        let main = &circuits[1];
+        assert_eq!(&*main.name, "main");
+        let symbol = |name: &str| {
+            main.symbols
+                .iter()
+                .find(|symbol| &*symbol.name == name)
+                .unwrap()
+        };
+
+        let adder = symbol("ha");
+        assert_eq!(adder.sub_circuit, Some(0));
+        assert_eq!(port_net(adder, "x"), port_net(symbol("a"), "Y"));
+        assert_eq!(port_net(adder, "y"), port_net(symbol("b"), "Y"));
+        assert_eq!(port_net(adder, "s"), port_net(symbol("sum"), "A"));
+
+        // The carry goes through a pair of tunnels into the inverter.
+        let not = symbol("NOT");
+        assert_eq!(port_net(adder, "c"), port_net(not, "A"));
+
+        let mux = symbol("MUX");
+        assert_eq!(port_net(mux, "S"), port_net(symbol("sel"), "Y"));
+        assert_eq!(port_net(mux, "A"), port_net(not, "Y"));
+        assert_eq!(port_net(mux, "B"), port_net(symbol("a"), "Y"));
+        assert_eq!(port_net(mux, "Y"), port_net(symbol("y"), "A"));
+
+        for symbol in main.symbols.iter().chain(circuits[0].symbols.iter()) {
+            for port in symbol.ports.iter() {
+                assert!(
+                    port.net.is_some(),
+                    "{}.{} is unconnected",
+                    symbol.name,
+                    port.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn loads_splitters_and_rotated_gates() {
+        let mut world = World::new();
+        let symbols = SymbolRegistry::default();
+        let circuit = load_logisim(
+            &mut world.commands(),
+            Path::new("testdata/splitter.circ"),
+            &symbols,
+        )
+        .unwrap();
+        world.flush();
+
+        let circuits = extract_hierarchy(&mut world, circuit).unwrap();
+        let main = &circuits[0];
+        let symbol = |name: &str| {
+            main.symbols
+                .iter()
+                .find(|symbol| &*symbol.name == name)
+                .unwrap()
+        };
+        let port = |name: &str| &symbol(name).ports[0];
+
+        // Both halves of the bus are split out of the input's net, swapped.
+        let net = port("in").net;
+        assert!(net.is_some());
+        assert_eq!(port("hi").net, net);
+        assert_eq!(port("lo").net, net);
+        assert_eq!(port("hi").bits.as_deref(), Some(&[2, 3][..]));
+        assert_eq!(port("lo").bits.as_deref(), Some(&[0, 1][..]));
+
+        let not = main
+            .symbols
+            .iter()
+            .find(|symbol| symbol.kind == SymbolKind::Not)
+            .unwrap();
+        assert_eq!(not.transform.rotation, Rotation::Rot270);
+        assert_eq!(not.transform.flip, Flip::HORIZONTAL);
+        assert_eq!(port_net(not, "A"), port("a").net);
+        assert_eq!(port_net(not, "Y"), port("y").net);
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Result};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Project {
+    #[serde(rename = "$value")]
+    pub items: Vec<ProjectItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectItem {
+    Lib(Lib),
+    Main(Main),
+    Circuit(Circuit),
+    Options(IgnoredAny),
+    Mappings(IgnoredAny),
+    Toolbar(IgnoredAny),
+    Vhdl(IgnoredAny),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Lib {
+    pub desc: String,
+    pub name: String,
+    #[serde(rename = "$value", default)]
+    pub tools: Vec<Tool>,
+}
+
+/// The default attributes of a tool in a library
+#[derive(Debug, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    #[serde(rename = "$value", default)]
+    pub attributes: Vec<Attribute>,
+}
+
+impl Tool {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        find_attribute(&self.attributes, name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Main {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Circuit {
+    pub name: String,
+    #[serde(rename = "$value", default)]
+    pub items: Vec<CircuitItem>,
+}
+
+impl Circuit {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.items.iter().find_map(|item| match item {
+            CircuitItem::A(attr) if attr.name == name => Some(attr.val.as_str()),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CircuitItem {
+    A(Attribute),
+    Comp(Component),
+    Wire(Wire),
+    Appear(IgnoredAny),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Attribute {
+    pub name: String,
+    #[serde(default)]
+    pub val: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Component {
+    pub lib: Option<String>,
+    pub loc: String,
+    pub name: String,
+    #[serde(rename = "$value", default)]
+    pub attributes: Vec<Attribute>,
+}
+
+impl Component {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        find_attribute(&self.attributes, name)
+    }
+}
+
+fn find_attribute<'a>(attributes: &'a [Attribute], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|attr| attr.name == name)
+        .map(|attr| attr.val.as_str())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Wire {
+    pub from: String,
+    pub to: String,
+}
+
+/// Parses a Logisim location, which looks like `(x,y)`.
+pub fn parse_location(loc: &str) -> Result<(i32, i32)> {
+    let (x, y) = loc
+        .trim()
+        .strip_prefix('(')
+        .and_then(|loc| loc.strip_suffix(')'))
+        .and_then(|loc| loc.split_once(','))
+        .ok_or_else(|| anyhow!("invalid location '{loc}'"))?;
+    Ok((x.trim().parse()?, y.trim().parse()?))
+}
@@ -0,0 +1,67 @@
+//! Where the symbols of a circuit were placed, by symbol name. Used to carry
+//! manual placement over to the same circuit after loading it from its file
+//! again.
+
+use aery::edges::{EdgeInfo, Edges};
+use bevy_ecs::prelude::*;
+use digilogic_core::components::{Child, Name, Symbol};
+use digilogic_core::transform::Transform;
+use digilogic_core::{HashMap, SharedStr};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Placement {
+    name: SharedStr,
+    transform: Transform,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Placements(Vec<Placement>);
+
+fn symbols_of(world: &mut World, circuit: Entity) -> Vec<Entity> {
+    let mut children = world.query::<Edges<Child>>();
+    children
+        .get(world, circuit)
+        .map(|edges| edges.hosts().to_vec())
+        .unwrap_or_default()
+}
+
+impl Placements {
+    pub fn of(world: &mut World, circuit: Entity) -> Self {
+        let mut symbols = world.query_filtered::<(&Name, &Transform), With<Symbol>>();
+        let placements = symbols_of(world, circuit)
+            .into_iter()
+            .filter_map(|child| symbols.get(world, child).ok())
+            .map(|(name, &transform)| Placement {
+                name: name.0.clone(),
+                transform,
+            })
+            .collect();
+        Self(placements)
+    }
+
+    /// Moves the symbols of `circuit` to where the symbol with the same name
+    /// was placed. Symbol names aren't unique, symbols sharing a name are
+    /// matched up in order.
+    pub fn apply(self, world: &mut World, circuit: Entity) {
+        let mut transforms: HashMap<SharedStr, VecDeque<Transform>> = HashMap::default();
+        for placement in self.0 {
+            transforms
+                .entry(placement.name)
+                .or_default()
+                .push_back(placement.transform);
+        }
+
+        let mut symbols = world.query_filtered::<(&Name, &mut Transform), With<Symbol>>();
+        for child in symbols_of(world, circuit) {
+            let Ok((name, mut transform)) = symbols.get_mut(world, child) else {
+                continue;
+            };
+
+            if let Some(placed) = transforms.get_mut(&name.0).and_then(VecDeque::pop_front) {
+                *transform = placed;
+            }
+        }
+    }
+}
@@ -0,0 +1,474 @@
+//! Autosaves unsaved changes to circuits, so they survive a crash. Every
+//! modified circuit gets a record in the recovery directory, with a snapshot
+//! of the whole circuit and the circuits it instantiates in the binary `.dlcb`
+//! format next to it. Circuits that can't be stored in that format, e.g. ones
+//! with symbols whose definition is gone, only keep where their symbols were
+//! placed, which is reapplied to the file they were loaded from.
+//!
+//! Records are named after a random ID given to the circuit when it's first
+//! autosaved, since entities are reused by every session and by other running
+//! instances. They're removed once their changes are saved or discarded, so
+//! records found on startup were left behind by a session that didn't exit
+//! cleanly.
+
+use crate::placement::Placements;
+use crate::{json, load_circuit_file, FileId, FileRegistry};
+use anyhow::{bail, Result};
+use bevy_ecs::prelude::*;
+use bevy_log::{error, info, warn};
+use digilogic_core::components::{Circuit, CircuitID, FilePath, Modified, Name};
+use digilogic_core::events::CircuitLoadedEvent;
+use digilogic_core::resources::Project;
+use digilogic_core::symbol::SymbolRegistry;
+use digilogic_core::SharedStr;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Names the recovery record of a circuit
+#[derive(Debug, Clone, Copy, Component)]
+pub(crate) struct RecoveryId(Uuid);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RecoveryRecord {
+    /// The session that wrote the record
+    session: Uuid,
+    /// The file the circuit was loaded from, if it was ever saved
+    file: Option<PathBuf>,
+    name: SharedStr,
+    /// Whether the whole circuit is stored next to the record
+    snapshot: bool,
+    placements: Placements,
+}
+
+impl RecoveryRecord {
+    fn load(path: &Path) -> Result<Self> {
+        let ron = std::fs::read_to_string(path)?;
+        Ok(ron::from_str(&ron)?)
+    }
+}
+
+/// Enables autosaving and crash recovery when inserted, with its records
+/// stored in the given directory.
+#[derive(Debug, Resource)]
+pub struct Recovery {
+    dir: PathBuf,
+    session: Uuid,
+    /// Records left behind by a previous session, with their path
+    found: Vec<(PathBuf, RecoveryRecord)>,
+}
+
+impl Recovery {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let mut found = Vec::new();
+
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+                if path.extension() != Some("ron".as_ref()) {
+                    continue;
+                }
+
+                let record = RecoveryRecord::load(&path);
+                match record {
+                    Ok(record) => found.push((path, record)),
+                    Err(e) => warn!("ignoring recovery record {}: {:?}", path.display(), e),
+                }
+            }
+        }
+
+        Self {
+            dir,
+            session: Uuid::new_v4(),
+            found,
+        }
+    }
+
+    /// The circuits with unsaved changes left behind by a previous session,
+    /// by name and the file they were loaded from, which can be restored with
+    /// `RecoveryEvent::Restore`.
+    pub fn found(&self) -> impl Iterator<Item = (&str, Option<&Path>)> {
+        self.found
+            .iter()
+            .map(|(_, record)| (&*record.name, record.file.as_deref()))
+    }
+
+    /// Where the record of a circuit is stored
+    fn record_path(&self, id: RecoveryId, file: Option<&Path>, name: &str) -> PathBuf {
+        let stem = file
+            .and_then(Path::file_stem)
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or(name.into());
+        let stem = crate::extract::sanitize(&stem, "circuit");
+        self.dir.join(format!("{stem}-{}.ron", id.0.simple()))
+    }
+}
+
+/// The snapshot of the circuit stored next to a record
+fn snapshot_path(record_path: &Path) -> PathBuf {
+    record_path.with_extension("dlcb")
+}
+
+/// What to do with the unsaved changes found by `Recovery`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Event)]
+pub enum RecoveryEvent {
+    /// Loads the circuits again and reapplies their unsaved changes
+    Restore,
+    /// Deletes the unsaved changes
+    Discard,
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+fn remove_record(record_path: &Path) {
+    for path in [record_path.to_owned(), snapshot_path(record_path)] {
+        if let Err(e) = std::fs::remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("error removing {}: {:?}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Writes the record of a modified circuit, with a snapshot of it if possible.
+fn autosave_circuit(world: &mut World, circuit: Entity) -> Result<()> {
+    let file = world
+        .get::<FilePath>(circuit)
+        .map(|file_path| absolute(&file_path.0));
+    let name = world
+        .get::<Name>(circuit)
+        .map(|name| name.0.clone())
+        .unwrap_or_default();
+    let id = match world.get::<RecoveryId>(circuit) {
+        Some(&id) => id,
+        None => {
+            let id = RecoveryId(Uuid::new_v4());
+            world.entity_mut(circuit).insert(id);
+            id
+        }
+    };
+    let recovery = world.resource::<Recovery>();
+    let session = recovery.session;
+    let record_path = recovery.record_path(id, file.as_deref(), &name);
+    std::fs::create_dir_all(record_path.parent().unwrap_or(Path::new(".")))?;
+
+    let snapshot = match json::extract_circuit(world, circuit) {
+        Ok(snapshot) => {
+            snapshot.save(snapshot_path(&record_path))?;
+            true
+        }
+        Err(e) if file.is_some() => {
+            info!("only autosaving where the symbols of {name} are: {e:?}");
+            remove_record(&snapshot_path(&record_path));
+            false
+        }
+        Err(e) => return Err(e),
+    };
+
+    let record = RecoveryRecord {
+        session,
+        file,
+        name,
+        snapshot,
+        placements: Placements::of(world, circuit),
+    };
+    let ron = ron::ser::to_string_pretty(&record, ron::ser::PrettyConfig::default())?;
+    std::fs::write(record_path, ron)?;
+    Ok(())
+}
+
+pub(crate) fn autosave_modified_circuits(
+    world: &mut World,
+    mut last_autosave: Local<Option<Instant>>,
+) {
+    let now = Instant::now();
+    if last_autosave.is_some_and(|last_autosave| now - last_autosave < AUTOSAVE_INTERVAL) {
+        return;
+    }
+    *last_autosave = Some(now);
+
+    let mut modified = world.query_filtered::<Entity, (With<Circuit>, With<Modified>)>();
+    let circuits: Vec<Entity> = modified.iter(world).collect();
+
+    for circuit in circuits {
+        let result = autosave_circuit(world, circuit);
+        if let Err(e) = result {
+            warn!("error autosaving circuit {circuit}: {:?}", e);
+        }
+    }
+}
+
+/// Deletes the record of a circuit once it's saved, discarded or closed.
+pub(crate) fn remove_recovery_record(
+    trigger: Trigger<OnRemove, Modified>,
+    circuits: Query<(&RecoveryId, &Name, Option<&FilePath>)>,
+    recovery: Option<Res<Recovery>>,
+) {
+    // Circuits that were never autosaved have no record
+    let (Some(recovery), Ok((&id, name, file_path))) = (recovery, circuits.get(trigger.entity()))
+    else {
+        return;
+    };
+
+    let file = file_path.map(|file_path| absolute(&file_path.0));
+    remove_record(&recovery.record_path(id, file.as_deref(), &name.0));
+}
+
+fn restore(world: &mut World, record_path: &Path, record: RecoveryRecord) -> Result<CircuitID> {
+    if !record.snapshot {
+        let Some(file) = &record.file else {
+            bail!("{} was never saved and has no snapshot", record.name);
+        };
+
+        let circuit = world.resource_scope(|world, mut registry: Mut<FileRegistry>| {
+            world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+                load_circuit_file(&mut world.commands(), file, &mut registry, &symbols)
+            })
+        });
+        world.flush();
+        let circuit = circuit?;
+
+        record.placements.apply(world, circuit.0);
+        world.entity_mut(circuit.0).insert(Modified);
+        return Ok(circuit);
+    }
+
+    let circuit = world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+        json::load_json(&mut world.commands(), &snapshot_path(record_path), &symbols)
+    });
+    world.flush();
+    let circuit = CircuitID(circuit?);
+
+    world
+        .entity_mut(circuit.0)
+        .insert((Name(record.name), Modified));
+    if let Some(file) = record.file {
+        // Loading the file again gets the restored circuit, but changes to the
+        // file aren't watched for, they'd replace the restored changes.
+        if let Ok(file_id) = FileId::for_path(&file) {
+            world
+                .resource_mut::<FileRegistry>()
+                .circuits
+                .insert(file_id, circuit);
+        }
+        world.entity_mut(circuit.0).insert(FilePath(file));
+    }
+    Ok(circuit)
+}
+
+pub(crate) fn handle_recovery_events(world: &mut World) {
+    let events: Vec<_> = world
+        .resource_mut::<Events<RecoveryEvent>>()
+        .drain()
+        .collect();
+
+    for ev in events {
+        let found = std::mem::take(&mut world.resource_mut::<Recovery>().found);
+
+        for (record_path, record) in found {
+            if ev == RecoveryEvent::Discard {
+                remove_record(&record_path);
+                continue;
+            }
+
+            let name = record.name.clone();
+            match restore(world, &record_path, record) {
+                Ok(circuit) => {
+                    info!("restored unsaved changes to {name}");
+                    world.send_event(CircuitLoadedEvent { circuit });
+
+                    if !world.contains_resource::<Project>() {
+                        world.insert_resource(Project {
+                            name: SharedStr::new_static("Recovered Project"),
+                            file_path: None,
+                            root_circuit: Some(circuit),
+                        });
+                    }
+
+                    // The restored circuit gets a new ID, and a new record with
+                    // it, which is kept until the changes are saved.
+                    remove_record(&record_path);
+                    let result = autosave_circuit(world, circuit.0);
+                    if let Err(e) = result {
+                        warn!("error autosaving {name}: {:?}", e);
+                    }
+                }
+                // TODO: instead of this, send an ErrorEvent
+                Err(e) => {
+                    error!("error restoring {name}: {:?}", e);
+                    remove_record(&record_path);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digilogic_core::transform::Transform;
+    use digilogic_core::{fixed, HashSet};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("digilogic-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn new_world(recovery_dir: &Path) -> World {
+        let mut world = World::new();
+        world.init_resource::<SymbolRegistry>();
+        world.init_resource::<FileRegistry>();
+        world.init_resource::<Events<RecoveryEvent>>();
+        world.init_resource::<Events<CircuitLoadedEvent>>();
+        world.insert_resource(Recovery::new(recovery_dir));
+        world.add_observer(remove_recovery_record);
+        world
+    }
+
+    fn positions(world: &mut World) -> HashSet<(i32, i32)> {
+        let mut symbols =
+            world.query_filtered::<&Transform, With<digilogic_core::components::Symbol>>();
+        symbols
+            .iter(world)
+            .map(|transform| {
+                (
+                    transform.translation.x.to_bits(),
+                    transform.translation.y.to_bits(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn restores_autosaved_changes() {
+        let dir = temp_dir("recovery");
+        let recovery_dir = dir.join("recovery");
+        let circuit_path = dir.join("circuit.dlc");
+        std::fs::copy("testdata/versions/v3.dlc", &circuit_path).unwrap();
+
+        // The first session moves every symbol, then crashes after autosaving
+        let mut world = new_world(&recovery_dir);
+        let circuit = world.resource_scope(|world, mut registry: Mut<FileRegistry>| {
+            world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+                load_circuit_file(
+                    &mut world.commands(),
+                    &circuit_path,
+                    &mut registry,
+                    &symbols,
+                )
+            })
+        });
+        world.flush();
+        let circuit = circuit.unwrap();
+
+        let mut symbols =
+            world.query_filtered::<&mut Transform, With<digilogic_core::components::Symbol>>();
+        for mut transform in symbols.iter_mut(&mut world) {
+            transform.translation.x += fixed!(1000);
+        }
+        world.entity_mut(circuit.0).insert(Modified);
+        world.run_system_cached(autosave_modified_circuits).unwrap();
+        let moved = positions(&mut world);
+
+        // The next session finds and restores them
+        let mut world = new_world(&recovery_dir);
+        assert_eq!(
+            world
+                .resource::<Recovery>()
+                .found()
+                .map(|(_, file)| file.map(Path::to_owned))
+                .collect::<Vec<_>>(),
+            [Some(absolute(&circuit_path))]
+        );
+        world.send_event(RecoveryEvent::Restore);
+        world.run_system_cached(handle_recovery_events).unwrap();
+        assert_eq!(positions(&mut world), moved);
+
+        // Saving the changes removes the record
+        let mut modified = world.query_filtered::<Entity, With<Modified>>();
+        let circuit = modified.single(&world);
+        world.entity_mut(circuit).remove::<Modified>();
+        world.flush();
+        assert_eq!(std::fs::read_dir(&recovery_dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn restores_circuits_that_were_never_saved() {
+        let dir = temp_dir("recovery-unsaved");
+        let recovery_dir = dir.join("recovery");
+
+        // A circuit that only exists in memory, built from a file that's gone
+        let circuit_path = dir.join("scratch.dlc");
+        std::fs::copy("testdata/versions/v7.dlc", &circuit_path).unwrap();
+        let mut world = new_world(&recovery_dir);
+        let circuit = world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+            json::load_json(&mut world.commands(), &circuit_path, &symbols)
+        });
+        world.flush();
+        let circuit = circuit.unwrap();
+        std::fs::remove_file(&circuit_path).unwrap();
+
+        world.entity_mut(circuit).insert(Modified);
+        world.run_system_cached(autosave_modified_circuits).unwrap();
+        let placed = positions(&mut world);
+
+        let mut world = new_world(&recovery_dir);
+        assert_eq!(
+            world.resource::<Recovery>().found().collect::<Vec<_>>(),
+            [("scratch", None)]
+        );
+        world.send_event(RecoveryEvent::Restore);
+        world.run_system_cached(handle_recovery_events).unwrap();
+        assert_eq!(positions(&mut world), placed);
+
+        let mut circuits = world.query_filtered::<&Name, (With<Circuit>, With<Modified>)>();
+        assert_eq!(&*circuits.single(&world).0, "scratch");
+
+        // The restored circuit is recorded again, under a new ID
+        assert_eq!(Recovery::new(&recovery_dir).found().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_records_of_other_sessions() {
+        let dir = temp_dir("recovery-sessions");
+        let recovery_dir = dir.join("recovery");
+
+        // Two instances running at once, or one after the other, each with an
+        // unsaved circuit that has the same entity
+        let mut circuits = Vec::new();
+        for _ in 0..2 {
+            let mut world = new_world(&recovery_dir);
+            let circuit = world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+                json::load_json(
+                    &mut world.commands(),
+                    Path::new("testdata/versions/v7.dlc"),
+                    &symbols,
+                )
+            });
+            world.flush();
+            let circuit = circuit.unwrap();
+            circuits.push(circuit);
+
+            world.entity_mut(circuit).insert(Modified);
+            world.run_system_cached(autosave_modified_circuits).unwrap();
+        }
+        assert_eq!(circuits[0], circuits[1]);
+
+        let recovery = Recovery::new(&recovery_dir);
+        assert_eq!(recovery.found().count(), 2);
+        assert_ne!(recovery.found[0].1.session, recovery.found[1].1.session);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
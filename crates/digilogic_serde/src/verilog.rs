@@ -0,0 +1,457 @@
+use crate::extract::*;
+use anyhow::Result;
+use bevy_ecs::prelude::*;
+use bevy_log::info;
+use digilogic_core::components::SymbolKind;
+use std::fmt::Write;
+use std::path::Path;
+
+const KEYWORDS: &[&str] = &[
+    "always",
+    "and",
+    "assign",
+    "begin",
+    "buf",
+    "case",
+    "default",
+    "else",
+    "end",
+    "endcase",
+    "endmodule",
+    "for",
+    "function",
+    "if",
+    "initial",
+    "inout",
+    "input",
+    "integer",
+    "module",
+    "nand",
+    "nor",
+    "not",
+    "or",
+    "output",
+    "parameter",
+    "reg",
+    "wire",
+    "xnor",
+    "xor",
+];
+
+pub fn save_verilog(world: &mut World, circuit: Entity, filename: &Path) -> Result<()> {
+    info!("saving Verilog netlist {}", filename.display());
+
+    let circuits = extract_hierarchy(world, circuit)?;
+    std::fs::write(filename, write_verilog(&circuits))?;
+    Ok(())
+}
+
+fn width_prefix(bit_width: u8) -> String {
+    if bit_width > 1 {
+        format!("[{}:0] ", bit_width - 1)
+    } else {
+        String::new()
+    }
+}
+
+/// Writes all circuits as structural Verilog modules. `circuits` must be in
+/// dependency order, as returned by `extract_hierarchy`.
+fn write_verilog(circuits: &[ExtractedCircuit]) -> String {
+    let mut module_names = IdentifierSet::default();
+    for keyword in KEYWORDS {
+        module_names.reserve(keyword);
+    }
+    let module_names: Vec<_> = circuits
+        .iter()
+        .map(|circuit| module_names.make_unique(&circuit.name, "circuit"))
+        .collect();
+
+    let mut out = String::new();
+    let mut module_ports = Vec::with_capacity(circuits.len());
+    for (i, (circuit, module_name)) in circuits.iter().zip(module_names.iter()).enumerate() {
+        if i > 0 {
+            writeln!(out).unwrap();
+        }
+        let ports = write_module(&mut out, circuit, module_name, &module_names, &module_ports);
+        module_ports.push(ports);
+    }
+    out
+}
+
+/// Returns the wire connected to `port`, declaring a new one if the port is
+/// unconnected so every expression stays valid.
+fn wire_for_port(
+    port: &ExtractedPort,
+    nets: &[String],
+    idents: &mut IdentifierSet,
+    decls: &mut String,
+) -> String {
+    match port.net {
+        Some(net) => match &port.bits {
+            Some(bits) => select_bits(&nets[net], bits),
+            None => nets[net].clone(),
+        },
+        None => {
+            let name = idents.make_unique("unconnected", "unconnected");
+            writeln!(decls, "    wire {}{name};", width_prefix(port.bit_width)).unwrap();
+            name
+        }
+    }
+}
+
+/// Selects `bits` of a wire, where the first bit becomes the least significant.
+fn select_bits(wire: &str, bits: &[u8]) -> String {
+    let (Some(&first), Some(&last)) = (bits.first(), bits.last()) else {
+        return wire.to_owned();
+    };
+
+    let contiguous = bits.windows(2).all(|pair| pair[1] == pair[0] + 1);
+    if bits.len() == 1 {
+        format!("{wire}[{first}]")
+    } else if contiguous {
+        format!("{wire}[{last}:{first}]")
+    } else {
+        let selected: Vec<_> = bits
+            .iter()
+            .rev()
+            .map(|bit| format!("{wire}[{bit}]"))
+            .collect();
+        format!("{{{}}}", selected.join(", "))
+    }
+}
+
+fn write_module(
+    out: &mut String,
+    circuit: &ExtractedCircuit,
+    module_name: &str,
+    module_names: &[String],
+    module_ports: &[PortIdents],
+) -> PortIdents {
+    let mut idents = IdentifierSet::default();
+    for keyword in KEYWORDS {
+        idents.reserve(keyword);
+    }
+
+    // In and Out symbols become the module ports, named after the symbol.
+    let port_names: Vec<_> = circuit
+        .symbols
+        .iter()
+        .map(|symbol| match symbol.kind {
+            SymbolKind::In => Some(idents.make_unique(&symbol.name, "in")),
+            SymbolKind::Out => Some(idents.make_unique(&symbol.name, "out")),
+            _ => None,
+        })
+        .collect();
+    // Global labels become inout ports, which are also the wires of their nets.
+    let global_names: Vec<_> = circuit
+        .globals
+        .iter()
+        .map(|global| idents.make_unique(&global.name, "global"))
+        .collect();
+    let nets: Vec<_> = circuit
+        .nets
+        .iter()
+        .enumerate()
+        .map(|(index, net)| {
+            match circuit
+                .globals
+                .iter()
+                .position(|global| global.net == index)
+            {
+                Some(global) => global_names[global].clone(),
+                None => idents.make_unique(&net.name, "net"),
+            }
+        })
+        .collect();
+
+    let mut ports = PortIdents::default();
+    for (symbol, name) in circuit.symbols.iter().zip(port_names.iter()) {
+        if let Some(name) = name {
+            let input = symbol.kind == SymbolKind::In;
+            ports.push(&symbol.name, input, !input, name);
+        }
+    }
+    for (global, name) in circuit.globals.iter().zip(global_names.iter()) {
+        ports.push(&global.name, true, true, name);
+    }
+
+    let port_decls: Vec<_> = [(SymbolKind::In, "input"), (SymbolKind::Out, "output")]
+        .into_iter()
+        .flat_map(|(kind, direction)| {
+            circuit
+                .symbols
+                .iter()
+                .zip(port_names.iter())
+                .filter(move |(symbol, _)| symbol.kind == kind)
+                .map(move |(symbol, name)| {
+                    format!(
+                        "{direction} wire {}{}",
+                        width_prefix(symbol.bit_width()),
+                        name.as_deref().unwrap_or_default(),
+                    )
+                })
+        })
+        .chain(
+            circuit
+                .globals
+                .iter()
+                .zip(global_names.iter())
+                .map(|(global, name)| {
+                    format!("inout wire {}{name}", width_prefix(global.bit_width))
+                }),
+        )
+        .collect();
+
+    writeln!(out, "module {module_name} (").unwrap();
+    for (i, decl) in port_decls.iter().enumerate() {
+        let separator = if i + 1 < port_decls.len() { "," } else { "" };
+        writeln!(out, "    {decl}{separator}").unwrap();
+    }
+    writeln!(out, ");").unwrap();
+
+    let mut decls = String::new();
+    for (index, (net, name)) in circuit.nets.iter().zip(nets.iter()).enumerate() {
+        if circuit.globals.iter().any(|global| global.net == index) {
+            continue;
+        }
+        writeln!(decls, "    wire {}{name};", width_prefix(net.bit_width)).unwrap();
+    }
+
+    let mut statements = String::new();
+    for (symbol, port_name) in circuit.symbols.iter().zip(port_names.iter()) {
+        // Custom symbols become instances of a module of the same name, which
+        // has to be defined elsewhere, like any other black box.
+        let instantiated = match (symbol.sub_circuit, &symbol.custom_kind) {
+            (Some(sub_circuit), _) => Some(module_names[sub_circuit].clone()),
+            (None, Some(custom_kind)) => Some(sanitize(custom_kind, "module")),
+            (None, None) => None,
+        };
+        if let Some(module_name) = instantiated {
+            let instance_name = idents.make_unique(&symbol.designator, "inst");
+            let port_idents = match symbol.sub_circuit {
+                Some(sub_circuit) => module_ports[sub_circuit].for_instance(symbol),
+                None => symbol
+                    .ports
+                    .iter()
+                    .map(|port| sanitize(&port.name, "port"))
+                    .collect(),
+            };
+            let connections: Vec<_> = symbol
+                .ports
+                .iter()
+                .zip(port_idents.iter())
+                .map(|(port, port_ident)| {
+                    let wire = wire_for_port(port, &nets, &mut idents, &mut decls);
+                    format!(".{port_ident}({wire})")
+                })
+                .collect();
+            writeln!(
+                statements,
+                "    {module_name} {instance_name} ({});",
+                connections.join(", ")
+            )
+            .unwrap();
+            continue;
+        }
+
+        let inputs: Vec<_> = symbol
+            .inputs()
+            .map(|port| wire_for_port(port, &nets, &mut idents, &mut decls))
+            .collect();
+        let outputs: Vec<_> = symbol
+            .outputs()
+            .map(|port| wire_for_port(port, &nets, &mut idents, &mut decls))
+            .collect();
+
+        let (target, expr) = match symbol.kind {
+            // Handled above, as instantiating a module can't be done with an assign.
+            SymbolKind::SubCircuit | SymbolKind::Custom => (None, None),
+            // Labels only merge nets, which the extraction already did.
+            SymbolKind::Label | SymbolKind::GlobalLabel => (None, None),
+            SymbolKind::In => (outputs.first(), port_name.clone()),
+            SymbolKind::Const => (
+                outputs.first(),
+                symbol
+                    .value
+                    .map(|value| format!("{}'d{value}", symbol.bit_width())),
+            ),
+            SymbolKind::Out => (port_name.as_ref(), inputs.first().cloned()),
+            SymbolKind::And => (outputs.first(), Some(inputs.join(" & "))),
+            SymbolKind::Or => (outputs.first(), Some(inputs.join(" | "))),
+            SymbolKind::Xor => (outputs.first(), Some(inputs.join(" ^ "))),
+            SymbolKind::Nand => (outputs.first(), Some(format!("~({})", inputs.join(" & ")))),
+            SymbolKind::Nor => (outputs.first(), Some(format!("~({})", inputs.join(" | ")))),
+            SymbolKind::Xnor => (outputs.first(), Some(format!("~({})", inputs.join(" ^ ")))),
+            SymbolKind::Not => (outputs.first(), inputs.first().map(|a| format!("~{a}"))),
+            SymbolKind::Buffer => (outputs.first(), inputs.first().cloned()),
+            SymbolKind::TriState => (
+                outputs.first(),
+                match inputs.as_slice() {
+                    [a, enable] => Some(format!("{enable} ? {a} : {}'bz", symbol.bit_width())),
+                    _ => None,
+                },
+            ),
+            SymbolKind::Mux => {
+                // The first input is the select line, followed by the data inputs.
+                let expr = inputs.split_first().and_then(|(select, data)| {
+                    let (last, rest) = data.split_last()?;
+                    let mut expr = String::new();
+                    for (i, input) in rest.iter().enumerate() {
+                        write!(expr, "({select} == {i}) ? {input} : ").unwrap();
+                    }
+                    expr.push_str(last);
+                    Some(expr)
+                });
+                (outputs.first(), expr)
+            }
+        };
+
+        if let (Some(target), Some(expr)) = (target, expr) {
+            writeln!(
+                statements,
+                "    assign {target} = {expr}; // {}",
+                symbol.designator
+            )
+            .unwrap();
+        }
+    }
+
+    write!(out, "{decls}").unwrap();
+    if !decls.is_empty() && !statements.is_empty() {
+        writeln!(out).unwrap();
+    }
+    write!(out, "{statements}").unwrap();
+    writeln!(out, "endmodule").unwrap();
+
+    ports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use digilogic_core::bundles::CircuitBundle;
+    use digilogic_core::components::*;
+    use digilogic_core::sub_circuit::circuit_ports;
+    use digilogic_core::symbol::SymbolRegistry;
+    use digilogic_core::transform::Vec2;
+    use digilogic_core::{Fixed, SharedStr};
+
+    #[test]
+    fn writes_small_sample() {
+        let mut world = World::new();
+        let symbols = SymbolRegistry::default();
+        let circuit = crate::json::load_json(
+            &mut world.commands(),
+            Path::new("testdata/small.dlc"),
+            &symbols,
+        )
+        .unwrap();
+        world.flush();
+
+        let circuits = extract_hierarchy(&mut world, circuit).unwrap();
+        let verilog = write_verilog(&circuits);
+
+        assert!(verilog.starts_with("module small ("));
+        assert!(verilog.contains(" & "));
+        assert!(verilog.ends_with("endmodule\n"));
+    }
+
+    #[test]
+    fn connects_instances_to_module_ports() {
+        let mut world = World::new();
+        let symbols = SymbolRegistry::default();
+        let mut spawn_circuit = |name| {
+            world
+                .spawn(CircuitBundle {
+                    circuit: Circuit,
+                    name: Name(SharedStr::new_static(name)),
+                })
+                .id()
+        };
+        let child = spawn_circuit("child");
+        let parent = spawn_circuit("parent");
+
+        // A keyword and two pins with the same name, which all need new identifiers
+        let pins = [
+            (SymbolKind::In, "input"),
+            (SymbolKind::In, "a"),
+            (SymbolKind::In, "a"),
+            (SymbolKind::Out, "y"),
+        ];
+        for (i, (kind, name)) in pins.into_iter().enumerate() {
+            symbols
+                .get(kind)
+                .name(SharedStr::new_static(name))
+                .position(Vec2 {
+                    x: Fixed::from_i16(0),
+                    y: Fixed::from_i16(i as i16 * 40),
+                })
+                .build(&mut world.commands(), child);
+        }
+        world.flush();
+
+        let ports = circuit_ports(&mut world, child);
+        symbols
+            .get(SymbolKind::SubCircuit)
+            .name(SharedStr::new_static("child"))
+            .sub_circuit(CircuitID(child), &ports)
+            .build(&mut world.commands(), parent);
+        world.flush();
+
+        let circuits = extract_hierarchy(&mut world, parent).unwrap();
+        let verilog = write_verilog(&circuits);
+
+        let header = verilog
+            .split_once("module child (\n")
+            .and_then(|(_, rest)| rest.split_once(");"))
+            .unwrap()
+            .0;
+        let module_ports: Vec<_> = header
+            .lines()
+            .map(|line| {
+                line.trim()
+                    .trim_end_matches(',')
+                    .rsplit(' ')
+                    .next()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(module_ports.len(), 4);
+        assert!(!module_ports.contains(&"input"));
+
+        let instance = verilog
+            .lines()
+            .find(|line| line.trim_start().starts_with("child "))
+            .unwrap();
+        let mut connected: Vec<_> = instance
+            .split('.')
+            .skip(1)
+            .map(|connection| connection.split('(').next().unwrap())
+            .collect();
+        connected.sort();
+        let mut expected = module_ports.clone();
+        expected.sort();
+        assert_eq!(connected, expected);
+    }
+
+    #[test]
+    fn merges_labelled_nets() {
+        let mut world = World::new();
+        let symbols = SymbolRegistry::default();
+        let circuit = crate::json::load_json(
+            &mut world.commands(),
+            Path::new("testdata/versions/v6.dlc"),
+            &symbols,
+        )
+        .unwrap();
+        world.flush();
+
+        let circuits = extract_hierarchy(&mut world, circuit).unwrap();
+        let verilog = write_verilog(&circuits);
+
+        // Both labels name the same wire, which the gate drives and the output reads
+        assert_eq!(verilog.matches("wire carry;").count(), 1);
+        assert!(verilog.contains("assign carry = "));
+        assert!(verilog.contains("assign y = carry;"));
+    }
+}
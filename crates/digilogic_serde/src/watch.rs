@@ -0,0 +1,552 @@
+//! Reloads circuits when the files they were loaded from change on disk, e.g.
+//! after rerunning Yosys. The platform's file notifications report changes to
+//! the directories of watched files as they happen. If they're unavailable,
+//! files are polled for a new modification time. Either way a file is only
+//! reloaded once its modification time changes, and files are still polled
+//! now and then because network drives don't report changes. Circuits with
+//! unsaved changes aren't reloaded, since that would lose them.
+//!
+//! A circuit is reloaded in place: it keeps its entity, so viewports and the
+//! symbols instantiating it stay bound to it, and symbols whose names match a
+//! symbol of the old circuit keep their position and rotation. The circuits
+//! embedded in the same file, e.g. the other modules of a Yosys netlist, are
+//! replaced along with it.
+
+use crate::placement::Placements;
+use crate::{load_circuit_file_uncached, FileId, FileRegistry};
+use aery::edges::{EdgeInfo, Edges};
+use aery::prelude::*;
+use anyhow::Result;
+use bevy_ecs::prelude::*;
+use bevy_log::{error, info, warn};
+use digilogic_core::components::*;
+use digilogic_core::symbol::SymbolRegistry;
+use digilogic_core::HashSet;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often files are polled while file notifications report changes
+const FALLBACK_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub(crate) struct WatchedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl WatchedFile {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            modified: modified(path),
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+impl FileRegistry {
+    /// Takes the new modification time of `path` after we wrote it, so the
+    /// save isn't mistaken for a change on disk.
+    pub(crate) fn record_save(&mut self, path: &Path) {
+        let Ok(saved) = FileId::for_path(path) else {
+            return;
+        };
+        for file in self.watched.values_mut() {
+            if FileId::for_path(&file.path).is_ok_and(|file_id| file_id == saved) {
+                file.modified = modified(path);
+            }
+        }
+    }
+}
+
+/// Watches the directories of files, which also reports files that are
+/// replaced instead of rewritten.
+struct DirectoryWatcher {
+    watcher: RecommendedWatcher,
+    directories: HashSet<PathBuf>,
+    changed: Arc<AtomicBool>,
+}
+
+impl DirectoryWatcher {
+    fn new() -> Option<Self> {
+        let changed = Arc::new(AtomicBool::new(false));
+        let handler_changed = changed.clone();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            // Errors may hide changes, polling finds out whether there were any
+            if event.map_or(true, |event| !event.kind.is_access()) {
+                handler_changed.store(true, Ordering::Relaxed);
+            }
+        });
+
+        match watcher {
+            Ok(watcher) => Some(Self {
+                watcher,
+                directories: HashSet::default(),
+                changed,
+            }),
+            Err(err) => {
+                warn!("file notifications are unavailable, polling watched files instead: {err}");
+                None
+            }
+        }
+    }
+
+    /// Watches the directory of `path`, if it isn't watched already.
+    fn watch(&mut self, path: &Path) {
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if self.directories.contains(directory) {
+            return;
+        }
+
+        let result = self.watcher.watch(directory, RecursiveMode::NonRecursive);
+        if let Err(err) = result {
+            // Polling still catches changes to files in this directory
+            warn!("error watching {}: {err}", directory.display());
+        }
+        self.directories.insert(directory.to_owned());
+    }
+
+    /// Whether anything changed since the last call.
+    fn has_changes(&self) -> bool {
+        self.changed.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct PollState {
+    last_poll: Option<Instant>,
+    /// Created on the first poll, `None` if file notifications are unavailable
+    watcher: Option<Option<DirectoryWatcher>>,
+}
+
+impl PollState {
+    /// Whether the watched files should be checked for changes now
+    fn is_due(&mut self, registry: &FileRegistry, now: Instant) -> bool {
+        let interval = match self.has_changes(registry) {
+            Some(true) => return true,
+            Some(false) => FALLBACK_POLL_INTERVAL,
+            None => POLL_INTERVAL,
+        };
+        self.last_poll
+            .map_or(true, |last_poll| now - last_poll >= interval)
+    }
+
+    /// Whether anything changed next to a watched file, or `None` if file
+    /// notifications are unavailable
+    fn has_changes(&mut self, registry: &FileRegistry) -> Option<bool> {
+        let watcher = self
+            .watcher
+            .get_or_insert_with(DirectoryWatcher::new)
+            .as_mut()?;
+        for file in registry.watched.values() {
+            watcher.watch(&file.path);
+        }
+        Some(watcher.has_changes())
+    }
+}
+
+pub(crate) fn poll_watched_files(world: &mut World, mut state: Local<PollState>) {
+    let now = Instant::now();
+    if !state.is_due(world.resource::<FileRegistry>(), now) {
+        return;
+    }
+    state.last_poll = Some(now);
+
+    let changed: Vec<(CircuitID, PathBuf)> =
+        world.resource_scope(|world, mut registry: Mut<FileRegistry>| {
+            registry
+                .watched
+                .retain(|&circuit, _| world.entities().contains(circuit));
+
+            registry
+                .watched
+                .iter_mut()
+                .filter_map(|(&circuit, file)| {
+                    let modified = modified(&file.path);
+                    if modified == file.modified {
+                        return None;
+                    }
+                    file.modified = modified;
+
+                    // A file that is missing, e.g. while it's being replaced,
+                    // is reloaded once it's back.
+                    modified.map(|_| (CircuitID(circuit), file.path.clone()))
+                })
+                .collect()
+        });
+
+    for (circuit, filename) in changed {
+        if world.get::<Modified>(circuit.0).is_some() {
+            warn!(
+                "{} changed on disk, keeping the unsaved changes of its circuit instead of reloading it",
+                filename.display()
+            );
+            continue;
+        }
+
+        let result = reload_circuit(world, circuit, &filename);
+        match result {
+            Ok(()) => info!("reloaded circuit {}", filename.display()),
+            // TODO: instead of this, send an ErrorEvent
+            Err(e) => error!("error reloading circuit {}: {:?}", filename.display(), e),
+        }
+    }
+}
+
+/// Returns the circuits instantiated by `circuit`, directly or indirectly,
+/// that were loaded from the same file as it, i.e. that aren't registered
+/// under a file of their own.
+fn embedded_circuits(world: &mut World, circuit: Entity) -> Vec<Entity> {
+    let registered: HashSet<Entity> = world
+        .resource::<FileRegistry>()
+        .circuits
+        .values()
+        .map(|circuit| circuit.0)
+        .collect();
+
+    let mut children = world.query::<Edges<Child>>();
+    let mut instances = world.query::<&CircuitID>();
+    let mut embedded = Vec::new();
+    let mut visited = HashSet::from_iter([circuit]);
+    let mut stack = vec![circuit];
+    while let Some(circuit) = stack.pop() {
+        let Ok(edges) = children.get(world, circuit) else {
+            continue;
+        };
+        for &child in edges.hosts() {
+            let Ok(&CircuitID(instance)) = instances.get(world, child) else {
+                continue;
+            };
+            if !registered.contains(&instance) && visited.insert(instance) {
+                embedded.push(instance);
+                stack.push(instance);
+            }
+        }
+    }
+    embedded
+}
+
+/// Loads `filename` again and swaps the contents of `circuit` for it. The
+/// circuits embedded in the file are replaced by the newly loaded ones.
+pub(crate) fn reload_circuit(world: &mut World, circuit: CircuitID, filename: &Path) -> Result<()> {
+    let file_id = FileId::for_path(filename)?;
+
+    let old_embedded = embedded_circuits(world, circuit.0);
+
+    let mut circuits = world.query_filtered::<Entity, With<Circuit>>();
+    let circuits_before: HashSet<Entity> = circuits.iter(world).collect();
+
+    let loaded = world.resource_scope(|world, mut registry: Mut<FileRegistry>| {
+        world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+            registry.loading.insert(file_id.clone());
+            let result = load_circuit_file_uncached(
+                &mut world.commands(),
+                filename,
+                &mut registry,
+                &symbols,
+            );
+            registry.loading.remove(&file_id);
+            result
+        })
+    });
+    world.flush();
+
+    let loaded = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            // Sub-circuits loaded from other files are kept, they're cached
+            let registered: HashSet<Entity> = world
+                .resource::<FileRegistry>()
+                .circuits
+                .values()
+                .map(|circuit| circuit.0)
+                .collect();
+            let spawned: Vec<Entity> = circuits
+                .iter(world)
+                .filter(|circuit| !circuits_before.contains(circuit))
+                .filter(|circuit| !registered.contains(circuit))
+                .collect();
+            for circuit in spawned {
+                world.despawn(circuit);
+            }
+            world.flush();
+            return Err(err);
+        }
+    };
+
+    let placements = Placements::of(world, circuit.0);
+
+    let mut children = world.query::<Edges<Child>>();
+    let mut children_of = |world: &World, entity: Entity| -> Vec<Entity> {
+        children
+            .get(world, entity)
+            .map(|edges| edges.hosts().to_vec())
+            .unwrap_or_default()
+    };
+    let old_children = children_of(world, circuit.0);
+    let new_children = children_of(world, loaded);
+
+    for child in old_children {
+        world.despawn(child);
+    }
+    for child in new_children {
+        world.entity_mut(child).set::<Child>(circuit.0);
+    }
+    world.flush();
+
+    placements.apply(world, circuit.0);
+
+    if let Some(name) = world.get::<Name>(loaded).cloned() {
+        world.entity_mut(circuit.0).insert(name);
+    }
+
+    // Embedded circuits that instantiate the file's main circuit, e.g. a
+    // recursive Yosys module, have to refer to the entity that's kept.
+    let mut instances = world.query::<&mut CircuitID>();
+    for mut instance in instances.iter_mut(world) {
+        if instance.0 == loaded {
+            instance.0 = circuit.0;
+        }
+    }
+
+    world.despawn(loaded);
+    for embedded in old_embedded {
+        world.despawn(embedded);
+    }
+    world.flush();
+
+    // The file may have been replaced instead of rewritten, giving it a new ID
+    let mut registry = world.resource_mut::<FileRegistry>();
+    registry
+        .circuits
+        .retain(|_, registered| *registered != circuit);
+    registry.circuits.insert(file_id, circuit);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlc::{self, CircuitFile, Id};
+    use crate::load_circuit_file;
+    use digilogic_core::events::CircuitSaveEvent;
+    use digilogic_core::fixed;
+    use digilogic_core::transform::{Flip, Rotation, Transform};
+
+    #[test]
+    fn reloads_in_place_and_keeps_positions() {
+        let dir = std::env::temp_dir().join(format!("digilogic-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("reload.dlc");
+        std::fs::copy("testdata/versions/v3.dlc", &path).unwrap();
+
+        let mut world = World::new();
+        world.init_resource::<SymbolRegistry>();
+        world.init_resource::<FileRegistry>();
+        let circuit = world.resource_scope(|world, mut registry: Mut<FileRegistry>| {
+            world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+                load_circuit_file(&mut world.commands(), &path, &mut registry, &symbols)
+            })
+        });
+        world.flush();
+        let circuit = circuit.unwrap();
+
+        // Move every symbol by hand, then add a gate to the file behind our back
+        let offset = fixed!(1000);
+        let mut symbols = world.query_filtered::<&mut Transform, With<Symbol>>();
+        for mut transform in symbols.iter_mut(&mut world) {
+            transform.translation.x += offset;
+        }
+
+        let mut file = CircuitFile::load(&path).unwrap();
+        file.modules[0].symbols.push(dlc::Symbol {
+            id: Id("added".into()),
+            symbol_kind_name: Some("AND".into()),
+            symbol_kind_id: None,
+            position: [fixed!(0), fixed!(0)],
+            rotation: Rotation::Rot0,
+            flip: Flip::empty(),
+            number: 4,
+            input_count: None,
+            bit_width: None,
+            name: None,
+            value: None,
+            suffix: None,
+            part_of: None,
+        });
+        file.save(&path).unwrap();
+
+        reload_circuit(&mut world, circuit, &path).unwrap();
+
+        let mut children = world.query::<Edges<Child>>();
+        let mut symbols = world.query_filtered::<(&Name, &Transform), With<Symbol>>();
+        let positions: Vec<_> = children
+            .get(&world, circuit.0)
+            .unwrap()
+            .hosts()
+            .iter()
+            .filter_map(|&child| symbols.get(&world, child).ok())
+            .map(|(name, transform)| (name.0.clone(), transform.translation))
+            .collect();
+
+        assert_eq!(positions.len(), 7);
+        let kept: Vec<_> = positions
+            .iter()
+            .filter(|(_, position)| position.x >= offset)
+            .collect();
+        assert_eq!(kept.len(), 6);
+        assert!(positions
+            .iter()
+            .any(|(name, position)| &**name == "AND" && position.x < offset));
+
+        let mut circuits = world.query_filtered::<(), With<Circuit>>();
+        assert_eq!(circuits.iter(&world).count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reloads_embedded_circuits_with_their_file() {
+        let dir =
+            std::env::temp_dir().join(format!("digilogic-watch-embedded-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("half_adder.circ");
+        std::fs::copy("testdata/half_adder.circ", &path).unwrap();
+
+        let mut world = World::new();
+        world.init_resource::<SymbolRegistry>();
+        world.init_resource::<FileRegistry>();
+        let circuit = world.resource_scope(|world, mut registry: Mut<FileRegistry>| {
+            world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+                load_circuit_file(&mut world.commands(), &path, &mut registry, &symbols)
+            })
+        });
+        world.flush();
+        let circuit = circuit.unwrap();
+
+        let mut circuits = world.query_filtered::<Entity, With<Circuit>>();
+        let before: HashSet<Entity> = circuits.iter(&world).collect();
+        assert_eq!(before.len(), 2);
+
+        reload_circuit(&mut world, circuit, &path).unwrap();
+
+        // The old half adder is gone, and the new one is instantiated
+        let after: HashSet<Entity> = circuits.iter(&world).collect();
+        assert_eq!(after.len(), 2);
+        assert!(after.contains(&circuit.0));
+        assert_eq!(after.intersection(&before).count(), 1);
+
+        let mut instances = world.query::<&CircuitID>();
+        let instances: Vec<CircuitID> = instances.iter(&world).copied().collect();
+        assert!(!instances.is_empty());
+        assert!(instances.iter().all(|instance| after.contains(&instance.0)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Loads a copy of `testdata/versions/v3.dlc` written an hour ago, so
+    /// writing it again gives it a new modification time.
+    fn load_old_copy(world: &mut World, path: &Path) -> CircuitID {
+        std::fs::copy("testdata/versions/v3.dlc", path).unwrap();
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+
+        world.init_resource::<SymbolRegistry>();
+        world.init_resource::<FileRegistry>();
+        let circuit = world.resource_scope(|world, mut registry: Mut<FileRegistry>| {
+            world.resource_scope(|world, symbols: Mut<SymbolRegistry>| {
+                load_circuit_file(&mut world.commands(), path, &mut registry, &symbols)
+            })
+        });
+        world.flush();
+        circuit.unwrap()
+    }
+
+    fn children(world: &mut World, circuit: CircuitID) -> Vec<Entity> {
+        let mut children = world.query::<Edges<Child>>();
+        children.get(world, circuit.0).unwrap().hosts().to_vec()
+    }
+
+    #[test]
+    fn ignores_saved_files() {
+        let dir = std::env::temp_dir().join(format!("digilogic-watch-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("saved.dlc");
+
+        let mut world = World::new();
+        world.init_resource::<Events<CircuitSaveEvent>>();
+        let circuit = load_old_copy(&mut world, &path);
+        let before = children(&mut world, circuit);
+
+        world.send_event(CircuitSaveEvent {
+            circuit,
+            filename: path.clone(),
+        });
+        crate::handle_circuit_save_events(&mut world);
+        world.run_system_cached(poll_watched_files).unwrap();
+
+        assert_eq!(children(&mut world, circuit), before);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_unsaved_changes() {
+        let dir =
+            std::env::temp_dir().join(format!("digilogic-watch-modified-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("modified.dlc");
+
+        let mut world = World::new();
+        let circuit = load_old_copy(&mut world, &path);
+        world.entity_mut(circuit.0).insert(Modified);
+        let before = children(&mut world, circuit);
+
+        std::fs::copy("testdata/versions/v3.dlc", &path).unwrap();
+        world.run_system_cached(poll_watched_files).unwrap();
+
+        assert_eq!(children(&mut world, circuit), before);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reports_saved_files() {
+        let dir = std::env::temp_dir().join(format!("digilogic-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("watched.dlc");
+        std::fs::write(&path, "").unwrap();
+
+        let mut watcher = DirectoryWatcher::new().unwrap();
+        watcher.watch(&path);
+        assert!(!watcher.has_changes());
+
+        // Notifications arrive on another thread
+        std::fs::write(&path, "changed").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !watcher.has_changes() {
+            assert!(Instant::now() < deadline, "no change reported");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
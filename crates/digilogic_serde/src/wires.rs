@@ -0,0 +1,268 @@
+use aery::prelude::*;
+use anyhow::{bail, Result};
+use bevy_ecs::prelude::*;
+use digilogic_core::bundles::*;
+use digilogic_core::components::*;
+use digilogic_core::transform::*;
+use digilogic_core::visibility::VisibilityBundle;
+use digilogic_core::{fixed, HashMap};
+use std::cell::Cell;
+use std::num::NonZeroU8;
+
+pub(crate) struct PosEntry {
+    pub port: Option<(Entity, BitWidth)>,
+    /// The port takes on the width of the net it ends up on, like a tunnel
+    pub fits_net: bool,
+    pub endpoint: Cell<Option<Entity>>,
+    pub wires: Vec<[Vec2; 2]>,
+}
+
+impl PosEntry {
+    pub fn port(port: Entity, bit_width: BitWidth) -> Self {
+        Self {
+            port: Some((port, bit_width)),
+            fits_net: false,
+            endpoint: Cell::new(None),
+            wires: vec![],
+        }
+    }
+
+    /// A port that's as wide as the net it ends up on, using all of its bits.
+    pub fn fitting_port(port: Entity) -> Self {
+        Self {
+            fits_net: true,
+            ..Self::port(port, BitWidth(NonZeroU8::MIN))
+        }
+    }
+}
+
+/// Connects bits of the wires at several positions, like a splitter does.
+/// Each end lists the bit of the link that every bit of its wire carries,
+/// ends that carry the same bit of the link are connected.
+#[derive(Debug, Default)]
+pub(crate) struct BitLink {
+    pub ends: Vec<(Vec2, Vec<u8>)>,
+}
+
+/// A set of positions connected by wires
+#[derive(Default)]
+struct WireGroup {
+    positions: Vec<Vec2>,
+    bit_width: u8,
+}
+
+#[derive(Default)]
+struct UnionFind(Vec<usize>);
+
+impl UnionFind {
+    fn add(&mut self) -> usize {
+        self.0.push(self.0.len());
+        self.0.len() - 1
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.0[i] != i {
+            self.0[i] = self.0[self.0[i]];
+            i = self.0[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        self.0[b] = a;
+    }
+}
+
+/// Builds nets out of wire segments, by connecting every port in `pos_map` to
+/// the ports it can reach by following wires from its position. Wires joined by
+/// `links` end up in one net, and the ports on them get `Bits` describing which
+/// bits of the net they use.
+pub(crate) fn translate_wires(
+    commands: &mut Commands,
+    wires: &[[Vec2; 2]],
+    links: &[BitLink],
+    circuit_id: Entity,
+    pos_map: &mut HashMap<Vec2, PosEntry>,
+) -> Result<()> {
+    // at this point, pos_map contains only the ports.
+    // add the wire and link ends to pos_map also.
+    let link_ends = links
+        .iter()
+        .flat_map(|link| link.ends.iter().map(|&(pos, _)| [pos, pos]));
+    for ends in wires.iter().copied().chain(link_ends) {
+        for end in ends.iter() {
+            let pos_entry = pos_map.entry(*end).or_insert_with(|| PosEntry {
+                port: None,
+                fits_net: false,
+                endpoint: Cell::new(None),
+                wires: vec![],
+            });
+            if ends[0] != ends[1] {
+                pos_entry.wires.push(ends);
+            }
+        }
+    }
+
+    // do a "flood fill" to find all connected positions and group them
+    let mut group_map = HashMap::<Vec2, usize>::default();
+    let mut groups = Vec::<WireGroup>::new();
+    let mut todo = Vec::<Vec2>::default();
+    for &pos in pos_map.keys() {
+        if group_map.contains_key(&pos) {
+            continue;
+        }
+
+        let group_index = groups.len();
+        let mut group = WireGroup {
+            positions: Vec::new(),
+            bit_width: 1,
+        };
+
+        todo.clear();
+        todo.push(pos);
+        group_map.insert(pos, group_index);
+        while let Some(pos) = todo.pop() {
+            group.positions.push(pos);
+
+            let pos_entry = &pos_map[&pos];
+            if let Some((_, bit_width)) = pos_entry.port {
+                group.bit_width = group.bit_width.max(bit_width.0.get());
+            }
+
+            for wire in pos_entry.wires.iter() {
+                for end in wire.iter() {
+                    if !group_map.contains_key(end) {
+                        group_map.insert(*end, group_index);
+                        todo.push(*end);
+                    }
+                }
+            }
+        }
+
+        groups.push(group);
+    }
+
+    for link in links.iter() {
+        for (pos, bits) in link.ends.iter() {
+            let group = &mut groups[group_map[pos]];
+            group.bit_width = group.bit_width.max(bits.len() as u8);
+        }
+    }
+
+    // Every bit of every group is a node, and the links connect nodes of
+    // different groups. Groups that share a node end up in the same net.
+    let mut bit_nodes = UnionFind::default();
+    let mut group_nodes = Vec::with_capacity(groups.len());
+    for group in groups.iter() {
+        let first = bit_nodes.0.len();
+        for _ in 0..group.bit_width {
+            bit_nodes.add();
+        }
+        group_nodes.push(first);
+    }
+
+    let mut group_sets = UnionFind::default();
+    for _ in groups.iter() {
+        group_sets.add();
+    }
+
+    for link in links.iter() {
+        let mut link_bits = HashMap::<u8, usize>::default();
+        for (pos, bits) in link.ends.iter() {
+            let group_index = group_map[pos];
+            for (i, &bit) in bits.iter().enumerate() {
+                let node = group_nodes[group_index] + i;
+                if let Some(&other) = link_bits.get(&bit) {
+                    bit_nodes.union(other, node);
+                } else {
+                    link_bits.insert(bit, node);
+                }
+            }
+
+            if let Some(&(first_pos, _)) = link.ends.first() {
+                group_sets.union(group_map[&first_pos], group_index);
+            }
+        }
+    }
+
+    let mut nets = HashMap::<usize, Vec<usize>>::default();
+    for group_index in 0..groups.len() {
+        nets.entry(group_sets.find(group_index))
+            .or_default()
+            .push(group_index);
+    }
+
+    for (_, mut net_groups) in nets {
+        // The widest group decides the order of the net's bits, as it's
+        // usually the bus being split up.
+        net_groups
+            .sort_by_key(|&group_index| (u8::MAX - groups[group_index].bit_width, group_index));
+
+        let mut net_bits = HashMap::<usize, u8>::default();
+        let mut group_bits = Vec::with_capacity(net_groups.len());
+        for &group_index in net_groups.iter() {
+            let mut bits = Vec::<u8>::new();
+            for i in 0..groups[group_index].bit_width as usize {
+                let node = bit_nodes.find(group_nodes[group_index] + i);
+                let next_bit = net_bits.len();
+                let bit = *net_bits.entry(node).or_insert(next_bit as u8);
+                if net_bits.len() > u8::MAX as usize {
+                    bail!("net is wider than {} bits", u8::MAX);
+                }
+                bits.push(bit);
+            }
+            group_bits.push(bits);
+        }
+
+        let bit_width = NonZeroU8::new(net_bits.len() as u8).unwrap_or(NonZeroU8::MIN);
+        let net_id = commands
+            .spawn(NetBundle {
+                net: Net,
+                name: Default::default(),
+                bit_width: BitWidth(bit_width),
+                visibility: VisibilityBundle::default(),
+            })
+            .set::<Child>(circuit_id)
+            .id();
+
+        for (&group_index, bits) in net_groups.iter().zip(group_bits.iter()) {
+            for pos in groups[group_index].positions.iter() {
+                let pos_entry = &pos_map[pos];
+                let Some((port, port_width)) = pos_entry.port else {
+                    continue;
+                };
+
+                // Connect port to net
+                let endpoint_id = commands
+                    .spawn(EndpointBundle {
+                        bounds: BoundingBoxBundle {
+                            bounding_box: BoundingBox::from_half_size(fixed!(2.5), fixed!(2.5)),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .insert(PortID(port))
+                    .set::<Child>(net_id)
+                    // Remember to disconnect this when disconnecting from the port.
+                    .set::<InheritTransform>(port)
+                    .id();
+
+                if pos_entry.fits_net {
+                    commands.entity(port).insert(BitWidth(bit_width));
+                } else {
+                    let port_bits = &bits[..bits.len().min(port_width.0.get() as usize)];
+                    if let Some(bits) = Bits::subset(port_bits, BitWidth(bit_width)) {
+                        commands.entity(endpoint_id).insert(bits);
+                    }
+                }
+
+                commands.entity(port).insert(NetID(net_id));
+                pos_entry.endpoint.set(Some(endpoint_id));
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,12 +1,14 @@
 mod netlist;
 
+use crate::extract::*;
 use aery::prelude::*;
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Context as _, Result};
 use bevy_ecs::prelude::*;
 use bevy_log::info;
 use digilogic_core::bundles::*;
 use digilogic_core::components::*;
 use digilogic_core::symbol::PortInfo;
+use digilogic_core::symbol::SubCircuitPort;
 use digilogic_core::symbol::SymbolRegistry;
 use digilogic_core::transform::BoundingBox;
 use digilogic_core::transform::Directions;
@@ -21,6 +23,7 @@ use digilogic_core::HashSet;
 use digilogic_core::SharedStr;
 use digilogic_layout::{Graph, Node, NodeEntity};
 use petgraph::graph::NodeIndex;
+use std::collections::BTreeMap;
 use std::num::NonZeroU8;
 use std::path::Path;
 
@@ -51,18 +54,28 @@ fn translate_netlist(
     netlist: &netlist::Netlist,
     symbols: &SymbolRegistry,
 ) -> Result<Entity> {
-    let mut bit_map = HashMap::new();
     let modules = &netlist.modules;
     let mut top_id: Option<Entity> = None;
 
+    // Cells can instantiate any module, so every circuit needs to exist up front.
+    let circuit_ids: HashMap<SharedStr, Entity> = modules
+        .keys()
+        .map(|name| {
+            let circuit_id = commands
+                .spawn(CircuitBundle {
+                    circuit: Circuit,
+                    name: Name(name.clone()),
+                })
+                .id();
+            (name.clone(), circuit_id)
+        })
+        .collect();
+
     for (name, module) in modules.iter() {
-        let circuit_id = commands
-            .spawn(CircuitBundle {
-                circuit: Circuit,
-                name: Name(name.clone()),
-            })
-            .id();
+        let circuit_id = circuit_ids[name];
 
+        // Bits are numbered separately in every module
+        let mut bit_map = HashMap::new();
         let mut graph = MetaGraph::default();
 
         for (name, port) in module.ports.iter() {
@@ -81,6 +94,8 @@ fn translate_netlist(
             translate_cell(
                 name,
                 cell,
+                netlist,
+                &circuit_ids,
                 &mut bit_map,
                 commands,
                 circuit_id,
@@ -100,11 +115,9 @@ fn translate_netlist(
             )?;
         }
 
-        layout_circuit(commands, &mut graph, &bit_map)?;
+        layout_circuit(commands, name, &mut graph, &bit_map)?;
 
-        // TODO: the top module is not necessarily the last one... need to figure out
-        // how to determine the top module
-        if top_id.is_none() {
+        if top_id.is_none() || module.is_top() {
             top_id = Some(circuit_id);
         }
     }
@@ -124,7 +137,8 @@ fn translate_module_port(
     let mut symbol_builder = match port.direction {
         netlist::PortDirection::Input => symbols.get(SymbolKind::In),
         netlist::PortDirection::Output => symbols.get(SymbolKind::Out),
-        netlist::PortDirection::InOut => todo!(),
+        // Ports in both directions are the global labels of a circuit
+        netlist::PortDirection::InOut => symbols.get(SymbolKind::GlobalLabel),
     };
     let symbol_id = symbol_builder
         .name(name.clone())
@@ -139,8 +153,7 @@ fn translate_module_port(
                 let net_bit = bit_map.entry(*bit).or_insert(NetBit { ports: Vec::new() });
                 net_bit.ports.push(port_info.clone());
             } else {
-                // figure out how to hard code a bit to a specific value
-                todo!();
+                bail!("port {name} has a constant bit, which isn't supported yet");
             }
         }
     }
@@ -148,9 +161,12 @@ fn translate_module_port(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn translate_cell(
     name: &SharedStr,
     cell: &netlist::Cell,
+    netlist: &netlist::Netlist,
+    circuit_ids: &HashMap<SharedStr, Entity>,
     bit_map: &mut HashMap<usize, NetBit>,
     commands: &mut Commands,
     circuit_id: Entity,
@@ -158,19 +174,21 @@ fn translate_cell(
     graph: &mut MetaGraph,
 ) -> Result<()> {
     let mut symbol_builder = match cell.cell_type {
-        netlist::CellType::Not => symbols.get(SymbolKind::Not),
-        netlist::CellType::Pos => todo!(),
+        netlist::CellType::Not | netlist::CellType::GateNot => symbols.get(SymbolKind::Not),
+        netlist::CellType::Pos | netlist::CellType::GateBuf => symbols.get(SymbolKind::Buffer),
         netlist::CellType::Neg => todo!(),
-        netlist::CellType::ReduceAnd => todo!(),
-        netlist::CellType::ReduceOr => todo!(),
-        netlist::CellType::ReduceXor => todo!(),
-        netlist::CellType::ReduceXnor => todo!(),
-        netlist::CellType::ReduceBool => todo!(),
+        netlist::CellType::ReduceAnd => symbols.get(SymbolKind::And),
+        netlist::CellType::ReduceOr => symbols.get(SymbolKind::Or),
+        netlist::CellType::ReduceXor => symbols.get(SymbolKind::Xor),
+        netlist::CellType::ReduceXnor => symbols.get(SymbolKind::Xnor),
+        netlist::CellType::ReduceBool => symbols.get(SymbolKind::Or),
         netlist::CellType::LogicNot => todo!(),
-        netlist::CellType::And => symbols.get(SymbolKind::And),
-        netlist::CellType::Or => symbols.get(SymbolKind::Or),
-        netlist::CellType::Xor => symbols.get(SymbolKind::Xor),
-        netlist::CellType::Xnor => todo!(),
+        netlist::CellType::And | netlist::CellType::GateAnd => symbols.get(SymbolKind::And),
+        netlist::CellType::Or | netlist::CellType::GateOr => symbols.get(SymbolKind::Or),
+        netlist::CellType::Xor | netlist::CellType::GateXor => symbols.get(SymbolKind::Xor),
+        netlist::CellType::Xnor | netlist::CellType::GateXnor => symbols.get(SymbolKind::Xnor),
+        netlist::CellType::GateNand => symbols.get(SymbolKind::Nand),
+        netlist::CellType::GateNor => symbols.get(SymbolKind::Nor),
         netlist::CellType::Shl => todo!(),
         netlist::CellType::Sshl => todo!(),
         netlist::CellType::Shr => todo!(),
@@ -193,9 +211,11 @@ fn translate_cell(
         netlist::CellType::Mod => todo!(),
         netlist::CellType::DivFloor => todo!(),
         netlist::CellType::ModFloor => todo!(),
-        netlist::CellType::Mux => todo!(),
+        netlist::CellType::Mux => symbols.get(SymbolKind::Mux),
         netlist::CellType::Pmux => todo!(),
-        netlist::CellType::TriBuf => todo!(),
+        netlist::CellType::TriBuf | netlist::CellType::GateTbuf => {
+            symbols.get(SymbolKind::TriState)
+        }
         netlist::CellType::Sr => todo!(),
         netlist::CellType::Dff => todo!(),
         netlist::CellType::Dffe => todo!(),
@@ -207,9 +227,67 @@ fn translate_cell(
         netlist::CellType::MemWrV2 => todo!(),
         netlist::CellType::MemInitV2 => todo!(),
         netlist::CellType::MemV2 => todo!(),
-        netlist::CellType::Unknown(_) => todo!(),
+        netlist::CellType::Unknown(ref module_name) => {
+            let (Some(module), Some(&sub_circuit)) = (
+                netlist.modules.get(module_name.as_ref()),
+                circuit_ids.get(module_name.as_ref()),
+            ) else {
+                bail!("cell {name} has unsupported type {module_name}");
+            };
+
+            let mut ports: Vec<_> = module
+                .ports
+                .iter()
+                .filter_map(|(port_name, port)| {
+                    let output = match port.direction {
+                        netlist::PortDirection::Input => false,
+                        netlist::PortDirection::Output => true,
+                        netlist::PortDirection::InOut => return None,
+                    };
+                    let bit_width: u8 = port.bits.len().try_into().ok()?;
+                    Some(SubCircuitPort {
+                        name: port_name.clone(),
+                        output,
+                        bit_width: BitWidth(bit_width.try_into().ok()?),
+                    })
+                })
+                .collect();
+            ports.sort_by_key(|port| port.output);
+
+            let mut symbol_builder = symbols.get(SymbolKind::SubCircuit);
+            symbol_builder.sub_circuit(CircuitID(sub_circuit), &ports);
+            symbol_builder
+        }
     };
 
+    // Reduce cells become gates with an input for every bit of A. A single
+    // bit is passed through, or inverted by xnor.
+    let reduce = matches!(
+        cell.cell_type,
+        netlist::CellType::ReduceAnd
+            | netlist::CellType::ReduceOr
+            | netlist::CellType::ReduceXor
+            | netlist::CellType::ReduceXnor
+            | netlist::CellType::ReduceBool
+    );
+    let reduce_width = cell.connections.get("A").map_or(0, |bits| bits.len());
+    if reduce && reduce_width == 0 {
+        bail!("cell {name} reduces no bits");
+    }
+    if reduce && reduce_width == 1 {
+        symbol_builder = match cell.cell_type {
+            netlist::CellType::ReduceXnor => symbols.get(SymbolKind::Not),
+            _ => symbols.get(SymbolKind::Buffer),
+        };
+    }
+    let reduce = reduce && reduce_width > 1;
+    if reduce {
+        let input_count: u8 = reduce_width
+            .try_into()
+            .context("unsupported number of gate inputs")?;
+        symbol_builder.input_count(input_count);
+    }
+
     let symbol_id = symbol_builder
         .name(name.clone())
         .build(commands, circuit_id);
@@ -219,6 +297,28 @@ fn translate_cell(
         .insert(symbol_id, symbol_builder.bounding_box());
 
     for (port_name, signals) in cell.connections.iter() {
+        if reduce && port_name == "A" {
+            let ports = symbol_builder.ports();
+            let inputs = ports.iter().filter(|port| *port.name != *"Y");
+            for (port_info, signal) in inputs.zip(signals.iter()) {
+                if let netlist::Signal::Net(bit) = signal {
+                    let net_bit = bit_map.entry(*bit).or_insert(NetBit { ports: Vec::new() });
+                    net_bit.ports.push(port_info.clone());
+                } else {
+                    bail!(
+                        "cell {name} has a constant bit on port {port_name}, which isn't supported yet"
+                    );
+                }
+            }
+            continue;
+        }
+
+        // The enable of a single bit tri-state buffer is called E instead of EN
+        let port_name = match (&cell.cell_type, port_name.as_str()) {
+            (netlist::CellType::GateTbuf, "E") => "EN",
+            (_, port_name) => port_name,
+        };
+
         if let Some(port_info) = symbol_builder
             .ports()
             .iter()
@@ -229,13 +329,16 @@ fn translate_cell(
                     let net_bit = bit_map.entry(*bit).or_insert(NetBit { ports: Vec::new() });
                     net_bit.ports.push(port_info.clone());
                 } else {
-                    // figure out how to hard code a bit to a specific value
-                    todo!();
+                    bail!(
+                        "cell {name} has a constant bit on port {port_name}, which isn't supported yet"
+                    );
                 }
             }
+        } else if cell.port_directions.get(port_name) == Some(&netlist::PortDirection::InOut) {
+            // Global labels of a sub-circuit connect by name instead of through a port
+            continue;
         } else {
-            // port names do not match what yosys produces
-            todo!();
+            bail!("cell {name} has no port named {port_name}");
         }
     }
 
@@ -425,6 +528,7 @@ fn translate_net(
 
 fn layout_circuit(
     commands: &mut Commands,
+    name: &str,
     graph: &mut MetaGraph,
     bit_map: &HashMap<usize, NetBit>,
 ) -> Result<()> {
@@ -439,13 +543,12 @@ fn layout_circuit(
 
     digilogic_layout::layout_graph(&mut graph.graph).map_err(anyhow::Error::msg)?;
 
-    std::fs::write(
-        "graph.dot",
+    crate::dump::dump(&format!("{name}.dot"), || {
         format!(
             "{:?}",
             petgraph::dot::Dot::with_config(&graph.graph, &[petgraph::dot::Config::EdgeNoLabel])
-        ),
-    )?;
+        )
+    });
 
     let mut max_x = 0.0;
     let mut max_y = 0.0;
@@ -489,3 +592,649 @@ fn layout_circuit(
 
     Ok(())
 }
+
+pub fn save_yosys(world: &mut World, circuit: Entity, filename: &Path) -> Result<()> {
+    info!("saving Yosys netlist {}", filename.display());
+
+    let circuits = extract_hierarchy(world, circuit)?;
+    build_netlist(&circuits)?.save(filename)
+}
+
+/// Builds a Yosys netlist from a circuit hierarchy in dependency order, as
+/// returned by `extract_hierarchy`. The last circuit is marked as the top module.
+fn build_netlist(circuits: &[ExtractedCircuit]) -> Result<netlist::Netlist> {
+    let mut module_names = IdentifierSet::default();
+    let module_names: Vec<SharedStr> = circuits
+        .iter()
+        .map(|circuit| module_names.make_unique(&circuit.name, "circuit").into())
+        .collect();
+
+    let mut modules = BTreeMap::new();
+    let mut module_ports = Vec::with_capacity(circuits.len());
+    for (i, (circuit, module_name)) in circuits.iter().zip(module_names.iter()).enumerate() {
+        let mut builder = ModuleBuilder::default();
+        let ports = builder.build(circuit, &module_names, &module_ports)?;
+        module_ports.push(ports);
+
+        if i + 1 == circuits.len() {
+            builder
+                .module
+                .attributes
+                .insert("top".to_owned(), binary_parameter(1));
+        }
+
+        modules.insert(module_name.clone(), builder.module);
+    }
+
+    Ok(netlist::Netlist { modules })
+}
+
+fn binary_parameter(value: usize) -> String {
+    format!("{value:032b}")
+}
+
+struct ModuleBuilder {
+    module: netlist::Module,
+    idents: IdentifierSet,
+    net_bits: Vec<netlist::Bits>,
+    // Bits 0 and 1 are reserved by Yosys.
+    next_bit: usize,
+}
+
+impl Default for ModuleBuilder {
+    fn default() -> Self {
+        Self {
+            module: netlist::Module {
+                attributes: BTreeMap::new(),
+                ports: BTreeMap::new(),
+                cells: BTreeMap::new(),
+                net_names: BTreeMap::new(),
+            },
+            idents: IdentifierSet::default(),
+            net_bits: Vec::new(),
+            next_bit: 2,
+        }
+    }
+}
+
+impl ModuleBuilder {
+    fn alloc_bits(&mut self, bit_width: u8) -> netlist::Bits {
+        let bits = (self.next_bit..(self.next_bit + bit_width as usize))
+            .map(netlist::Signal::Net)
+            .collect();
+        self.next_bit += bit_width as usize;
+        bits
+    }
+
+    /// Allocates bits for an internal net that has no name in the schematic.
+    fn alloc_hidden_net(&mut self, bit_width: u8) -> netlist::Bits {
+        let bits = self.alloc_bits(bit_width);
+        let name = format!("$auto${}", self.module.net_names.len());
+        self.module.net_names.insert(
+            name.into(),
+            netlist::NetNameOpts {
+                hide_name: 1,
+                bits: bits.clone(),
+            },
+        );
+        bits
+    }
+
+    fn port_bits(&mut self, port: &ExtractedPort) -> netlist::Bits {
+        let Some(net) = port.net else {
+            return self.alloc_hidden_net(port.bit_width);
+        };
+
+        let mut bits = match &port.bits {
+            Some(selected) => selected
+                .iter()
+                .filter_map(|&bit| self.net_bits[net].get(bit as usize).cloned())
+                .collect(),
+            None => self.net_bits[net].clone(),
+        };
+        bits.truncate(port.bit_width as usize);
+        if bits.len() < port.bit_width as usize {
+            let missing = port.bit_width - bits.len() as u8;
+            bits.extend(self.alloc_hidden_net(missing));
+        }
+        bits
+    }
+
+    /// Adds a cell with an A input and a Y output of the same width.
+    fn add_unary_cell(
+        &mut self,
+        name: &str,
+        cell_type: netlist::CellType,
+        width: u8,
+        input: netlist::Bits,
+        output: netlist::Bits,
+    ) {
+        let parameters = BTreeMap::from([
+            ("A_SIGNED".to_owned(), binary_parameter(0)),
+            ("A_WIDTH".to_owned(), binary_parameter(width as usize)),
+            ("Y_WIDTH".to_owned(), binary_parameter(width as usize)),
+        ]);
+        self.add_cell(
+            name,
+            cell_type,
+            parameters,
+            vec![
+                ("A", netlist::PortDirection::Input, input),
+                ("Y", netlist::PortDirection::Output, output),
+            ],
+        );
+    }
+
+    fn add_cell(
+        &mut self,
+        name: &str,
+        cell_type: netlist::CellType,
+        parameters: BTreeMap<String, String>,
+        connections: Vec<(&str, netlist::PortDirection, netlist::Bits)>,
+    ) {
+        let name = self.idents.make_unique(name, "cell");
+        let mut port_directions = BTreeMap::new();
+        let mut cell_connections = BTreeMap::new();
+        for (port, direction, bits) in connections {
+            port_directions.insert(port.to_owned(), direction);
+            cell_connections.insert(port.to_owned(), bits);
+        }
+
+        self.module.cells.insert(
+            name.into(),
+            netlist::Cell {
+                hide_name: 0,
+                cell_type,
+                parameters,
+                port_directions,
+                connections: cell_connections,
+            },
+        );
+    }
+
+    /// Builds the module of `circuit` and returns the names of its ports.
+    fn build(
+        &mut self,
+        circuit: &ExtractedCircuit,
+        module_names: &[SharedStr],
+        module_ports: &[PortIdents],
+    ) -> Result<PortIdents> {
+        use netlist::PortDirection::{Input, Output};
+
+        for net in circuit.nets.iter() {
+            let bits = self.alloc_bits(net.bit_width);
+            self.net_bits.push(bits);
+        }
+
+        // Yosys has no cell for constants, the driven bits are constant values instead.
+        for symbol in circuit.symbols.iter() {
+            let (Some(value), Some(port)) = (symbol.value, symbol.outputs().next()) else {
+                continue;
+            };
+            let Some(net) = port.net else {
+                continue;
+            };
+
+            let indices: Vec<usize> = match &port.bits {
+                Some(bits) => bits.iter().map(|&bit| bit as usize).collect(),
+                None => (0..port.bit_width as usize).collect(),
+            };
+            for (i, index) in indices.into_iter().enumerate() {
+                let high = value.checked_shr(i as u32).unwrap_or(0) & 1 == 1;
+                if let Some(bit) = self.net_bits[net].get_mut(index) {
+                    *bit = netlist::Signal::Value(if high { "1" } else { "0" }.into());
+                }
+            }
+        }
+
+        // Nets connected to a module port take the name of the port, like Yosys does.
+        let mut net_names: Vec<Option<SharedStr>> = vec![None; circuit.nets.len()];
+        let mut ports = PortIdents::default();
+        for symbol in circuit.symbols.iter() {
+            let direction = match symbol.kind {
+                SymbolKind::In => Input,
+                SymbolKind::Out => Output,
+                _ => continue,
+            };
+
+            let name: SharedStr = self.idents.make_unique(&symbol.name, "port").into();
+            ports.push(&symbol.name, direction == Input, direction == Output, &name);
+            let port = symbol.ports.first();
+            let bits = match port {
+                Some(port) => self.port_bits(port),
+                None => self.alloc_hidden_net(1),
+            };
+
+            if let Some(net) = port.and_then(|port| port.net) {
+                net_names[net].get_or_insert_with(|| name.clone());
+            }
+
+            self.module
+                .ports
+                .insert(name, netlist::Port { direction, bits });
+        }
+
+        // Global labels connect to the circuits above, so they're ports too.
+        for global in circuit.globals.iter() {
+            let name: SharedStr = self.idents.make_unique(&global.name, "port").into();
+            ports.push(&global.name, true, true, &name);
+            net_names[global.net].get_or_insert_with(|| name.clone());
+
+            self.module.ports.insert(
+                name,
+                netlist::Port {
+                    direction: netlist::PortDirection::InOut,
+                    bits: self.net_bits[global.net].clone(),
+                },
+            );
+        }
+
+        for (i, net) in circuit.nets.iter().enumerate() {
+            let (name, hide_name) = match net_names[i].take() {
+                Some(name) => (name, 0),
+                None if net.name.is_empty() => (format!("$net${i}").into(), 1),
+                None => (self.idents.make_unique(&net.name, "net").into(), 0),
+            };
+
+            self.module.net_names.insert(
+                name,
+                netlist::NetNameOpts {
+                    hide_name,
+                    bits: self.net_bits[i].clone(),
+                },
+            );
+        }
+
+        for symbol in circuit.symbols.iter() {
+            // Custom symbols become cells of a module of the same name, which
+            // Yosys treats as a black box.
+            let instantiated = match (symbol.sub_circuit, &symbol.custom_kind) {
+                (Some(sub_circuit), _) => Some((
+                    module_names[sub_circuit].clone(),
+                    module_ports[sub_circuit].for_instance(symbol),
+                )),
+                (None, Some(custom_kind)) => Some((
+                    custom_kind.clone(),
+                    symbol
+                        .ports
+                        .iter()
+                        .map(|port| sanitize(&port.name, "port"))
+                        .collect(),
+                )),
+                (None, None) => None,
+            };
+            if let Some((module_name, port_names)) = instantiated {
+                let connections: Vec<_> = symbol
+                    .ports
+                    .iter()
+                    .zip(port_names)
+                    .map(|(port, port_name)| {
+                        let direction = match (port.input, port.output) {
+                            (true, true) => netlist::PortDirection::InOut,
+                            (_, true) => Output,
+                            _ => Input,
+                        };
+                        (port_name, direction, self.port_bits(port))
+                    })
+                    .collect();
+
+                self.add_cell(
+                    &symbol.designator,
+                    netlist::CellType::Unknown(module_name.as_str().into()),
+                    BTreeMap::new(),
+                    connections
+                        .iter()
+                        .map(|(name, direction, bits)| (name.as_str(), *direction, bits.clone()))
+                        .collect(),
+                );
+                continue;
+            }
+
+            // Pins, constants and labels were handled above and have no cell.
+            if matches!(
+                symbol.kind,
+                SymbolKind::In
+                    | SymbolKind::Out
+                    | SymbolKind::Const
+                    | SymbolKind::Label
+                    | SymbolKind::GlobalLabel
+            ) {
+                continue;
+            }
+
+            let inputs: Vec<_> = symbol.inputs().map(|port| self.port_bits(port)).collect();
+            let Some(output) = symbol.outputs().next() else {
+                continue;
+            };
+            let width = output.bit_width;
+            let output = self.port_bits(output);
+
+            // Inverted gates are the plain gate followed by a NOT
+            let (binary_cell_type, inverted) = match symbol.kind {
+                SymbolKind::In
+                | SymbolKind::Out
+                | SymbolKind::SubCircuit
+                | SymbolKind::Const
+                | SymbolKind::Custom
+                | SymbolKind::Label
+                | SymbolKind::GlobalLabel => continue,
+                SymbolKind::And => (netlist::CellType::And, false),
+                SymbolKind::Or => (netlist::CellType::Or, false),
+                SymbolKind::Xor => (netlist::CellType::Xor, false),
+                SymbolKind::Nand => (netlist::CellType::And, true),
+                SymbolKind::Nor => (netlist::CellType::Or, true),
+                SymbolKind::Xnor => (netlist::CellType::Xor, true),
+                SymbolKind::Not | SymbolKind::Buffer => {
+                    let Some(input) = inputs.into_iter().next() else {
+                        bail!("{} has no input", symbol.designator);
+                    };
+
+                    let cell_type = match symbol.kind {
+                        SymbolKind::Not => netlist::CellType::Not,
+                        _ => netlist::CellType::Pos,
+                    };
+                    self.add_unary_cell(&symbol.designator, cell_type, width, input, output);
+                    continue;
+                }
+                SymbolKind::TriState => {
+                    let [a, enable] = <[_; 2]>::try_from(inputs).map_err(|_| {
+                        anyhow::anyhow!("{} must have an input and an enable", symbol.designator)
+                    })?;
+
+                    let parameters =
+                        BTreeMap::from([("WIDTH".to_owned(), binary_parameter(width as usize))]);
+                    self.add_cell(
+                        &symbol.designator,
+                        netlist::CellType::TriBuf,
+                        parameters,
+                        vec![
+                            ("A", Input, a),
+                            ("EN", Input, enable),
+                            ("Y", Output, output),
+                        ],
+                    );
+                    continue;
+                }
+                SymbolKind::Mux => {
+                    // The first input is the select line, followed by the data inputs.
+                    let [select, a, b] = <[_; 3]>::try_from(inputs).map_err(|_| {
+                        anyhow::anyhow!("{} must have exactly two data inputs", symbol.designator)
+                    })?;
+
+                    let parameters =
+                        BTreeMap::from([("WIDTH".to_owned(), binary_parameter(width as usize))]);
+                    self.add_cell(
+                        &symbol.designator,
+                        netlist::CellType::Mux,
+                        parameters,
+                        vec![
+                            ("A", Input, a),
+                            ("B", Input, b),
+                            ("S", Input, select),
+                            ("Y", Output, output),
+                        ],
+                    );
+                    continue;
+                }
+            };
+
+            // Yosys gates have exactly two inputs, so wider gates become a chain.
+            let mut inputs = inputs.into_iter();
+            let (Some(mut a), Some(mut b)) = (inputs.next(), inputs.next()) else {
+                bail!("{} needs at least two inputs", symbol.designator);
+            };
+
+            let parameters = BTreeMap::from([
+                ("A_SIGNED".to_owned(), binary_parameter(0)),
+                ("A_WIDTH".to_owned(), binary_parameter(width as usize)),
+                ("B_SIGNED".to_owned(), binary_parameter(0)),
+                ("B_WIDTH".to_owned(), binary_parameter(width as usize)),
+                ("Y_WIDTH".to_owned(), binary_parameter(width as usize)),
+            ]);
+
+            loop {
+                let next = inputs.next();
+                let y = match next {
+                    Some(_) => self.alloc_hidden_net(width),
+                    None if inverted => self.alloc_hidden_net(width),
+                    None => output.clone(),
+                };
+
+                self.add_cell(
+                    &symbol.designator,
+                    binary_cell_type.clone(),
+                    parameters.clone(),
+                    vec![("A", Input, a), ("B", Input, b), ("Y", Output, y.clone())],
+                );
+
+                let Some(next) = next else {
+                    if inverted {
+                        self.add_unary_cell(
+                            &symbol.designator,
+                            netlist::CellType::Not,
+                            width,
+                            y,
+                            output,
+                        );
+                    }
+                    break;
+                };
+                a = y;
+                b = next;
+            }
+        }
+
+        Ok(ports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_small_sample() {
+        let symbols = SymbolRegistry::default();
+
+        let mut world = World::new();
+        let circuit = crate::json::load_json(
+            &mut world.commands(),
+            Path::new("testdata/small.dlc"),
+            &symbols,
+        )
+        .unwrap();
+        world.flush();
+        let original = extract_hierarchy(&mut world, circuit).unwrap();
+
+        let json = serde_json::to_string(&build_netlist(&original).unwrap()).unwrap();
+        let netlist = netlist::Netlist::try_from(json.as_str()).unwrap();
+
+        let mut world = World::new();
+        let circuit = translate_netlist(&mut world.commands(), &netlist, &symbols).unwrap();
+        world.flush();
+        let imported = extract_hierarchy(&mut world, circuit).unwrap();
+
+        let count_kinds = |circuit: &ExtractedCircuit| {
+            let mut counts = HashMap::new();
+            for symbol in circuit.symbols.iter() {
+                *counts.entry(format!("{:?}", symbol.kind)).or_insert(0) += 1;
+            }
+            counts
+        };
+
+        assert_eq!(original.len(), imported.len());
+        assert_eq!(count_kinds(&original[0]), count_kinds(&imported[0]));
+        assert_eq!(original[0].nets.len(), imported[0].nets.len());
+    }
+
+    #[test]
+    fn round_trips_sub_circuits() {
+        let symbols = SymbolRegistry::default();
+
+        let mut world = World::new();
+        let mut spawn_circuit = |name| {
+            world
+                .spawn(CircuitBundle {
+                    circuit: Circuit,
+                    name: Name(SharedStr::new_static(name)),
+                })
+                .id()
+        };
+        let child = spawn_circuit("child");
+        let parent = spawn_circuit("parent");
+
+        let pins = [
+            (SymbolKind::In, "a"),
+            (SymbolKind::In, "a"),
+            (SymbolKind::In, "b c"),
+            (SymbolKind::Out, "y"),
+        ];
+        for (i, (kind, name)) in pins.into_iter().enumerate() {
+            symbols
+                .get(kind)
+                .name(SharedStr::new_static(name))
+                .position(Vec2 {
+                    x: Fixed::from_i16(0),
+                    y: Fixed::from_i16(i as i16 * 40),
+                })
+                .build(&mut world.commands(), child);
+        }
+        world.flush();
+
+        let ports = digilogic_core::sub_circuit::circuit_ports(&mut world, child);
+        symbols
+            .get(SymbolKind::SubCircuit)
+            .name(SharedStr::new_static("child"))
+            .sub_circuit(CircuitID(child), &ports)
+            .build(&mut world.commands(), parent);
+        world.flush();
+        let original = extract_hierarchy(&mut world, parent).unwrap();
+
+        let netlist = build_netlist(&original).unwrap();
+        let module_ports: Vec<_> = netlist.modules["child"].ports.keys().cloned().collect();
+        let cell = netlist.modules["parent"].cells.values().next().unwrap();
+        let cell_ports: Vec<_> = cell.connections.keys().map(|name| name.as_str()).collect();
+        assert_eq!(module_ports.len(), 4);
+        assert_eq!(
+            module_ports.iter().map(|name| &**name).collect::<Vec<_>>(),
+            cell_ports,
+        );
+
+        let json = serde_json::to_string(&netlist).unwrap();
+        let netlist = netlist::Netlist::try_from(json.as_str()).unwrap();
+
+        let mut world = World::new();
+        let circuit = translate_netlist(&mut world.commands(), &netlist, &symbols).unwrap();
+        world.flush();
+        let imported = extract_hierarchy(&mut world, circuit).unwrap();
+
+        assert_eq!(imported.len(), 2);
+        let instance = &imported[1].symbols[0];
+        assert_eq!(instance.kind, SymbolKind::SubCircuit);
+        assert_eq!(instance.sub_circuit, Some(0));
+        assert_eq!(instance.ports.len(), 4);
+        assert_eq!(&*imported[0].name, "child");
+    }
+
+    #[test]
+    fn imports_gate_cells() {
+        let json = r#"{
+            "modules": {
+                "top": {
+                    "ports": {
+                        "a": { "direction": "input", "bits": [2] },
+                        "b": { "direction": "input", "bits": [3] },
+                        "en": { "direction": "input", "bits": [4] },
+                        "y": { "direction": "output", "bits": [6] }
+                    },
+                    "cells": {
+                        "nand": {
+                            "type": "$_NAND_",
+                            "port_directions": { "A": "input", "B": "input", "Y": "output" },
+                            "connections": { "A": [2], "B": [3], "Y": [5] }
+                        },
+                        "tbuf": {
+                            "type": "$_TBUF_",
+                            "port_directions": { "A": "input", "E": "input", "Y": "output" },
+                            "connections": { "A": [5], "E": [4], "Y": [6] }
+                        }
+                    },
+                    "netnames": {}
+                }
+            }
+        }"#;
+        let netlist = netlist::Netlist::try_from(json).unwrap();
+
+        let symbols = SymbolRegistry::default();
+        let mut world = World::new();
+        let circuit = translate_netlist(&mut world.commands(), &netlist, &symbols).unwrap();
+        world.flush();
+        let circuits = extract_hierarchy(&mut world, circuit).unwrap();
+
+        let kinds: Vec<_> = circuits[0]
+            .symbols
+            .iter()
+            .map(|symbol| symbol.kind)
+            .filter(|kind| !matches!(kind, SymbolKind::In | SymbolKind::Out))
+            .collect();
+        assert!(kinds.contains(&SymbolKind::Nand));
+        assert!(kinds.contains(&SymbolKind::TriState));
+    }
+
+    #[test]
+    fn imports_reduce_cells() {
+        let json = r#"{
+            "modules": {
+                "top": {
+                    "ports": {
+                        "a": { "direction": "input", "bits": [2, 3, 4] },
+                        "y": { "direction": "output", "bits": [5, 6, 7] }
+                    },
+                    "cells": {
+                        "and": {
+                            "type": "$reduce_and",
+                            "port_directions": { "A": "input", "Y": "output" },
+                            "connections": { "A": [2, 3, 4], "Y": [5] }
+                        },
+                        "or": {
+                            "type": "$reduce_or",
+                            "port_directions": { "A": "input", "Y": "output" },
+                            "connections": { "A": [2], "Y": [6] }
+                        },
+                        "xnor": {
+                            "type": "$reduce_xnor",
+                            "port_directions": { "A": "input", "Y": "output" },
+                            "connections": { "A": [3], "Y": [7] }
+                        }
+                    },
+                    "netnames": {}
+                }
+            }
+        }"#;
+        let netlist = netlist::Netlist::try_from(json).unwrap();
+
+        let symbols = SymbolRegistry::default();
+        let mut world = World::new();
+        let circuit = translate_netlist(&mut world.commands(), &netlist, &symbols).unwrap();
+        world.flush();
+        let circuits = extract_hierarchy(&mut world, circuit).unwrap();
+
+        let gate = |name: &str| {
+            let symbol = circuits[0]
+                .symbols
+                .iter()
+                .find(|symbol| &*symbol.name == name)
+                .unwrap();
+            (symbol.kind, symbol.inputs().count())
+        };
+        assert_eq!(gate("and"), (SymbolKind::And, 3));
+        assert_eq!(gate("or"), (SymbolKind::Buffer, 1));
+        assert_eq!(gate("xnor"), (SymbolKind::Not, 1));
+
+        // Constant bits aren't supported yet, but don't bring the importer down
+        let json = json.replace(r#""A": [2], "Y": [6]"#, r#""A": ["1"], "Y": [6]"#);
+        let netlist = netlist::Netlist::try_from(json.as_str()).unwrap();
+        let mut world = World::new();
+        assert!(translate_netlist(&mut world.commands(), &netlist, &symbols).is_err());
+    }
+}
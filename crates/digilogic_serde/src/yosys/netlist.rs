@@ -1,4 +1,4 @@
-//! Import circuits from Yosys JSON format
+//! Import and export circuits in Yosys JSON format
 //!
 //! Use the following command to generate compatible JSON files, unoptimized:
 //!
@@ -9,7 +9,7 @@
 //! yosys -p "read_verilog <VERILOG-FILE>; hierarchy -auto-top; proc; opt; fsm -expand; memory -nomap; wreduce -memx; opt; write_json <OUTPUT-FILE>"
 
 use digilogic_core::SharedStr;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{collections::BTreeMap, path::Path, sync::Arc};
 
 /// The known Yosys cell types
@@ -65,6 +65,16 @@ pub enum CellType {
     MemWrV2,
     MemInitV2,
     MemV2,
+    // Single bit cells, as left by techmapping
+    GateBuf,
+    GateNot,
+    GateAnd,
+    GateNand,
+    GateOr,
+    GateNor,
+    GateXor,
+    GateXnor,
+    GateTbuf,
     Unknown(Arc<str>),
 }
 
@@ -120,11 +130,86 @@ impl From<String> for CellType {
             "$memwr_v2" => Self::MemWrV2,
             "$meminit_v2" => Self::MemInitV2,
             "$mem_v2" => Self::MemV2,
+            "$_BUF_" => Self::GateBuf,
+            "$_NOT_" => Self::GateNot,
+            "$_AND_" => Self::GateAnd,
+            "$_NAND_" => Self::GateNand,
+            "$_OR_" => Self::GateOr,
+            "$_NOR_" => Self::GateNor,
+            "$_XOR_" => Self::GateXor,
+            "$_XNOR_" => Self::GateXnor,
+            "$_TBUF_" => Self::GateTbuf,
             _ => Self::Unknown(value.into()),
         }
     }
 }
 
+impl CellType {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Not => "$not",
+            Self::Pos => "$pos",
+            Self::Neg => "$neg",
+            Self::ReduceAnd => "$reduce_and",
+            Self::ReduceOr => "$reduce_or",
+            Self::ReduceXor => "$reduce_xor",
+            Self::ReduceXnor => "$reduce_xnor",
+            Self::ReduceBool => "$reduce_bool",
+            Self::LogicNot => "$logic_not",
+            Self::And => "$and",
+            Self::Or => "$or",
+            Self::Xor => "$xor",
+            Self::Xnor => "$xnor",
+            Self::Shl => "$shl",
+            Self::Sshl => "$sshl",
+            Self::Shr => "$shr",
+            Self::Sshr => "$sshr",
+            Self::LogicAnd => "$logic_and",
+            Self::LogicOr => "$logic_or",
+            Self::EqX => "$eqx",
+            Self::NeX => "$nex",
+            Self::Pow => "$pow",
+            Self::Lt => "$lt",
+            Self::Le => "$le",
+            Self::Eq => "$eq",
+            Self::Ne => "$ne",
+            Self::Ge => "$ge",
+            Self::Gt => "$gt",
+            Self::Add => "$add",
+            Self::Sub => "$sub",
+            Self::Mul => "$mul",
+            Self::Div => "$div",
+            Self::Mod => "$mod",
+            Self::DivFloor => "$divfloor",
+            Self::ModFloor => "$modfloor",
+            Self::Mux => "$mux",
+            Self::Pmux => "$pmux",
+            Self::TriBuf => "$tribuf",
+            Self::Sr => "$sr",
+            Self::Dff => "$dff",
+            Self::Dffe => "$dffe",
+            Self::Sdff => "$sdff",
+            Self::Sdffe => "$sdffe",
+            Self::Sdffce => "$sdffce",
+            Self::Dlatch => "$dlatch",
+            Self::MemRdV2 => "$memrd_v2",
+            Self::MemWrV2 => "$memwr_v2",
+            Self::MemInitV2 => "$meminit_v2",
+            Self::MemV2 => "$mem_v2",
+            Self::GateBuf => "$_BUF_",
+            Self::GateNot => "$_NOT_",
+            Self::GateAnd => "$_AND_",
+            Self::GateNand => "$_NAND_",
+            Self::GateOr => "$_OR_",
+            Self::GateNor => "$_NOR_",
+            Self::GateXor => "$_XOR_",
+            Self::GateXnor => "$_XNOR_",
+            Self::GateTbuf => "$_TBUF_",
+            Self::Unknown(name) => name,
+        }
+    }
+}
+
 fn cell_type<'de, D>(deserializer: D) -> Result<CellType, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -133,7 +218,14 @@ where
     Ok(name.into())
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+fn serialize_cell_type<S>(cell_type: &CellType, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(cell_type.name())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PortDirection {
     #[serde(rename = "input")]
     Input,
@@ -145,7 +237,7 @@ pub enum PortDirection {
 
 type NetId = usize;
 
-#[derive(Clone, PartialEq, Eq, Hash, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Signal {
     Value(Arc<str>),
@@ -155,17 +247,21 @@ pub enum Signal {
 /// LSB first
 pub type Bits = Vec<Signal>;
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Port {
     pub direction: PortDirection,
     pub bits: Bits,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Cell {
     #[serde(default)]
     pub hide_name: u8,
-    #[serde(rename = "type", deserialize_with = "cell_type")]
+    #[serde(
+        rename = "type",
+        serialize_with = "serialize_cell_type",
+        deserialize_with = "cell_type"
+    )]
     pub cell_type: CellType,
     #[serde(default)]
     pub parameters: BTreeMap<String, String>,
@@ -173,15 +269,17 @@ pub struct Cell {
     pub connections: BTreeMap<String, Bits>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct NetNameOpts {
     #[serde(default)]
     pub hide_name: u8,
     pub bits: Bits,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Module {
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
     pub ports: BTreeMap<SharedStr, Port>,
     #[serde(default)]
     pub cells: BTreeMap<SharedStr, Cell>,
@@ -189,7 +287,7 @@ pub struct Module {
     pub net_names: BTreeMap<SharedStr, NetNameOpts>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Netlist {
     pub modules: BTreeMap<SharedStr, Module>,
 }
@@ -203,10 +301,26 @@ impl TryFrom<&str> for Netlist {
     }
 }
 
+impl Module {
+    /// Whether Yosys marked this as the top module of the design
+    pub fn is_top(&self) -> bool {
+        self.attributes
+            .get("top")
+            .is_some_and(|top| top.contains('1'))
+    }
+}
+
 impl Netlist {
     pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
         let file = std::fs::File::open(path)?;
         let reader = std::io::BufReader::new(file);
         Ok(serde_json::from_reader(reader)?)
     }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)?;
+        let writer = std::io::BufWriter::new(file);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
 }
@@ -67,6 +67,7 @@ pub struct DragEvent {
 
 #[derive(Event, Debug)]
 pub struct MoveEntity {
+    pub drag_type: DragType,
     pub viewport: Entity,
     pub circuit: CircuitID,
     pub entity: Entity,
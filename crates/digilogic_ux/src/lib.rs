@@ -36,6 +36,11 @@ impl bevy_app::Plugin for UxPlugin {
         );
         app.add_observer(spatial_index::on_remove_bounding_box_update_spatial_index);
         app.add_observer(spatial_index::on_remove_net_update_spatial_index);
-        app.add_systems(bevy_app::PostUpdate, move_entities_with_snap);
+        app.add_systems(
+            bevy_app::PostUpdate,
+            move_entities_with_snap.before(digilogic_core::history::HistorySet),
+        );
+        app.add_observer(spatial_index::on_attach_update_spatial_index);
+        app.add_observer(spatial_index::on_detach_update_spatial_index);
     }
 }
@@ -2,6 +2,7 @@ use aery::prelude::*;
 use bevy_ecs::prelude::*;
 use bvh_arena::{Bvh, VolumeHandle};
 use digilogic_core::components::{Child, Circuit, Net};
+use digilogic_core::history::{Attached, Detached};
 use digilogic_core::transform::{AbsoluteBoundingBox, BoundingBox, Vec2};
 use digilogic_core::{fixed, Fixed, HashMap};
 use digilogic_routing::{RoutingComplete, VertexKind, Vertices};
@@ -162,3 +163,42 @@ pub(crate) fn on_remove_net_update_spatial_index(
             }
         });
 }
+
+/// Puts an entity that an edit attached back into the spatial index, once
+/// its bounding boxes are updated.
+pub(crate) fn on_attach_update_spatial_index(
+    trigger: Trigger<Attached>,
+    children: Query<(Entity, Relations<Child>)>,
+    mut bounding_boxes: Query<&mut AbsoluteBoundingBox>,
+) {
+    children
+        .traverse::<Child>([trigger.entity()])
+        .for_each(|&mut entity, _| {
+            if let Ok(mut bounds) = bounding_boxes.get_mut(entity) {
+                bounds.set_changed();
+            }
+        });
+}
+
+/// Removes an entity that an edit detached, and everything it contains, from
+/// the spatial index of the circuit it was in.
+pub(crate) fn on_detach_update_spatial_index(
+    trigger: Trigger<Detached>,
+    mut circuits: Query<&mut SpatialIndex, With<Circuit>>,
+    children: Query<(Entity, Relations<Child>)>,
+) {
+    let mut removed = Vec::new();
+    children
+        .traverse::<Child>([trigger.entity()])
+        .for_each(|&mut entity, _| removed.push(entity));
+
+    children
+        .traverse::<Up<Child>>([trigger.event().parent])
+        .for_each(|&mut entity, _| {
+            if let Ok(mut spatial_index) = circuits.get_mut(entity) {
+                for &removed in &removed {
+                    spatial_index.remove(removed);
+                }
+            }
+        });
+}
@@ -4,6 +4,7 @@ use crate::{ClickEvent, DragEvent, DragType, HoverEvent, MoveEntity, PointerButt
 use aery::prelude::*;
 use bevy_ecs::prelude::*;
 use bevy_state::prelude::*;
+use digilogic_core::history::{Edit, HistoryEvent};
 use digilogic_core::states::SimulationState;
 use digilogic_core::transform::{BoundingBox, GlobalTransform, Transform, Vec2};
 use digilogic_core::Fixed;
@@ -170,6 +171,7 @@ fn mouse_drag_system(
     for entity_offset in moving.0.iter() {
         // send an event to the entity move system, mainly to keep the argument list of this system shorter
         move_events.send(MoveEntity {
+            drag_type: event.drag_type,
             viewport,
             circuit: event.circuit,
             entity: entity_offset.entity,
@@ -187,20 +189,27 @@ fn mouse_drag_system(
 const SNAP_CANDIDATE_DISTANCE: Fixed = fixed!(500);
 const SNAP_DISTANCE: Fixed = fixed!(7);
 
-/// Move entities while snapping the entity's ports to nearby ports
+/// Move entities while snapping the entity's ports to nearby ports. The moves
+/// of one drag are undone together.
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn move_entities_with_snap(
     mut events: EventReader<MoveEntity>,
+    mut history_events: EventWriter<HistoryEvent>,
     spatial_indices: Query<&SpatialIndex, With<Circuit>>,
     children: Query<(Entity, Relations<Child>)>,
     port_transform_query: Query<&GlobalTransform, With<Port>>,
-    mut transform_query: Query<&mut Transform, Without<Port>>,
+    transform_query: Query<&Transform, Without<Port>>,
     mut port_positions: Local<Vec<Vec2>>,
     mut excluded_ports: Local<Vec<Entity>>,
 ) {
     // for each MoveEntity event
     for event in events.read() {
+        if event.drag_type == DragType::Start {
+            history_events.send(HistoryEvent::BeginGroup(event.circuit));
+        }
+
         // find the transform for the entity
-        if let Ok(mut transform) = transform_query.get_mut(event.entity) {
+        if let Ok(transform) = transform_query.get(event.entity) {
             let proposed_pos = event.pos + event.offset;
             let delta = proposed_pos - transform.translation;
 
@@ -272,11 +281,25 @@ pub(crate) fn move_entities_with_snap(
             }
 
             // update the position with any snap delta added
-            transform.translation = proposed_pos
+            let mut moved = *transform;
+            moved.translation = proposed_pos
                 + Vec2 {
                     x: x_delta,
                     y: y_delta,
                 };
+
+            history_events.send(HistoryEvent::Edit(
+                event.circuit,
+                Edit::Move {
+                    entity: event.entity,
+                    from: *transform,
+                    to: moved,
+                },
+            ));
+        }
+
+        if event.drag_type == DragType::End {
+            history_events.send(HistoryEvent::EndGroup(event.circuit));
         }
     }
 }